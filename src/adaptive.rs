@@ -0,0 +1,126 @@
+//! Adaptive probing frequency (--adaptive)
+//!
+//! A round still sends as many probes as the fixed schedule would, --burst
+//! per hop, but hands them out by how the hops have been behaving: a hop with
+//! recent loss or high jitter gets `UNSTABLE_WEIGHT` times the share of a
+//! stable one. Shares are fractional and carry over between rounds, so a
+//! stable hop is still probed when the unstable ones take most of the
+//! budget, just not every round.
+
+use crate::{hop_stats::PacketOutcome, HopStats};
+use std::sync::Mutex;
+
+/// Share of the budget an unstable hop gets, relative to a stable one
+const UNSTABLE_WEIGHT: f64 = 3.0;
+/// Outcomes looked at for recent loss
+const RECENT_PROBES: usize = 10;
+/// Mean jitter above this fraction of the average RTT makes a hop unstable
+const JITTER_RATIO: f64 = 0.25;
+/// Allowance for rounding when turning shares into whole probes
+const EPSILON: f64 = 1e-9;
+
+#[derive(Debug)]
+pub struct ProbeBudget {
+    credit: Mutex<Vec<f64>>, // Probes earned but not sent yet, per hop
+}
+
+impl ProbeBudget {
+    pub fn new(hops: usize) -> Self {
+        Self {
+            credit: Mutex::new(vec![0.0; hops]),
+        }
+    }
+
+    /// Number of probes to send each of `hops` this round, `per_hop` of them
+    /// apiece on average
+    pub fn allocate(&self, hops: &[HopStats], per_hop: usize) -> Vec<usize> {
+        let weights: Vec<f64> = hops.iter().map(weight).collect();
+        let total: f64 = weights.iter().sum();
+        let budget = (hops.len() * per_hop) as f64;
+
+        let mut credit = self.credit.lock().unwrap();
+        hops.iter()
+            .zip(weights)
+            .zip(credit.iter_mut())
+            .map(|((hop, weight), credit)| {
+                *credit += budget * weight / total;
+                let mut probes = (*credit + EPSILON).floor();
+                if hop.sent == 0 {
+                    // A hop new to the range is probed straight away and pays it back later
+                    probes = probes.max(1.0);
+                }
+                *credit -= probes;
+                probes as usize
+            })
+            .collect()
+    }
+}
+
+/// Whether a hop has lost probes lately or its jitter is high for its RTT
+pub fn is_unstable(hop: &HopStats) -> bool {
+    if hop.received == 0 {
+        return false; // Silent hops aren't worth extra probes
+    }
+    let recent_loss = hop
+        .packet_history
+        .iter()
+        .rev()
+        .take(RECENT_PROBES)
+        .any(|outcome| matches!(outcome, PacketOutcome::Lost));
+    let jittery = match (hop.jitter_avg, hop.avg_rtt) {
+        (Some(jitter), Some(avg)) => jitter.as_secs_f64() > avg.as_secs_f64() * JITTER_RATIO,
+        _ => false,
+    };
+    recent_loss || jittery
+}
+
+fn weight(hop: &HopStats) -> f64 {
+    if is_unstable(hop) {
+        UNSTABLE_WEIGHT
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn answered(ttl: u8) -> HopStats {
+        let mut hop = HopStats::new(ttl);
+        for _ in 0..3 {
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_millis(10));
+        }
+        hop
+    }
+
+    #[test]
+    fn test_unstable_hops_get_more_of_the_same_budget() {
+        let mut hops: Vec<HopStats> = (1..=4).map(answered).collect();
+        hops[2].increment_sent();
+        hops[2].add_timeout();
+        assert!(is_unstable(&hops[2]));
+        assert!(!is_unstable(&hops[0]));
+
+        let budget = ProbeBudget::new(4);
+        let mut totals = [0; 4];
+        for _ in 0..12 {
+            for (total, probes) in totals.iter_mut().zip(budget.allocate(&hops, 1)) {
+                *total += probes;
+            }
+        }
+        // 48 probes, as without --adaptive, with the lossy hop getting 3 shares of 6
+        assert_eq!(totals.iter().sum::<usize>(), 48);
+        assert_eq!(totals, [8, 8, 24, 8]);
+    }
+
+    #[test]
+    fn test_stable_path_keeps_a_fixed_schedule() {
+        let mut hops: Vec<HopStats> = (1..=3).map(answered).collect();
+        hops.push(HopStats::new(4)); // Just joined the range
+        let budget = ProbeBudget::new(4);
+        assert_eq!(budget.allocate(&hops, 2), vec![2, 2, 2, 2]);
+    }
+}
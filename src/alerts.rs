@@ -0,0 +1,248 @@
+//! Loss and latency thresholds
+//!
+//! Thresholds are evaluated against the end-to-end hop (the furthest hop that
+//! has answered) so that rate-limited intermediate routers don't trigger
+//! alerts on their own.
+
+use crate::{utils, Args, HopStats, MtrSession};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tracing::{info, warn};
+
+/// Which limit was exceeded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreachKind {
+    Loss(f64),    // Loss percentage observed
+    Latency(f64), // Average RTT observed, in milliseconds
+}
+
+/// A threshold breach at a specific hop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breach {
+    pub hop: u8,
+    pub kind: BreachKind,
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            BreachKind::Loss(loss) => write!(f, "hop {} loss {:.1}%", self.hop, loss),
+            BreachKind::Latency(ms) => write!(f, "hop {} latency {:.1}ms", self.hop, ms),
+        }
+    }
+}
+
+/// Configured alert limits
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Thresholds {
+    pub loss_percent: Option<f64>,
+    pub latency_ms: Option<u64>,
+}
+
+impl Thresholds {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            loss_percent: args.loss_threshold,
+            latency_ms: args.latency_threshold,
+        }
+    }
+
+    /// True when at least one limit is configured
+    pub fn is_enabled(&self) -> bool {
+        self.loss_percent.is_some() || self.latency_ms.is_some()
+    }
+
+    /// Check a single hop against the configured limits
+    pub fn check_hop(&self, hop: &HopStats) -> Option<Breach> {
+        if hop.sent == 0 {
+            return None;
+        }
+
+        if let Some(limit) = self.loss_percent {
+            if hop.loss_percent > limit {
+                return Some(Breach {
+                    hop: hop.hop,
+                    kind: BreachKind::Loss(hop.loss_percent),
+                });
+            }
+        }
+
+        if let (Some(limit), Some(avg)) = (self.latency_ms, hop.avg_rtt) {
+            let avg_ms = utils::time::duration_to_ms_f64(avg);
+            if avg_ms > limit as f64 {
+                return Some(Breach {
+                    hop: hop.hop,
+                    kind: BreachKind::Latency(avg_ms),
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Check the end-to-end hop of a session
+    pub fn check_session(&self, session: &MtrSession) -> Option<Breach> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let last = session.stats.rposition(|hop| hop.received > 0)?;
+        self.check_hop(&session.stats.hop(last))
+    }
+}
+
+/// The --on-alert command, run once each time the thresholds are breached
+///
+/// The command goes to `sh -c` without being waited for, so a slow one
+/// doesn't hold up probing. It isn't run again until a check finds the
+/// path back within its limits.
+#[derive(Debug)]
+pub struct AlertCommand {
+    command: String,
+    thresholds: Mutex<Thresholds>,
+    breached: AtomicBool,
+}
+
+impl AlertCommand {
+    pub fn from_args(args: &Args) -> Option<Self> {
+        args.on_alert.as_ref().map(|command| Self {
+            command: command.clone(),
+            thresholds: Mutex::new(Thresholds::from_args(args)),
+            breached: AtomicBool::new(false),
+        })
+    }
+
+    /// Replace the limits, e.g. after a config reload
+    pub fn set_thresholds(&self, thresholds: Thresholds) {
+        *self.thresholds.lock().unwrap() = thresholds;
+    }
+
+    /// Check a session and run the command if it has just gone over a limit
+    pub fn check(&self, session: &MtrSession) -> Option<Breach> {
+        let thresholds = *self.thresholds.lock().unwrap();
+        let last = session.stats.rposition(|hop| hop.received > 0)?;
+        let hop = session.stats.hop(last).clone();
+        let breach = thresholds.check_hop(&hop);
+        if self.breached.swap(breach.is_some(), Ordering::Relaxed) {
+            return None; // Already alerted for this breach
+        }
+        let breach = breach?;
+        self.run(&session.target, &breach, &hop);
+        Some(breach)
+    }
+
+    fn run(&self, target: &str, breach: &Breach, hop: &HopStats) {
+        let mut command = tokio::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .env("MTR_NG_TARGET", target)
+            .env("MTR_NG_ALERT", breach.to_string())
+            .env("MTR_NG_HOP", hop.hop.to_string())
+            .env("MTR_NG_HOST", hop.addr.map(|addr| addr.to_string()).unwrap_or_default())
+            .env("MTR_NG_LOSS", format!("{:.1}", hop.loss_percent))
+            .env(
+                "MTR_NG_RTT",
+                hop.avg_rtt
+                    .map(|rtt| format!("{:.1}", utils::time::duration_to_ms_f64(rtt)))
+                    .unwrap_or_default(),
+            )
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(false);
+        match command.spawn() {
+            Ok(_) => info!("Ran alert command for {}", breach),
+            Err(e) => warn!("Alert command failed to start: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_loss_threshold() {
+        let thresholds = Thresholds {
+            loss_percent: Some(10.0),
+            latency_ms: None,
+        };
+        let mut hop = HopStats::new(3);
+        for _ in 0..5 {
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_millis(20));
+        }
+        assert_eq!(thresholds.check_hop(&hop), None);
+
+        hop.increment_sent();
+        hop.add_timeout();
+        let breach = thresholds.check_hop(&hop).unwrap();
+        assert_eq!(breach.hop, 3);
+        assert!(matches!(breach.kind, BreachKind::Loss(_)));
+    }
+
+    #[test]
+    fn test_latency_threshold() {
+        let thresholds = Thresholds {
+            loss_percent: None,
+            latency_ms: Some(50),
+        };
+        let mut hop = HopStats::new(2);
+        hop.increment_sent();
+        hop.add_rtt(Duration::from_millis(80));
+        let breach = thresholds.check_hop(&hop).unwrap();
+        assert_eq!(breach.to_string(), "hop 2 latency 80.0ms");
+    }
+
+    #[tokio::test]
+    async fn test_alert_command_runs_once_per_breach() {
+        use clap::Parser;
+        let path = std::env::temp_dir().join(format!("mtr-ng-alert-{}.txt", std::process::id()));
+        let command = format!("echo \"$MTR_NG_HOP $MTR_NG_LOSS\" >> {}", path.display());
+        let args = Args::try_parse_from([
+            "mtr-ng", "-n", "--loss-threshold", "10", "--on-alert", &command, "192.0.2.1",
+        ])
+        .unwrap();
+        let session = MtrSession::new(args).await.unwrap();
+        let alert = session.alert.clone().unwrap();
+        {
+            let mut hop = session.stats.hop_mut(1);
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_millis(20));
+        }
+        assert_eq!(alert.check(&session), None);
+
+        {
+            let mut hop = session.stats.hop_mut(1);
+            hop.increment_sent();
+            hop.add_timeout();
+        }
+        assert_eq!(alert.check(&session).unwrap().hop, 2);
+        assert_eq!(alert.check(&session), None); // Still breached
+
+        let mut output = String::new();
+        for _ in 0..50 {
+            output = std::fs::read_to_string(&path).unwrap_or_default();
+            if !output.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(output, "2 50.0\n");
+    }
+
+    #[test]
+    fn test_disabled_thresholds_never_fire() {
+        let thresholds = Thresholds::default();
+        let mut hop = HopStats::new(1);
+        hop.increment_sent();
+        hop.add_timeout();
+        assert!(!thresholds.is_enabled());
+        assert_eq!(thresholds.check_hop(&hop), None);
+    }
+}
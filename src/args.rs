@@ -1,22 +1,79 @@
 use clap::{Parser, ValueEnum};
+use serde::Deserialize;
 
-#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum SparklineScale {
     Linear,
     Logarithmic,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RttUnit {
+    /// Milliseconds everywhere
+    Ms,
+    /// Microseconds everywhere
+    Us,
+    /// Microseconds below 1ms, milliseconds above
+    Auto,
+}
+
+impl RttUnit {
+    /// Short unit name for status lines
+    pub fn name(&self) -> &'static str {
+        match self {
+            RttUnit::Ms => "ms",
+            RttUnit::Us => "μs",
+            RttUnit::Auto => "auto",
+        }
+    }
+}
+
+/// What --jsonl writes a line for
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum JsonlMode {
+    /// Every reply or timeout as it is recorded
+    Probe,
+    /// Every hop's statistics at the end of each round
+    Round,
+}
+
 #[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum FaultScenario {
+    /// Six hops, light jitter, no loss
+    Clean,
+    /// Independent random loss from hop 3 onwards
+    Lossy,
+    /// Bursty (Gilbert-Elliott) loss from hop 4 onwards
+    Burst,
+    /// 20% of replies held back so they arrive out of order
+    Reorder,
+    /// 20% of replies delivered twice
+    Duplicate,
+    /// Large, highly variable delay from hop 4 onwards
+    Congested,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
 pub enum ProbeProtocol {
     /// ICMP Echo (ping) - default and most common
+    #[default]
     Icmp,
     /// UDP probes (useful for firewalls that block ICMP)
     Udp,
     /// TCP SYN probes (useful for strict firewalls)
     Tcp,
+    /// ICMP Timestamp requests (IPv4), splitting the destination's RTT by direction
+    Timestamp,
+    /// QUIC-shaped UDP datagrams to port 443, following HTTP/3 traffic's path
+    Quic,
+    /// DNS queries to port 53, timing the resolver's answer at the destination
+    Dns,
 }
 
-#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Column {
     /// Hop number
     Hop,
@@ -26,6 +83,8 @@ pub enum Column {
     Loss,
     /// Number of packets sent
     Sent,
+    /// Percentage of rounds with at least one reply
+    Avail,
     /// Last RTT measurement
     Last,
     /// Average RTT
@@ -40,6 +99,24 @@ pub enum Column {
     Best,
     /// Worst (maximum) RTT
     Worst,
+    /// Standard deviation of the recent RTTs
+    #[value(name = "stdev")]
+    #[serde(rename = "stdev")]
+    StDev,
+    /// Median RTT of the recent samples
+    P50,
+    /// 90th percentile RTT of the recent samples
+    P90,
+    /// 95th percentile RTT of the recent samples
+    P95,
+    /// 99th percentile RTT of the recent samples
+    P99,
+    /// Recent RTT trend (↑ worse, ↓ better, → stable)
+    Trend,
+    /// TTL of the latest reply, to spot asymmetric return paths
+    ReplyTtl,
+    /// Country and city of the hop (needs --geoip)
+    Location,
     /// RTT sparkline graph
     Graph,
 }
@@ -52,6 +129,7 @@ impl Column {
             Column::Host,
             Column::Loss,
             Column::Sent,
+            Column::Avail,
             Column::Last,
             Column::Avg,
             Column::Ema,
@@ -59,6 +137,14 @@ impl Column {
             Column::JitterAvg,
             Column::Best,
             Column::Worst,
+            Column::StDev,
+            Column::P50,
+            Column::P90,
+            Column::P95,
+            Column::P99,
+            Column::Trend,
+            Column::ReplyTtl,
+            Column::Location,
             Column::Graph,
         ]
     }
@@ -86,6 +172,7 @@ impl Column {
             Column::Host => "Hostname",
             Column::Loss => "Loss%",
             Column::Sent => "Pkts",
+            Column::Avail => "Avail%",
             Column::Last => "LastRTT",
             Column::Avg => "AvgRTT",
             Column::Ema => "EmaRTT",
@@ -93,25 +180,55 @@ impl Column {
             Column::JitterAvg => "JitAvg",
             Column::Best => "BestRTT",
             Column::Worst => "WorstRTT",
+            Column::StDev => "StDev",
+            Column::P50 => "P50",
+            Column::P90 => "P90",
+            Column::P95 => "P95",
+            Column::P99 => "P99",
+            Column::Trend => "Trend",
+            Column::ReplyTtl => "rTTL",
+            Column::Location => "Location",
             Column::Graph => "RTT History",
         }
     }
 
-    /// Get column width for formatting
-    pub fn width(&self) -> usize {
+    /// The column of one of mtr's --order field letters
+    ///
+    /// Ok(None) for fields of mtr's that have no column here; they are left
+    /// out, so order strings written for mtr keep working.
+    pub fn from_mtr_field(field: char) -> Result<Option<Column>, String> {
+        Ok(Some(match field {
+            'L' => Column::Loss,
+            'S' => Column::Sent,
+            'N' => Column::Last,
+            'B' => Column::Best,
+            'A' => Column::Avg,
+            'W' => Column::Worst,
+            'V' => Column::StDev,
+            'J' => Column::Jitter,
+            'M' => Column::JitterAvg,
+            'D' | 'R' | 'G' | 'X' | 'I' => return Ok(None),
+            _ => return Err(format!("unknown field {:?}", field)),
+        }))
+    }
+
+    /// The columns of an mtr order string such as "LS NABWV": hop and host, then each field
+    pub fn from_mtr_order(order: &str) -> Result<Vec<Column>, String> {
+        let mut columns = vec![Column::Hop, Column::Host];
+        for field in order.chars().filter(|c| *c != ' ') {
+            columns.extend(Column::from_mtr_field(field)?);
+        }
+        Ok(columns)
+    }
+
+    /// The percentile a percentile column shows, None for other columns
+    pub fn percentile(&self) -> Option<f64> {
         match self {
-            Column::Hop => 3,
-            Column::Host => 21,
-            Column::Loss => 7,
-            Column::Sent => 4,
-            Column::Last => 8,
-            Column::Avg => 8,
-            Column::Ema => 8,
-            Column::Jitter => 8,
-            Column::JitterAvg => 8,
-            Column::Best => 8,
-            Column::Worst => 8,
-            Column::Graph => 20, // Minimum width for sparkline
+            Column::P50 => Some(50.0),
+            Column::P90 => Some(90.0),
+            Column::P95 => Some(95.0),
+            Column::P99 => Some(99.0),
+            _ => None,
         }
     }
 }
@@ -124,6 +241,7 @@ impl Column {
 #[command(version = env!("CARGO_PKG_VERSION"))]
 pub struct Args {
     /// Target hostname or IP address
+    #[arg(required_unless_present = "merge", default_value = "", hide_default_value = true)]
     pub target: String,
 
     /// Number of pings per round (default: infinite)
@@ -134,22 +252,103 @@ pub struct Args {
     #[arg(short, long, default_value = "1000")]
     pub interval: u64,
 
+    /// Probes sent back-to-back to each hop every round, for finer loss figures on short runs
+    #[arg(long, value_name = "N", default_value = "1", value_parser = clap::value_parser!(u16).range(1..=100))]
+    pub burst: u16,
+
+    /// Spread each round's probes over this percentage of the interval instead of sending them at once
+    #[arg(long, value_name = "PCT", default_value = "0", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub spread: u8,
+
+    /// Send each probe at a random point in the --spread window rather than evenly spaced
+    #[arg(long, requires = "spread")]
+    pub jitter: bool,
+
+    /// Probe hops with recent loss or high jitter more often than stable ones, sending no more probes in all
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Seconds to keep waiting for replies to the last probes once --count is reached
+    #[arg(short = 'G', long, value_name = "SECONDS", default_value = "5", value_parser = parse_seconds)]
+    pub grace_period: f64,
+
+    /// TTL of the first hop to probe, skipping the local hops before it
+    #[arg(short = 'f', long, value_name = "TTL", default_value = "1", value_parser = clap::value_parser!(u8).range(1..))]
+    pub first_ttl: u8,
+
     /// Maximum number of hops
     #[arg(short = 'M', long, default_value = "30")]
     pub max_hops: u8,
 
+    /// Stop probing further once this many consecutive hops past the last answering one stay silent
+    #[arg(short = 'U', long, value_name = "N", default_value = "5", value_parser = clap::value_parser!(u8).range(1..))]
+    pub max_unknown: u8,
+
     /// Enable report mode (non-interactive)
     #[arg(short, long)]
     pub report: bool,
 
+    /// End each report row with a sparkline of the hop's RTT history, colored when printing to a terminal
+    #[arg(long, requires = "report")]
+    pub graph: bool,
+
+    /// Also print the report every N rounds while the trace runs, not only at the end
+    #[arg(long, value_name = "N", requires = "report")]
+    pub report_every: Option<std::num::NonZeroUsize>,
+
+    /// Append the --report-every snapshots to this file instead of printing them
+    #[arg(long, value_name = "FILE", requires = "report_every")]
+    pub report_file: Option<std::path::PathBuf>,
+
+    /// Print the report as XML, in the same layout as classic mtr (implies --report)
+    #[arg(short = 'x', long)]
+    pub xml: bool,
+
+    /// Write JSON Lines to stdout as the trace runs, one per probe result or per round (implies --report)
+    #[arg(long, value_enum, value_name = "EACH", num_args = 0..=1, require_equals = true,
+          default_missing_value = "probe", conflicts_with = "xml")]
+    pub jsonl: Option<JsonlMode>,
+
+    /// Print mtr's raw line protocol (x/h/d/p records) as probes go and come back (implies --report)
+    #[arg(short = 'l', long, conflicts_with_all = ["xml", "jsonl"])]
+    pub raw: bool,
+
+    /// After every round print a line for each hop that changed, as mtr's split mode does (implies --report)
+    #[arg(short = 'p', long, conflicts_with_all = ["xml", "jsonl", "raw"])]
+    pub split: bool,
+
+    /// Also write a self-contained HTML report with per-hop latency and loss charts to this file
+    #[arg(long, value_name = "FILE")]
+    pub html: Option<std::path::PathBuf>,
+
     /// Show IP addresses instead of hostnames
     #[arg(short, long)]
     pub numeric: bool,
 
+    /// Send DNS queries to this server instead of the system's resolvers
+    #[arg(long, value_name = "IP")]
+    pub dns_server: Option<std::net::IpAddr>,
+
+    /// Reverse DNS lookups allowed to run at the same time
+    #[arg(long, value_name = "N", default_value = "4", value_parser = clap::value_parser!(u16).range(1..))]
+    pub dns_workers: u16,
+
+    /// Give up on a reverse DNS lookup after this many milliseconds
+    #[arg(long, value_name = "MS", default_value = "3000")]
+    pub dns_timeout: u64,
+
     /// Sparkline scaling mode: linear or logarithmic (default: logarithmic)
     #[arg(long, value_enum, default_value = "logarithmic")]
     pub sparkline_scale: SparklineScale,
 
+    /// Unit for displaying RTT values in the table, scale and report
+    #[arg(long, value_enum, default_value = "ms")]
+    pub rtt_unit: RttUnit,
+
+    /// Draw the graphs with text even on a terminal that shows inline images (iTerm2)
+    #[arg(long)]
+    pub no_images: bool,
+
     /// Exponential smoothing factor for EMA (0.0-1.0). Higher values = more responsive to recent changes
     #[arg(long, default_value = "0.1")]
     pub ema_alpha: f64,
@@ -158,6 +357,10 @@ pub struct Args {
     #[arg(long, value_enum, value_delimiter = ',')]
     pub fields: Option<Vec<Column>>,
 
+    /// Select columns with mtr's field letters instead, e.g. "LS NABWV" (hop and host always come first)
+    #[arg(short = 'o', long, value_name = "FIELDS", value_parser = parse_order, conflicts_with = "fields")]
+    pub order: Option<String>,
+
     /// Show all available columns including jitter metrics
     #[arg(long, help = "Display all available columns")]
     pub show_all: bool,
@@ -170,6 +373,27 @@ pub struct Args {
     #[arg(short = 'P', long, value_enum, default_value = "icmp")]
     pub protocol: ProbeProtocol,
 
+    /// Trace the target's IPv4 and IPv6 addresses side by side
+    #[arg(long)]
+    pub dual_stack: bool,
+
+    /// Destination port for UDP, TCP, QUIC and DNS probes (default: 33434 and up for UDP, 80 for TCP, 443 for QUIC, 53 for DNS)
+    #[arg(long, value_name = "PORT", value_parser = clap::value_parser!(u16).range(1..))]
+    pub port: Option<u16>,
+
+    /// Name DNS probes look up (default: example.com)
+    #[arg(long, value_name = "NAME")]
+    pub dns_query: Option<String>,
+
+    /// Paris-traceroute mode: keep flow identifiers constant so per-flow load balancers pick one path
+    #[arg(long)]
+    pub paris: bool,
+
+    /// Enumerate ECMP branches by cycling each hop's probes through FLOWS flow identifiers (default 8)
+    #[arg(long, value_name = "FLOWS", num_args = 0..=1, default_missing_value = "8",
+          value_parser = clap::value_parser!(u16).range(1..=64))]
+    pub multipath: Option<u16>,
+
     /// Force simulation mode even when run as root (useful for testing)
     #[arg(long, help = "Force simulation mode even with root privileges")]
     pub force_simulate: bool,
@@ -181,6 +405,148 @@ pub struct Args {
     /// Quiet mode - reduce output verbosity
     #[arg(short, long, help = "Quiet mode - minimal output")]
     pub quiet: bool,
+
+    /// Alert when end-to-end packet loss exceeds this percentage
+    #[arg(long, value_name = "PCT")]
+    pub loss_threshold: Option<f64>,
+
+    /// Alert when end-to-end average RTT exceeds this many milliseconds
+    #[arg(long, value_name = "MS")]
+    pub latency_threshold: Option<u64>,
+
+    /// Run this shell command when a loss or latency threshold is crossed, with the hop in MTR_NG_* variables
+    #[arg(long, value_name = "CMD")]
+    pub on_alert: Option<String>,
+
+    /// Don't ring the terminal bell when a threshold is crossed; the status line still flags it
+    #[arg(long)]
+    pub no_bell: bool,
+
+    /// Minimum milliseconds between redraws while the terminal is unfocused
+    #[arg(long, value_name = "MS")]
+    pub unfocused_redraw: Option<u64>,
+
+    /// Probe interval in milliseconds while the terminal is unfocused
+    #[arg(long, value_name = "MS")]
+    pub unfocused_interval: Option<u64>,
+
+    /// Enter this network namespace before creating sockets: a name under /run/netns or a path (Linux)
+    #[arg(long, value_name = "NAME|PATH")]
+    pub netns: Option<String>,
+
+    /// Set SO_MARK on probe sockets so probes follow a policy-routing table (Linux), decimal or 0x hex
+    #[arg(long, visible_alias = "mark", value_name = "MARK", value_parser = parse_mark)]
+    pub fwmark: Option<u32>,
+
+    /// Send probes from this local address (multi-homed hosts)
+    #[arg(short = 'a', long, value_name = "ADDR")]
+    pub address: Option<std::net::IpAddr>,
+
+    /// Send probes out of this network interface (SO_BINDTODEVICE on Linux, IP_BOUND_IF on macOS)
+    #[arg(short = 'I', long, value_name = "NAME")]
+    pub interface: Option<String>,
+
+    /// Trace inside a Linux VRF by binding probe sockets to its device
+    #[arg(long, value_name = "NAME", conflicts_with = "interface")]
+    pub vrf: Option<String>,
+
+    /// Stay root after opening the probe sockets instead of dropping to the sudo user
+    #[arg(long)]
+    pub keep_privileges: bool,
+
+    /// Drive probe I/O through io_uring (Linux, requires a build with the `io-uring` feature)
+    #[arg(long)]
+    pub io_uring: bool,
+
+    /// Set the ToS byte (DSCP << 2 | ECN) on outgoing probes, e.g. 184 for DSCP EF
+    #[arg(short = 'Q', long, value_name = "TOS")]
+    pub tos: Option<u8>,
+
+    /// Total size in bytes of each ICMP or UDP probe, IP header included (padded with --bitpattern)
+    #[arg(short = 's', long, value_name = "BYTES", value_parser = clap::value_parser!(u16).range(28..))]
+    pub psize: Option<u16>,
+
+    /// Byte used to fill the probe payload
+    #[arg(short = 'B', long, value_name = "BYTE", default_value = "0")]
+    pub bitpattern: u8,
+
+    /// Publish the NDJSON event stream to clients connecting on this Unix socket
+    #[arg(long, value_name = "PATH")]
+    pub stream_socket: Option<std::path::PathBuf>,
+
+    /// Send per-hop metrics to a Graphite (Carbon plaintext) server after every round
+    #[arg(long, value_name = "HOST:PORT", value_parser = parse_host_port)]
+    pub graphite: Option<String>,
+
+    /// Append every probe result and round summary to this SQLite database
+    #[arg(long, value_name = "DB")]
+    pub record: Option<std::path::PathBuf>,
+
+    /// Append a line per probe (send time, hop, seq, outcome, RTT, responder) to this file
+    #[arg(long, value_name = "FILE")]
+    pub probe_log: Option<std::path::PathBuf>,
+
+    /// Drive the real probe pipeline with a deterministic fault-injection scenario (with --simulate)
+    #[arg(long, value_enum, value_name = "NAME")]
+    pub scenario: Option<FaultScenario>,
+
+    /// JSON config file overriding display and alert options, reloaded on change or SIGHUP
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<std::path::PathBuf>,
+
+    /// Compare hops against (and update) baselines stored from previous runs
+    #[arg(long)]
+    pub baseline: bool,
+
+    /// Annotate hops with country and city from a MaxMind database (.mmdb)
+    #[arg(long, value_name = "PATH")]
+    pub geoip: Option<std::path::PathBuf>,
+
+    /// Look up the network name and organization of hops in the registries' whois
+    #[arg(long)]
+    pub whois: bool,
+
+    /// Save all hop statistics, histories included, to this file when the session ends
+    #[arg(long, value_name = "FILE")]
+    pub save: Option<std::path::PathBuf>,
+
+    /// Continue from hop statistics saved with --save
+    #[arg(long, value_name = "FILE")]
+    pub load: Option<std::path::PathBuf>,
+
+    /// Report on the combined statistics of files saved with --save for one target, instead of tracing
+    #[arg(long, value_name = "FILE", num_args = 2.., conflicts_with_all = ["load", "save", "dual_stack", "simulate"])]
+    pub merge: Vec<std::path::PathBuf>,
+}
+
+/// Parse a non-negative number of seconds
+fn parse_seconds(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("{} is not a number of seconds", value)),
+    }
+}
+
+/// Check an mtr field order string, keeping it as given
+fn parse_order(value: &str) -> Result<String, String> {
+    Column::from_mtr_order(value).map(|_| value.to_string())
+}
+
+/// Check a `host:port` address, leaving the host to be resolved on connecting
+fn parse_host_port(value: &str) -> Result<String, String> {
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(value.to_string()),
+        _ => Err(format!("{:?} is not a HOST:PORT address", value)),
+    }
+}
+
+/// Parse a firewall mark, in decimal or `0x` hex as `wg` and `ip rule` print them
+fn parse_mark(value: &str) -> Result<u32, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|e| format!("invalid mark {:?}: {}", value, e))
 }
 
 impl Args {
@@ -190,8 +556,15 @@ impl Args {
             Column::all()
         } else if let Some(ref fields) = self.fields {
             fields.clone()
+        } else if let Some(columns) = self.order.as_deref().and_then(|order| Column::from_mtr_order(order).ok()) {
+            columns
         } else {
-            Column::default_columns()
+            let mut columns = Column::default_columns();
+            // Asking for a GeoIP database is asking to see the locations
+            if self.geoip.is_some() {
+                columns.insert(2, Column::Location);
+            }
+            columns
         }
     }
 }
@@ -206,18 +579,69 @@ mod tests {
         assert_eq!(args.target, "example.com");
         assert_eq!(args.count, None); // Default is now infinite (None)
         assert_eq!(args.interval, 1000);
+        assert_eq!(args.burst, 1);
+        assert_eq!(args.spread, 0);
+        assert!(!args.jitter);
+        assert!(!args.adaptive);
+        assert_eq!(args.max_unknown, 5);
+        assert_eq!(args.grace_period, 5.0);
+        assert_eq!(args.first_ttl, 1);
         assert_eq!(args.max_hops, 30);
         assert!(!args.report);
+        assert!(!args.graph);
+        assert!(args.report_every.is_none());
+        assert!(args.report_file.is_none());
+        assert!(!args.xml);
+        assert!(args.jsonl.is_none());
+        assert!(!args.raw);
+        assert!(!args.split);
+        assert!(args.html.is_none());
         assert!(!args.numeric);
+        assert!(args.dns_server.is_none());
+        assert_eq!(args.dns_workers, 4);
+        assert_eq!(args.dns_timeout, 3000);
         assert_eq!(args.sparkline_scale, SparklineScale::Logarithmic);
         assert_eq!(args.ema_alpha, 0.1);
+        assert_eq!(args.rtt_unit, RttUnit::Ms);
         assert!(args.fields.is_none());
+        assert!(args.order.is_none());
         assert!(!args.show_all);
         assert!(!args.simulate);
         assert_eq!(args.protocol, ProbeProtocol::Icmp);
+        assert!(!args.paris);
+        assert!(args.multipath.is_none());
         assert!(!args.force_simulate);
         assert!(!args.timing);
         assert!(!args.quiet);
+        assert!(args.loss_threshold.is_none());
+        assert!(args.latency_threshold.is_none());
+        assert!(args.on_alert.is_none());
+        assert!(!args.no_bell);
+        assert!(!args.no_images);
+        assert!(args.unfocused_redraw.is_none());
+        assert!(args.unfocused_interval.is_none());
+        assert!(args.netns.is_none());
+        assert!(args.fwmark.is_none());
+        assert!(args.address.is_none());
+        assert!(args.interface.is_none());
+        assert!(args.vrf.is_none());
+        assert!(!args.keep_privileges);
+        assert!(!args.io_uring);
+        assert!(args.tos.is_none());
+        assert!(args.psize.is_none());
+        assert_eq!(args.bitpattern, 0);
+        assert!(args.stream_socket.is_none());
+        assert!(args.graphite.is_none());
+        assert!(args.record.is_none());
+        assert!(args.probe_log.is_none());
+        assert!(args.scenario.is_none());
+        assert!(args.config.is_none());
+        assert!(!args.baseline);
+        assert!(args.geoip.is_none());
+        assert!(!args.whois);
+        assert!(args.save.is_none());
+        assert!(args.load.is_none());
+        assert!(args.merge.is_empty());
     }
 
     #[test]
@@ -232,6 +656,10 @@ mod tests {
             "50",
             "--report",
             "--numeric",
+            "--dns-server",
+            "2001:db8::53",
+            "--mark",
+            "0xca6c",
             "google.com",
         ])
         .unwrap();
@@ -242,10 +670,23 @@ mod tests {
         assert_eq!(args.max_hops, 50);
         assert!(args.report);
         assert!(args.numeric);
+        assert_eq!(args.dns_server, Some("2001:db8::53".parse().unwrap()));
         assert_eq!(args.sparkline_scale, SparklineScale::Logarithmic);
         assert_eq!(args.ema_alpha, 0.1);
         assert!(args.fields.is_none());
         assert!(!args.show_all);
+        assert_eq!(args.fwmark, Some(0xca6c));
+        assert!(Args::try_parse_from(["mtr-ng", "--fwmark", "0xzz", "google.com"]).is_err());
+        // A VRF and an interface would both claim the socket's bound device
+        assert!(Args::try_parse_from(["mtr-ng", "--vrf", "red", "-I", "eth0", "google.com"]).is_err());
+        assert_eq!(
+            Args::try_parse_from(["mtr-ng", "--jsonl", "google.com"]).unwrap().jsonl,
+            Some(JsonlMode::Probe)
+        );
+        assert!(Args::try_parse_from(["mtr-ng", "--graphite", "carbon", "google.com"]).is_err());
+        // Jitter only picks times within the spread window
+        assert!(Args::try_parse_from(["mtr-ng", "--jitter", "google.com"]).is_err());
+        assert!(Args::try_parse_from(["mtr-ng", "--spread", "101", "google.com"]).is_err());
     }
 
     #[test]
@@ -256,10 +697,22 @@ mod tests {
             "15",
             "-i",
             "2000",
+            "-f",
+            "3",
             "-M",
             "25",
             "-r",
             "-n",
+            "-Q",
+            "184",
+            "-s",
+            "1400",
+            "-B",
+            "255",
+            "-a",
+            "192.0.2.10",
+            "-I",
+            "wg0",
             "test.example.com",
         ])
         .unwrap();
@@ -267,12 +720,43 @@ mod tests {
         assert_eq!(args.target, "test.example.com");
         assert_eq!(args.count, Some(15));
         assert_eq!(args.interval, 2000);
+        assert_eq!(args.first_ttl, 3);
         assert_eq!(args.max_hops, 25);
         assert!(args.report);
         assert!(args.numeric);
+        assert_eq!(args.tos, Some(184));
+        assert_eq!(args.psize, Some(1400));
+        assert_eq!(args.bitpattern, 255);
+        assert_eq!(args.address, Some("192.0.2.10".parse().unwrap()));
+        assert_eq!(args.interface.as_deref(), Some("wg0"));
         assert_eq!(args.sparkline_scale, SparklineScale::Logarithmic);
         assert_eq!(args.ema_alpha, 0.1);
         assert!(args.fields.is_none());
         assert!(!args.show_all);
     }
+
+    #[test]
+    fn test_mtr_order_string() {
+        let args = Args::try_parse_from(["mtr-ng", "-o", "LS NABWV", "example.com"]).unwrap();
+        assert_eq!(
+            args.get_columns(),
+            vec![
+                Column::Hop,
+                Column::Host,
+                Column::Loss,
+                Column::Sent,
+                Column::Last,
+                Column::Avg,
+                Column::Best,
+                Column::Worst,
+                Column::StDev,
+            ]
+        );
+
+        // Fields mtr-ng has no column for are skipped; unknown letters are refused
+        let args = Args::try_parse_from(["mtr-ng", "-o", "LDRJM", "example.com"]).unwrap();
+        assert_eq!(args.get_columns()[2..], [Column::Loss, Column::Jitter, Column::JitterAvg]);
+        assert!(Args::try_parse_from(["mtr-ng", "-o", "LQ", "example.com"]).is_err());
+        assert!(Args::try_parse_from(["mtr-ng", "-o", "L", "--fields", "hop", "example.com"]).is_err());
+    }
 }
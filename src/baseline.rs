@@ -0,0 +1,223 @@
+//! Persistent per-path baselines
+//!
+//! At the end of a run the median RTT and loss of every well-sampled hop are
+//! folded into a history file keyed by target and hop address. Later runs
+//! compare live statistics against that history and flag hops that are clearly
+//! worse than usual for this path.
+
+use crate::{utils, HopStats};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+/// Weight given to a new run once a hop already has history
+const BASELINE_WEIGHT: f64 = 0.2;
+
+/// Replies a hop needs before it is recorded or compared
+const MIN_SAMPLES: usize = 5;
+
+/// A hop is slower than usual when its median exceeds the baseline by this factor...
+const RTT_FACTOR: f64 = 1.5;
+
+/// ...and by at least this many milliseconds
+const RTT_MARGIN_MS: f64 = 5.0;
+
+/// Percentage points of extra loss that count as a deviation
+const LOSS_MARGIN: f64 = 10.0;
+
+/// Historical statistics for one hop on one path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub median_rtt_ms: f64,
+    pub loss_percent: f64,
+    pub runs: u32,
+    pub updated: DateTime<Utc>,
+}
+
+/// How a hop differs from its baseline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Deviation {
+    Slower { baseline_ms: f64, current_ms: f64 },
+    Lossier { baseline_percent: f64, current_percent: f64 },
+}
+
+impl fmt::Display for Deviation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Deviation::Slower { baseline_ms, current_ms } => {
+                write!(f, "RTT {:.1}ms vs usual {:.1}ms", current_ms, baseline_ms)
+            }
+            Deviation::Lossier { baseline_percent, current_percent } => {
+                write!(f, "loss {:.1}% vs usual {:.1}%", current_percent, baseline_percent)
+            }
+        }
+    }
+}
+
+/// All recorded baselines, as stored on disk
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BaselineStore {
+    entries: HashMap<String, Baseline>,
+}
+
+impl BaselineStore {
+    /// `$XDG_DATA_HOME/mtr-ng/baselines.json`, falling back to `~/.local/share`
+    pub fn default_path() -> Option<PathBuf> {
+        Some(utils::paths::data_dir()?.join("baselines.json"))
+    }
+
+    /// Load the store, treating a missing file as empty history
+    pub fn load(path: &Path) -> Result<Self> {
+        utils::paths::load_json_or_default(path, "baseline")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        utils::paths::write_atomic(path, &serde_json::to_string_pretty(self)?)
+    }
+
+    fn key(target: &str, hop_addr: IpAddr) -> String {
+        format!("{}|{}", target, hop_addr)
+    }
+
+    pub fn get(&self, target: &str, hop_addr: IpAddr) -> Option<&Baseline> {
+        self.entries.get(&Self::key(target, hop_addr))
+    }
+
+    /// Compare a live hop against its baseline
+    pub fn deviation(&self, target: &str, hop: &HopStats) -> Option<Deviation> {
+        let baseline = self.get(target, hop.addr?)?;
+        if hop.sent < MIN_SAMPLES {
+            return None;
+        }
+
+        if hop.loss_percent > baseline.loss_percent + LOSS_MARGIN {
+            return Some(Deviation::Lossier {
+                baseline_percent: baseline.loss_percent,
+                current_percent: hop.loss_percent,
+            });
+        }
+
+        let current_ms = median_rtt_ms(hop)?;
+        if current_ms > baseline.median_rtt_ms * RTT_FACTOR
+            && current_ms - baseline.median_rtt_ms > RTT_MARGIN_MS
+        {
+            return Some(Deviation::Slower {
+                baseline_ms: baseline.median_rtt_ms,
+                current_ms,
+            });
+        }
+
+        None
+    }
+
+    /// Fold the results of a finished run into the history
+    pub fn record(&mut self, target: &str, hops: &[HopStats]) {
+        let now = Utc::now();
+        let mut seen = HashSet::new();
+        for hop in hops {
+            let (Some(addr), Some(median)) = (hop.addr, median_rtt_ms(hop)) else {
+                continue;
+            };
+            // Probes past the destination repeat its address; count a run once
+            if !seen.insert(addr) {
+                continue;
+            }
+
+            self.entries
+                .entry(Self::key(target, addr))
+                .and_modify(|baseline| {
+                    baseline.median_rtt_ms += BASELINE_WEIGHT * (median - baseline.median_rtt_ms);
+                    baseline.loss_percent += BASELINE_WEIGHT * (hop.loss_percent - baseline.loss_percent);
+                    baseline.runs += 1;
+                    baseline.updated = now;
+                })
+                .or_insert(Baseline {
+                    median_rtt_ms: median,
+                    loss_percent: hop.loss_percent,
+                    runs: 1,
+                    updated: now,
+                });
+        }
+    }
+}
+
+/// Median RTT of a hop in milliseconds, once it has enough replies
+fn median_rtt_ms(hop: &HopStats) -> Option<f64> {
+    if hop.received < MIN_SAMPLES {
+        return None;
+    }
+    let mut rtts: Vec<_> = hop.rtts.iter().copied().collect();
+    utils::time::calculate_timing_percentile(&mut rtts, 50.0).map(utils::time::duration_to_ms_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn hop_with(addr: &str, rtt_ms: u64, replies: usize, timeouts: usize) -> HopStats {
+        let mut hop = HopStats::new(1);
+        hop.addr = Some(addr.parse().unwrap());
+        for _ in 0..replies {
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_millis(rtt_ms));
+        }
+        for _ in 0..timeouts {
+            hop.increment_sent();
+            hop.add_timeout();
+        }
+        hop
+    }
+
+    #[test]
+    fn test_record_and_deviation() {
+        let mut store = BaselineStore::default();
+        store.record("example.com", &[hop_with("10.0.0.1", 20, 10, 0)]);
+
+        let normal = hop_with("10.0.0.1", 22, 10, 0);
+        assert_eq!(store.deviation("example.com", &normal), None);
+
+        let slow = hop_with("10.0.0.1", 45, 10, 0);
+        assert!(matches!(store.deviation("example.com", &slow), Some(Deviation::Slower { .. })));
+
+        let lossy = hop_with("10.0.0.1", 20, 8, 2);
+        assert!(matches!(store.deviation("example.com", &lossy), Some(Deviation::Lossier { .. })));
+
+        // Different target, no history
+        assert_eq!(store.deviation("other.net", &slow), None);
+    }
+
+    #[test]
+    fn test_record_blends_runs() {
+        let mut store = BaselineStore::default();
+        store.record("t", &[hop_with("10.0.0.1", 20, 10, 0)]);
+        store.record("t", &[hop_with("10.0.0.1", 30, 10, 0)]);
+
+        let baseline = store.get("t", "10.0.0.1".parse().unwrap()).unwrap();
+        assert_eq!(baseline.runs, 2);
+
+        // The destination repeated past the end of the path counts once
+        store.record("t", &[hop_with("10.0.0.1", 20, 10, 0), hop_with("10.0.0.1", 20, 10, 0)]);
+        assert_eq!(store.get("t", "10.0.0.1".parse().unwrap()).unwrap().runs, 3);
+        let baseline = store.get("t", "10.0.0.1".parse().unwrap()).unwrap();
+        assert!((baseline.median_rtt_ms - 21.6).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let path = std::env::temp_dir().join(format!("mtr-ng-baselines-{}.json", std::process::id()));
+        let mut store = BaselineStore::default();
+        store.record("t", &[hop_with("10.0.0.1", 20, 10, 0)]);
+        store.save(&path).unwrap();
+
+        assert_eq!(BaselineStore::load(&path).unwrap(), store);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(BaselineStore::load(&path).unwrap(), BaselineStore::default());
+    }
+}
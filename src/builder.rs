@@ -0,0 +1,286 @@
+//! Library interface
+//!
+//! Other programs can run a trace without going through the command line,
+//! the TUI or the report:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use mtr_ng::MtrSession;
+//! use std::time::Duration;
+//! use tokio_stream::StreamExt;
+//!
+//! let (handle, mut updates) = MtrSession::builder("example.com")
+//!     .interval(Duration::from_millis(500))
+//!     .count(10)
+//!     .build()
+//!     .await?;
+//! while let Some(update) = updates.next().await {
+//!     println!("hop {}: {:.1}% loss", update.stats.hop, update.stats.loss_percent);
+//! }
+//! handle.wait().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The stream gets a `HopUpdate` for every reply or timeout as it is
+//! recorded, and ends when the trace does.
+
+use crate::{args::ProbeProtocol, hooks::Hooks, stats::SharedStats, Args, HopStats, MtrSession, Result};
+use clap::Parser;
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+/// Results of a running trace, one per recorded reply or timeout
+pub type HopUpdates = UnboundedReceiverStream<HopUpdate>;
+
+/// A hop's statistics right after a reply or timeout was recorded
+#[derive(Debug, Clone)]
+pub struct HopUpdate {
+    pub index: usize, // Position in the hop table; the TTL is `stats.hop`
+    pub stats: HopStats,
+}
+
+impl HopUpdate {
+    pub fn new(index: usize, stats: HopStats) -> Self {
+        Self { index, stats }
+    }
+}
+
+/// Configures a session the way the command line options would
+///
+/// Anything without a setter here can be set on the `Args` given to
+/// `from_args`.
+#[derive(Debug, Clone)]
+pub struct MtrSessionBuilder {
+    args: Args,
+    cancel: Option<CancellationToken>,
+    hooks: Arc<Hooks>,
+}
+
+impl MtrSessionBuilder {
+    /// A trace to `target` with the command line defaults
+    pub fn new(target: impl Into<String>) -> Self {
+        let target = target.into();
+        let args = Args::try_parse_from(["mtr-ng", "--", target.as_str()]).expect("a lone target always parses");
+        Self::from_args(args)
+    }
+
+    pub fn from_args(args: Args) -> Self {
+        Self {
+            args,
+            cancel: None,
+            hooks: Arc::default(),
+        }
+    }
+
+    /// Event registrations for the session, made before it starts so none are missed
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.args.interval = interval.as_millis() as u64;
+        self
+    }
+
+    /// Stop after this many rounds; without it the trace runs until stopped
+    pub fn count(mut self, count: usize) -> Self {
+        self.args.count = Some(count);
+        self
+    }
+
+    pub fn protocol(mut self, protocol: ProbeProtocol) -> Self {
+        self.args.protocol = protocol;
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.args.port = Some(port);
+        self
+    }
+
+    pub fn first_ttl(mut self, ttl: u8) -> Self {
+        self.args.first_ttl = ttl;
+        self
+    }
+
+    pub fn max_hops(mut self, hops: u8) -> Self {
+        self.args.max_hops = hops;
+        self
+    }
+
+    pub fn max_unknown(mut self, hops: u8) -> Self {
+        self.args.max_unknown = hops;
+        self
+    }
+
+    pub fn burst(mut self, probes: u16) -> Self {
+        self.args.burst = probes;
+        self
+    }
+
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.args.grace_period = grace_period.as_secs_f64();
+        self
+    }
+
+    pub fn ema_alpha(mut self, alpha: f64) -> Self {
+        self.args.ema_alpha = alpha;
+        self
+    }
+
+    /// Skip reverse DNS lookups of the hops
+    pub fn numeric(mut self, numeric: bool) -> Self {
+        self.args.numeric = numeric;
+        self
+    }
+
+    /// Trace a simulated path instead of the network, needing no privileges
+    pub fn simulate(mut self, simulate: bool) -> Self {
+        self.args.simulate = simulate;
+        self
+    }
+
+    /// Shut the trace down when `token` is cancelled, as `TraceHandle::shutdown` does
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Resolve the target, open the probe sockets and start tracing
+    ///
+    /// Unlike the TUI, a trace that can't open its sockets fails here
+    /// rather than falling back to simulation.
+    pub async fn build(self) -> Result<(TraceHandle, HopUpdates)> {
+        anyhow::ensure!((1..=100).contains(&self.args.burst), "Burst must be between 1 and 100 probes");
+        anyhow::ensure!(self.args.first_ttl >= 1, "First TTL must be at least 1");
+        anyhow::ensure!(self.args.max_unknown >= 1, "Max unknown must be at least 1 hop");
+
+        let mut session = MtrSession::new(self.args).await?;
+        if !session.args.simulate {
+            let engine = MtrSession::open_probe_engine(&session.args)?;
+            session.set_probe_engine(Ok(engine));
+        }
+        if let Some(token) = self.cancel {
+            session.cancel = token;
+        }
+        session.hooks = self.hooks;
+        let (tx, rx) = mpsc::unbounded_channel();
+        session.hop_updates = Some(tx);
+
+        let handle = TraceHandle {
+            target_addr: session.target_addr,
+            stats: Arc::clone(&session.stats),
+            interval_ms: Arc::clone(&session.interval_ms),
+            cancel: session.cancel.clone(),
+            hooks: Arc::clone(&session.hooks),
+            task: tokio::spawn(MtrSession::run_trace_with_realtime_updates(Arc::new(session))),
+        };
+        Ok((handle, UnboundedReceiverStream::new(rx)))
+    }
+}
+
+/// A trace started by `MtrSessionBuilder::build`
+#[derive(Debug)]
+pub struct TraceHandle {
+    target_addr: IpAddr,
+    stats: Arc<SharedStats>,
+    interval_ms: Arc<AtomicU64>,
+    cancel: CancellationToken,
+    hooks: Arc<Hooks>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl TraceHandle {
+    pub fn target_addr(&self) -> IpAddr {
+        self.target_addr
+    }
+
+    /// The live statistics of every hop
+    pub fn stats(&self) -> &SharedStats {
+        &self.stats
+    }
+
+    /// Event registrations; only events from now on reach new ones
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// A copy of the hops discovered so far
+    pub fn hops(&self) -> Vec<HopStats> {
+        self.stats.snapshot_range(self.stats.display_count())
+    }
+
+    /// Change the probe interval of the running trace
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval_ms.store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Wait for a trace with a count to finish
+    pub async fn wait(self) -> Result<()> {
+        self.task.await?
+    }
+
+    /// Stop starting rounds, wait for the replies to the probes in flight and
+    /// for the trace to finish
+    ///
+    /// The results stream gets those last replies before it ends.
+    pub async fn shutdown(self) -> Result<()> {
+        self.cancel.cancel();
+        self.wait().await
+    }
+
+    /// Stop the trace straight away, dropping the probes in flight
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_builder_streams_hop_updates() {
+        let (handle, updates) = MtrSession::builder("192.0.2.1")
+            .simulate(true)
+            .numeric(true)
+            .count(1)
+            .interval(Duration::from_millis(10))
+            .build()
+            .await
+            .unwrap();
+        let updates: Vec<HopUpdate> = updates.collect().await; // Ends with the trace
+        assert!(!updates.is_empty());
+        for update in &updates {
+            assert_eq!(update.stats.hop as usize, update.index + 1);
+            assert_eq!(update.stats.sent, 1);
+        }
+        assert!(!handle.hops().is_empty());
+        handle.wait().await.unwrap();
+
+        // Without a count only a shutdown ends the trace
+        let (handle, _updates) = MtrSession::builder("192.0.2.1").simulate(true).build().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), handle.shutdown())
+            .await
+            .expect("shutdown ends the trace")
+            .unwrap();
+
+        let result = MtrSession::builder("192.0.2.1").burst(0).build().await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Configuration file with live reload
+//!
+//! The file is JSON and every key is optional; keys that are present override
+//! the matching command-line option. While a session runs the file is re-read
+//! on SIGHUP or whenever its modification time changes, and the new values are
+//! applied in place. A file that fails to parse is logged and ignored, leaving
+//! the previous settings active.
+
+use crate::args::{Args, Column, RttUnit, SparklineScale};
+use crate::ui::visualization::ColorSupport;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+};
+use tracing::{info, warn};
+
+/// How often the file's modification time is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings that can be changed without restarting the session
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub fields: Option<Vec<Column>>,
+    pub color: Option<ColorSupport>,
+    pub sparkline_scale: Option<SparklineScale>,
+    pub rtt_unit: Option<RttUnit>,
+    pub loss_threshold: Option<f64>,
+    pub latency_threshold: Option<u64>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Override the arguments that this config sets
+    pub fn apply_to_args(&self, args: &mut Args) {
+        if let Some(ref fields) = self.fields {
+            args.fields = Some(fields.clone());
+            args.show_all = false;
+            args.order = None;
+        }
+        if let Some(scale) = self.sparkline_scale {
+            args.sparkline_scale = scale;
+        }
+        if let Some(unit) = self.rtt_unit {
+            args.rtt_unit = unit;
+        }
+        if self.loss_threshold.is_some() {
+            args.loss_threshold = self.loss_threshold;
+        }
+        if self.latency_threshold.is_some() {
+            args.latency_threshold = self.latency_threshold;
+        }
+    }
+}
+
+/// Watch a config file and send every successfully parsed revision
+///
+/// Reloads are triggered by SIGHUP or by a change in modification time.
+pub fn spawn_reloader(path: PathBuf) -> Result<mpsc::UnboundedReceiver<Config>> {
+    let mut hangup = signal(SignalKind::hangup()).context("Failed to install SIGHUP handler")?;
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            let reason = tokio::select! {
+                _ = ticker.tick() => {
+                    let current = modified(&path);
+                    if current.is_none() || current == last_modified {
+                        continue;
+                    }
+                    last_modified = current;
+                    "file changed"
+                }
+                received = hangup.recv() => {
+                    if received.is_none() {
+                        break;
+                    }
+                    "SIGHUP"
+                }
+            };
+
+            match Config::load(&path) {
+                Ok(config) => {
+                    info!("Reloaded configuration from {} ({})", path.display(), reason);
+                    if tx.send(config).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => warn!("Keeping previous configuration: {:#}", e),
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_and_apply() {
+        let config = Config::parse(
+            r#"{
+                "fields": ["hop", "host", "jitter-avg"],
+                "color": "true-color",
+                "rtt-unit": "us",
+                "loss-threshold": 5.0
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.color, Some(ColorSupport::TrueColor));
+
+        let mut args = Args::try_parse_from(["mtr-ng", "--latency-threshold", "100", "example.com"]).unwrap();
+        config.apply_to_args(&mut args);
+        assert_eq!(args.fields, Some(vec![Column::Hop, Column::Host, Column::JitterAvg]));
+        assert_eq!(args.rtt_unit, RttUnit::Us);
+        assert_eq!(args.loss_threshold, Some(5.0));
+        assert_eq!(args.latency_threshold, Some(100)); // Untouched by the config
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(Config::parse(r#"{"colour": "basic"}"#).is_err());
+        assert_eq!(Config::parse("{}").unwrap(), Config::default());
+    }
+}
@@ -0,0 +1,523 @@
+//! Deterministic network fault injection
+//!
+//! `FaultTransport` implements [`ProbeTransport`] without touching the network.
+//! Every reply is scripted from a [`Scenario`] and a seeded RNG, so a scenario
+//! produces the same RTTs, losses, duplicates and reorderings on every run. It
+//! backs `--simulate --scenario` and lets tests drive the real session pipeline.
+
+use crate::args::{FaultScenario, ProbeProtocol};
+use crate::probe::{IcmpResponseType, DnsRcode, IcmpTimestamps, PortState, ProbeResponse, ProbeTransport};
+use anyhow::Result;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// Longest a single collect call waits when nothing is due
+const MAX_IDLE: Duration = Duration::from_millis(5);
+
+/// Seed used by the built-in scenarios
+const DEFAULT_SEED: u64 = 0x6d74_726e;
+
+/// Initial TTL of simulated replies, which lose one per hop on the way back
+const REPLY_TTL: u8 = 64;
+
+/// Distribution of one-way-and-back delay for a hop
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delay {
+    Fixed(Duration),
+    Uniform { min: Duration, max: Duration },
+    Normal { mean: Duration, std_dev: Duration },
+}
+
+impl Delay {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            Delay::Fixed(delay) => delay,
+            Delay::Uniform { min, max } => {
+                if max <= min {
+                    min
+                } else {
+                    rng.gen_range(min..=max)
+                }
+            }
+            Delay::Normal { mean, std_dev } => {
+                // Box-Muller transform
+                let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+                let u2: f64 = rng.gen();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                let secs = mean.as_secs_f64() + z * std_dev.as_secs_f64();
+                Duration::from_secs_f64(secs.max(0.0))
+            }
+        }
+    }
+}
+
+/// Packet loss model for a hop
+#[derive(Debug, Clone, PartialEq)]
+pub enum Loss {
+    None,
+    /// Each probe is lost independently with this probability
+    Random(f64),
+    /// Gilbert-Elliott: per-probe chance to enter and leave a state where everything is lost
+    Burst { enter: f64, exit: f64 },
+}
+
+/// Behaviour of a single hop
+#[derive(Debug, Clone, PartialEq)]
+pub struct HopProfile {
+    pub delay: Delay,
+    pub loss: Loss,
+}
+
+impl HopProfile {
+    pub fn new(delay: Delay) -> Self {
+        Self {
+            delay,
+            loss: Loss::None,
+        }
+    }
+
+    pub fn with_loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+}
+
+/// A scripted network path
+///
+/// The last hop is the destination and answers with echo replies; earlier hops
+/// answer with time exceeded from synthetic router addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub seed: u64,
+    pub hops: Vec<HopProfile>,
+    pub duplicate: f64,         // Probability a reply is delivered twice
+    pub reorder: f64,           // Probability a reply is held back
+    pub reorder_delay: Duration, // How long held-back replies are delayed
+}
+
+impl Scenario {
+    pub fn new(hops: Vec<HopProfile>) -> Self {
+        Self {
+            seed: DEFAULT_SEED,
+            hops,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_duplicates(mut self, probability: f64) -> Self {
+        self.duplicate = probability;
+        self
+    }
+
+    pub fn with_reordering(mut self, probability: f64, delay: Duration) -> Self {
+        self.reorder = probability;
+        self.reorder_delay = delay;
+        self
+    }
+
+    /// Built-in scenarios selectable with `--scenario`
+    pub fn builtin(kind: FaultScenario) -> Self {
+        let jittered = |ms: u64| Delay::Uniform {
+            min: Duration::from_micros(ms * 900),
+            max: Duration::from_micros(ms * 1100),
+        };
+        let clean: Vec<HopProfile> = [1, 8, 12, 20, 24, 25]
+            .into_iter()
+            .map(|ms| HopProfile::new(jittered(ms)))
+            .collect();
+
+        match kind {
+            FaultScenario::Clean => Self::new(clean),
+            FaultScenario::Lossy => Self::new(
+                clean
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, hop)| match i {
+                        0 | 1 => hop,
+                        5 => hop.with_loss(Loss::Random(0.05)),
+                        _ => hop.with_loss(Loss::Random(0.10)),
+                    })
+                    .collect(),
+            ),
+            FaultScenario::Burst => Self::new(
+                clean
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, hop)| {
+                        if i >= 3 {
+                            hop.with_loss(Loss::Burst {
+                                enter: 0.05,
+                                exit: 0.3,
+                            })
+                        } else {
+                            hop
+                        }
+                    })
+                    .collect(),
+            ),
+            FaultScenario::Reorder => Self::new(clean).with_reordering(0.2, Duration::from_millis(30)),
+            FaultScenario::Duplicate => Self::new(clean).with_duplicates(0.2),
+            FaultScenario::Congested => Self::new(
+                [1, 8, 12, 60, 64, 65]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, ms)| {
+                        if i >= 3 {
+                            HopProfile::new(Delay::Normal {
+                                mean: Duration::from_millis(ms),
+                                std_dev: Duration::from_millis(25),
+                            })
+                        } else {
+                            HopProfile::new(jittered(ms))
+                        }
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Counters describing what the transport did
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaultStats {
+    pub sent: usize,
+    pub lost: usize,
+    pub duplicated: usize,
+    pub reordered: usize,
+}
+
+#[derive(Debug)]
+struct Scheduled {
+    deliver_at: Instant,
+    response: ProbeResponse,
+}
+
+/// Probe transport that answers from a scripted scenario
+#[derive(Debug)]
+pub struct FaultTransport {
+    scenario: Scenario,
+    rng: StdRng,
+    next_seq: u16,
+    in_burst: Vec<bool>,
+    pending: HashMap<u16, usize>, // seq -> hop, like ProbeEngine's pending table
+    queue: Vec<Scheduled>,
+    stats: FaultStats,
+}
+
+impl FaultTransport {
+    pub fn new(scenario: Scenario) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(scenario.seed),
+            in_burst: vec![false; scenario.hops.len()],
+            scenario,
+            next_seq: 1,
+            pending: HashMap::new(),
+            queue: Vec::new(),
+            stats: FaultStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    /// Synthetic router address for an intermediate hop
+    fn router_addr(ttl: u8, dst: IpAddr) -> IpAddr {
+        match dst {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(10, 255, 0, ttl)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, ttl.into())),
+        }
+    }
+
+    fn is_lost(&mut self, index: usize) -> bool {
+        match self.scenario.hops[index].loss {
+            Loss::None => false,
+            Loss::Random(probability) => self.rng.gen_bool(probability.clamp(0.0, 1.0)),
+            Loss::Burst { enter, exit } => {
+                let flip = if self.in_burst[index] { exit } else { enter };
+                if self.rng.gen_bool(flip.clamp(0.0, 1.0)) {
+                    self.in_burst[index] = !self.in_burst[index];
+                }
+                self.in_burst[index]
+            }
+        }
+    }
+
+    fn schedule(&mut self, deliver_at: Instant, response: ProbeResponse) {
+        self.queue.push(Scheduled {
+            deliver_at,
+            response,
+        });
+    }
+}
+
+impl ProbeTransport for FaultTransport {
+    fn send_probe_with_protocol(
+        &mut self,
+        hop: usize,
+        dst: SocketAddr,
+        ttl: u8,
+        timeout: Duration,
+        protocol: ProbeProtocol,
+    ) -> Result<u16> {
+        anyhow::ensure!(!self.scenario.hops.is_empty(), "Scenario has no hops");
+
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1).max(1);
+        let sent_at = Instant::now();
+        self.pending.insert(seq, hop);
+        self.stats.sent += 1;
+
+        // Probes beyond the path length are answered by the destination
+        let index = (ttl.max(1) as usize - 1).min(self.scenario.hops.len() - 1);
+        let is_destination = index == self.scenario.hops.len() - 1;
+
+        let timeout_response = ProbeResponse {
+            hop,
+            seq,
+            source_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            icmp_type: IcmpResponseType::Timeout,
+            icmp_code: 0,
+            rtt: timeout,
+            send_time: sent_at,
+            receive_time: sent_at + timeout,
+            precise_rtt_ns: timeout.as_nanos(),
+            extensions: None,
+            port_state: None,
+            reply_ttl: None,
+            timestamps: None,
+            dns_rcode: None,
+        };
+
+        if self.is_lost(index) {
+            self.stats.lost += 1;
+            self.schedule(sent_at + timeout, timeout_response);
+            return Ok(seq);
+        }
+
+        let mut rtt = self.scenario.hops[index].delay.sample(&mut self.rng);
+        if self.rng.gen_bool(self.scenario.reorder.clamp(0.0, 1.0)) {
+            self.stats.reordered += 1;
+            rtt += self.scenario.reorder_delay;
+        }
+
+        // Replies slower than the timeout arrive after the probe was given up on
+        if rtt >= timeout {
+            self.schedule(sent_at + timeout, timeout_response);
+        }
+
+        let (source_addr, icmp_type) = if is_destination {
+            (dst.ip(), IcmpResponseType::EchoReply)
+        } else {
+            (Self::router_addr(ttl, dst.ip()), IcmpResponseType::TimeExceeded)
+        };
+        // Simulated clocks agree, and every path is symmetric
+        let timestamps = (is_destination && protocol == ProbeProtocol::Timestamp).then(|| {
+            let rtt_ms = rtt.as_millis() as u32;
+            IcmpTimestamps {
+                originate: 0,
+                receive: rtt_ms / 2,
+                transmit: rtt_ms / 2,
+                arrival: rtt_ms,
+            }
+        });
+        let reply = ProbeResponse {
+            hop,
+            seq,
+            source_addr,
+            icmp_type,
+            icmp_code: 0,
+            rtt,
+            send_time: sent_at,
+            receive_time: sent_at + rtt,
+            precise_rtt_ns: rtt.as_nanos(),
+            extensions: None,
+            // Simulated destinations accept connections
+            port_state: (is_destination && matches!(protocol, ProbeProtocol::Tcp | ProbeProtocol::Quic))
+                .then_some(PortState::Open),
+            reply_ttl: Some(REPLY_TTL.saturating_sub(index as u8)),
+            timestamps,
+            dns_rcode: (is_destination && protocol == ProbeProtocol::Dns).then_some(DnsRcode(0)),
+        };
+
+        if self.rng.gen_bool(self.scenario.duplicate.clamp(0.0, 1.0)) {
+            self.stats.duplicated += 1;
+            self.schedule(sent_at + rtt + Duration::from_millis(1), reply.clone());
+        }
+        self.schedule(sent_at + rtt, reply);
+
+        Ok(seq)
+    }
+
+    async fn collect_responses_async(&mut self) -> Result<Vec<ProbeResponse>> {
+        let now = Instant::now();
+        let wait = match self.queue.iter().map(|s| s.deliver_at).min() {
+            Some(next) => next.saturating_duration_since(now).min(MAX_IDLE),
+            None => MAX_IDLE,
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        let now = Instant::now();
+        let mut due: Vec<Scheduled> = Vec::new();
+        let mut i = 0;
+        while i < self.queue.len() {
+            if self.queue[i].deliver_at <= now {
+                due.push(self.queue.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        due.sort_by_key(|s| s.deliver_at);
+
+        // Match against outstanding probes exactly like ProbeEngine: the first
+        // answer wins, duplicates and late replies are dropped
+        Ok(due
+            .into_iter()
+            .filter(|s| self.pending.remove(&s.response.seq).is_some())
+            .map(|s| s.response)
+            .collect())
+    }
+
+    fn expire_all(&mut self) -> Vec<ProbeResponse> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        for scheduled in self.queue.drain(..) {
+            if self.pending.remove(&scheduled.response.seq).is_none() {
+                continue; // A duplicate of a probe already expired
+            }
+            let rtt = now.saturating_duration_since(scheduled.response.send_time);
+            expired.push(ProbeResponse {
+                source_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                icmp_type: IcmpResponseType::Timeout,
+                icmp_code: 0,
+                rtt,
+                receive_time: now,
+                precise_rtt_ns: rtt.as_nanos(),
+                extensions: None,
+                port_state: None,
+                reply_ttl: None,
+                timestamps: None,
+                dns_rcode: None,
+                ..scheduled.response
+            });
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dst() -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 1], 0))
+    }
+
+    async fn drain(transport: &mut FaultTransport) -> Vec<ProbeResponse> {
+        let mut responses = Vec::new();
+        while !transport.queue.is_empty() {
+            responses.extend(transport.collect_responses_async().await.unwrap());
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn test_scenario_is_deterministic() {
+        let run = || async {
+            let mut transport = FaultTransport::new(Scenario::builtin(FaultScenario::Lossy));
+            for round in 0..5 {
+                for ttl in 1..=6u8 {
+                    transport
+                        .send_probe(round, dst(), ttl, Duration::from_millis(200))
+                        .unwrap();
+                }
+            }
+            let mut rtts: Vec<(u16, Duration, IcmpResponseType)> = drain(&mut transport)
+                .await
+                .into_iter()
+                .map(|r| (r.seq, r.rtt, r.icmp_type))
+                .collect();
+            rtts.sort_by_key(|r| r.0);
+            (rtts, transport.stats())
+        };
+
+        assert_eq!(run().await, run().await);
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_and_late_replies_are_dropped() {
+        let scenario = Scenario::new(vec![HopProfile::new(Delay::Fixed(Duration::from_millis(2)))])
+            .with_duplicates(1.0);
+        let mut transport = FaultTransport::new(scenario);
+        let seq = transport
+            .send_probe(0, dst(), 1, Duration::from_millis(100))
+            .unwrap();
+
+        let responses = drain(&mut transport).await;
+        assert_eq!(transport.stats().duplicated, 1);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].seq, seq);
+        assert_eq!(responses[0].icmp_type, IcmpResponseType::EchoReply);
+        assert_eq!(responses[0].source_addr, dst().ip());
+    }
+
+    #[test]
+    fn test_expire_all_gives_up_on_outstanding_probes() {
+        let scenario = Scenario::new(vec![HopProfile::new(Delay::Fixed(Duration::from_secs(10)))])
+            .with_duplicates(1.0);
+        let mut transport = FaultTransport::new(scenario);
+        let seq = transport.send_probe(0, dst(), 1, Duration::from_secs(60)).unwrap();
+
+        let expired = transport.expire_all();
+        assert_eq!(expired.len(), 1); // Its duplicate is no second loss
+        assert_eq!(expired[0].seq, seq);
+        assert_eq!(expired[0].icmp_type, IcmpResponseType::Timeout);
+        assert!(transport.queue.is_empty() && transport.pending.is_empty());
+        assert!(transport.expire_all().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_intermediate_hops_and_loss() {
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))).with_loss(Loss::Random(1.0)),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+        ]);
+        let mut transport = FaultTransport::new(scenario);
+        transport.send_probe(0, dst(), 1, Duration::from_millis(10)).unwrap();
+        transport.send_probe(1, dst(), 2, Duration::from_millis(10)).unwrap();
+        transport.send_probe(2, dst(), 3, Duration::from_millis(10)).unwrap();
+
+        let mut responses = drain(&mut transport).await;
+        responses.sort_by_key(|r| r.hop);
+        assert_eq!(responses[0].icmp_type, IcmpResponseType::Timeout);
+        assert_eq!(responses[1].icmp_type, IcmpResponseType::EchoReply);
+        assert_eq!(responses[2].icmp_type, IcmpResponseType::EchoReply); // Beyond path answers as destination
+        assert_eq!(transport.stats().lost, 1);
+    }
+
+    #[test]
+    fn test_burst_loss_clusters() {
+        let scenario = Scenario::new(vec![HopProfile::new(Delay::Fixed(Duration::ZERO))
+            .with_loss(Loss::Burst { enter: 0.1, exit: 0.3 })]);
+        let mut transport = FaultTransport::new(scenario);
+        let losses: Vec<bool> = (0..500).map(|_| transport.is_lost(0)).collect();
+
+        let lost = losses.iter().filter(|l| **l).count();
+        let runs = losses.windows(2).filter(|w| !w[0] && w[1]).count();
+        assert!(lost > 0);
+        assert!(lost as f64 / runs as f64 > 1.5); // Losses come in bursts, not singly
+    }
+}
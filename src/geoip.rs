@@ -0,0 +1,414 @@
+//! GeoIP annotation from a local MaxMind database
+//!
+//! `--geoip` points at a GeoLite2 or GeoIP2 City/Country database (`.mmdb`).
+//! Every hop address is looked up once it is known, and its country and city
+//! are shown in the Location column, so a path that detours through another
+//! country or continent is visible at a glance.
+//!
+//! The reader covers the part of the MaxMind DB format these databases use:
+//! the binary search tree with 24, 28 or 32 bit records, and the data section
+//! types needed to reach `country.iso_code` and `city.names.en`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, net::IpAddr, path::Path, sync::Mutex};
+use tracing::{debug, info};
+
+/// Marks the start of the metadata section, near the end of the file
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+
+/// The metadata must start within this many bytes of the end of the file
+const METADATA_MAX_SIZE: usize = 128 * 1024;
+
+/// Zero bytes separating the search tree from the data section
+const DATA_SECTION_SEPARATOR: usize = 16;
+
+/// Limit on nested maps, arrays and pointers in one record
+const MAX_DEPTH: usize = 32;
+
+/// Where a hop's address is, as far as the database knows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeoLocation {
+    pub addr: IpAddr,
+    pub country: Option<String>, // ISO 3166-1 alpha-2 code
+    pub city: Option<String>,    // English city name
+}
+
+impl fmt::Display for GeoLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.city, &self.country) {
+            (Some(city), Some(country)) => write!(f, "{}, {}", city, country),
+            (Some(place), None) | (None, Some(place)) => f.write_str(place),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// A MaxMind database held in memory
+#[derive(Debug)]
+pub struct GeoIpDb {
+    data: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    ipv4_start: usize,
+    data_start: usize,
+    cache: Mutex<HashMap<IpAddr, Option<GeoLocation>>>, // Every address looked up, found or not
+}
+
+impl GeoIpDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read GeoIP database {}", path.display()))?;
+        let db = Self::from_bytes(data).with_context(|| format!("Invalid GeoIP database {}", path.display()))?;
+        info!("Loaded GeoIP database {} ({} nodes)", path.display(), db.node_count);
+        Ok(db)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let search_from = data.len().saturating_sub(METADATA_MAX_SIZE);
+        let marker = data[search_from..]
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or_else(|| anyhow!("Not a MaxMind database (metadata marker missing)"))?;
+        let metadata_start = search_from + marker + METADATA_MARKER.len();
+        let metadata = &data[metadata_start..];
+
+        let (metadata, _) = decode(metadata, 0, 0)?;
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(Value::as_uint)
+                .ok_or_else(|| anyhow!("Metadata is missing {}", name))
+        };
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        let ip_version = field("ip_version")?;
+
+        if !matches!(record_size, 24 | 28 | 32) {
+            bail!("Unsupported record size {}", record_size);
+        }
+        if !matches!(ip_version, 4 | 6) {
+            bail!("Unsupported IP version {}", ip_version);
+        }
+        let tree_size = node_count
+            .checked_mul(record_size / 4)
+            .ok_or_else(|| anyhow!("Search tree size overflows"))?;
+        let data_start = tree_size + DATA_SECTION_SEPARATOR;
+        if data_start > metadata_start - METADATA_MARKER.len() {
+            bail!("Search tree of {} nodes does not fit in the file", node_count);
+        }
+
+        let mut db = Self {
+            data,
+            node_count,
+            record_size,
+            ip_version,
+            ipv4_start: 0,
+            data_start,
+            cache: Mutex::default(),
+        };
+        // IPv4 addresses live under ::/96 in an IPv6 tree
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = db.record(node, 0);
+            }
+            db.ipv4_start = node;
+        }
+        Ok(db)
+    }
+
+    /// Country and city for an address, if the database has them
+    pub fn lookup(&self, addr: IpAddr) -> Option<GeoLocation> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entry(addr).or_insert_with(|| self.search(addr)).clone()
+    }
+
+    /// Walk the search tree for an address and decode its record
+    fn search(&self, addr: IpAddr) -> Option<GeoLocation> {
+        let (octets, start) = match addr {
+            IpAddr::V4(v4) => (v4.octets().to_vec(), self.ipv4_start),
+            IpAddr::V6(_) if self.ip_version == 4 => return None,
+            IpAddr::V6(v6) => (v6.octets().to_vec(), 0),
+        };
+
+        let mut node = start;
+        for bit in 0..octets.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, (octets[bit / 8] >> (7 - bit % 8)) & 1);
+        }
+        // Equal to node_count means no data; below it the tree ran out of bits
+        if node <= self.node_count {
+            return None;
+        }
+
+        // A corrupt tree can point into the separator
+        let Some(offset) = (node - self.node_count).checked_sub(DATA_SECTION_SEPARATOR) else {
+            debug!("GeoIP record for {} points before the data section", addr);
+            return None;
+        };
+        let record = match decode(&self.data[self.data_start..], offset, 0) {
+            Ok((record, _)) => record,
+            Err(e) => {
+                debug!("GeoIP record for {} unreadable: {:#}", addr, e);
+                return None;
+            }
+        };
+
+        let country = ["country", "registered_country"]
+            .iter()
+            .find_map(|key| record.get(key)?.get("iso_code")?.as_str())
+            .map(str::to_string);
+        let city = record
+            .get("city")
+            .and_then(|city| city.get("names")?.get("en")?.as_str())
+            .map(str::to_string);
+        if country.is_none() && city.is_none() {
+            return None;
+        }
+        Some(GeoLocation { addr, country, city })
+    }
+
+    /// Left (bit 0) or right (bit 1) record of a search tree node
+    ///
+    /// Returns node_count, which means "not found", for a truncated tree.
+    fn record(&self, node: usize, bit: u8) -> usize {
+        let node_size = self.record_size / 4;
+        let Some(bytes) = self.data.get(node * node_size..(node + 1) * node_size) else {
+            return self.node_count;
+        };
+        let be = |bytes: &[u8]| bytes.iter().fold(0usize, |acc, &b| acc << 8 | b as usize);
+        match (self.record_size, bit) {
+            (24, 0) => be(&bytes[0..3]),
+            (24, _) => be(&bytes[3..6]),
+            (28, 0) => (bytes[3] as usize >> 4) << 24 | be(&bytes[0..3]),
+            (28, _) => (bytes[3] as usize & 0x0f) << 24 | be(&bytes[4..7]),
+            (_, 0) => be(&bytes[0..4]),
+            (_, _) => be(&bytes[4..8]),
+        }
+    }
+}
+
+/// A decoded data section value; types the lookups never read are skipped
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Uint(u64),
+    Map(Vec<(String, Value)>),
+    Other,
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_uint(&self) -> Option<u64> {
+        match *self {
+            Value::Uint(n) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+/// Decode the value at `offset`, returning it and the offset just past it
+///
+/// Pointers are relative to the start of `section`.
+fn decode(section: &[u8], offset: usize, depth: usize) -> Result<(Value, usize)> {
+    if depth > MAX_DEPTH {
+        bail!("Data nested too deeply");
+    }
+    let byte = |at: usize| {
+        section
+            .get(at)
+            .map(|&b| b as usize)
+            .ok_or_else(|| anyhow!("Data truncated at offset {}", at))
+    };
+    let bytes = |at: usize, len: usize| {
+        section
+            .get(at..at + len)
+            .ok_or_else(|| anyhow!("Data truncated at offset {}", at))
+    };
+    let be = |bytes: &[u8]| bytes.iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+
+    let control = byte(offset)?;
+    let mut at = offset + 1;
+    let mut kind = control >> 5;
+    if kind == 1 {
+        let size = (control >> 3) & 0x3;
+        let high = control & 0x7;
+        let target = match size {
+            0 => high << 8 | byte(at)?,
+            1 => (high << 16 | be(bytes(at, 2)?) as usize) + 2048,
+            2 => (high << 24 | be(bytes(at, 3)?) as usize) + 526_336,
+            _ => be(bytes(at, 4)?) as usize,
+        };
+        let (value, _) = decode(section, target, depth + 1)?;
+        return Ok((value, at + size + 1));
+    }
+    if kind == 0 {
+        kind = 7 + byte(at)?;
+        at += 1;
+    }
+    let mut size = control & 0x1f;
+    match size {
+        29 => {
+            size = 29 + byte(at)?;
+            at += 1;
+        }
+        30 => {
+            size = 285 + be(bytes(at, 2)?) as usize;
+            at += 2;
+        }
+        31 => {
+            size = 65_821 + be(bytes(at, 3)?) as usize;
+            at += 3;
+        }
+        _ => {}
+    }
+
+    match kind {
+        // UTF-8 string
+        2 => {
+            let text = std::str::from_utf8(bytes(at, size)?).context("String is not UTF-8")?;
+            Ok((Value::String(text.to_string()), at + size))
+        }
+        // Unsigned integers of 16, 32, 64 and 128 bits, and int32
+        5 | 6 | 8 | 9 | 10 => {
+            let raw = bytes(at, size)?;
+            let value = if size <= 8 { Value::Uint(be(raw)) } else { Value::Other };
+            Ok((value, at + size))
+        }
+        7 => {
+            let mut entries = Vec::with_capacity(size.min(64));
+            for _ in 0..size {
+                let (key, next) = decode(section, at, depth + 1)?;
+                let Value::String(key) = key else {
+                    bail!("Map key at offset {} is not a string", at);
+                };
+                let (value, next) = decode(section, next, depth + 1)?;
+                entries.push((key, value));
+                at = next;
+            }
+            Ok((Value::Map(entries), at))
+        }
+        11 => {
+            for _ in 0..size {
+                let (_, next) = decode(section, at, depth + 1)?;
+                at = next;
+            }
+            Ok((Value::Other, at))
+        }
+        // Double and float have fixed sizes; bytes use the size field
+        3 => Ok((Value::Other, at + 8)),
+        15 => Ok((Value::Other, at + 4)),
+        4 => Ok((Value::Other, at + size)),
+        // Booleans keep their value in the size field
+        14 => Ok((Value::Other, at)),
+        _ => bail!("Unsupported data type {} at offset {}", kind, offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut out = vec![0x40 | s.len() as u8];
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    fn map(entries: usize) -> Vec<u8> {
+        vec![0xe0 | entries as u8]
+    }
+
+    /// An IPv4 database with a single node: 0.0.0.0/1 has a record, 128.0.0.0/1 has none
+    fn test_database() -> Vec<u8> {
+        let node_count = 1;
+        let mut data = Vec::new();
+
+        // Data section: "NL" at offset 0, then the record at offset 3
+        let mut section = string("NL");
+        let record_offset = section.len();
+        section.extend(map(2));
+        section.extend(string("country"));
+        section.extend(map(1));
+        section.extend(string("iso_code"));
+        section.extend([0x20, 0x00]); // pointer to "NL"
+        section.extend(string("city"));
+        section.extend(map(1));
+        section.extend(string("names"));
+        section.extend(map(2));
+        section.extend(string("de"));
+        section.extend(string("Amsterdam"));
+        section.extend(string("en"));
+        section.extend(string("Amsterdam"));
+
+        // 24-bit records: left points at the record, right means not found
+        let left = node_count + DATA_SECTION_SEPARATOR + record_offset;
+        data.extend(&(left as u32).to_be_bytes()[1..]);
+        data.extend(&(node_count as u32).to_be_bytes()[1..]);
+        data.extend([0; DATA_SECTION_SEPARATOR]);
+        data.extend(section);
+
+        data.extend(METADATA_MARKER);
+        data.extend(map(3));
+        data.extend(string("node_count"));
+        data.extend([0xc1, node_count as u8]);
+        data.extend(string("record_size"));
+        data.extend([0xa1, 24]);
+        data.extend(string("ip_version"));
+        data.extend([0xa1, 4]);
+        data
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = GeoIpDb::from_bytes(test_database()).unwrap();
+        let addr: IpAddr = "93.184.216.34".parse().unwrap();
+        let location = db.lookup(addr).unwrap();
+        assert_eq!(location.addr, addr);
+        assert_eq!(location.country.as_deref(), Some("NL"));
+        assert_eq!(location.city.as_deref(), Some("Amsterdam"));
+        assert_eq!(location.to_string(), "Amsterdam, NL");
+
+        assert_eq!(db.lookup("192.0.2.1".parse().unwrap()), None);
+        assert_eq!(db.lookup("2001:db8::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_invalid_database() {
+        let err = GeoIpDb::from_bytes(b"not a database".to_vec()).unwrap_err();
+        assert!(err.to_string().contains("metadata marker"));
+
+        // Metadata claiming more nodes than the file holds
+        let mut data = test_database();
+        let at = data.windows(10).position(|w| w == b"node_count").unwrap() + 11;
+        data[at] = 200;
+        let err = GeoIpDb::from_bytes(data).unwrap_err();
+        assert!(err.to_string().contains("does not fit"));
+
+        // A record pointing into the separator before the data section
+        let mut data = test_database();
+        data[..3].copy_from_slice(&2u32.to_be_bytes()[1..]);
+        let db = GeoIpDb::from_bytes(data).unwrap();
+        assert_eq!(db.lookup("93.184.216.34".parse().unwrap()), None);
+    }
+}
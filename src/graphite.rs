@@ -0,0 +1,224 @@
+//! Graphite plaintext sink (--graphite)
+//!
+//! After every round each hop's statistics go to a Carbon server as
+//! `mtr-ng.<target>.hop_<NN>.<metric> <value> <timestamp>` lines over TCP.
+//! Sending happens on a task of its own so a slow or unreachable server
+//! never holds up probing. Rounds that can't be delivered, or that arrive
+//! while the queue is full or the server is down, are dropped; reconnects
+//! back off from one second up to a minute.
+
+use crate::{utils, HopStats};
+use std::{fmt::Write as _, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+    time::{self, Instant},
+};
+use tracing::{debug, warn};
+
+/// First component of every metric path
+const PREFIX: &str = "mtr-ng";
+/// Longest a connection attempt or write may take
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest the final flush waits before giving up on what is still queued
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Rounds waiting to be sent; newer ones are dropped beyond this
+const QUEUE_LEN: usize = 16;
+/// Wait after the first failed connection attempt, doubled with every further one
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait between connection attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+enum Message {
+    Batch(String),
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphiteSink {
+    tx: mpsc::Sender<Message>,
+}
+
+impl GraphiteSink {
+    /// Start the sender task for a server at `addr` (host:port)
+    pub fn spawn(addr: String) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        tokio::spawn(run(addr, rx));
+        Self { tx }
+    }
+
+    /// Queue the metrics of every probed hop after a round
+    pub fn send_round(&self, target: &str, hops: &[HopStats]) {
+        let timestamp = chrono::Utc::now().timestamp();
+        if self.tx.try_send(Message::Batch(format_round(target, hops, timestamp))).is_err() {
+            debug!("Graphite queue full, dropping a round");
+        }
+    }
+
+    /// Wait until everything queued so far has been sent or given up on, for at most FLUSH_TIMEOUT
+    pub async fn flush(&self) {
+        let flush = async {
+            let (done_tx, done_rx) = oneshot::channel();
+            if self.tx.send(Message::Flush(done_tx)).await.is_ok() {
+                let _ = done_rx.await;
+            }
+        };
+        if time::timeout(FLUSH_TIMEOUT, flush).await.is_err() {
+            warn!("Gave up flushing metrics to Graphite after {:?}", FLUSH_TIMEOUT);
+        }
+    }
+}
+
+async fn run(addr: String, mut rx: mpsc::Receiver<Message>) {
+    let mut conn: Option<TcpStream> = None;
+    let mut failing = false; // Only the first of a run of failures is a warning
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    let mut retry_at: Option<Instant> = None; // No connection attempts before this
+
+    while let Some(message) = rx.recv().await {
+        let batch = match message {
+            Message::Batch(batch) => batch,
+            Message::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            }
+        };
+        if conn.is_none() {
+            if retry_at.is_some_and(|at| Instant::now() < at) {
+                continue; // Still backing off
+            }
+            let error = match time::timeout(SEND_TIMEOUT, TcpStream::connect(&addr)).await {
+                Ok(Ok(stream)) => {
+                    debug!("Connected to Graphite at {}", addr);
+                    conn = Some(stream);
+                    reconnect_delay = MIN_RECONNECT_DELAY;
+                    retry_at = None;
+                    None
+                }
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some("connection timed out".to_string()),
+            };
+            if let Some(error) = error {
+                report_failure(&mut failing, &addr, &error);
+                retry_at = Some(Instant::now() + reconnect_delay);
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+        }
+        let stream = conn.as_mut().expect("connected above");
+        match time::timeout(SEND_TIMEOUT, stream.write_all(batch.as_bytes())).await {
+            Ok(Ok(())) => failing = false,
+            Ok(Err(e)) => {
+                report_failure(&mut failing, &addr, &e.to_string());
+                conn = None;
+            }
+            Err(_) => {
+                report_failure(&mut failing, &addr, "write timed out");
+                conn = None;
+            }
+        }
+    }
+}
+
+fn report_failure(failing: &mut bool, addr: &str, error: &str) {
+    if !*failing {
+        warn!("Dropping metrics for Graphite at {}: {}", addr, error);
+    }
+    *failing = true;
+}
+
+/// One line per metric of every probed hop; RTTs only once a hop has answered
+fn format_round(target: &str, hops: &[HopStats], timestamp: i64) -> String {
+    let target = metric_component(target);
+    let mut out = String::new();
+    for hop in hops.iter().filter(|hop| hop.sent > 0) {
+        let mut metric = |name: &str, value: f64| {
+            let _ = writeln!(out, "{}.{}.hop_{:02}.{} {} {}", PREFIX, target, hop.hop, name, value, timestamp);
+        };
+        metric("sent", hop.sent as f64);
+        metric("received", hop.received as f64);
+        metric("loss_percent", hop.loss_percent);
+        let rtts = [
+            ("last_ms", hop.last_rtt),
+            ("avg_ms", hop.avg_rtt),
+            ("best_ms", hop.best_rtt),
+            ("worst_ms", hop.worst_rtt),
+            ("jitter_ms", hop.jitter_avg),
+        ];
+        for (name, rtt) in rtts {
+            if let Some(rtt) = rtt {
+                metric(name, utils::time::duration_to_ms_f64(rtt));
+            }
+        }
+    }
+    out
+}
+
+/// A target as one path component: dots and anything Graphite treats specially become `_`
+fn metric_component(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn test_round_lines() {
+        let mut hops = vec![HopStats::new(1), HopStats::new(2), HopStats::new(3)];
+        hops[0].increment_sent();
+        hops[0].add_rtt(Duration::from_millis(12));
+        hops[1].increment_sent();
+        hops[1].add_timeout();
+
+        let out = format_round("2001:db8::1", &hops, 1700000000);
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines.contains(&"mtr-ng.2001_db8__1.hop_01.avg_ms 12 1700000000"));
+        assert!(lines.contains(&"mtr-ng.2001_db8__1.hop_02.loss_percent 100 1700000000"));
+        assert!(!lines.iter().any(|line| line.contains("hop_02.avg_ms"))); // Never answered
+        assert!(!lines.iter().any(|line| line.contains("hop_03"))); // Never probed
+    }
+
+    #[tokio::test]
+    async fn test_sink_sends_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sink = GraphiteSink::spawn(listener.local_addr().unwrap().to_string());
+
+        let mut hop = HopStats::new(1);
+        hop.increment_sent();
+        hop.add_rtt(Duration::from_millis(5));
+        sink.send_round("example.com", &[hop]);
+        sink.flush().await;
+
+        let (client, _) = listener.accept().await.unwrap();
+        let mut lines = BufReader::new(client).lines();
+        let first = lines.next_line().await.unwrap().unwrap();
+        assert!(first.starts_with("mtr-ng.example_com.hop_01.sent 1 "));
+    }
+
+    #[tokio::test]
+    async fn test_sink_gives_up_on_dead_server() {
+        // Nothing listens here any more, so every connection is refused
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let sink = GraphiteSink::spawn(addr);
+
+        let mut hop = HopStats::new(1);
+        hop.increment_sent();
+        for _ in 0..QUEUE_LEN * 4 {
+            sink.send_round("example.com", std::slice::from_ref(&hop)); // Never blocks
+        }
+        let started = std::time::Instant::now();
+        sink.flush().await;
+        assert!(started.elapsed() <= FLUSH_TIMEOUT + Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,174 @@
+//! Typed session events for integrations
+//!
+//! Each `on_*` registration returns a channel that gets one event per
+//! occurrence, so alerting, logging or a test can wait on what it cares about
+//! instead of polling the hop table. Registrations made after the trace has
+//! started only see what happens from then on. A receiver that is dropped is
+//! unregistered on the next event.
+
+use crate::{route::RouteChange, HopStats};
+use std::{
+    collections::HashSet,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// The target answered, for the first time or at a different hop than before
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetReached {
+    pub hop: u8,
+    pub rtt: Duration,
+}
+
+/// A hop's loss rose above a registered threshold
+///
+/// Sent again only after the loss has dropped back to the threshold or below.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HopLossExceeded {
+    pub hop: u8,
+    pub loss_percent: f64,
+    pub threshold: f64,
+}
+
+/// A probing round ended, with the displayed hops as they stood
+#[derive(Debug, Clone)]
+pub struct RoundComplete {
+    pub round: usize,
+    pub hops: Vec<HopStats>,
+}
+
+#[derive(Debug)]
+struct LossWatch {
+    threshold: f64,
+    above: HashSet<u8>, // Hops over the threshold, by TTL
+    tx: UnboundedSender<HopLossExceeded>,
+}
+
+/// Event subscribers of a session
+#[derive(Debug, Default)]
+pub struct Hooks {
+    target_reached: Mutex<Vec<UnboundedSender<TargetReached>>>,
+    loss_exceeded: Mutex<Vec<LossWatch>>,
+    path_change: Mutex<Vec<UnboundedSender<RouteChange>>>,
+    round_complete: Mutex<Vec<UnboundedSender<RoundComplete>>>,
+}
+
+impl Hooks {
+    pub fn on_target_reached(&self) -> UnboundedReceiver<TargetReached> {
+        subscribe(&self.target_reached)
+    }
+
+    /// Be told whenever a hop's loss goes above `threshold` percent
+    pub fn on_hop_loss_exceeded(&self, threshold: f64) -> UnboundedReceiver<HopLossExceeded> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.loss_exceeded.lock().unwrap().push(LossWatch {
+            threshold,
+            above: HashSet::new(),
+            tx,
+        });
+        rx
+    }
+
+    /// Be told of every route change, as shown in the route events view
+    pub fn on_path_change(&self) -> UnboundedReceiver<RouteChange> {
+        subscribe(&self.path_change)
+    }
+
+    pub fn on_round_complete(&self) -> UnboundedReceiver<RoundComplete> {
+        subscribe(&self.round_complete)
+    }
+
+    pub(crate) fn target_reached(&self, event: TargetReached) {
+        deliver(&self.target_reached, event);
+    }
+
+    /// Compare a hop that was just updated against every loss threshold
+    pub(crate) fn check_loss(&self, hop: &HopStats) {
+        let mut watches = self.loss_exceeded.lock().unwrap();
+        watches.retain_mut(|watch| {
+            if watch.tx.is_closed() {
+                return false;
+            }
+            if hop.loss_percent <= watch.threshold {
+                watch.above.remove(&hop.hop);
+                return true;
+            }
+            if !watch.above.insert(hop.hop) {
+                return true; // Still over since the last event
+            }
+            watch
+                .tx
+                .send(HopLossExceeded {
+                    hop: hop.hop,
+                    loss_percent: hop.loss_percent,
+                    threshold: watch.threshold,
+                })
+                .is_ok()
+        });
+    }
+
+    pub(crate) fn path_changed(&self, change: &RouteChange) {
+        deliver(&self.path_change, change.clone());
+    }
+
+    /// Send a round's end to subscribers; `hops` is only called if there are any
+    pub(crate) fn round_complete(&self, round: usize, hops: impl FnOnce() -> Vec<HopStats>) {
+        let mut subscribers = self.round_complete.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = RoundComplete { round, hops: hops() };
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+fn subscribe<T>(subscribers: &Mutex<Vec<UnboundedSender<T>>>) -> UnboundedReceiver<T> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    subscribers.lock().unwrap().push(tx);
+    rx
+}
+
+fn deliver<T: Clone>(subscribers: &Mutex<Vec<UnboundedSender<T>>>, event: T) {
+    subscribers.lock().unwrap().retain(|tx| tx.send(event.clone()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loss_exceeded_fires_once_per_crossing() {
+        let hooks = Hooks::default();
+        let mut rx = hooks.on_hop_loss_exceeded(50.0);
+        let mut hop = HopStats::new(3);
+
+        hop.increment_sent();
+        hop.add_timeout();
+        hooks.check_loss(&hop); // 100% loss
+        hop.increment_sent();
+        hop.add_timeout();
+        hooks.check_loss(&hop); // Still over: no new event
+        let event = rx.try_recv().unwrap();
+        assert_eq!((event.hop, event.loss_percent, event.threshold), (3, 100.0, 50.0));
+        assert!(rx.try_recv().is_err());
+
+        for _ in 0..3 {
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_millis(5));
+            hooks.check_loss(&hop);
+        }
+        assert!(rx.try_recv().is_err()); // Back to 40%
+        hop.increment_sent();
+        hop.add_timeout();
+        hop.increment_sent();
+        hop.add_timeout();
+        hooks.check_loss(&hop); // 57%, over again
+        assert_eq!(rx.try_recv().unwrap().hop, 3);
+
+        // A dropped receiver is unregistered
+        drop(rx);
+        hooks.check_loss(&HopStats::new(4));
+        assert!(hooks.loss_exceeded.lock().unwrap().is_empty());
+    }
+}
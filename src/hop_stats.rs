@@ -1,25 +1,90 @@
+use crate::geoip::GeoLocation;
+use crate::icmp_ext::IcmpExtensions;
+use crate::probe::{DnsRcode, IcmpTimestamps, PortState};
 use crate::utils;
+use crate::whois::WhoisInfo;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, VecDeque},
     net::IpAddr,
     time::{Duration, Instant},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PacketOutcome {
     Received(Duration), // RTT
     Lost,               // Timeout/no response
     Pending,            // Sent but no response yet
 }
 
-#[derive(Debug, Clone)]
+/// Smoothing factor for the short-window EMA used by trend detection
+const TREND_EMA_ALPHA: f64 = 0.3;
+/// Relative deviation from the long-window average that counts as a trend
+const TREND_THRESHOLD: f64 = 0.1;
+/// Samples required before a trend is reported
+const TREND_MIN_SAMPLES: usize = 5;
+
+/// Multiple of the smoothed RTT a probe gets before it is declared lost
+const TIMEOUT_EMA_FACTOR: u32 = 3;
+/// Shortest adaptive timeout, so jitter on LAN hops isn't mistaken for loss
+pub const MIN_PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Timeout for hops without RTT history, and the cap for slow ones
+pub const MAX_PROBE_TIMEOUT: Duration = Duration::from_millis(5000);
+
+/// Direction of recent RTT movement for a hop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Rising,  // Recent RTTs above the long-window average (getting worse)
+    Falling, // Recent RTTs below the long-window average (improving)
+    Stable,
+}
+
+impl Trend {
+    /// Classify a short-window average against a long-window one
+    fn between(short: Duration, long: Duration) -> Self {
+        let long = utils::time::duration_to_ms_f64(long);
+        if long <= 0.0 {
+            return Trend::Stable;
+        }
+        let deviation = (utils::time::duration_to_ms_f64(short) - long) / long;
+        if deviation > TREND_THRESHOLD {
+            Trend::Rising
+        } else if deviation < -TREND_THRESHOLD {
+            Trend::Falling
+        } else {
+            Trend::Stable
+        }
+    }
+
+    /// Compact arrow used in the trend column
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Trend::Rising => "↑",
+            Trend::Falling => "↓",
+            Trend::Stable => "→",
+        }
+    }
+}
+
+/// Runs of consecutive lost probes, see [`HopStats::loss_streaks`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LossStreaks {
+    pub count: usize,   // Separate runs of loss
+    pub longest: usize, // Probes lost in the longest run
+    pub current: usize, // Probes lost since the last reply
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AlternatePath {
     pub addr: IpAddr,
     pub hostname: Option<String>,
     pub frequency: usize,
+    #[serde(skip, default = "Instant::now")]
     pub last_seen: Instant,
     pub last_rtt: Option<Duration>,
     pub avg_rtt: Option<Duration>,
+    /// Replies averaged into `avg_rtt`
+    pub rtt_samples: u32,
 }
 
 impl AlternatePath {
@@ -27,10 +92,11 @@ impl AlternatePath {
         Self {
             addr,
             hostname: None,
-            frequency: 1,
+            frequency: 0, // Counted by the first update
             last_seen: Instant::now(),
             last_rtt: None,
             avg_rtt: None,
+            rtt_samples: 0,
         }
     }
 
@@ -38,12 +104,17 @@ impl AlternatePath {
         self.frequency += 1;
         self.last_seen = Instant::now();
         self.last_rtt = Some(rtt);
-        // Simple running average
-        self.avg_rtt = Some(rtt);
+        // Incremental mean, so no sample history is kept per alternate
+        self.rtt_samples += 1;
+        self.avg_rtt = Some(match self.avg_rtt {
+            Some(avg) if rtt >= avg => avg + (rtt - avg) / self.rtt_samples,
+            Some(avg) => avg - (avg - rtt) / self.rtt_samples,
+            None => rtt,
+        });
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HopStats {
     pub hop: u8,
     pub addr: Option<IpAddr>,
@@ -55,12 +126,18 @@ pub struct HopStats {
     pub worst_rtt: Option<Duration>,
     pub avg_rtt: Option<Duration>,
     pub ema_rtt: Option<Duration>, // Exponentially smoothed average RTT
+    pub trend_ema_rtt: Option<Duration>, // Short-window EMA for trend detection
     pub jitter_avg: Option<Duration>, // Mean jitter (average of jitter values)
     pub last_jitter: Option<Duration>, // Last calculated jitter value
     pub jitters: VecDeque<Duration>, // Store jitter values for average calculation
     pub rtts: VecDeque<Duration>,
     pub packet_history: VecDeque<PacketOutcome>, // Chronological packet outcomes
     pub loss_percent: f64,
+    // Round-level availability (did the hop answer at least once per round?)
+    pub rounds_completed: usize,
+    pub rounds_answered: usize,
+    pub round_in_progress: bool,
+    pub answered_this_round: bool,
     // Exponential smoothing factor (0.0 to 1.0)
     // Higher values = more responsive to recent changes
     // Lower values = more stable, less sensitive to spikes
@@ -76,8 +153,29 @@ pub struct HopStats {
     
     // ICMP error tracking (for MTR algorithm compatibility)
     pub icmp_error: bool,
+    /// ICMP code of the last destination unreachable reply from this hop
+    pub unreachable_code: Option<u8>,
+    /// Latest RFC 4884 extensions (MPLS labels, interface info) from this hop
+    pub extensions: Option<IcmpExtensions>,
+    /// Whether the destination accepted or refused the latest TCP or QUIC probe
+    pub port_state: Option<PortState>,
+    /// TTL (hop limit) of the latest reply from this hop as it reached us
+    pub reply_ttl: Option<u8>,
+    /// Clock readings from the latest ICMP timestamp reply
+    pub timestamps: Option<IcmpTimestamps>,
+    /// Response code of the latest answer to a DNS probe
+    pub dns_rcode: Option<DnsRcode>,
+    /// Where the hop's address is, from the --geoip database
+    pub geo: Option<GeoLocation>,
+    /// Who holds the hop's address, from --whois
+    pub whois: Option<WhoisInfo>,
+    /// Address of the latest reply, whichever path it came over
+    pub last_responder: Option<IpAddr>,
+    /// Times a different address answered than the one before it
+    pub route_changes: usize,
 
     /// Real-time timing statistics tracker
+    #[serde(skip)]
     pub timing_stats: Option<crate::utils::time::TimingStats>,
     /// High-precision RTT values in nanoseconds for detailed analysis
     pub precise_rtts_ns: VecDeque<u128>,
@@ -100,17 +198,32 @@ impl HopStats {
             worst_rtt: None,
             avg_rtt: None,
             ema_rtt: None,
+            trend_ema_rtt: None,
             jitter_avg: None,
             last_jitter: None,
             jitters: VecDeque::with_capacity(100),
             rtts: VecDeque::with_capacity(100),
             packet_history: VecDeque::with_capacity(100),
             loss_percent: 0.0,
+            rounds_completed: 0,
+            rounds_answered: 0,
+            round_in_progress: false,
+            answered_this_round: false,
             ema_alpha: 0.1,
             alternate_paths: HashMap::new(),
             path_frequency: HashMap::new(),
             is_target: false,
             icmp_error: false,
+            unreachable_code: None,
+            extensions: None,
+            port_state: None,
+            reply_ttl: None,
+            timestamps: None,
+            dns_rcode: None,
+            geo: None,
+            whois: None,
+            last_responder: None,
+            route_changes: 0,
             timing_stats: None,
             precise_rtts_ns: VecDeque::new(),
             jitter_threshold: 2.0,
@@ -133,6 +246,22 @@ impl HopStats {
                     .unwrap_or(&0);
 
         if is_primary {
+            if let Some(previous) = self.addr.filter(|&previous| previous != addr) {
+                // Overtaken: the old primary becomes an alternate in the new one's place
+                let promoted = self.alternate_paths.remove(&addr);
+                let frequency = self.path_frequency.get(&previous).copied().unwrap_or(0);
+                let demoted = AlternatePath {
+                    addr: previous,
+                    hostname: self.hostname.take(),
+                    frequency,
+                    last_seen: Instant::now(),
+                    last_rtt: self.last_rtt,
+                    avg_rtt: self.avg_rtt,
+                    rtt_samples: frequency as u32,
+                };
+                self.alternate_paths.insert(previous, demoted);
+                self.hostname = promoted.and_then(|path| path.hostname);
+            }
             // Update primary path stats
             self.addr = Some(addr);
             self.add_rtt(rtt);
@@ -147,6 +276,8 @@ impl HopStats {
 
             // For alternate paths, we still need to count the received packet
             self.received += 1;
+            self.answered_this_round = true;
+            self.mark_received(rtt);
             self.update_loss_percent();
 
             tracing::debug!(
@@ -216,6 +347,7 @@ impl HopStats {
 
     pub fn add_rtt(&mut self, rtt: Duration) {
         self.received += 1;
+        self.answered_this_round = true;
 
         // Initialize timing stats if not already done
         if self.timing_stats.is_none() {
@@ -279,13 +411,7 @@ impl HopStats {
         self.last_rtt = Some(rtt);
         self.rtts.push_back(rtt);
 
-        // Find the last pending packet and mark it as received
-        for outcome in self.packet_history.iter_mut().rev() {
-            if matches!(outcome, PacketOutcome::Pending) {
-                *outcome = PacketOutcome::Received(rtt);
-                break;
-            }
-        }
+        self.mark_received(rtt);
 
         if self.rtts.len() > 100 {
             self.rtts.pop_front();
@@ -312,10 +438,21 @@ impl HopStats {
 
         // Calculate exponential moving average using high-precision timing
         self.ema_rtt = Some(utils::time::calculate_timing_ema(rtt, self.ema_rtt, self.ema_alpha));
+        self.trend_ema_rtt = Some(utils::time::calculate_timing_ema(rtt, self.trend_ema_rtt, TREND_EMA_ALPHA));
 
         self.update_loss_percent();
     }
 
+    /// Find the last pending packet and mark it as received
+    fn mark_received(&mut self, rtt: Duration) {
+        for outcome in self.packet_history.iter_mut().rev() {
+            if matches!(outcome, PacketOutcome::Pending) {
+                *outcome = PacketOutcome::Received(rtt);
+                break;
+            }
+        }
+    }
+
     pub fn add_timeout(&mut self) {
         // Find the oldest pending packet and mark it as lost
         for outcome in self.packet_history.iter_mut() {
@@ -342,6 +479,44 @@ impl HopStats {
         }
     }
 
+    /// Note which address answered, returning the previous one if it changed
+    pub fn record_responder(&mut self, addr: IpAddr) -> Option<IpAddr> {
+        let previous = self.last_responder.replace(addr)?;
+        if previous == addr {
+            return None;
+        }
+        self.route_changes += 1;
+        Some(previous)
+    }
+
+    /// Start a new probing round for this hop, closing out the previous one
+    pub fn begin_round(&mut self) {
+        if self.round_in_progress {
+            self.rounds_completed += 1;
+            if self.answered_this_round {
+                self.rounds_answered += 1;
+            }
+        }
+        self.round_in_progress = true;
+        self.answered_this_round = false;
+    }
+
+    /// Percentage of rounds in which this hop answered at least one probe
+    ///
+    /// Unlike `loss_percent` this is insensitive to how many probes a round
+    /// loses, so a hop that rate-limits replies scores high while one that
+    /// disappears for whole rounds scores low. The open round only counts once
+    /// it has been answered, so availability doesn't dip while replies are in
+    /// flight.
+    pub fn availability_percent(&self) -> Option<f64> {
+        let current = usize::from(self.round_in_progress && self.answered_this_round);
+        let rounds = self.rounds_completed + current;
+        if rounds == 0 {
+            return None;
+        }
+        Some((self.rounds_answered + current) as f64 / rounds as f64 * 100.0)
+    }
+
     pub fn increment_sent(&mut self) {
         self.sent += 1;
 
@@ -370,6 +545,98 @@ impl HopStats {
         self.ema_alpha = utils::math::clamp_ratio(alpha);
     }
     
+    /// How long a probe to this hop may go unanswered before it counts as lost
+    ///
+    /// Derived from the hop's own history as `max(3×EMA, floor)`, so fast hops
+    /// declare loss quickly while satellite links get the time they need.
+    /// Hops that have never answered wait the full `MAX_PROBE_TIMEOUT`.
+    pub fn probe_timeout(&self) -> Duration {
+        self.ema_rtt.map_or(MAX_PROBE_TIMEOUT, |ema| {
+            (ema * TIMEOUT_EMA_FACTOR).clamp(MIN_PROBE_TIMEOUT, MAX_PROBE_TIMEOUT)
+        })
+    }
+
+    /// Compare the short-window EMA against the long-window average
+    ///
+    /// Returns `None` until enough samples have been collected to tell a trend
+    /// from noise.
+    pub fn trend(&self) -> Option<Trend> {
+        if self.rtts.len() < TREND_MIN_SAMPLES {
+            return None;
+        }
+
+        Some(Trend::between(self.trend_ema_rtt?, self.avg_rtt?))
+    }
+
+    /// Compare the newest quarter of the jitter samples against all of them
+    pub fn jitter_trend(&self) -> Option<Trend> {
+        let recent = (self.jitters.len() / 4).max(TREND_MIN_SAMPLES);
+        if self.jitters.len() < 2 * recent {
+            return None;
+        }
+        let mean = |jitters: &[Duration]| jitters.iter().sum::<Duration>() / jitters.len() as u32;
+        let jitters: Vec<Duration> = self.jitters.iter().copied().collect();
+        Some(Trend::between(mean(&jitters[jitters.len() - recent..]), mean(&jitters)))
+    }
+
+    /// Runs of consecutive losses in the packet history
+    pub fn loss_streaks(&self) -> LossStreaks {
+        let mut streaks = LossStreaks::default();
+        for outcome in &self.packet_history {
+            match outcome {
+                PacketOutcome::Lost => {
+                    if streaks.current == 0 {
+                        streaks.count += 1;
+                    }
+                    streaks.current += 1;
+                    streaks.longest = streaks.longest.max(streaks.current);
+                }
+                PacketOutcome::Received(_) => streaks.current = 0,
+                PacketOutcome::Pending => {}
+            }
+        }
+        streaks
+    }
+
+    /// Recent RTTs counted into `bins` equal slices of their range
+    ///
+    /// Returns the fastest and slowest sample with the counts, or None
+    /// without replies.
+    pub fn rtt_histogram(&self, bins: usize) -> Option<(Duration, Duration, Vec<usize>)> {
+        let min = *self.rtts.iter().min()?;
+        let max = *self.rtts.iter().max()?;
+        let mut counts = vec![0; bins.max(1)];
+        let span = (max - min).as_secs_f64();
+        for rtt in &self.rtts {
+            let bin = if span > 0.0 {
+                ((*rtt - min).as_secs_f64() / span * counts.len() as f64) as usize
+            } else {
+                0
+            };
+            counts[bin.min(bins.max(1) - 1)] += 1;
+        }
+        Some((min, max, counts))
+    }
+
+    /// Standard deviation of the recent RTTs, None until two replies came back
+    pub fn rtt_stddev(&self) -> Option<Duration> {
+        if self.rtts.len() < 2 {
+            return None;
+        }
+        let rtts_ms: Vec<f64> = self.rtts.iter().map(|rtt| utils::time::duration_to_ms_f64(*rtt)).collect();
+        let mean = rtts_ms.iter().sum::<f64>() / rtts_ms.len() as f64;
+        Some(Duration::from_secs_f64(utils::math::calculate_stddev(&rtts_ms, mean) / 1000.0))
+    }
+
+    /// RTT below which `percentile` percent of the recent samples fall
+    ///
+    /// Taken from the retained history, so it describes the last 100
+    /// replies rather than the whole run.
+    pub fn rtt_percentile(&self, percentile: f64) -> Option<Duration> {
+        let mut rtts: Vec<Duration> = self.rtts.iter().copied().collect();
+        utils::time::calculate_timing_percentile(&mut rtts, percentile)
+    }
+
     /// Mark this hop as containing the target destination
     pub fn mark_as_target(&mut self) {
         self.is_target = true;
@@ -384,6 +651,37 @@ impl HopStats {
     pub fn set_icmp_error(&mut self) {
         self.icmp_error = true;
     }
+
+    /// Record a destination unreachable reply with its ICMP code
+    pub fn set_unreachable(&mut self, addr: IpAddr, code: u8) {
+        self.set_icmp_error();
+        self.unreachable_code = Some(code);
+        // Still set the address so it shows up instead of "???"
+        if self.addr.is_none() {
+            self.addr = Some(addr);
+        }
+    }
+
+    /// Keep the latest non-empty ICMP extensions reported for this hop
+    pub fn set_extensions(&mut self, extensions: IcmpExtensions) {
+        if !extensions.is_empty() {
+            self.extensions = Some(extensions);
+        }
+    }
+
+    /// Human-readable "blocked" label for a hop that rejected the trace
+    pub fn blocked_description(&self) -> String {
+        let reason = match (self.unreachable_code, self.addr) {
+            (Some(code), Some(addr)) => utils::icmp::describe_unreachable(code, addr.is_ipv6()),
+            _ => "unreachable",
+        };
+        format!(
+            "✗ blocked at hop {} by {} ({})",
+            self.hop,
+            utils::network::format_optional_ip(self.addr),
+            reason
+        )
+    }
 }
 
 #[cfg(test)]
@@ -593,6 +891,20 @@ mod tests {
         assert!((hop.loss_percent - 33.333333333333336).abs() < 1e-10); // 1 lost out of 3
     }
 
+    #[test]
+    fn test_record_responder_counts_changes() {
+        let mut hop = HopStats::new(5);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(hop.record_responder(a), None); // First reply is no change
+        assert_eq!(hop.record_responder(a), None);
+        assert_eq!(hop.record_responder(b), Some(a));
+        assert_eq!(hop.record_responder(a), Some(b));
+        assert_eq!(hop.route_changes, 2);
+        assert_eq!(hop.last_responder, Some(a));
+    }
+
     #[test]
     fn test_rtt_statistics_accuracy() {
         let mut hop = HopStats::new(1);
@@ -676,4 +988,220 @@ mod tests {
         assert_eq!(expected_avg_ms, 23); // Rounded to nearest ms
         assert_eq!(hop.jitters.len(), 3);
     }
+
+    #[test]
+    fn test_availability_percent() {
+        let mut hop = HopStats::new(1);
+        assert_eq!(hop.availability_percent(), None);
+
+        // Round 1: answered
+        hop.begin_round();
+        hop.increment_sent();
+        hop.add_rtt(Duration::from_millis(10));
+        assert_eq!(hop.availability_percent(), Some(100.0));
+
+        // Round 2: silent - not counted until the round closes
+        hop.begin_round();
+        hop.increment_sent();
+        assert_eq!(hop.availability_percent(), Some(100.0));
+
+        // Round 3 closes round 2 as unanswered
+        hop.begin_round();
+        hop.increment_sent();
+        assert_eq!(hop.availability_percent(), Some(50.0));
+
+        hop.add_rtt(Duration::from_millis(10));
+        let avail = hop.availability_percent().unwrap();
+        assert!((avail - 66.666).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_availability_counts_alternate_replies() {
+        let mut hop = HopStats::new(2);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        // Rounds alternate between two ECMP responders sharing this hop
+        for round in 0..4 {
+            hop.begin_round();
+            hop.increment_sent();
+            let addr = if round % 2 == 0 { a } else { b };
+            hop.add_rtt_from_addr(addr, Duration::from_millis(10));
+        }
+        hop.begin_round();
+
+        assert!(hop.alternate_paths.contains_key(&b));
+        assert_eq!(hop.rounds_completed, 4);
+        assert_eq!(hop.availability_percent(), Some(100.0));
+    }
+
+    #[test]
+    fn test_blocked_description() {
+        let mut hop = HopStats::new(4);
+        hop.set_unreachable("10.0.0.1".parse().unwrap(), 13);
+        assert!(hop.has_icmp_error());
+        assert_eq!(hop.addr, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(
+            hop.blocked_description(),
+            "✗ blocked at hop 4 by 10.0.0.1 (admin prohibited)"
+        );
+    }
+
+    #[test]
+    fn test_probe_timeout_adapts() {
+        let mut hop = HopStats::new(1);
+        assert_eq!(hop.probe_timeout(), MAX_PROBE_TIMEOUT);
+
+        // LAN hop: 3×1ms is below the floor
+        hop.add_rtt(Duration::from_millis(1));
+        assert_eq!(hop.probe_timeout(), MIN_PROBE_TIMEOUT);
+
+        // Satellite hop: 3×600ms
+        let mut hop = HopStats::new(1);
+        hop.add_rtt(Duration::from_millis(600));
+        assert_eq!(hop.probe_timeout(), Duration::from_millis(1800));
+
+        let mut hop = HopStats::new(1);
+        hop.add_rtt(Duration::from_secs(4));
+        assert_eq!(hop.probe_timeout(), MAX_PROBE_TIMEOUT);
+    }
+
+    #[test]
+    fn test_trend_detection() {
+        let mut hop = HopStats::new(1);
+
+        // Not enough samples yet
+        for _ in 0..4 {
+            hop.add_rtt(Duration::from_millis(50));
+        }
+        assert_eq!(hop.trend(), None);
+
+        // Steady RTTs are stable
+        for _ in 0..10 {
+            hop.add_rtt(Duration::from_millis(50));
+        }
+        assert_eq!(hop.trend(), Some(Trend::Stable));
+
+        // A run of higher RTTs pulls the short EMA above the long average
+        for _ in 0..5 {
+            hop.add_rtt(Duration::from_millis(100));
+        }
+        assert_eq!(hop.trend(), Some(Trend::Rising));
+
+        // Dropping back down reverses the trend
+        for _ in 0..10 {
+            hop.add_rtt(Duration::from_millis(20));
+        }
+        assert_eq!(hop.trend(), Some(Trend::Falling));
+    }
+
+    #[test]
+    fn test_rtt_stddev() {
+        let mut hop = HopStats::new(1);
+        hop.add_rtt(Duration::from_millis(10));
+        assert_eq!(hop.rtt_stddev(), None);
+
+        for ms in [20, 30] {
+            hop.add_rtt(Duration::from_millis(ms));
+        }
+        let stddev = utils::time::duration_to_ms_f64(hop.rtt_stddev().unwrap());
+        assert!((stddev - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rtt_percentiles() {
+        let mut hop = HopStats::new(1);
+        assert_eq!(hop.rtt_percentile(50.0), None);
+
+        // 1..=100ms with one slow outlier the average hides
+        for ms in 1..=99 {
+            hop.add_rtt(Duration::from_millis(ms));
+        }
+        hop.add_rtt(Duration::from_millis(900));
+        assert_eq!(hop.rtt_percentile(50.0), Some(Duration::from_millis(51)));
+        assert_eq!(hop.rtt_percentile(90.0), Some(Duration::from_millis(90)));
+        assert_eq!(hop.rtt_percentile(99.0), Some(Duration::from_millis(99)));
+        assert_eq!(hop.rtt_percentile(100.0), Some(Duration::from_millis(900)));
+    }
+
+    #[test]
+    fn test_loss_streaks() {
+        let mut hop = HopStats::new(1);
+        for lost in [true, false, true, true, true, false, false, true, true] {
+            hop.increment_sent();
+            if lost {
+                hop.add_timeout();
+            } else {
+                hop.add_rtt(Duration::from_millis(10));
+            }
+        }
+        hop.increment_sent(); // Still in flight, doesn't end the streak
+        let streaks = hop.loss_streaks();
+        assert_eq!((streaks.count, streaks.longest, streaks.current), (3, 3, 2));
+    }
+
+    #[test]
+    fn test_rtt_histogram() {
+        let mut hop = HopStats::new(1);
+        assert_eq!(hop.rtt_histogram(4), None);
+
+        for ms in [10, 11, 12, 19, 50] {
+            hop.add_rtt(Duration::from_millis(ms));
+        }
+        let (min, max, counts) = hop.rtt_histogram(4).unwrap();
+        assert_eq!((min, max), (Duration::from_millis(10), Duration::from_millis(50)));
+        assert_eq!(counts, vec![4, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_jitter_trend() {
+        let mut hop = HopStats::new(1);
+        for i in 0..20 {
+            hop.add_rtt(Duration::from_millis(if i % 2 == 0 { 10 } else { 12 }));
+        }
+        assert_eq!(hop.jitter_trend(), Some(Trend::Stable));
+        for i in 0..6 {
+            hop.add_rtt(Duration::from_millis(if i % 2 == 0 { 10 } else { 30 }));
+        }
+        assert_eq!(hop.jitter_trend(), Some(Trend::Rising));
+    }
+
+    #[test]
+    fn test_alternate_overtakes_primary() {
+        let mut hop = HopStats::new(3);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+        hop.add_rtt_from_addr(a, Duration::from_millis(10));
+        hop.hostname = Some("a.example.net".to_string());
+        hop.add_rtt_from_addr(b, Duration::from_millis(20));
+        hop.set_hostname_for_addr(b, "b.example.net".to_string());
+        assert_eq!(hop.addr, Some(a));
+
+        hop.add_rtt_from_addr(b, Duration::from_millis(20));
+        assert_eq!(hop.addr, Some(b));
+        assert_eq!(hop.hostname.as_deref(), Some("b.example.net"));
+        assert!(!hop.alternate_paths.contains_key(&b));
+        let demoted = &hop.alternate_paths[&a];
+        assert_eq!(demoted.frequency, 1);
+        assert_eq!(demoted.hostname.as_deref(), Some("a.example.net"));
+        let total = hop.get_primary_path_percentage() + hop.get_path_percentage(demoted);
+        assert!((total - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_alternate_reply_resolves_pending() {
+        let mut hop = HopStats::new(3);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+        hop.increment_sent();
+        hop.add_rtt_from_addr(a, Duration::from_millis(10));
+        hop.increment_sent();
+        hop.add_rtt_from_addr(b, Duration::from_millis(20));
+        assert!(hop.alternate_paths.contains_key(&b));
+        assert!(!hop
+            .packet_history
+            .iter()
+            .any(|outcome| matches!(outcome, PacketOutcome::Pending)));
+        assert_eq!(hop.loss_percent, 0.0);
+    }
 }
@@ -0,0 +1,329 @@
+//! ICMP multi-part message extensions (RFC 4884)
+//!
+//! Time Exceeded and Destination Unreachable messages may append an extension
+//! structure after the quoted datagram. The ICMP header then carries the
+//! length of the quoted datagram so the two can be told apart. Routers use the
+//! extensions to report the MPLS label stack a probe arrived with (RFC 4950)
+//! and which interface handled it (RFC 5837).
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// Extension structure version defined by RFC 4884
+const EXTENSION_VERSION: u8 = 2;
+
+/// Quoted datagram length assumed by pre-RFC 4884 implementations
+const LEGACY_QUOTE_LEN: usize = 128;
+
+/// Extension object classes
+const CLASS_MPLS_LABEL_STACK: u8 = 1;
+const CLASS_INTERFACE_INFO: u8 = 2;
+
+/// Address family identifiers in interface information objects
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+
+/// Objects carried in an ICMP extension structure
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IcmpExtensions {
+    pub mpls: Vec<MplsLabel>,
+    pub interfaces: Vec<InterfaceInfo>,
+}
+
+impl IcmpExtensions {
+    pub fn is_empty(&self) -> bool {
+        self.mpls.is_empty() && self.interfaces.is_empty()
+    }
+}
+
+/// One entry of an MPLS label stack (RFC 4950)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MplsLabel {
+    pub label: u32,
+    pub traffic_class: u8,
+    pub bottom_of_stack: bool,
+    pub ttl: u8,
+}
+
+impl fmt::Display for MplsLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MPLS: Lbl {} TC {} S {} TTL {}",
+            self.label, self.traffic_class, self.bottom_of_stack as u8, self.ttl
+        )
+    }
+}
+
+/// Which interface an interface information object describes (RFC 5837)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InterfaceRole {
+    Incoming,
+    SubIp,
+    Outgoing,
+    NextHop,
+}
+
+impl InterfaceRole {
+    pub fn name(self) -> &'static str {
+        match self {
+            InterfaceRole::Incoming => "incoming",
+            InterfaceRole::SubIp => "sub-IP",
+            InterfaceRole::Outgoing => "outgoing",
+            InterfaceRole::NextHop => "next-hop",
+        }
+    }
+}
+
+/// Interface identification reported by the router (RFC 5837)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterfaceInfo {
+    pub role: InterfaceRole,
+    pub if_index: Option<u32>,
+    pub addr: Option<IpAddr>,
+    pub name: Option<String>,
+    pub mtu: Option<u32>,
+}
+
+impl fmt::Display for InterfaceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IF: {}", self.role.name())?;
+        if let Some(ref name) = self.name {
+            write!(f, " {}", name)?;
+        }
+        if let Some(addr) = self.addr {
+            write!(f, " {}", addr)?;
+        }
+        if let Some(if_index) = self.if_index {
+            write!(f, " ifIndex {}", if_index)?;
+        }
+        if let Some(mtu) = self.mtu {
+            write!(f, " MTU {}", mtu)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split the body of an ICMP error into the quoted datagram and its extensions
+///
+/// `icmp` starts at the ICMP header. The quoted datagram length sits in byte 5
+/// (32-bit words) for ICMP and byte 4 (64-bit words) for ICMPv6. Messages that
+/// leave it at zero but still append extensions after a 128-byte quote, as
+/// older MPLS routers do, are accepted when the structure checks out.
+pub fn split_icmp_error(icmp: &[u8], ipv6: bool) -> (&[u8], Option<IcmpExtensions>) {
+    let Some(body) = icmp.get(8..) else {
+        return (&[], None);
+    };
+    let quote_len = if ipv6 {
+        icmp[4] as usize * 8
+    } else {
+        icmp[5] as usize * 4
+    };
+
+    if quote_len == 0 {
+        if body.len() > LEGACY_QUOTE_LEN {
+            if let Some(extensions) = parse_extensions(&body[LEGACY_QUOTE_LEN..]) {
+                return (&body[..LEGACY_QUOTE_LEN], Some(extensions));
+            }
+        }
+        return (body, None);
+    }
+
+    match body.split_at_checked(quote_len) {
+        Some((quoted, rest)) if !rest.is_empty() => (quoted, parse_extensions(rest)),
+        Some((quoted, _)) => (quoted, None),
+        None => (body, None),
+    }
+}
+
+/// Parse an extension structure: a 4-byte header followed by objects
+fn parse_extensions(structure: &[u8]) -> Option<IcmpExtensions> {
+    if structure.len() < 4 || structure[0] >> 4 != EXTENSION_VERSION {
+        return None;
+    }
+    // A zero checksum means the sender did not compute one
+    if structure[2..4] != [0, 0] && internet_checksum(structure) != 0 {
+        return None;
+    }
+
+    let mut extensions = IcmpExtensions::default();
+    let mut objects = &structure[4..];
+    while objects.len() >= 4 {
+        let length = u16::from_be_bytes([objects[0], objects[1]]) as usize;
+        if length < 4 || length > objects.len() {
+            break;
+        }
+        let (class, c_type, payload) = (objects[2], objects[3], &objects[4..length]);
+        match class {
+            CLASS_MPLS_LABEL_STACK if c_type == 1 => {
+                extensions.mpls.extend(payload.chunks_exact(4).map(parse_mpls_entry));
+            }
+            CLASS_INTERFACE_INFO => {
+                extensions.interfaces.extend(parse_interface_info(c_type, payload));
+            }
+            _ => {}
+        }
+        objects = &objects[length..];
+    }
+
+    Some(extensions)
+}
+
+fn parse_mpls_entry(entry: &[u8]) -> MplsLabel {
+    let word = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+    MplsLabel {
+        label: word >> 12,
+        traffic_class: ((word >> 9) & 0x7) as u8,
+        bottom_of_stack: word & 0x100 != 0,
+        ttl: (word & 0xff) as u8,
+    }
+}
+
+/// Parse an interface information object; `c_type` flags which fields follow
+fn parse_interface_info(c_type: u8, mut payload: &[u8]) -> Option<InterfaceInfo> {
+    let role = match c_type >> 6 {
+        0 => InterfaceRole::Incoming,
+        1 => InterfaceRole::SubIp,
+        2 => InterfaceRole::Outgoing,
+        _ => InterfaceRole::NextHop,
+    };
+    let mut take = |len: usize| -> Option<&[u8]> {
+        let (field, rest) = payload.split_at_checked(len)?;
+        payload = rest;
+        Some(field)
+    };
+
+    let if_index = match c_type & 0x08 {
+        0 => None,
+        _ => Some(u32::from_be_bytes(take(4)?.try_into().ok()?)),
+    };
+    let addr = match c_type & 0x04 {
+        0 => None,
+        _ => {
+            let header = take(4)?;
+            match u16::from_be_bytes([header[0], header[1]]) {
+                AFI_IPV4 => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(take(4)?).ok()?))),
+                AFI_IPV6 => Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(take(16)?).ok()?))),
+                _ => return None,
+            }
+        }
+    };
+    let name = match c_type & 0x02 {
+        0 => None,
+        _ => {
+            // The length octet counts itself and the padding
+            let length = *take(1)?.first()? as usize;
+            let name = take(length.checked_sub(1)?)?;
+            let name = name.split(|&b| b == 0).next().unwrap_or_default();
+            Some(String::from_utf8_lossy(name).into_owned())
+        }
+    };
+    let mtu = match c_type & 0x01 {
+        0 => None,
+        _ => Some(u32::from_be_bytes(take(4)?.try_into().ok()?)),
+    };
+
+    Some(InterfaceInfo {
+        role,
+        if_index,
+        addr,
+        name,
+        mtu,
+    })
+}
+
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an extension structure with a valid checksum
+    fn structure(objects: &[(u8, u8, Vec<u8>)]) -> Vec<u8> {
+        let mut out = vec![EXTENSION_VERSION << 4, 0, 0, 0];
+        for (class, c_type, payload) in objects {
+            out.extend_from_slice(&((payload.len() + 4) as u16).to_be_bytes());
+            out.extend_from_slice(&[*class, *c_type]);
+            out.extend_from_slice(payload);
+        }
+        let checksum = internet_checksum(&out);
+        out[2..4].copy_from_slice(&checksum.to_be_bytes());
+        out
+    }
+
+    fn time_exceeded(quote_words: u8, quoted: &[u8], extensions: &[u8]) -> Vec<u8> {
+        let mut icmp = vec![11, 0, 0, 0, 0, quote_words, 0, 0];
+        icmp.extend_from_slice(quoted);
+        icmp.extend_from_slice(extensions);
+        icmp
+    }
+
+    #[test]
+    fn test_mpls_and_interface_objects() {
+        // Label 24001, TC 0, bottom of stack, TTL 1
+        let mpls = ((24001u32 << 12) | 0x100 | 1).to_be_bytes().to_vec();
+        // Incoming interface with ifIndex, IPv4 address, name and MTU
+        let mut info = 7u32.to_be_bytes().to_vec();
+        info.extend_from_slice(&[0, 1, 0, 0, 10, 0, 0, 1]);
+        info.extend_from_slice(&[8, b'g', b'e', b'-', b'0', b'/', b'1', 0]);
+        info.extend_from_slice(&1500u32.to_be_bytes());
+        let ext = structure(&[(CLASS_MPLS_LABEL_STACK, 1, mpls), (CLASS_INTERFACE_INFO, 0x0f, info)]);
+
+        let quoted = vec![0x45; LEGACY_QUOTE_LEN];
+        let icmp = time_exceeded(32, &quoted, &ext);
+        let (quote, extensions) = split_icmp_error(&icmp, false);
+        assert_eq!(quote.len(), LEGACY_QUOTE_LEN);
+
+        let extensions = extensions.unwrap();
+        assert_eq!(extensions.mpls[0].to_string(), "MPLS: Lbl 24001 TC 0 S 1 TTL 1");
+        assert_eq!(
+            extensions.interfaces[0],
+            InterfaceInfo {
+                role: InterfaceRole::Incoming,
+                if_index: Some(7),
+                addr: Some("10.0.0.1".parse().unwrap()),
+                name: Some("ge-0/1".to_string()),
+                mtu: Some(1500),
+            }
+        );
+        assert_eq!(extensions.interfaces[0].to_string(), "IF: incoming ge-0/1 10.0.0.1 ifIndex 7 MTU 1500");
+    }
+
+    #[test]
+    fn test_quote_without_extensions() {
+        // No length field and a short quote: everything is the quoted datagram
+        let icmp = time_exceeded(0, &[0x45; 28], &[]);
+        assert_eq!(split_icmp_error(&icmp, false), (&icmp[8..], None));
+
+        // Legacy layout: no length field, extensions after a 128-byte quote
+        let ext = structure(&[(CLASS_MPLS_LABEL_STACK, 1, vec![0, 0, 0x11, 0xff])]);
+        let icmp = time_exceeded(0, &[0x45; LEGACY_QUOTE_LEN], &ext);
+        let (quote, extensions) = split_icmp_error(&icmp, false);
+        assert_eq!(quote.len(), LEGACY_QUOTE_LEN);
+        assert_eq!(extensions.unwrap().mpls[0].label, 1);
+
+        // A corrupted structure is not mistaken for extensions
+        let mut bad = ext.clone();
+        bad[2] ^= 0xff;
+        let icmp = time_exceeded(0, &[0x45; LEGACY_QUOTE_LEN], &bad);
+        assert_eq!(split_icmp_error(&icmp, false).1, None);
+
+        // ICMPv6 counts the quote in 64-bit words
+        let mut icmp6 = vec![3, 0, 0, 0, 16, 0, 0, 0];
+        icmp6.extend_from_slice(&[0x60; 128]);
+        icmp6.extend_from_slice(&ext);
+        assert_eq!(split_icmp_error(&icmp6, true).0.len(), 128);
+    }
+}
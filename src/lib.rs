@@ -1,13 +1,39 @@
+pub mod adaptive;
+pub mod alerts;
 pub mod args;
+pub mod baseline;
+pub mod builder;
+pub mod config;
+pub mod fault;
+pub mod geoip;
+pub mod graphite;
+pub mod hooks;
 pub mod hop_stats;
+pub mod icmp_ext;
+pub mod merge;
+pub mod netns;
+pub mod output;
+pub mod persist;
+pub mod privsep;
 pub mod probe;
+pub mod probe_log;
+pub mod raw;
+pub mod rdns;
+pub mod record;
 pub mod report;
+pub mod route;
 pub mod session;
+pub mod split;
+pub mod stats;
+pub mod stream;
+pub mod systemd;
 pub mod ui;
 pub mod utils;
+pub mod whois;
 
 // Re-export commonly used types
-pub use args::{Args, SparklineScale};
+pub use args::{Args, RttUnit, SparklineScale};
+pub use builder::{HopUpdate, HopUpdates, MtrSessionBuilder, TraceHandle};
 pub use hop_stats::HopStats;
 pub use session::MtrSession;
 
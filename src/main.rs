@@ -1,10 +1,17 @@
 use clap::Parser;
-use mtr_ng::{report::run_report, ui::run_interactive, Args, MtrSession, Result};
+use mtr_ng::{
+    privsep,
+    probe::ProbeEngine,
+    report::{run_dual_stack_report, run_merge, run_report},
+    ui::{run_dual_stack_interactive, run_interactive},
+    Args, MtrSession, Result,
+};
 use tracing::info;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+fn main() -> Result<()> {
+    let mut args = Args::parse();
+    // Every output format other than the TUI is a report
+    args.report |= args.xml || args.jsonl.is_some() || args.raw || args.split || !args.merge.is_empty();
 
     // Configure logging based on mode
     if args.report {
@@ -36,7 +43,45 @@ async fn main() -> Result<()> {
         info!("Target: {}", args.target);
     }
 
-    let session = MtrSession::new(args).await?;
+    if !args.merge.is_empty() {
+        // Nothing is probed, so no sockets: the privileges go straight away
+        if !args.keep_privileges {
+            privsep::drop_privileges()?;
+        }
+        return tokio::runtime::Runtime::new()?.block_on(run_merge(args));
+    }
+
+    // Sockets first, while privileged and single-threaded; then everything
+    // else runs as the invoking user
+    let engines = privsep::open_probe_engines(&args);
+    if !args.keep_privileges {
+        privsep::drop_privileges()?;
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(run(args, engines))
+}
+
+async fn run(args: Args, engines: Vec<Result<ProbeEngine>>) -> Result<()> {
+    if args.dual_stack {
+        let (mut ipv4, mut ipv6) = MtrSession::new_dual_stack(args).await?;
+        let mut engines = engines.into_iter();
+        if let Some(engine) = engines.next() {
+            ipv4.set_probe_engine(engine);
+        }
+        if let Some(engine) = engines.next() {
+            ipv6.set_probe_engine(engine);
+        }
+        return if ipv4.args.report {
+            run_dual_stack_report(ipv4, ipv6).await
+        } else {
+            run_dual_stack_interactive(ipv4, ipv6).await
+        };
+    }
+
+    let mut session = MtrSession::new(args).await?;
+    if let Some(engine) = engines.into_iter().next() {
+        session.set_probe_engine(engine);
+    }
 
     if session.args.report {
         run_report(session).await
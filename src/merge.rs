@@ -0,0 +1,154 @@
+//! Combining saved sessions (--merge)
+//!
+//! Several `--save` files of one target, say the mornings' and the
+//! evenings', become one set of statistics as if a single run had sent all
+//! their probes: counts add up, loss is weighted by probes sent and the
+//! average by replies, and the RTT samples of every file are pooled so the
+//! percentiles and deviation describe them all. Everything that says how a
+//! hop is now (its address, last RTT, EMA) comes from the newest file.
+
+use crate::{
+    hop_stats::AlternatePath,
+    persist::SavedSession,
+    route::{RouteChange, RouteHistory},
+    HopStats, Result,
+};
+use std::{collections::BTreeMap, time::Duration};
+
+/// Combine saved sessions of one target into one
+pub fn merge(mut sessions: Vec<SavedSession>) -> Result<SavedSession> {
+    let Some(first) = sessions.first() else {
+        anyhow::bail!("No sessions to merge");
+    };
+    if let Some(other) = sessions.iter().find(|session| session.target != first.target) {
+        anyhow::bail!("Cannot merge sessions for {} and {}", first.target, other.target);
+    }
+    sessions.sort_by_key(|session| session.saved_at);
+
+    let mut by_ttl: BTreeMap<u8, Vec<&HopStats>> = BTreeMap::new();
+    for hop in sessions.iter().flat_map(|session| &session.hops) {
+        by_ttl.entry(hop.hop).or_default().push(hop);
+    }
+    let hops: Vec<HopStats> = by_ttl.into_values().map(|hops| merge_hops(&hops)).collect();
+
+    let mut changes: Vec<RouteChange> = sessions
+        .iter()
+        .flat_map(|session| session.route_history.events().cloned())
+        .collect();
+    changes.sort_by_key(|change| change.timestamp);
+    let mut route_history = RouteHistory::default();
+    for change in changes {
+        route_history.record(change);
+    }
+
+    let latest = sessions.last().expect("checked above");
+    let mut merged = SavedSession::new(&latest.target, latest.target_addr, &hops, &route_history);
+    merged.saved_at = latest.saved_at;
+    Ok(merged)
+}
+
+/// One hop's statistics from several sessions, oldest first
+fn merge_hops(hops: &[&HopStats]) -> HopStats {
+    let latest = hops[hops.len() - 1];
+    let mut merged = latest.clone();
+
+    merged.sent = hops.iter().map(|hop| hop.sent).sum();
+    merged.received = hops.iter().map(|hop| hop.received).sum();
+    merged.update_loss_percent();
+    merged.rounds_completed = hops.iter().map(|hop| hop.rounds_completed).sum();
+    merged.rounds_answered = hops.iter().map(|hop| hop.rounds_answered).sum();
+    merged.route_changes = hops.iter().map(|hop| hop.route_changes).sum();
+    merged.timing_anomalies = hops.iter().map(|hop| hop.timing_anomalies).sum();
+
+    merged.best_rtt = hops.iter().filter_map(|hop| hop.best_rtt).min();
+    merged.worst_rtt = hops.iter().filter_map(|hop| hop.worst_rtt).max();
+    merged.avg_rtt = weighted_mean(hops.iter().map(|hop| (hop.avg_rtt, hop.received)));
+    merged.jitter_avg = weighted_mean(hops.iter().map(|hop| (hop.jitter_avg, hop.jitters.len())));
+    merged.last_rtt = hops.iter().rev().find_map(|hop| hop.last_rtt);
+    if let Some(answered) = hops.iter().rev().find(|hop| hop.addr.is_some()) {
+        merged.addr = answered.addr;
+        merged.hostname = answered.hostname.clone();
+    }
+
+    merged.rtts = hops.iter().flat_map(|hop| hop.rtts.iter().copied()).collect();
+    merged.jitters = hops.iter().flat_map(|hop| hop.jitters.iter().copied()).collect();
+    merged.precise_rtts_ns = hops.iter().flat_map(|hop| hop.precise_rtts_ns.iter().copied()).collect();
+    merged.packet_history = hops.iter().flat_map(|hop| hop.packet_history.iter().cloned()).collect();
+
+    merged.path_frequency.clear();
+    merged.alternate_paths.clear();
+    for hop in hops {
+        for (addr, count) in &hop.path_frequency {
+            *merged.path_frequency.entry(*addr).or_insert(0) += count;
+        }
+        for (addr, path) in &hop.alternate_paths {
+            merged
+                .alternate_paths
+                .entry(*addr)
+                .and_modify(|merged| merge_path(merged, path))
+                .or_insert_with(|| path.clone());
+        }
+    }
+    merged
+}
+
+/// Fold a newer session's view of an alternate path into the merged one
+fn merge_path(merged: &mut AlternatePath, newer: &AlternatePath) {
+    merged.avg_rtt = weighted_mean(
+        [(merged.avg_rtt, merged.rtt_samples as usize), (newer.avg_rtt, newer.rtt_samples as usize)].into_iter(),
+    );
+    merged.frequency += newer.frequency;
+    merged.rtt_samples += newer.rtt_samples;
+    merged.last_rtt = newer.last_rtt.or(merged.last_rtt);
+    merged.hostname = newer.hostname.clone().or(merged.hostname.take());
+}
+
+/// Mean of per-session means, each counting for its number of samples
+fn weighted_mean(parts: impl Iterator<Item = (Option<Duration>, usize)>) -> Option<Duration> {
+    let (total, samples) = parts
+        .filter_map(|(mean, samples)| mean.map(|mean| (mean.as_secs_f64() * samples as f64, samples)))
+        .fold((0.0, 0), |(total, count), (sum, samples)| (total + sum, count + samples));
+    (samples > 0).then(|| Duration::from_secs_f64(total / samples as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(target: &str, rtts_ms: &[u64], lost: usize) -> SavedSession {
+        let mut hop = HopStats::new(1);
+        hop.addr = Some("192.0.2.1".parse().unwrap());
+        for &ms in rtts_ms {
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_millis(ms));
+        }
+        for _ in 0..lost {
+            hop.increment_sent();
+            hop.add_timeout();
+        }
+        SavedSession::new(target, "192.0.2.9".parse().unwrap(), &[hop], &RouteHistory::default())
+    }
+
+    #[test]
+    fn test_merge_weights_and_pools() {
+        let quiet = session("example.com", &[10, 10, 10], 1);
+        let busy = session("example.com", &[40, 40, 40, 40, 40, 40], 0);
+        let merged = merge(vec![quiet, busy]).unwrap();
+
+        let hop = &merged.hops[0];
+        assert_eq!((hop.sent, hop.received), (10, 9));
+        assert!((hop.loss_percent - 10.0).abs() < 1e-9); // Not the 12.5% mean of 25% and 0%
+        assert_eq!(hop.avg_rtt, Some(Duration::from_millis(30)));
+        assert_eq!(hop.best_rtt, Some(Duration::from_millis(10)));
+        assert_eq!(hop.rtts.len(), 9);
+        assert_eq!(hop.rtt_percentile(50.0), Some(Duration::from_millis(40)));
+        assert_eq!(hop.packet_history.len(), 10);
+    }
+
+    #[test]
+    fn test_merge_refuses_other_targets() {
+        let sessions = vec![session("example.com", &[10], 0), session("example.net", &[10], 0)];
+        assert!(merge(sessions).is_err());
+        assert!(merge(vec![]).is_err());
+    }
+}
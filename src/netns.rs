@@ -0,0 +1,84 @@
+//! Network namespace support (Linux)
+//!
+//! `setns(2)` only switches the namespace of the calling thread, and the tokio
+//! worker threads are already running by the time we create sockets. Socket
+//! creation therefore runs on a dedicated thread that joins the namespace
+//! first; the resulting file descriptors stay bound to that namespace no matter
+//! which thread uses them afterwards.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+
+/// Directory where `ip netns add` bind-mounts named namespaces
+pub const NETNS_RUN_DIR: &str = "/run/netns";
+
+/// Resolve a namespace name to its bind-mount path
+///
+/// Anything containing a slash is already a path, such as a container's
+/// `/proc/<pid>/ns/net`.
+pub fn namespace_path(name: &str) -> PathBuf {
+    if name.contains('/') {
+        PathBuf::from(name)
+    } else {
+        PathBuf::from(NETNS_RUN_DIR).join(name)
+    }
+}
+
+/// Run `f` on a thread that has entered the given network namespace
+#[cfg(target_os = "linux")]
+pub fn run_in_namespace<T, F>(name: &str, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    use std::os::fd::AsRawFd;
+
+    let path = namespace_path(name);
+    let ns_file = std::fs::File::open(&path)
+        .with_context(|| format!("Failed to open network namespace '{}'", path.display()))?;
+
+    let ns_path = path.display().to_string();
+    std::thread::spawn(move || {
+        // SAFETY: setns only reads the descriptor, which stays open for the call
+        let rc = unsafe { libc::setns(ns_file.as_raw_fd(), libc::CLONE_NEWNET) };
+        if rc != 0 {
+            return Err(anyhow!(
+                "Failed to enter network namespace '{}': {} (requires CAP_SYS_ADMIN)",
+                ns_path,
+                std::io::Error::last_os_error()
+            ));
+        }
+        tracing::info!("Entered network namespace {}", ns_path);
+        f()
+    })
+    .join()
+    .map_err(|_| anyhow!("Network namespace worker thread panicked"))?
+}
+
+/// Network namespaces are a Linux-only feature
+#[cfg(not(target_os = "linux"))]
+pub fn run_in_namespace<T, F>(_name: &str, _f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    Err(anyhow!("--netns is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_path() {
+        assert_eq!(namespace_path("blue"), PathBuf::from("/run/netns/blue"));
+        assert_eq!(namespace_path("/proc/42/ns/net"), PathBuf::from("/proc/42/ns/net"));
+        assert_eq!(namespace_path("./ns"), PathBuf::from("./ns"));
+    }
+
+    #[test]
+    fn test_missing_namespace_is_an_error() {
+        let result = run_in_namespace("mtr-ng-does-not-exist", || Ok(()));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Structured output schema
+//!
+//! The types here are what `--jsonl` and `--stream-socket` write, one JSON
+//! object per line, and what the TUI's JSON snapshots hold. Every line
+//! carries `schema_version` next to its `event` tag, as does every snapshot
+//! next to its `sessions`, and within a version the structure only grows:
+//!
+//! - new fields and new event types may be added, so consumers should skip
+//!   what they don't know;
+//! - fields the schema marks optional may be left out, which reads as null;
+//! - removing or renaming a field, or changing its type or meaning, bumps
+//!   [`SCHEMA_VERSION`].
+//!
+//! Times are RFC 3339 in UTC, RTTs are floating-point milliseconds and
+//! addresses are plain IPv4 or IPv6 strings. Every type deserializes as well,
+//! so a Rust consumer can read the lines back with [`Event::from_ndjson`].
+
+use crate::{route::RouteChange, utils, HopStats};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Version of the structure described here, written as `schema_version`
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Per-hop statistics carried in round events
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HopSnapshot {
+    pub hop: u8,
+    pub addr: Option<IpAddr>,
+    pub hostname: Option<String>,
+    pub sent: usize,
+    pub received: usize,
+    pub loss_percent: f64,
+    pub last_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub best_ms: Option<f64>,
+    pub worst_ms: Option<f64>,
+    pub jitter_ms: Option<f64>,
+    /// Share of replies from `addr` when others answered too
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_percent: Option<f64>,
+    /// The other addresses that answered for this hop (ECMP), most frequent first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_paths: Vec<PathSnapshot>,
+}
+
+/// An alternate responder of a hop
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PathSnapshot {
+    pub addr: IpAddr,
+    pub hostname: Option<String>,
+    pub replies: usize,
+    pub percent: f64,
+    pub last_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+}
+
+impl From<&HopStats> for HopSnapshot {
+    fn from(hop: &HopStats) -> Self {
+        let ms = |rtt: Option<Duration>| rtt.map(utils::time::duration_to_ms_f64);
+        Self {
+            hop: hop.hop,
+            addr: hop.addr,
+            hostname: hop.hostname.clone(),
+            sent: hop.sent,
+            received: hop.received,
+            loss_percent: hop.loss_percent,
+            last_ms: ms(hop.last_rtt),
+            avg_ms: ms(hop.avg_rtt),
+            best_ms: ms(hop.best_rtt),
+            worst_ms: ms(hop.worst_rtt),
+            jitter_ms: ms(hop.jitter_avg),
+            path_percent: hop.has_multiple_paths().then(|| hop.get_primary_path_percentage()),
+            alternate_paths: hop
+                .get_alternate_paths()
+                .into_iter()
+                .map(|path| PathSnapshot {
+                    addr: path.addr,
+                    hostname: path.hostname.clone(),
+                    replies: path.frequency,
+                    percent: hop.get_path_percentage(path),
+                    last_ms: ms(path.last_rtt),
+                    avg_ms: ms(path.avg_rtt),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A single line of the event stream
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Start {
+        timestamp: DateTime<Utc>,
+        target: String,
+        target_addr: IpAddr,
+    },
+    Round {
+        timestamp: DateTime<Utc>,
+        round: usize,
+        hops: Vec<HopSnapshot>,
+    },
+    Probe {
+        timestamp: DateTime<Utc>,
+        target_addr: IpAddr, // Tells the IPv4 and IPv6 traces of a dual-stack run apart
+        hop: u8,
+        addr: Option<IpAddr>,
+        rtt_ms: Option<f64>, // None when the probe timed out
+    },
+    RouteChanged {
+        timestamp: DateTime<Utc>,
+        hop: u8,
+        from: IpAddr,
+        to: IpAddr,
+    },
+    ConfigReloaded {
+        timestamp: DateTime<Utc>,
+        path: PathBuf,
+    },
+    Stop {
+        timestamp: DateTime<Utc>,
+    },
+}
+
+impl Event {
+    pub fn start(target: &str, target_addr: IpAddr) -> Self {
+        Event::Start {
+            timestamp: Utc::now(),
+            target: target.to_string(),
+            target_addr,
+        }
+    }
+
+    /// Snapshot of every probed hop after a completed round
+    pub fn round(round: usize, hops: &[HopStats]) -> Self {
+        Event::Round {
+            timestamp: Utc::now(),
+            round,
+            hops: hops
+                .iter()
+                .filter(|hop| hop.sent > 0)
+                .map(HopSnapshot::from)
+                .collect(),
+        }
+    }
+
+    /// One reply, or a timeout when `rtt` is None, of a probe towards `target_addr`
+    pub fn probe(target_addr: IpAddr, hop: u8, addr: Option<IpAddr>, rtt: Option<Duration>) -> Self {
+        Event::Probe {
+            timestamp: Utc::now(),
+            target_addr,
+            hop,
+            addr,
+            rtt_ms: rtt.map(utils::time::duration_to_ms_f64),
+        }
+    }
+
+    /// A different address answering at a hop than before
+    pub fn route_changed(change: &RouteChange) -> Self {
+        Event::RouteChanged {
+            timestamp: change.timestamp,
+            hop: change.hop,
+            from: change.from,
+            to: change.to,
+        }
+    }
+
+    pub fn config_reloaded(path: &Path) -> Self {
+        Event::ConfigReloaded {
+            timestamp: Utc::now(),
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn stop() -> Self {
+        Event::Stop {
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Serialize as a single newline-terminated JSON line
+    pub fn to_ndjson(&self) -> Result<String> {
+        let mut line = serde_json::to_string(&Versioned {
+            schema_version: SCHEMA_VERSION,
+            content: self,
+        })?;
+        line.push('\n');
+        Ok(line)
+    }
+
+    /// Parse one line written by [`Event::to_ndjson`] of this schema version
+    pub fn from_ndjson(line: &str) -> Result<Self> {
+        let versioned: Versioned<Event> = serde_json::from_str(line)?;
+        if versioned.schema_version != SCHEMA_VERSION {
+            anyhow::bail!(
+                "Event has schema version {}, this version of mtr-ng reads {}",
+                versioned.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(versioned.content)
+    }
+}
+
+/// The statistics of every session at one moment, as the TUI's JSON snapshots (`w`) save them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Snapshot {
+    pub sessions: Vec<SessionSnapshot>,
+}
+
+/// One session of a snapshot, its hops in the shape of round events
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionSnapshot {
+    pub target: String,
+    pub target_addr: IpAddr,
+    pub timestamp: DateTime<Utc>,
+    pub hops: Vec<HopSnapshot>,
+}
+
+impl Snapshot {
+    /// Serialize as a pretty-printed JSON document with `schema_version` beside the sessions
+    pub fn to_json(&self) -> Result<String> {
+        let document = serde_json::to_string_pretty(&Versioned {
+            schema_version: SCHEMA_VERSION,
+            content: self,
+        })?;
+        Ok(document + "\n")
+    }
+
+    /// Parse a document written by [`Snapshot::to_json`] of this schema version
+    pub fn from_json(document: &str) -> Result<Self> {
+        let versioned: Versioned<Snapshot> = serde_json::from_str(document)?;
+        if versioned.schema_version != SCHEMA_VERSION {
+            anyhow::bail!(
+                "Snapshot has schema version {}, this version of mtr-ng reads {}",
+                versioned.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(versioned.content)
+    }
+}
+
+/// An event or snapshot with the schema version alongside its fields
+#[derive(Serialize, Deserialize)]
+struct Versioned<T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    content: T,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_event_serialization() {
+        let mut hops = vec![HopStats::new(1), HopStats::new(2)];
+        hops[0].increment_sent();
+        hops[0].add_rtt(Duration::from_millis(12));
+
+        let line = Event::round(3, &hops).to_ndjson().unwrap();
+        assert!(line.ends_with('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(value["event"], "round");
+        assert_eq!(value["round"], 3);
+        assert_eq!(value["hops"].as_array().unwrap().len(), 1); // Unprobed hop skipped
+        assert_eq!(value["hops"][0]["last_ms"], 12.0);
+        assert!(value["hops"][0].get("alternate_paths").is_none()); // Left out with a single responder
+    }
+
+    #[test]
+    fn test_alternate_paths_in_round_event() {
+        let (primary, other) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        let mut hop = HopStats::new(1);
+        for (addr, ms) in [(primary, 10), (primary, 12), (primary, 11), (other, 20)] {
+            hop.increment_sent();
+            hop.add_rtt_from_addr(addr, Duration::from_millis(ms));
+        }
+
+        let value = serde_json::to_value(Event::round(1, &[hop])).unwrap();
+        let snapshot = &value["hops"][0];
+        assert_eq!(snapshot["addr"], "192.0.2.1");
+        assert_eq!(snapshot["path_percent"], 75.0);
+        assert_eq!(snapshot["alternate_paths"][0]["addr"], "192.0.2.2");
+        assert_eq!(snapshot["alternate_paths"][0]["replies"], 1);
+        assert_eq!(snapshot["alternate_paths"][0]["percent"], 25.0);
+        assert_eq!(snapshot["alternate_paths"][0]["avg_ms"], 20.0);
+    }
+
+    #[test]
+    fn test_events_round_trip() {
+        let (primary, other) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        let mut hops = vec![HopStats::new(1), HopStats::new(2)];
+        hops[0].hostname = Some("router.example.net".to_string());
+        for (addr, ms) in [(primary, 10), (other, 20), (primary, 11)] {
+            hops[0].increment_sent();
+            hops[0].add_rtt_from_addr(addr, Duration::from_millis(ms));
+        }
+        hops[1].increment_sent();
+        hops[1].add_timeout();
+        let change = RouteChange::new(1, primary, other);
+
+        let events = [
+            Event::start("example.com", "192.0.2.9".parse().unwrap()),
+            Event::round(1, &hops),
+            Event::probe("192.0.2.9".parse().unwrap(), 1, Some(primary), Some(Duration::from_micros(10250))),
+            Event::probe("192.0.2.9".parse().unwrap(), 2, None, None),
+            Event::route_changed(&change),
+            Event::config_reloaded(Path::new("/etc/mtr-ng.json")),
+            Event::stop(),
+        ];
+        for event in events {
+            let line = event.to_ndjson().unwrap();
+            assert_eq!(Event::from_ndjson(&line).unwrap(), event, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_schema_compatibility() {
+        // Fields added later are skipped and optional ones may be missing
+        let line = r#"{"schema_version":1,"event":"round","timestamp":"2026-03-01T12:00:00Z","round":2,"new":true,
+            "hops":[{"hop":1,"addr":null,"hostname":null,"sent":2,"received":0,"loss_percent":100.0}]}"#;
+        let Event::Round { round, hops, .. } = Event::from_ndjson(line).unwrap() else {
+            panic!("not a round event");
+        };
+        assert_eq!(round, 2);
+        assert_eq!(hops[0].last_ms, None);
+        assert!(hops[0].alternate_paths.is_empty());
+
+        let future = r#"{"schema_version":2,"event":"stop","timestamp":"2026-03-01T12:00:00Z"}"#;
+        assert!(Event::from_ndjson(future).is_err());
+    }
+}
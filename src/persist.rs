@@ -0,0 +1,115 @@
+//! Saved session state
+//!
+//! `--save` writes every probed hop's statistics to a JSON file when the
+//! session ends, sample and packet histories included. `--load` puts them back
+//! before the first probe, so a long-running measurement can carry on after a
+//! restart, or be picked up on another machine.
+
+use crate::{hop_stats::PacketOutcome, route::RouteHistory, utils, HopStats};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, path::Path};
+
+/// Bumped whenever a change to `HopStats` makes older files unreadable
+const FORMAT_VERSION: u32 = 1;
+
+/// Everything needed to resume a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub version: u32,
+    pub target: String,
+    pub target_addr: IpAddr,
+    pub saved_at: DateTime<Utc>,
+    pub hops: Vec<HopStats>,
+    pub route_history: RouteHistory,
+}
+
+impl SavedSession {
+    /// Capture the hops that have been probed so far
+    pub fn new(target: &str, target_addr: IpAddr, hops: &[HopStats], route_history: &RouteHistory) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            target: target.to_string(),
+            target_addr,
+            saved_at: Utc::now(),
+            hops: hops.iter().filter(|hop| hop.sent > 0).cloned().collect(),
+            route_history: route_history.clone(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let saved: Self = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid session file {}", path.display()))?;
+        if saved.version != FORMAT_VERSION {
+            anyhow::bail!(
+                "{} was saved in format version {}, this version of mtr-ng reads {}",
+                path.display(),
+                saved.version,
+                FORMAT_VERSION
+            );
+        }
+        Ok(saved)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        utils::paths::write_atomic(path, &serde_json::to_string(self)?)
+    }
+
+    /// Put the saved statistics into a session's hops, matching them by TTL
+    ///
+    /// Probes that were still in flight when the file was saved never get an
+    /// answer now, so they are counted as lost.
+    pub fn restore_into(self, hops: &mut [HopStats]) {
+        for mut saved in self.hops {
+            let Some(hop) = hops.iter_mut().find(|hop| hop.hop == saved.hop) else {
+                continue;
+            };
+            for outcome in saved.packet_history.iter_mut() {
+                if matches!(outcome, PacketOutcome::Pending) {
+                    *outcome = PacketOutcome::Lost;
+                }
+            }
+            saved.ema_alpha = hop.ema_alpha;
+            *hop = saved;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_save_and_restore() {
+        let router: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut hops: Vec<HopStats> = (1..=3).map(HopStats::new).collect();
+        for rtt in [10, 12, 11] {
+            hops[1].increment_sent();
+            hops[1].add_rtt_from_addr(router, Duration::from_millis(rtt));
+        }
+        hops[1].increment_sent(); // Still in flight when saved
+
+        let path = std::env::temp_dir().join(format!("mtr-ng-session-{}.json", std::process::id()));
+        SavedSession::new("example.com", router, &hops, &RouteHistory::default())
+            .save(&path)
+            .unwrap();
+        let saved = SavedSession::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.target, "example.com");
+        assert_eq!(saved.hops.len(), 1); // Only probed hops are kept
+
+        let mut restored: Vec<HopStats> = (1..=3).map(HopStats::new).collect();
+        saved.restore_into(&mut restored);
+        let hop = &restored[1];
+        assert_eq!((hop.sent, hop.received), (4, 3));
+        assert_eq!(hop.addr, Some(router));
+        assert_eq!(hop.rtts, hops[1].rtts);
+        assert_eq!(hop.best_rtt, Some(Duration::from_millis(10)));
+        assert!(matches!(hop.packet_history.back(), Some(PacketOutcome::Lost)));
+        assert_eq!(restored[0].sent, 0);
+    }
+}
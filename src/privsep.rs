@@ -0,0 +1,138 @@
+//! Privilege separation
+//!
+//! Probe sockets are the only thing mtr-ng needs root (or CAP_NET_RAW) for,
+//! and the probe engine opens every socket it will use up front. `main`
+//! therefore opens the engines first, then drops to the user who invoked
+//! sudo and clears any file capabilities, so the resolver, the packet
+//! parsers and the TUI never run privileged.
+//!
+//! Both steps happen before the async runtime starts, while the main thread
+//! is the only one: credentials changed later would not cover threads that
+//! already exist.
+
+use crate::{probe::ProbeEngine, Args, MtrSession};
+use anyhow::{anyhow, Result};
+use tracing::{debug, info};
+
+/// Open the probe engines the run will need while still privileged
+///
+/// One per session, the IPv4 one first with --dual-stack. A failure is kept
+/// for its session to report, with the usual error (or simulation fallback):
+/// trying again once privileges are gone would only fail with EPERM.
+pub fn open_probe_engines(args: &Args) -> Vec<Result<ProbeEngine>> {
+    if args.simulate || args.force_simulate {
+        return Vec::new();
+    }
+    let count = if args.dual_stack { 2 } else { 1 };
+    (0..count)
+        .map(|_| {
+            MtrSession::open_probe_engine(args)
+                .inspect_err(|e| debug!("Could not open probe sockets up front: {:#}", e))
+        })
+        .collect()
+}
+
+/// Give up root and capabilities once the probe sockets are open
+///
+/// Under sudo (or a setuid install) the process becomes the invoking user.
+/// Root with no one to drop to, such as a system service, stays root.
+#[cfg(unix)]
+pub fn drop_privileges() -> Result<()> {
+    // SAFETY: these calls only read the process credentials
+    let (uid, euid, gid) = unsafe { (libc::getuid(), libc::geteuid(), libc::getgid()) };
+
+    if euid == 0 {
+        let sudo_uid = std::env::var("SUDO_UID").ok();
+        let sudo_gid = std::env::var("SUDO_GID").ok();
+        let Some((uid, gid)) = invoking_user(uid, gid, sudo_uid.as_deref(), sudo_gid.as_deref()) else {
+            debug!("Running as root with no invoking user to drop to");
+            return Ok(());
+        };
+        // Groups first: once the uid changes they can no longer be changed
+        // SAFETY: plain credential syscalls; setgroups gets an empty list
+        let failed = unsafe {
+            libc::setgroups(0, std::ptr::null()) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0
+        };
+        // SAFETY: as above
+        if failed || unsafe { libc::setuid(0) } == 0 {
+            return Err(anyhow!(
+                "Failed to drop privileges to uid {}: {}",
+                uid,
+                std::io::Error::last_os_error()
+            ));
+        }
+        info!("Probe sockets open, dropped privileges to uid {} gid {}", uid, gid);
+        // Leaving root cleared every capability along with it
+        return Ok(());
+    }
+
+    #[cfg(target_os = "linux")]
+    clear_capabilities()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges() -> Result<()> {
+    Ok(())
+}
+
+/// The unprivileged user to become, if there is one
+///
+/// sudo records the caller in `SUDO_UID`/`SUDO_GID`; a setuid binary keeps
+/// it as the real uid.
+fn invoking_user(uid: u32, gid: u32, sudo_uid: Option<&str>, sudo_gid: Option<&str>) -> Option<(u32, u32)> {
+    if uid != 0 {
+        return Some((uid, gid));
+    }
+    let uid = sudo_uid?.parse().ok().filter(|&uid| uid != 0)?;
+    let gid = sudo_gid.and_then(|gid| gid.parse().ok()).unwrap_or(uid);
+    Some((uid, gid))
+}
+
+/// Clear the effective, permitted and inheritable capability sets
+///
+/// A binary installed with `setcap cap_net_raw+ep` runs as the user but
+/// keeps CAP_NET_RAW until told otherwise.
+#[cfg(target_os = "linux")]
+fn clear_capabilities() -> Result<()> {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+    let header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    let data = [CapData { effective: 0, permitted: 0, inheritable: 0 }; 2];
+    // SAFETY: version 3 takes one header and two data structs, both live for the call
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        return Err(anyhow!("Failed to clear capabilities: {}", std::io::Error::last_os_error()));
+    }
+    debug!("Probe sockets open, capabilities cleared");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoking_user() {
+        // sudo from uid 1000
+        assert_eq!(invoking_user(0, 0, Some("1000"), Some("100")), Some((1000, 100)));
+        assert_eq!(invoking_user(0, 0, Some("1000"), None), Some((1000, 1000)));
+        // A setuid binary run by uid 1000
+        assert_eq!(invoking_user(1000, 1000, None, None), Some((1000, 1000)));
+        // Root itself, directly or through sudo, has no one to drop to
+        assert_eq!(invoking_user(0, 0, None, None), None);
+        assert_eq!(invoking_user(0, 0, Some("0"), Some("0")), None);
+        assert_eq!(invoking_user(0, 0, Some("bogus"), None), None);
+    }
+}
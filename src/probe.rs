@@ -5,32 +5,197 @@
 
 use std::{
     collections::HashMap,
+    fmt,
+    future::Future,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Result};
-use socket2::{Domain, Protocol, Socket, Type};
-use crate::args::ProbeProtocol;
-use tokio::io::Interest;
-use tokio::net::UdpSocket;
-use tokio::time::timeout;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use crate::args::{Args, ProbeProtocol};
+use crate::icmp_ext::{self, IcmpExtensions};
+use tokio::io::{unix::AsyncFd, Interest};
+
+mod packet;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+
+use packet::{IcmpMessage, Ipv4Packet, Ipv6Packet, QuotedTcp, TcpHeader, UdpHeader};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use uring::UringProbeEngine;
 
 /// Maximum MTU size for network packets
 const MAX_MTU: usize = 1500;
 
+/// Datagrams read per receive call (one `recvmmsg` on Linux)
+const RECV_BATCH_SIZE: usize = 32;
+
+/// Kernel receive timestamps older than this are treated as bogus (clock steps)
+const MAX_TIMESTAMP_AGE: Duration = Duration::from_secs(10);
+
 /// Starting sequence number for probe packets
 const INITIAL_SEQUENCE: u16 = 32768;
 
+/// Last sequence number before wrapping back to `INITIAL_SEQUENCE`
+const MAX_SEQUENCE: u16 = 60999;
+
+/// First destination port for UDP probes, as in classic traceroute
+const UDP_BASE_PORT: u16 = 33434;
+
+/// UDP payload size (64-byte packets including IP and UDP headers)
+const UDP_PAYLOAD_SIZE: usize = 36;
+
+/// Bytes of per-engine cookie leading every ICMP echo payload
+const COOKIE_LEN: usize = 4;
+
+/// Header length in front of a probe's payload
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const ICMP_HEADER_LEN: usize = 8;
+const UDP_HEADER_LEN: usize = 8;
+
+/// Destination port for TCP SYN probes
+const TCP_DEFAULT_PORT: u16 = 80;
+
+/// Destination port for QUIC probes (HTTP/3)
+const QUIC_DEFAULT_PORT: u16 = 443;
+
+/// QUIC Initial datagrams are padded to the protocol's minimum size
+const QUIC_INITIAL_LEN: usize = 1200;
+
+/// Reserved version (RFC 9000, section 15) no server implements, so any QUIC
+/// endpoint must answer with Version Negotiation
+const QUIC_PROBE_VERSION: u32 = 0x1a2a_3a4a;
+
+/// Length of the connection IDs in our QUIC probes
+const QUIC_CID_LEN: usize = 8;
+
+/// Destination port and default name for DNS probes
+const DNS_DEFAULT_PORT: u16 = 53;
+const DNS_DEFAULT_QUERY: &str = "example.com";
+
+/// DNS wire format constants
+const DNS_HEADER_LEN: usize = 12;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_OPT: u16 = 41;
+const DNS_CLASS_IN: u16 = 1;
+const EDNS_UDP_SIZE: u16 = 1232;
+const EDNS_PADDING: u16 = 12;
+
+/// TCP header flags
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// IP protocol numbers found in quoted probe headers
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// ICMP Timestamp request and reply (RFC 792)
+const ICMP_TIMESTAMP: u8 = 13;
+const ICMP_TIMESTAMP_REPLY: u8 = 14;
+const ICMP_TIMESTAMP_LEN: usize = 20;
+
+/// Milliseconds in the day that ICMP timestamps count
+const MS_PER_DAY: u32 = 86_400_000;
+
+/// ICMPv6 message types a trace receives
+const ICMP6_DST_UNREACH: u8 = 1;
+const ICMP6_TIME_EXCEEDED: u8 = 3;
+const ICMP6_ECHO_REPLY: u8 = 129;
+
 /// Types of ICMP responses we care about
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IcmpResponseType {
+    /// The destination answered: an echo reply, or port unreachable for a UDP probe
     EchoReply,
     TimeExceeded,
     DestinationUnreachable,
     Timeout,
 }
 
+/// How the destination answered a TCP SYN or QUIC probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PortState {
+    Open,   // SYN-ACK / QUIC Version Negotiation
+    Closed, // RST / ICMP port unreachable
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortState::Open => write!(f, "port open"),
+            PortState::Closed => write!(f, "port closed"),
+        }
+    }
+}
+
+/// Response code of the destination's answer to a DNS probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsRcode(pub u8);
+
+impl fmt::Display for DnsRcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => write!(f, "NOERROR"),
+            1 => write!(f, "FORMERR"),
+            2 => write!(f, "SERVFAIL"),
+            3 => write!(f, "NXDOMAIN"),
+            4 => write!(f, "NOTIMP"),
+            5 => write!(f, "REFUSED"),
+            code => write!(f, "RCODE{}", code),
+        }
+    }
+}
+
+/// Clock readings from an ICMP Timestamp exchange, in ms since midnight UTC
+///
+/// The remote readings come from the remote clock, so each one-way figure
+/// is off by the offset between the two clocks (in opposite directions).
+/// Changes over time, or a gap between the two directions that moves, still
+/// point at delay building up on one side of the path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IcmpTimestamps {
+    pub originate: u32, // We sent the request
+    pub receive: u32,   // The remote host received it
+    pub transmit: u32,  // The remote host sent the reply
+    pub arrival: u32,   // The reply reached us
+}
+
+impl IcmpTimestamps {
+    /// Apparent one-way delay towards the remote host
+    pub fn forward_ms(&self) -> i64 {
+        ms_between(self.originate, self.receive)
+    }
+
+    /// Apparent one-way delay back from the remote host
+    pub fn return_ms(&self) -> i64 {
+        ms_between(self.transmit, self.arrival)
+    }
+}
+
+impl fmt::Display for IcmpTimestamps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "→{}ms ←{}ms", self.forward_ms(), self.return_ms())
+    }
+}
+
+/// Milliseconds from one timestamp to another, taking the shorter way round midnight
+fn ms_between(from: u32, to: u32) -> i64 {
+    let day = i64::from(MS_PER_DAY);
+    (i64::from(to) - i64::from(from) + day / 2).rem_euclid(day) - day / 2
+}
+
+/// A wall-clock time as an ICMP timestamp
+fn ms_since_midnight(time: SystemTime) -> u32 {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    (since_epoch.as_millis() % u128::from(MS_PER_DAY)) as u32
+}
+
 /// Information about a probe response
 #[derive(Debug, Clone)]
 pub struct ProbeResponse {
@@ -38,16 +203,23 @@ pub struct ProbeResponse {
     pub seq: u16,
     pub source_addr: IpAddr,
     pub icmp_type: IcmpResponseType,
+    pub icmp_code: u8,          // ICMP code (e.g. unreachable reason)
     pub rtt: Duration,
     pub send_time: Instant,
-    pub receive_time: Instant,  // High-precision receive timestamp
+    pub receive_time: Instant,  // Kernel receive timestamp when available, else when read
     pub precise_rtt_ns: u128,   // Nanosecond precision RTT
+    pub extensions: Option<IcmpExtensions>, // RFC 4884 objects (MPLS labels, interface info)
+    pub port_state: Option<PortState>, // Set when a TCP or QUIC probe reached the destination
+    pub reply_ttl: Option<u8>,  // TTL / hop limit the reply arrived with, when the socket reports it
+    pub timestamps: Option<IcmpTimestamps>, // Set when a timestamp probe reached the destination
+    pub dns_rcode: Option<DnsRcode>, // Set when the destination answered a DNS probe
 }
 
 /// A probe that has been sent but not yet answered.
 #[derive(Debug)]
 struct ProbeInfo {
     hop: usize,
+    ipv6: bool, // Replies must come back in the family the probe went out in
     sent_at: Instant,
     timeout: Duration,
     sequence_timestamp_ns: u128,  // High-precision send timestamp
@@ -65,42 +237,487 @@ impl ProbeInfo {
     }
 }
 
+/// One probe of a batch handed to `ProbeTransport::send_probes`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeRequest {
+    pub hop: usize,
+    pub dst: SocketAddr, // Port 0 picks the protocol's default destination port
+    pub ttl: u8,
+    pub timeout: Duration,
+}
+
+/// Interface between the session and whatever puts probes on the wire
+///
+/// `ProbeEngine` is the real implementation; `fault::FaultTransport` stands in
+/// for it in simulations and tests.
+pub trait ProbeTransport: Send {
+    /// Send a probe and start tracking it, returning its sequence number
+    fn send_probe_with_protocol(
+        &mut self,
+        hop: usize,
+        dst: SocketAddr,
+        ttl: u8,
+        timeout: Duration,
+        protocol: ProbeProtocol,
+    ) -> Result<u16>;
+
+    /// Send a probe with ICMP (default protocol)
+    fn send_probe(&mut self, hop: usize, dst: SocketAddr, ttl: u8, timeout: Duration) -> Result<u16> {
+        self.send_probe_with_protocol(hop, dst, ttl, timeout, ProbeProtocol::Icmp)
+    }
+
+    /// Send a batch of probes, returning one sequence number or error per request
+    fn send_probes(&mut self, requests: &[ProbeRequest], protocol: ProbeProtocol) -> Vec<Result<u16>> {
+        requests
+            .iter()
+            .map(|r| self.send_probe_with_protocol(r.hop, r.dst, r.ttl, r.timeout, protocol))
+            .collect()
+    }
+
+    /// Collect replies that have arrived plus probes that have timed out
+    fn collect_responses_async(&mut self) -> impl Future<Output = Result<Vec<ProbeResponse>>> + Send;
+
+    /// Give up on every probe still outstanding, returning a `Timeout` response for each
+    fn expire_all(&mut self) -> Vec<ProbeResponse>;
+
+    /// Replies thrown away as corrupt or malformed so far, for diagnostics
+    fn discarded_replies(&self) -> usize {
+        0
+    }
+}
+
+/// Socket-level options applied when the probe engine is created
+#[derive(Debug, Clone, Default)]
+pub struct ProbeOptions {
+    pub protocol: ProbeProtocol, // Opens the UDP/TCP probe sockets up front
+    pub paris: bool, // Keep the flow identifier constant across probes
+    pub flows: u16, // Flow identifiers each hop cycles through (multipath), 0 or 1 for one flow
+    pub fwmark: Option<u32>, // SO_MARK for policy routing (Linux)
+    pub tos: Option<u8>, // IP_TOS / IPV6_TCLASS on outgoing probes
+    pub source: Option<IpAddr>, // Local address probe sockets are bound to
+    pub interface: Option<String>, // Network interface probes are forced out of
+    pub payload: PayloadSpec, // Probe size and fill byte
+    pub dns_query: Option<String>, // Name looked up by DNS probes
+}
+
+/// Size and content of the payload carried by ICMP and UDP probes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PayloadSpec {
+    pub packet_size: Option<u16>, // Total IP packet size, `None` for the protocol's default
+    pub pattern: u8,
+}
+
+impl PayloadSpec {
+    /// Payload for a probe to `dst` behind a `header_len`-byte transport header
+    ///
+    /// `default_len` is used when no packet size was requested; `min_len`
+    /// bytes are always kept, as Paris UDP needs two to steer the checksum.
+    fn bytes(&self, dst: IpAddr, header_len: usize, default_len: usize, min_len: usize) -> Vec<u8> {
+        let ip_header_len = if dst.is_ipv6() { IPV6_HEADER_LEN } else { IPV4_HEADER_LEN };
+        let len = self
+            .packet_size
+            .map_or(default_len, |size| (size as usize).saturating_sub(ip_header_len + header_len));
+        vec![self.pattern; len.max(min_len)]
+    }
+}
+
+impl ProbeOptions {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            protocol: args.protocol,
+            // Multipath varies Paris-style flows deliberately, one at a time. A
+            // fixed UDP port can't carry the sequence, so it moves to the checksum.
+            paris: args.paris
+                || args.multipath.is_some()
+                || (args.port.is_some() && args.protocol == ProbeProtocol::Udp)
+                || matches!(args.protocol, ProbeProtocol::Quic | ProbeProtocol::Dns),
+            flows: args.multipath.unwrap_or(1),
+            fwmark: args.fwmark,
+            tos: args.tos,
+            source: args.address,
+            // A VRF is entered by binding to its device, like an interface
+            interface: args.vrf.clone().or_else(|| args.interface.clone()),
+            payload: PayloadSpec {
+                packet_size: args.psize,
+                pattern: args.bitpattern,
+            },
+            dns_query: args.dns_query.clone(),
+        }
+    }
+
+    /// Apply the options to a freshly created probe socket
+    fn apply(&self, socket: &Socket) -> Result<()> {
+        if let Some(mark) = self.fwmark {
+            #[cfg(target_os = "linux")]
+            socket
+                .set_mark(mark)
+                .with_context(|| format!("Failed to set SO_MARK {} - requires CAP_NET_ADMIN", mark))?;
+            #[cfg(not(target_os = "linux"))]
+            anyhow::bail!("--fwmark {} is only supported on Linux", mark);
+        }
+        if let Some(tos) = self.tos {
+            // Unbound sockets still report their address family
+            let result = if socket.local_addr()?.is_ipv6() {
+                socket.set_tclass_v6(tos as u32)
+            } else {
+                socket.set_tos(tos as u32)
+            };
+            result.with_context(|| format!("Failed to set ToS {:#04x} on probe socket", tos))?;
+        }
+        let domain = if socket.local_addr()?.is_ipv6() { Domain::IPV6 } else { Domain::IPV4 };
+        if let Some(ref interface) = self.interface {
+            bind_to_interface(socket, domain, interface)
+                .with_context(|| format!("Failed to bind probe socket to interface {}", interface))?;
+        }
+        if let Some(source) = self.source_in(domain) {
+            socket
+                .bind(&source.into())
+                .with_context(|| format!("Cannot send from {} - is it assigned to this host?", source.ip()))?;
+        }
+        Ok(())
+    }
+
+    /// Address to bind sockets of `domain` to, when `--address` is of that family
+    fn source_in(&self, domain: Domain) -> Option<SocketAddr> {
+        self.source
+            .filter(|source| source.is_ipv6() == (domain == Domain::IPV6))
+            .map(|source| SocketAddr::new(source, 0))
+    }
+}
+
+/// Restrict a socket to one network interface
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_interface(socket: &Socket, _domain: Domain, interface: &str) -> Result<()> {
+    Ok(socket.bind_device(Some(interface.as_bytes()))?)
+}
+
+/// Restrict a socket to one network interface
+#[cfg(target_vendor = "apple")]
+fn bind_to_interface(socket: &Socket, domain: Domain, interface: &str) -> Result<()> {
+    let name = std::ffi::CString::new(interface)?;
+    // SAFETY: `name` is a valid NUL-terminated string
+    let index = std::num::NonZeroU32::new(unsafe { libc::if_nametoindex(name.as_ptr()) })
+        .with_context(|| format!("No such interface: {}", interface))?;
+    if domain == Domain::IPV6 {
+        socket.bind_device_by_index_v6(Some(index))?;
+    } else {
+        socket.bind_device_by_index_v4(Some(index))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+fn bind_to_interface(_socket: &Socket, _domain: Domain, _interface: &str) -> Result<()> {
+    anyhow::bail!("--interface is not supported on this platform")
+}
+
+/// Whether a network device is a VRF master, or `None` if sysfs doesn't know it
+pub fn is_vrf_device(name: &str) -> Option<bool> {
+    let uevent = std::fs::read_to_string(format!("/sys/class/net/{}/uevent", name)).ok()?;
+    Some(uevent.lines().any(|line| line == "DEVTYPE=vrf"))
+}
+
 /// Simplified probe engine focused on core functionality
 pub struct ProbeEngine {
     next_seq: u16,
     icmp_socket: Socket,
+    icmp_kind: IcmpSocketKind,
     icmp6_socket: Option<Socket>, // IPv6 ICMP socket
+    udp_socket: Option<UdpProbeSocket>, // IPv4 UDP probes
+    udp6_socket: Option<UdpProbeSocket>, // IPv6 UDP probes
+    tcp_socket: Option<TcpProbeSocket>, // IPv4 TCP SYN probes
+    paris: bool, // Constant ICMP checksum / UDP ports, see `ProbeOptions::paris`
+    service: Option<UdpService>, // Answered on the UDP socket by the destination
+    dns_qname: Vec<u8>, // Wire-format name for DNS probes
+    payload: PayloadSpec,
+    flows: FlowCycle,
     pending: HashMap<u16, ProbeInfo>,
     packet_id: u16,
+    cookie: [u8; COOKIE_LEN], // Tells our echo replies from another tracer's
+    recv_batch: RecvBatch,
+    ready: Option<ReadySockets>, // Runtime registrations, made on first collection
+    discarded: usize, // Replies dropped for a bad checksum or header
+}
+
+/// Hands out flow identifiers for multipath enumeration
+///
+/// Each TTL cycles through the flows on its own, so every hop is probed with
+/// every flow regardless of how many hops a round covers.
+#[derive(Debug, Default)]
+struct FlowCycle {
+    flows: u16,
+    next: HashMap<u8, u16>,
+}
+
+impl FlowCycle {
+    fn new(flows: u16) -> Self {
+        Self {
+            flows: flows.max(1),
+            next: HashMap::new(),
+        }
+    }
+
+    fn next(&mut self, ttl: u8) -> u16 {
+        let next = self.next.entry(ttl).or_insert(0);
+        let flow = *next;
+        *next = (flow + 1) % self.flows;
+        flow
+    }
+}
+
+/// Finds the source address the kernel will use towards a destination
+///
+/// Needed wherever a checksum covers the pseudo-header. The socket is created
+/// with the engine so the lookup happens in the right network namespace.
+struct RouteLookup {
+    socket: Socket,
+}
+
+impl RouteLookup {
+    fn new(domain: Domain, options: &ProbeOptions) -> Result<Self> {
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        options.apply(&socket)?;
+        Ok(Self { socket })
+    }
+
+    fn source_for(&self, dst: SocketAddr) -> Result<IpAddr> {
+        self.socket
+            .connect(&dst.into())
+            .with_context(|| format!("No route to {}", dst.ip()))?;
+        self.socket
+            .local_addr()?
+            .as_socket()
+            .map(|local| local.ip())
+            .with_context(|| format!("No source address towards {}", dst.ip()))
+    }
+}
+
+/// Unprivileged socket used to send UDP probes
+///
+/// Replies come back as ICMP errors on the raw ICMP sockets; the bound source
+/// port tells our probes apart from other UDP traffic quoted in them.
+struct UdpProbeSocket {
+    socket: Socket,
+    /// Raw UDP socket for Paris mode, where we must write the final checksum
+    /// ourselves (with offload the kernel may never fill it in)
+    raw: Option<Socket>,
+    route: RouteLookup,
+    src_port: u16,
+}
+
+impl UdpProbeSocket {
+    fn bind(domain: Domain, options: &ProbeOptions) -> Result<Self> {
+        let raw = if options.paris {
+            let raw = Socket::new(domain, Type::RAW, Some(Protocol::UDP))
+                .context("Failed to create raw UDP socket for --paris - need sudo/root privileges")?;
+            options.apply(&raw)?;
+            Some(raw)
+        } else {
+            None
+        };
+
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))
+            .context("Failed to create UDP probe socket")?;
+        socket.set_nonblocking(true)?;
+        options.apply(&socket)?;
+        // QUIC and DNS servers answer on this socket
+        if UdpService::for_protocol(options.protocol).is_some() {
+            enable_rx_timestamps(&socket);
+            enable_reply_ttl(&socket, domain);
+        }
+
+        let any: SocketAddr = if domain == Domain::IPV6 {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        // With --address, apply() has already bound it
+        if options.source_in(domain).is_none() {
+            socket.bind(&any.into()).context("Failed to bind UDP probe socket")?;
+        }
+        let src_port = socket
+            .local_addr()?
+            .as_socket()
+            .map(|addr| addr.port())
+            .context("UDP probe socket has no local port")?;
+
+        Ok(Self {
+            socket,
+            raw,
+            route: RouteLookup::new(domain, options)?,
+            src_port,
+        })
+    }
+}
+
+/// Raw socket used to send TCP SYN probes (IPv4)
+///
+/// Intermediate hops answer with ICMP errors quoting our TCP header; the
+/// destination answers with SYN-ACK or RST, which arrive on this socket.
+struct TcpProbeSocket {
+    socket: Socket,
+    /// Bound but never connected, so no one else can claim `src_ports`
+    _port_guards: Vec<Socket>,
+    route: RouteLookup,
+    /// One source port per flow
+    src_ports: Vec<u16>,
+}
+
+impl TcpProbeSocket {
+    fn open(options: &ProbeOptions) -> Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::TCP))
+            .context("Failed to create raw TCP socket - need sudo/root privileges")?;
+        // BSD stacks keep every TCP segment for themselves; raw sockets only
+        // ever see what we send
+        #[cfg(any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly",
+            target_vendor = "apple"
+        ))]
+        tracing::warn!(
+            "Raw sockets on this system don't receive TCP replies - the destination \
+             only shows up if it answers with ICMP"
+        );
+        socket.set_nonblocking(true)?;
+        enable_rx_timestamps(&socket);
+        options.apply(&socket)?;
+
+        let mut port_guards = Vec::new();
+        let mut src_ports = Vec::new();
+        for _ in 0..options.flows.max(1) {
+            let port_guard = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
+            let local = options
+                .source_in(Domain::IPV4)
+                .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+            port_guard.bind(&local.into())?;
+            let src_port = port_guard
+                .local_addr()?
+                .as_socket()
+                .map(|addr| addr.port())
+                .context("TCP probe socket has no local port")?;
+            port_guards.push(port_guard);
+            src_ports.push(src_port);
+        }
+
+        Ok(Self {
+            socket,
+            _port_guards: port_guards,
+            route: RouteLookup::new(Domain::IPV4, options)?,
+            src_ports,
+        })
+    }
 }
 
 impl ProbeEngine {
     pub fn new() -> Result<Self> {
-        // Create raw ICMP socket (requires root/sudo)
-        let icmp_socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))
-            .context("Failed to create raw ICMP socket - need sudo/root privileges")?;
-        
+        Self::with_options(ProbeOptions::default())
+    }
+
+    pub fn with_options(options: ProbeOptions) -> Result<Self> {
+        let mut attempts = Vec::new();
+        let (icmp_socket, icmp_kind, fallback_udp) = match open_icmp_socket(options.protocol, &mut attempts) {
+            Some((socket, kind)) => (socket, kind, None),
+            None => {
+                let udp = open_udp_fallback(&options, &mut attempts).ok_or_else(|| no_usable_socket(&attempts))?;
+                (udp.socket.try_clone()?, IcmpSocketKind::UdpErrors, Some(udp))
+            }
+        };
+        if !attempts.is_empty() {
+            tracing::info!("Using {}{}", icmp_kind.describe(), describe_attempts(&attempts));
+        }
         icmp_socket.set_nonblocking(true)?;
+        if !enable_rx_timestamps(&icmp_socket) {
+            tracing::debug!("Kernel receive timestamps unavailable - timing replies in userspace");
+        }
+        if icmp_kind != IcmpSocketKind::Raw && !enable_reply_ttl(&icmp_socket, Domain::IPV4) {
+            tracing::debug!("Reply TTLs unavailable on the ICMP socket");
+        }
+        if options.paris && icmp_kind == IcmpSocketKind::Ping {
+            tracing::warn!("Ping sockets rewrite the echo identifier - --paris has no effect");
+        }
+        if icmp_kind == IcmpSocketKind::UdpErrors && options.protocol == ProbeProtocol::Icmp {
+            tracing::warn!("No ICMP socket available - sending UDP probes instead");
+        }
+        if !matches!(options.protocol, ProbeProtocol::Icmp | ProbeProtocol::Udp)
+            && options.payload.packet_size.is_some()
+        {
+            tracing::warn!("{:?} probes have a fixed size - --psize is ignored", options.protocol);
+        }
+        // The UDP fallback shares a socket that bind() already set up
+        if fallback_udp.is_none() {
+            options.apply(&icmp_socket)?;
+        }
 
         // Try to create IPv6 ICMP socket (optional)
         let icmp6_socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))
-            .inspect(|sock| {
-                let _ = sock.set_nonblocking(true);
+            .and_then(|sock| {
+                sock.set_nonblocking(true)?;
+                enable_rx_timestamps(&sock);
+                enable_reply_ttl(&sock, Domain::IPV6);
+                #[cfg(any(
+                    target_os = "linux",
+                    target_os = "freebsd",
+                    target_os = "openbsd",
+                    target_os = "netbsd",
+                    target_os = "dragonfly",
+                    target_vendor = "apple"
+                ))]
+                if !filter_icmp6_replies(&sock) {
+                    tracing::debug!("ICMP6_FILTER unavailable, reading all ICMPv6 traffic");
+                }
+                Ok(sock)
             })
-            .ok();
+            .map_err(anyhow::Error::from)
+            .and_then(|sock| options.apply(&sock).map(|_| sock));
+        let icmp6_socket = match icmp6_socket {
+            Ok(sock) => {
+                tracing::info!("IPv6 ICMP socket created successfully");
+                Some(sock)
+            }
+            Err(e) => {
+                tracing::warn!("IPv6 ICMP socket creation failed - IPv6 support disabled: {:#}", e);
+                None
+            }
+        };
 
-        if icmp6_socket.is_some() {
-            tracing::info!("IPv6 ICMP socket created successfully");
-        } else {
-            tracing::warn!("IPv6 ICMP socket creation failed - IPv6 support disabled");
-        }
+        // Protocol sockets are opened here rather than on first use so they
+        // land in the same network namespace as the ICMP sockets
+        let (udp_socket, udp6_socket, tcp_socket) = match options.protocol {
+            ProbeProtocol::Icmp | ProbeProtocol::Timestamp => (fallback_udp, None, None),
+            ProbeProtocol::Udp | ProbeProtocol::Quic | ProbeProtocol::Dns => (
+                Some(match fallback_udp {
+                    Some(udp) => udp,
+                    None => UdpProbeSocket::bind(Domain::IPV4, &options)?,
+                }),
+                icmp6_socket
+                    .as_ref()
+                    .and_then(|_| UdpProbeSocket::bind(Domain::IPV6, &options).ok()),
+                None,
+            ),
+            ProbeProtocol::Tcp => (None, None, Some(TcpProbeSocket::open(&options)?)),
+        };
 
         Ok(Self {
             next_seq: INITIAL_SEQUENCE,
             icmp_socket,
+            icmp_kind,
             icmp6_socket,
+            udp_socket,
+            udp6_socket,
+            tcp_socket,
+            paris: options.paris,
+            service: UdpService::for_protocol(options.protocol),
+            dns_qname: encode_dns_name(options.dns_query.as_deref().unwrap_or(DNS_DEFAULT_QUERY))?,
+            payload: options.payload,
+            flows: FlowCycle::new(options.flows),
             pending: HashMap::new(),
             packet_id: std::process::id() as u16,
+            cookie: rand::random(),
+            recv_batch: RecvBatch::default(),
+            ready: None,
+            discarded: 0,
         })
     }
 
@@ -125,338 +742,1893 @@ impl ProbeEngine {
         protocol: ProbeProtocol,
     ) -> Result<u16> {
         let seq = self.alloc_seq();
+        let flow = self.flows.next(ttl);
 
-        // Select appropriate socket based on destination address family
-        let (socket, packet) = match dst {
-            SocketAddr::V4(_) => {
-                self.icmp_socket.set_ttl(ttl.into())?;
-                let packet = match protocol {
-                    ProbeProtocol::Icmp => construct_icmp_packet(seq, self.packet_id)?,
-                    ProbeProtocol::Udp => {
-                        tracing::debug!("Sending UDP-style probe via ICMP socket");
-                        construct_icmp_packet(seq, self.packet_id)?
-                    }
-                    ProbeProtocol::Tcp => {
-                        tracing::debug!("Sending TCP-style probe via ICMP socket");
-                        construct_icmp_packet(seq, self.packet_id)?
-                    }
-                };
-                (&self.icmp_socket, packet)
-            }
-            SocketAddr::V6(_) => {
-                if let Some(ref icmp6_sock) = self.icmp6_socket {
-                    icmp6_sock.set_ttl(ttl.into())?;
-                    let packet = match protocol {
-                        ProbeProtocol::Icmp => construct_icmp6_packet(seq, self.packet_id)?,
-                        ProbeProtocol::Udp => {
-                            tracing::debug!("Sending UDP-style probe via ICMPv6 socket");
-                            construct_icmp6_packet(seq, self.packet_id)?
-                        }
-                        ProbeProtocol::Tcp => {
-                            tracing::debug!("Sending TCP-style probe via ICMPv6 socket");  
-                            construct_icmp6_packet(seq, self.packet_id)?
-                        }
-                    };
-                    (icmp6_sock, packet)
-                } else {
-                    return Err(anyhow::anyhow!("IPv6 not supported - no ICMPv6 socket available"));
+        self.build_probe(protocol, dst, ttl, seq, flow)?.send()?;
+        self.track_probe(ProbeRequest { hop, dst, ttl, timeout }, seq, flow, protocol, Instant::now());
+
+        Ok(seq)
+    }
+
+    /// Send a batch of probes, using one `sendmmsg` call per socket on Linux
+    pub fn send_probes(&mut self, requests: &[ProbeRequest], protocol: ProbeProtocol) -> Vec<Result<u16>> {
+        self.send_probes_with(requests, protocol, send_outgoing)
+    }
+
+    /// Build a batch of probes and put it on the wire with `send`
+    ///
+    /// `send` returns one result per probe it was given, in order.
+    fn send_probes_with(
+        &mut self,
+        requests: &[ProbeRequest],
+        protocol: ProbeProtocol,
+        send: impl FnOnce(&[OutgoingProbe]) -> Vec<std::io::Result<()>>,
+    ) -> Vec<Result<u16>> {
+        let ids: Vec<(u16, u16)> = requests
+            .iter()
+            .map(|request| (self.alloc_seq(), self.flows.next(request.ttl)))
+            .collect();
+
+        let mut results: Vec<Result<u16>> = Vec::with_capacity(requests.len());
+        let mut outgoing = Vec::with_capacity(requests.len());
+        for (request, &(seq, flow)) in requests.iter().zip(&ids) {
+            match self.build_probe(protocol, request.dst, request.ttl, seq, flow) {
+                Ok(probe) => {
+                    outgoing.push(probe);
+                    results.push(Ok(seq));
                 }
+                Err(e) => results.push(Err(e)),
             }
-        };
-
-        socket.send_to(&packet, &dst.into())?;
+        }
 
-        // Track the probe
+        let mut sent = send(&outgoing).into_iter();
         let now = Instant::now();
+        for (result, (request, &(seq, flow))) in results.iter_mut().zip(requests.iter().zip(&ids)) {
+            if result.is_err() {
+                continue;
+            }
+            match sent.next() {
+                Some(Ok(())) => self.track_probe(*request, seq, flow, protocol, now),
+                Some(Err(e)) => *result = Err(e.into()),
+                None => *result = Err(anyhow::anyhow!("Probe was not sent")),
+            }
+        }
+        results
+    }
+
+    /// Start waiting for the reply to a probe that went out at `sent_at`
+    fn track_probe(&mut self, request: ProbeRequest, seq: u16, flow: u16, protocol: ProbeProtocol, sent_at: Instant) {
         let probe = ProbeInfo {
-            hop,
-            sent_at: now,
-            timeout,
+            hop: request.hop,
+            ipv6: request.dst.is_ipv6(),
+            sent_at,
+            timeout: request.timeout,
             sequence_timestamp_ns: crate::utils::time::get_system_timestamp_ns(),
         };
 
         self.pending.insert(seq, probe);
         
-        let addr_family = match dst {
+        let addr_family = match request.dst {
             SocketAddr::V4(_) => "IPv4",
             SocketAddr::V6(_) => "IPv6",
         };
         
-        tracing::debug!("Sent {:?} probe ({}): hop={}, ttl={}, seq={}", 
-                       protocol, addr_family, hop + 1, ttl, seq);
-
-        Ok(seq)
+        tracing::debug!("Sent {:?} probe ({}): hop={}, ttl={}, seq={}, flow={}", 
+                       protocol, addr_family, request.hop + 1, request.ttl, seq, flow);
     }
 
-    /// Event-driven async response collection (no polling!)
-    pub async fn collect_responses_async(&mut self) -> Result<Vec<ProbeResponse>> {
-        let mut responses = Vec::new();
-        let mut buffer = [0u8; MAX_MTU];
-
-        // Use tokio's async socket operations for event-driven I/O
-        // This waits for actual socket events instead of polling
-        
-        // Check IPv4 ICMP socket for readiness
-        if let Ok(ready) = timeout(Duration::from_micros(1), async {
-            // Convert to tokio socket for async operations
-            let std_socket = std::net::UdpSocket::from(self.icmp_socket.try_clone()?);
-            std_socket.set_nonblocking(true)?;
-            let tokio_socket = UdpSocket::from_std(std_socket)?;
-            
-            // Wait for socket to become readable (event-driven!)
-            tokio_socket.ready(Interest::READABLE).await
-        }).await {
-            if ready.is_ok() {
-                // Socket is ready - collect all available responses
-                loop {
-                    let mut uninit_buffer = [std::mem::MaybeUninit::<u8>::uninit(); MAX_MTU];
-                    match self.icmp_socket.recv_from(&mut uninit_buffer) {
-                        Ok((len, addr)) => {
-                            // Convert MaybeUninit to initialized bytes
-                            for i in 0..len {
-                                buffer[i] = unsafe { uninit_buffer[i].assume_init() };
-                            }
-                            if let Some(response) = self.parse_icmp_response(&buffer[..len], addr)? {
-                                responses.push(response);
-                            }
-                        }
-                        Err(_) => break, // No more data available
-                    }
-                }
+    fn build_probe(
+        &self,
+        protocol: ProbeProtocol,
+        dst: SocketAddr,
+        ttl: u8,
+        seq: u16,
+        flow: u16,
+    ) -> Result<OutgoingProbe<'_>> {
+        match protocol {
+            ProbeProtocol::Icmp if self.icmp_kind == IcmpSocketKind::UdpErrors => {
+                self.build_udp_probe(dst, ttl, seq, flow)
             }
+            ProbeProtocol::Icmp => self.build_icmp_probe(dst, ttl, seq, flow),
+            ProbeProtocol::Udp => self.build_udp_probe(dst, ttl, seq, flow),
+            ProbeProtocol::Quic => self.build_quic_probe(dst, ttl, seq),
+            ProbeProtocol::Dns => self.build_dns_probe(dst, ttl, seq),
+            ProbeProtocol::Tcp => self.build_tcp_probe(dst, ttl, seq, flow),
+            ProbeProtocol::Timestamp => self.build_timestamp_probe(dst, ttl, seq),
         }
+    }
 
-        // Handle IPv6 socket if available
-        if let Some(ref _icmp6_socket) = self.icmp6_socket {
-            // Similar async approach for IPv6
-            if let Ok(ready) = timeout(Duration::from_micros(1), async {
-                // IPv6 socket readiness check would go here
-                // For now, fall back to non-blocking check
-                Ok::<(), anyhow::Error>(())
-            }).await {
-                if ready.is_ok() {
-                    loop {
-                        let mut uninit_buffer = [std::mem::MaybeUninit::<u8>::uninit(); MAX_MTU];
-                        let recv_result = if let Some(ref icmp6_socket) = self.icmp6_socket {
-                            icmp6_socket.recv_from(&mut uninit_buffer)
-                        } else {
-                            break;
-                        };
+    /// Build an ICMP Timestamp request carrying `seq`
+    ///
+    /// The request has no room for our cookie, so replies are matched on the
+    /// identifier alone.
+    fn build_timestamp_probe(&self, dst: SocketAddr, ttl: u8, seq: u16) -> Result<OutgoingProbe<'_>> {
+        anyhow::ensure!(dst.is_ipv4(), "ICMP timestamp probes are IPv4 only");
+        let originate = ms_since_midnight(SystemTime::now());
+        let packet = construct_icmp_timestamp_packet(seq, self.packet_id, originate);
+        Ok(OutgoingProbe { socket: &self.icmp_socket, packet, target: dst.into(), ttl })
+    }
 
-                        match recv_result {
-                            Ok((len, addr)) => {
-                                for i in 0..len {
-                                    buffer[i] = unsafe { uninit_buffer[i].assume_init() };
-                                }
-                                if let Some(response) = self.parse_icmp6_response(&buffer[..len], addr)? {
-                                    responses.push(response);
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                    }
+    /// Build an ICMP echo request carrying `seq`
+    fn build_icmp_probe(&self, dst: SocketAddr, ttl: u8, seq: u16, flow: u16) -> Result<OutgoingProbe<'_>> {
+        // In Paris mode the identifier offsets the sequence so the checksum,
+        // which ICMP-aware load balancers hash, stays the same for every probe
+        let id = if self.paris {
+            paris_icmp_id(self.packet_id.wrapping_add(flow), seq)
+        } else {
+            self.packet_id
+        };
+
+        // Room permitting, the payload opens with our cookie
+        let mut payload = self.payload.bytes(dst.ip(), ICMP_HEADER_LEN, COOKIE_LEN, 0);
+        if let Some(head) = payload.get_mut(..COOKIE_LEN) {
+            head.copy_from_slice(&self.cookie);
+        }
+
+        // Select appropriate socket based on destination address family
+        let (socket, packet) = match dst {
+            SocketAddr::V4(_) => (&self.icmp_socket, construct_icmp_packet(seq, id, &payload)?),
+            SocketAddr::V6(_) => {
+                if let Some(ref icmp6_sock) = self.icmp6_socket {
+                    (icmp6_sock, construct_icmp6_packet(seq, id, &payload)?)
+                } else {
+                    return Err(anyhow::anyhow!("IPv6 not supported - no ICMPv6 socket available"));
                 }
             }
-        }
+        };
 
-        // Check for timeouts (no change needed here)
-        let timed_out: Vec<_> = self
-            .pending
-            .iter()
-            .filter(|(_, probe)| probe.timed_out())
-            .map(|(seq, probe)| (*seq, probe.hop, probe.sent_at))
-            .collect();
+        Ok(OutgoingProbe { socket, packet, target: dst.into(), ttl })
+    }
 
-        for (seq, hop, send_time) in timed_out {
-            if let Some(probe) = self.pending.remove(&seq) {
-                let (rtt, precise_rtt_ns) = probe.get_precise_rtt(Instant::now());
-                responses.push(ProbeResponse {
-                    hop,
-                    seq,
-                    source_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-                    icmp_type: IcmpResponseType::Timeout,
-                    rtt,
-                    send_time,
-                    receive_time: Instant::now(),
-                    precise_rtt_ns,
-                });
-            }
-        }
+    /// Build a UDP datagram whose destination port (or checksum, in Paris mode) encodes `seq`
+    fn build_udp_probe(&self, dst: SocketAddr, ttl: u8, seq: u16, flow: u16) -> Result<OutgoingProbe<'_>> {
+        let udp = self.udp_socket_for(dst)?;
+        let socket = udp.raw.as_ref().unwrap_or(&udp.socket);
 
-        Ok(responses)
+        let mut target = dst;
+        let paris_min = if self.paris { 2 } else { 0 };
+        let mut payload = self.payload.bytes(dst.ip(), UDP_HEADER_LEN, UDP_PAYLOAD_SIZE, paris_min);
+        let packet = if self.paris {
+            // Fixed ports keep the flow; the checksum carries the sequence instead
+            let dst_port = match dst.port() {
+                0 => UDP_BASE_PORT + flow,
+                port => port,
+            };
+            target.set_port(dst_port);
+            let source = udp.route.source_for(target)?;
+            set_udp_checksum_payload(&mut payload, 0, source, dst.ip(), udp.src_port, dst_port, seq);
+            // Raw sockets take no port in the address
+            target.set_port(0);
+            construct_udp_datagram(source, dst.ip(), udp.src_port, dst_port, &payload)
+        } else {
+            target.set_port(udp_port_for_seq(seq));
+            payload
+        };
+        Ok(OutgoingProbe { socket, packet, target: target.into(), ttl })
     }
 
-    /// Backward-compatible synchronous method (now calls async version)
-    pub fn collect_responses(&mut self) -> Result<Vec<ProbeResponse>> {
-        // Use tokio's block_in_place for sync compatibility
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                self.collect_responses_async().await
-            })
-        })
+    /// Build a QUIC Initial-shaped datagram to port 443 (or `--port`)
+    ///
+    /// A QUIC server answers the reserved version with Version Negotiation
+    /// echoing our connection ID, which carries `seq`.
+    fn build_quic_probe(&self, dst: SocketAddr, ttl: u8, seq: u16) -> Result<OutgoingProbe<'_>> {
+        let payload = construct_quic_initial(&quic_connection_id(&self.cookie, self.packet_id, seq));
+        self.build_service_probe(dst, ttl, seq, QUIC_DEFAULT_PORT, payload, QUIC_INITIAL_LEN - 2)
     }
 
-    fn alloc_seq(&mut self) -> u16 {
-        let seq = self.next_seq;
-        self.next_seq = self.next_seq.wrapping_add(1);
-        if self.next_seq > 60999 {
-            self.next_seq = INITIAL_SEQUENCE;
+    /// Build a DNS query to port 53 (or `--port`) whose ID is `seq`
+    fn build_dns_probe(&self, dst: SocketAddr, ttl: u8, seq: u16) -> Result<OutgoingProbe<'_>> {
+        let (payload, adjust_at) = construct_dns_query(seq, &self.dns_qname);
+        self.build_service_probe(dst, ttl, seq, DNS_DEFAULT_PORT, payload, adjust_at)
+    }
+
+    /// Wrap a service request in a UDP datagram to `default_port` (or `--port`)
+    ///
+    /// Sent Paris-style: the two payload bytes at `adjust_at` steer the UDP
+    /// checksum to `seq`, so routers' ICMP errors can be matched to the probe.
+    fn build_service_probe(
+        &self,
+        dst: SocketAddr,
+        ttl: u8,
+        seq: u16,
+        default_port: u16,
+        mut payload: Vec<u8>,
+        adjust_at: usize,
+    ) -> Result<OutgoingProbe<'_>> {
+        let udp = self.udp_socket_for(dst)?;
+        let socket = udp.raw.as_ref().context("Service probes need a raw UDP socket")?;
+
+        let dst_port = match dst.port() {
+            0 => default_port,
+            port => port,
+        };
+        let source = udp.route.source_for(SocketAddr::new(dst.ip(), dst_port))?;
+        set_udp_checksum_payload(&mut payload, adjust_at, source, dst.ip(), udp.src_port, dst_port, seq);
+        let packet = construct_udp_datagram(source, dst.ip(), udp.src_port, dst_port, &payload);
+        // Raw sockets take no port in the address
+        Ok(OutgoingProbe { socket, packet, target: SocketAddr::new(dst.ip(), 0).into(), ttl })
+    }
+
+    /// The UDP probe socket for `dst`'s address family
+    fn udp_socket_for(&self, dst: SocketAddr) -> Result<&UdpProbeSocket> {
+        match dst {
+            SocketAddr::V4(_) => self.udp_socket.as_ref().context("UDP probe socket not open"),
+            SocketAddr::V6(_) => self
+                .udp6_socket
+                .as_ref()
+                .context("IPv6 not supported - no ICMPv6 socket to receive replies"),
         }
-        seq
+    }
+
+    /// Build a TCP SYN whose sequence number encodes `seq`
+    fn build_tcp_probe(&self, dst: SocketAddr, ttl: u8, seq: u16, flow: u16) -> Result<OutgoingProbe<'_>> {
+        let SocketAddr::V4(dst_v4) = dst else {
+            return Err(anyhow::anyhow!("TCP probes are only supported over IPv4"));
+        };
+        let tcp = self.tcp_socket.as_ref().context("TCP probe socket not open")?;
+        let IpAddr::V4(source) = tcp.route.source_for(dst)? else {
+            return Err(anyhow::anyhow!("No IPv4 source address towards {}", dst.ip()));
+        };
+
+        let packet = construct_tcp_syn(
+            source,
+            *dst_v4.ip(),
+            tcp.src_ports[flow as usize % tcp.src_ports.len()],
+            match dst.port() {
+                0 => TCP_DEFAULT_PORT,
+                port => port,
+            },
+            tcp_seq_for_probe(self.packet_id, seq),
+        );
+        Ok(OutgoingProbe { socket: &tcp.socket, packet, target: dst.into(), ttl })
+    }
+
+    /// Wait until replies have arrived or probes have timed out, and return them
+    ///
+    /// Sleeps on socket readiness and the earliest probe deadline, so it never
+    /// returns an empty batch. With no probe outstanding only a reply ends the
+    /// wait, so callers bound it with a deadline or a `select!`. Cancel-safe:
+    /// sockets are only read once the wait is over, right before returning.
+    pub async fn collect_responses_async(&mut self) -> Result<Vec<ProbeResponse>> {
+        if self.ready.is_none() {
+            // Registered here rather than in the constructor, which may run on
+            // a thread without a runtime (see `netns::run_in_namespace`)
+            self.ready = Some(ReadySockets::register(self)?);
+        }
+
+        loop {
+            let responses = self.drain_responses()?;
+            if !responses.is_empty() {
+                return Ok(responses);
+            }
+
+            let deadline = self.next_deadline();
+            if let Some(ref ready) = self.ready {
+                ready.wait(deadline).await;
+            }
+        }
+    }
+
+    /// Read every queued reply without blocking and expire overdue probes
+    fn drain_responses(&mut self) -> Result<Vec<ProbeResponse>> {
+        let mut responses = Vec::new();
+        // Taken out of self so parsing can borrow the engine mutably
+        let mut batch = std::mem::take(&mut self.recv_batch);
+
+        for kind in ReplySocket::ALL {
+            while let Some(socket) = self.reply_socket(kind) {
+                let Ok(count) = batch.recv(socket) else { break };
+
+                for (packet, addr, received_at, reply_ttl) in batch.packets(count) {
+                    if let Some(response) = self.parse_reply(kind, packet, addr, received_at, reply_ttl)? {
+                        responses.push(response);
+                    }
+                }
+                if count < RECV_BATCH_SIZE {
+                    break;
+                }
+            }
+        }
+        self.recv_batch = batch;
+
+        self.drain_error_queue(&mut responses);
+        self.expire_probes(&mut responses);
+        Ok(responses)
+    }
+
+    /// The socket replies of a given kind are read from, if it is open
+    fn reply_socket(&self, kind: ReplySocket) -> Option<&Socket> {
+        match kind {
+            // The UDP fallback only hears back through its error queue
+            ReplySocket::Icmp => Some(&self.icmp_socket).filter(|_| self.icmp_kind != IcmpSocketKind::UdpErrors),
+            ReplySocket::Icmp6 => self.icmp6_socket.as_ref(),
+            ReplySocket::Tcp => self.tcp_socket.as_ref().map(|tcp| &tcp.socket),
+            // Only QUIC and DNS servers answer on the UDP sockets
+            ReplySocket::Udp => self.udp_socket.as_ref().filter(|_| self.service.is_some()).map(|udp| &udp.socket),
+            ReplySocket::Udp6 => self.udp6_socket.as_ref().filter(|_| self.service.is_some()).map(|udp| &udp.socket),
+        }
+    }
+
+    /// Match a packet read from one of the reply sockets to a pending probe
+    ///
+    /// `reply_ttl` is the TTL the kernel reported alongside the packet, for
+    /// sockets that don't hand us the IP header to read it from.
+    fn parse_reply(
+        &mut self,
+        kind: ReplySocket,
+        packet: &[u8],
+        addr: &SockAddr,
+        received_at: Instant,
+        reply_ttl: Option<u8>,
+    ) -> Result<Option<ProbeResponse>> {
+        let response = match kind {
+            ReplySocket::Icmp => self.parse_icmp_response(packet, addr, received_at)?,
+            ReplySocket::Icmp6 => self.parse_icmp6_response(packet, addr, received_at)?,
+            // SYN-ACK / RST from the destination of TCP probes
+            ReplySocket::Tcp => {
+                let Some(ref tcp) = self.tcp_socket else {
+                    return Ok(None);
+                };
+                let Some((source, seq, state)) = parse_tcp_reply(packet, &tcp.src_ports, self.packet_id) else {
+                    return Ok(None);
+                };
+                self.complete_probe(seq, IpAddr::V4(source), IcmpResponseType::EchoReply, 0, received_at)
+                    .map(|response| ProbeResponse { port_state: Some(state), ..response })
+            }
+            // Version Negotiation or a DNS response from the destination
+            ReplySocket::Udp | ReplySocket::Udp6 => {
+                let Some(source) = addr.as_socket() else {
+                    return Ok(None);
+                };
+                match self.service {
+                    Some(UdpService::Quic) => {
+                        let Some(seq) = parse_version_negotiation(packet, &self.cookie, self.packet_id) else {
+                            return Ok(None);
+                        };
+                        self.complete_probe(seq, source.ip(), IcmpResponseType::EchoReply, 0, received_at)
+                            .map(|response| ProbeResponse { port_state: Some(PortState::Open), ..response })
+                    }
+                    Some(UdpService::Dns) => {
+                        let Some((seq, rcode)) = parse_dns_response(packet) else {
+                            return Ok(None);
+                        };
+                        self.complete_probe(seq, source.ip(), IcmpResponseType::EchoReply, 0, received_at)
+                            .map(|response| ProbeResponse { dns_rcode: Some(rcode), ..response })
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        // Raw IPv4 sockets include the IP header; the rest rely on the kernel
+        let header_ttl = match kind {
+            ReplySocket::Icmp if self.icmp_kind == IcmpSocketKind::Ping => None,
+            ReplySocket::Icmp | ReplySocket::Tcp => packet.get(8).copied(),
+            ReplySocket::Icmp6 | ReplySocket::Udp | ReplySocket::Udp6 => None,
+        };
+        Ok(response.map(|response| ProbeResponse {
+            reply_ttl: header_ttl.or(reply_ttl),
+            ..response
+        }))
+    }
+
+    /// Ping sockets and the UDP fallback report Time Exceeded and Unreachable
+    /// on the error queue
+    fn drain_error_queue(&mut self, responses: &mut Vec<ProbeResponse>) {
+        #[cfg(target_os = "linux")]
+        if self.icmp_kind.queues_errors() {
+            while let Ok(queued) = recv_queued_icmp_error(&self.icmp_socket, self.icmp_kind) {
+                let Some(error) = queued else { continue };
+                let response_type = match (error.icmp_type, error.icmp_code) {
+                    (11, _) => IcmpResponseType::TimeExceeded,
+                    // Port unreachable means a UDP probe reached the destination
+                    (3, 3) if self.icmp_kind == IcmpSocketKind::UdpErrors => IcmpResponseType::EchoReply,
+                    (3, _) => IcmpResponseType::DestinationUnreachable,
+                    _ => continue,
+                };
+                let received_at = receive_instant(error.timestamp);
+                if let Some(response) = self.complete_probe(
+                    error.seq,
+                    IpAddr::V4(error.offender),
+                    response_type,
+                    error.icmp_code,
+                    received_at,
+                ) {
+                    responses.push(ProbeResponse {
+                        reply_ttl: error.reply_ttl,
+                        ..response
+                    });
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = responses;
+    }
+
+    /// Turn every probe past its timeout into a `Timeout` response
+    fn expire_probes(&mut self, responses: &mut Vec<ProbeResponse>) {
+        self.expire_where(responses, ProbeInfo::timed_out);
+    }
+
+    /// Give up on every probe still outstanding, timed out or not
+    pub fn expire_all(&mut self) -> Vec<ProbeResponse> {
+        let mut responses = Vec::new();
+        self.expire_where(&mut responses, |_| true);
+        responses
+    }
+
+    fn expire_where(&mut self, responses: &mut Vec<ProbeResponse>, expired: impl Fn(&ProbeInfo) -> bool) {
+        let timed_out: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, probe)| expired(probe))
+            .map(|(seq, probe)| (*seq, probe.hop, probe.sent_at))
+            .collect();
+
+        for (seq, hop, send_time) in timed_out {
+            if let Some(probe) = self.pending.remove(&seq) {
+                let (rtt, precise_rtt_ns) = probe.get_precise_rtt(Instant::now());
+                responses.push(ProbeResponse {
+                    hop,
+                    seq,
+                    source_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    icmp_type: IcmpResponseType::Timeout,
+                    icmp_code: 0,
+                    rtt,
+                    send_time,
+                    receive_time: Instant::now(),
+                    precise_rtt_ns,
+                    extensions: None,
+                    port_state: None,
+                    reply_ttl: None,
+                    timestamps: None,
+                    dns_rcode: None,
+                });
+            }
+        }
+    }
+
+    /// When the next pending probe times out
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|probe| probe.sent_at + probe.timeout).min()
+    }
+
+    /// Blocking wrapper around `collect_responses_async`
+    ///
+    /// With no probe outstanding there is nothing to wait for, so whatever is
+    /// queued is returned straight away instead of blocking until a stray reply.
+    pub fn collect_responses(&mut self) -> Result<Vec<ProbeResponse>> {
+        if self.pending.is_empty() {
+            return self.drain_responses();
+        }
+        // Use tokio's block_in_place for sync compatibility
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async {
+                self.collect_responses_async().await
+            })
+        })
+    }
+
+    fn alloc_seq(&mut self) -> u16 {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        if self.next_seq > MAX_SEQUENCE {
+            self.next_seq = INITIAL_SEQUENCE;
+        }
+        seq
+    }
+
+    /// Take a pending probe and turn it into a response
+    fn complete_probe(
+        &mut self,
+        seq: u16,
+        source_addr: IpAddr,
+        icmp_type: IcmpResponseType,
+        icmp_code: u8,
+        received_at: Instant,
+    ) -> Option<ProbeResponse> {
+        // Another engine in this process (--dual-stack) may use the same
+        // sequence numbers in the other address family
+        if self.pending.get(&seq)?.ipv6 != source_addr.is_ipv6() {
+            return None;
+        }
+        let probe = self.pending.remove(&seq)?;
+        let (rtt, precise_rtt_ns) = probe.get_precise_rtt(received_at);
+        Some(ProbeResponse {
+            hop: probe.hop,
+            seq,
+            source_addr,
+            icmp_type,
+            icmp_code,
+            rtt,
+            send_time: probe.sent_at,
+            receive_time: received_at,
+            precise_rtt_ns,
+            extensions: None,
+            port_state: None,
+            reply_ttl: None,
+            timestamps: None,
+            dns_rcode: None,
+        })
     }
 
     fn parse_icmp_response(
         &mut self,
         buf: &[u8],
-        _addr: socket2::SockAddr,
+        addr: &SockAddr,
+        received_at: Instant,
+    ) -> Result<Option<ProbeResponse>> {
+        let (source, icmp) = if self.icmp_kind == IcmpSocketKind::Ping {
+            // Ping sockets strip the IP header; the sender comes from recvfrom
+            let Some(SocketAddr::V4(from)) = addr.as_socket() else {
+                return Ok(None);
+            };
+            let Ok(icmp) = IcmpMessage::parse(buf) else {
+                return Ok(None);
+            };
+            (*from.ip(), icmp)
+        } else {
+            // Raw sockets see packets before the kernel's own ICMP checks,
+            // so anything damaged or forged in transit would reach us as is
+            let parsed = Ipv4Packet::parse(buf).and_then(|ip| Ok((ip, IcmpMessage::parse(ip.payload)?)));
+            let (ip, icmp) = match parsed {
+                Ok((ip, icmp)) if ip.protocol == IPPROTO_ICMP => (ip, icmp),
+                Ok((ip, _)) => {
+                    self.discarded += 1;
+                    tracing::debug!("Discarding protocol {} packet from {}", ip.protocol, ip.source);
+                    return Ok(None);
+                }
+                Err(e) => {
+                    self.discarded += 1;
+                    tracing::debug!("Discarding malformed IPv4 packet ({} bytes): {}", buf.len(), e);
+                    return Ok(None);
+                }
+            };
+            if calculate_icmp_checksum(icmp.bytes) != 0 {
+                self.discarded += 1;
+                tracing::debug!("Discarding ICMP packet from {} with a bad checksum", ip.source);
+                return Ok(None);
+            }
+            (ip.source, icmp)
+        };
+
+        let icmp_code = icmp.code;
+        if icmp.icmp_type == ICMP_TIMESTAMP_REPLY {
+            return Ok(self.parse_timestamp_reply(source, &icmp, received_at));
+        }
+
+        let response_type = match icmp.icmp_type {
+            0 => IcmpResponseType::EchoReply,
+            11 => IcmpResponseType::TimeExceeded,
+            3 => IcmpResponseType::DestinationUnreachable,
+            _ => return Ok(None),
+        };
+
+        // Extract sequence number
+        let mut extensions = None;
+        let mut port_state = None;
+        let (seq, response_type) = match response_type {
+            IcmpResponseType::EchoReply => {
+                // Another instance's reply would otherwise steal our sequence number
+                if !cookie_matches(icmp.body, &self.cookie) {
+                    return Ok(None);
+                }
+                (icmp.sequence(), response_type)
+            }
+            IcmpResponseType::TimeExceeded | IcmpResponseType::DestinationUnreachable => {
+                // Extract from the original packet quoted in the ICMP payload
+                let quoted;
+                (quoted, extensions) = icmp_ext::split_icmp_error(icmp.bytes, false);
+                let ours = QuotedProbeMatch {
+                    packet_id: self.packet_id,
+                    udp_src_port: self.udp_socket.as_ref().map(|udp| udp.src_port),
+                    tcp_src_ports: self.tcp_socket.as_ref().map_or(&[], |tcp| &tcp.src_ports),
+                    paris: self.paris,
+                    cookie: self.cookie,
+                };
+                let Some((seq, protocol)) = parse_quoted_ipv4(quoted, &ours) else {
+                    return Ok(None);
+                };
+                // Port unreachable means a UDP probe reached the destination
+                if protocol == ProbeProtocol::Udp
+                    && response_type == IcmpResponseType::DestinationUnreachable
+                    && icmp_code == 3
+                {
+                    port_state = self.service.map(|_| PortState::Closed);
+                    (seq, IcmpResponseType::EchoReply)
+                } else {
+                    (seq, response_type)
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        // Find matching probe
+        Ok(self
+            .complete_probe(seq, IpAddr::V4(source), response_type, icmp_code, received_at)
+            .map(|response| ProbeResponse { extensions, port_state, ..response }))
+    }
+
+    /// Match an ICMP Timestamp reply and collect the clock readings it carries
+    fn parse_timestamp_reply(&mut self, source: Ipv4Addr, icmp: &IcmpMessage, received_at: Instant) -> Option<ProbeResponse> {
+        let clocks = icmp.body.get(..ICMP_TIMESTAMP_LEN - ICMP_HEADER_LEN)?;
+        if icmp.identifier() != self.packet_id {
+            return None;
+        }
+        let seq = icmp.sequence();
+        let field = |offset: usize| u32::from_be_bytes([clocks[offset], clocks[offset + 1], clocks[offset + 2], clocks[offset + 3]]);
+
+        // The high bit flags a non-standard clock, which can't be compared with ours
+        let (originate, receive, transmit) = (field(0), field(4), field(8));
+        let arrival = ms_since_midnight(SystemTime::now() - received_at.elapsed());
+        let timestamps = [receive, transmit]
+            .iter()
+            .all(|&ts| ts < MS_PER_DAY)
+            .then_some(IcmpTimestamps { originate, receive, transmit, arrival });
+
+        self.complete_probe(seq, IpAddr::V4(source), IcmpResponseType::EchoReply, 0, received_at)
+            .map(|response| ProbeResponse { timestamps, ..response })
+    }
+
+    fn parse_icmp6_response(
+        &mut self,
+        buf: &[u8],
+        _addr: &SockAddr,
+        received_at: Instant,
     ) -> Result<Option<ProbeResponse>> {
-        if buf.len() < 28 { // IP header (20) + ICMP header (8)
+        // Raw ICMPv6 sockets deliver the message without the IPv6 header
+        let Ok(icmp) = IcmpMessage::parse(buf) else {
             return Ok(None);
+        };
+        let icmp_code = icmp.code;
+
+        let response_type = match icmp.icmp_type {
+            ICMP6_ECHO_REPLY => IcmpResponseType::EchoReply,
+            ICMP6_TIME_EXCEEDED => IcmpResponseType::TimeExceeded,
+            ICMP6_DST_UNREACH => IcmpResponseType::DestinationUnreachable,
+            _ => return Ok(None),
+        };
+
+        // Extract sequence number based on message type
+        let mut extensions = None;
+        let mut port_state = None;
+        let (seq, response_type) = match response_type {
+            IcmpResponseType::EchoReply => {
+                if !cookie_matches(icmp.body, &self.cookie) {
+                    return Ok(None);
+                }
+                (icmp.sequence(), response_type)
+            }
+            IcmpResponseType::TimeExceeded | IcmpResponseType::DestinationUnreachable => {
+                // For error messages, the original packet is embedded after the ICMPv6 header
+                let quoted;
+                (quoted, extensions) = icmp_ext::split_icmp_error(icmp.bytes, true);
+                let ours = QuotedProbeMatch {
+                    packet_id: self.packet_id,
+                    udp_src_port: self.udp6_socket.as_ref().map(|udp| udp.src_port),
+                    tcp_src_ports: &[],
+                    paris: self.paris,
+                    cookie: self.cookie,
+                };
+                let Some((seq, protocol)) = parse_quoted_ipv6(quoted, &ours) else {
+                    return Ok(None);
+                };
+                // Port unreachable (code 4) means a UDP probe reached the destination
+                if protocol == ProbeProtocol::Udp
+                    && response_type == IcmpResponseType::DestinationUnreachable
+                    && icmp_code == 4
+                {
+                    port_state = self.service.map(|_| PortState::Closed);
+                    (seq, IcmpResponseType::EchoReply)
+                } else {
+                    (seq, response_type)
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        // Extract source address from socket address
+        let source_addr = match _addr.as_socket() {
+            Some(SocketAddr::V6(v6_addr)) => IpAddr::V6(*v6_addr.ip()),
+            Some(SocketAddr::V4(v4_addr)) => IpAddr::V4(*v4_addr.ip()), // Shouldn't happen but handle it
+            None => return Ok(None),
+        };
+
+        // Find matching probe
+        Ok(self
+            .complete_probe(seq, source_addr, response_type, icmp_code, received_at)
+            .map(|response| ProbeResponse { extensions, port_state, ..response }))
+    }
+}
+
+impl ProbeTransport for ProbeEngine {
+    fn send_probe_with_protocol(
+        &mut self,
+        hop: usize,
+        dst: SocketAddr,
+        ttl: u8,
+        timeout: Duration,
+        protocol: ProbeProtocol,
+    ) -> Result<u16> {
+        ProbeEngine::send_probe_with_protocol(self, hop, dst, ttl, timeout, protocol)
+    }
+
+    fn send_probes(&mut self, requests: &[ProbeRequest], protocol: ProbeProtocol) -> Vec<Result<u16>> {
+        ProbeEngine::send_probes(self, requests, protocol)
+    }
+
+    fn collect_responses_async(&mut self) -> impl Future<Output = Result<Vec<ProbeResponse>>> + Send {
+        ProbeEngine::collect_responses_async(self)
+    }
+
+    fn expire_all(&mut self) -> Vec<ProbeResponse> {
+        ProbeEngine::expire_all(self)
+    }
+
+    fn discarded_replies(&self) -> usize {
+        self.discarded
+    }
+}
+
+/// Sockets the engine reads replies from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplySocket {
+    Icmp,
+    Icmp6,
+    Tcp,
+    Udp,
+    Udp6,
+}
+
+impl ReplySocket {
+    const ALL: [ReplySocket; 5] = [
+        ReplySocket::Icmp,
+        ReplySocket::Icmp6,
+        ReplySocket::Tcp,
+        ReplySocket::Udp,
+        ReplySocket::Udp6,
+    ];
+}
+
+/// Service spoken by UDP probes whose destination answers on the UDP socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UdpService {
+    Quic,
+    Dns,
+}
+
+impl UdpService {
+    fn for_protocol(protocol: ProbeProtocol) -> Option<Self> {
+        match protocol {
+            ProbeProtocol::Quic => Some(UdpService::Quic),
+            ProbeProtocol::Dns => Some(UdpService::Dns),
+            _ => None,
+        }
+    }
+}
+
+/// How the IPv4 ICMP socket was opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IcmpSocketKind {
+    Raw,
+    /// Linux ping socket: replies lack the IP header, errors use the error queue
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    Ping,
+    /// macOS datagram socket: unprivileged, but reads like a raw socket
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Datagram,
+    /// Linux last resort: the UDP probe socket itself, whose error queue
+    /// carries the ICMP errors our probes provoke
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    UdpErrors,
+}
+
+impl IcmpSocketKind {
+    /// Whether ICMP errors arrive on the socket error queue
+    fn queues_errors(self) -> bool {
+        matches!(self, IcmpSocketKind::Ping | IcmpSocketKind::UdpErrors)
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            IcmpSocketKind::Raw => "raw ICMP socket",
+            IcmpSocketKind::Ping => "unprivileged ping socket",
+            IcmpSocketKind::Datagram => "unprivileged ICMP datagram socket",
+            IcmpSocketKind::UdpErrors => "unprivileged UDP socket with IP_RECVERR",
+        }
+    }
+}
+
+/// A receive mechanism that could not be used, and why
+#[derive(Debug)]
+struct FailedAttempt {
+    mechanism: &'static str,
+    reason: String,
+}
+
+/// The attempts that came before the mechanism in use, for the log
+fn describe_attempts(attempts: &[FailedAttempt]) -> String {
+    attempts
+        .iter()
+        .map(|attempt| format!("\n  {} unavailable: {}", attempt.mechanism, attempt.reason))
+        .collect()
+}
+
+/// The error for when every mechanism failed, listing each with its cause
+fn no_usable_socket(attempts: &[FailedAttempt]) -> anyhow::Error {
+    anyhow::anyhow!(
+        "No usable probe socket:{}",
+        attempts
+            .iter()
+            .map(|attempt| format!("\n  {}: {}", attempt.mechanism, attempt.reason))
+            .collect::<String>()
+    )
+}
+
+/// Why a raw socket could not be opened
+fn explain_raw_error(err: &std::io::Error) -> String {
+    match err.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) => {
+            "missing CAP_NET_RAW - run with sudo, or grant it with `setcap cap_net_raw+ep`".to_string()
+        }
+        _ => err.to_string(),
+    }
+}
+
+/// Why a ping socket could not be opened
+///
+/// `ping_group_range` is the contents of `net.ipv4.ping_group_range`, when
+/// it could be read.
+fn explain_ping_error(err: &std::io::Error, gid: u32, ping_group_range: Option<&str>) -> String {
+    if err.kind() == std::io::ErrorKind::Unsupported {
+        return "not available on this platform".to_string();
+    }
+    match err.raw_os_error() {
+        Some(libc::EACCES) | Some(libc::EPERM) => {
+            let range = ping_group_range.map(str::trim).unwrap_or("unknown");
+            format!(
+                "group {} is outside net.ipv4.ping_group_range ({}) - \
+                 allow it with `sysctl net.ipv4.ping_group_range=\"0 2147483647\"`",
+                gid,
+                range.replace('\t', " ")
+            )
+        }
+        Some(libc::EAFNOSUPPORT) | Some(libc::EPROTONOSUPPORT) => {
+            "not supported by this kernel".to_string()
+        }
+        _ => err.to_string(),
+    }
+}
+
+/// Open the IPv4 ICMP socket used for echo probes and for receiving replies
+///
+/// macOS lets any user open ICMP datagram sockets, so ICMP traces use one
+/// there without trying for a raw socket. Elsewhere a raw socket is required
+/// for UDP and TCP probes; ICMP traces fall back to a Linux ping socket.
+/// Each mechanism that fails is recorded in `attempts`.
+fn open_icmp_socket(protocol: ProbeProtocol, attempts: &mut Vec<FailedAttempt>) -> Option<(Socket, IcmpSocketKind)> {
+    #[cfg(target_os = "macos")]
+    if protocol == ProbeProtocol::Icmp {
+        match Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4)) {
+            Ok(socket) => return Some((socket, IcmpSocketKind::Datagram)),
+            Err(e) => attempts.push(FailedAttempt {
+                mechanism: "ICMP datagram socket",
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    match Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)) {
+        Ok(socket) => return Some((socket, IcmpSocketKind::Raw)),
+        Err(e) => attempts.push(FailedAttempt {
+            mechanism: "raw ICMP socket",
+            reason: explain_raw_error(&e),
+        }),
+    }
+
+    if protocol == ProbeProtocol::Icmp {
+        match open_ping_socket() {
+            Ok(socket) => return Some((socket, IcmpSocketKind::Ping)),
+            Err(e) => {
+                // SAFETY: getegid only reads the process credentials
+                let gid = unsafe { libc::getegid() };
+                let range = std::fs::read_to_string("/proc/sys/net/ipv4/ping_group_range").ok();
+                attempts.push(FailedAttempt {
+                    mechanism: "ping socket",
+                    reason: explain_ping_error(&e, gid, range.as_deref()),
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Open the UDP probe socket as the last resort for ICMP and UDP traces
+///
+/// Linux queues the ICMP errors a connectionless UDP socket provokes once
+/// `IP_RECVERR` is on, which is enough to trace without privileges. Paris
+/// mode writes its own UDP headers and still needs a raw socket.
+#[cfg(target_os = "linux")]
+fn open_udp_fallback(options: &ProbeOptions, attempts: &mut Vec<FailedAttempt>) -> Option<UdpProbeSocket> {
+    const MECHANISM: &str = "UDP socket with IP_RECVERR";
+    if !matches!(options.protocol, ProbeProtocol::Icmp | ProbeProtocol::Udp) {
+        attempts.push(FailedAttempt {
+            mechanism: MECHANISM,
+            reason: format!("cannot carry {:?} probes", options.protocol),
+        });
+        return None;
+    }
+    if options.paris {
+        attempts.push(FailedAttempt {
+            mechanism: MECHANISM,
+            reason: "--paris needs a raw socket".to_string(),
+        });
+        return None;
+    }
+
+    let opened = UdpProbeSocket::bind(Domain::IPV4, options).and_then(|udp| {
+        enable_recv_errors(&udp.socket)?;
+        enable_rx_timestamps(&udp.socket);
+        Ok(udp)
+    });
+    opened
+        .map_err(|e| {
+            attempts.push(FailedAttempt {
+                mechanism: MECHANISM,
+                reason: format!("{:#}", e),
+            })
+        })
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_udp_fallback(_options: &ProbeOptions, _attempts: &mut Vec<FailedAttempt>) -> Option<UdpProbeSocket> {
+    None
+}
+
+/// Open an unprivileged ICMP socket (Linux `net.ipv4.ping_group_range`)
+///
+/// The kernel strips the IP header from replies and rewrites the echo
+/// identifier, and ICMP errors are only delivered through the socket error
+/// queue, so `IP_RECVERR` is enabled.
+#[cfg(target_os = "linux")]
+fn open_ping_socket() -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))?;
+    enable_recv_errors(&socket)?;
+    Ok(socket)
+}
+
+/// Have ICMP errors about our datagrams queued on the socket (`IP_RECVERR`)
+#[cfg(target_os = "linux")]
+fn enable_recv_errors(socket: &Socket) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let enable: libc::c_int = 1;
+    // SAFETY: the option value is a valid c_int that outlives the call
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_IP,
+            libc::IP_RECVERR,
+            (&enable as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_ping_socket() -> std::io::Result<Socket> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// An ICMP error reported for one of our probes on the error queue
+#[cfg(target_os = "linux")]
+struct QueuedIcmpError {
+    offender: Ipv4Addr,
+    icmp_type: u8,
+    icmp_code: u8,
+    seq: u16,
+    timestamp: Option<SystemTime>,
+    reply_ttl: Option<u8>,
+}
+
+/// Read one entry from the socket error queue
+///
+/// Fails with `WouldBlock` once the queue is empty; entries that are not ICMP
+/// errors about our probes yield `Ok(None)`.
+#[cfg(target_os = "linux")]
+fn recv_queued_icmp_error(socket: &Socket, kind: IcmpSocketKind) -> std::io::Result<Option<QueuedIcmpError>> {
+    use std::os::fd::AsRawFd;
+
+    // The queued payload is the echo request or UDP payload we sent, and the
+    // address is the probe's original destination
+    let mut data = [0u8; 64];
+    let mut control = [0u64; 32];
+    // SAFETY: sockaddr_in is plain data
+    let mut target: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    // SAFETY: msghdr is plain data; every pointer set below outlives recvmsg
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = (&mut target as *mut libc::sockaddr_in).cast();
+    msg.msg_namelen = std::mem::size_of_val(&target) as _;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    // SAFETY: msg describes valid, writable buffers
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let seq = if kind == IcmpSocketKind::UdpErrors {
+        // UDP probes carry the sequence in their destination port
+        match seq_for_udp_port(u16::from_be(target.sin_port)) {
+            Some(seq) => seq,
+            None => return Ok(None),
+        }
+    } else {
+        match IcmpMessage::parse(&data[..len as usize]) {
+            Ok(echo) => echo.sequence(),
+            Err(_) => return Ok(None),
+        }
+    };
+
+    // SAFETY: the CMSG_* helpers walk the control buffer the kernel just filled
+    let error = unsafe {
+        let mut error = None;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_IP && (*cmsg).cmsg_type == libc::IP_RECVERR {
+                let err_ptr = libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err;
+                let err = std::ptr::read_unaligned(err_ptr);
+                if err.ee_origin != libc::SO_EE_ORIGIN_ICMP {
+                    return Ok(None);
+                }
+                let offender = std::ptr::read_unaligned(libc::SO_EE_OFFENDER(err_ptr) as *const libc::sockaddr_in);
+                if offender.sin_family != libc::AF_INET as libc::sa_family_t {
+                    return Ok(None);
+                }
+                error = Some((Ipv4Addr::from(u32::from_be(offender.sin_addr.s_addr)), err.ee_type, err.ee_code));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        error
+    };
+
+    Ok(error.map(|(offender, icmp_type, icmp_code)| QueuedIcmpError {
+        offender,
+        icmp_type,
+        icmp_code,
+        seq,
+        // SAFETY: msg still describes the control buffer filled above
+        timestamp: unsafe { cmsg_timestamp(&msg) },
+        // SAFETY: as above
+        reply_ttl: unsafe { cmsg_reply_ttl(&msg) },
+    }))
+}
+
+/// Ask the kernel to timestamp incoming packets, returning whether it agreed
+///
+/// Linux reports nanoseconds (`SO_TIMESTAMPNS`), other systems microseconds
+/// (`SO_TIMESTAMP`). Without it, replies are timed when we read them.
+fn enable_rx_timestamps(socket: &Socket) -> bool {
+    use std::os::fd::AsRawFd;
+
+    #[cfg(target_os = "linux")]
+    let option = libc::SO_TIMESTAMPNS;
+    #[cfg(not(target_os = "linux"))]
+    let option = libc::SO_TIMESTAMP;
+    let on: libc::c_int = 1;
+    // SAFETY: `on` is a valid c_int for the duration of the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    result == 0
+}
+
+/// Ask the kernel to report the TTL (hop limit) of incoming packets
+///
+/// Needed wherever replies arrive without their IP header: ping and datagram
+/// ICMP sockets, and every IPv6 socket.
+fn enable_reply_ttl(socket: &Socket, domain: Domain) -> bool {
+    use std::os::fd::AsRawFd;
+
+    let (level, option) = if domain == Domain::IPV6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_RECVTTL)
+    };
+    let on: libc::c_int = 1;
+    // SAFETY: `on` is a valid c_int for the duration of the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    result == 0
+}
+
+/// Keep everything but the replies a trace uses off the raw ICMPv6 socket
+///
+/// Without it every Neighbor Discovery and Router Advertisement packet on the
+/// link wakes the engine up. Best effort: parsing ignores them anyway.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+    target_vendor = "apple"
+))]
+fn filter_icmp6_replies(socket: &Socket) -> bool {
+    use std::os::fd::AsRawFd;
+
+    #[cfg(target_os = "linux")]
+    const ICMP6_FILTER: libc::c_int = 1;
+    #[cfg(not(target_os = "linux"))]
+    const ICMP6_FILTER: libc::c_int = 18;
+    let filter = icmp6_filter(
+        &[ICMP6_DST_UNREACH, ICMP6_TIME_EXCEEDED, ICMP6_ECHO_REPLY],
+        cfg!(target_os = "linux"),
+    );
+    // SAFETY: `filter` has the layout of `struct icmp6_filter` for the duration of the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_ICMPV6,
+            ICMP6_FILTER,
+            filter.as_ptr().cast(),
+            std::mem::size_of_val(&filter) as libc::socklen_t,
+        )
+    };
+    result == 0
+}
+
+/// A `struct icmp6_filter` that lets only the `pass` types through
+///
+/// The layout is shared, but Linux blocks the types whose bit is set while
+/// the BSDs and macOS pass them.
+fn icmp6_filter(pass: &[u8], set_bit_blocks: bool) -> [u32; 8] {
+    let mut passing = [0u32; 8];
+    for &icmp_type in pass {
+        passing[usize::from(icmp_type >> 5)] |= 1 << (icmp_type & 31);
+    }
+    if set_bit_blocks {
+        passing.map(|word| !word)
+    } else {
+        passing
+    }
+}
+
+/// The engine's receive sockets registered with the tokio reactor
+///
+/// Each holds a duplicate of the engine's descriptor, so readiness is
+/// tracked here while reads still go through the original socket.
+struct ReadySockets {
+    icmp: AsyncFd<Socket>,
+    icmp_interest: Interest,
+    icmp6: Option<AsyncFd<Socket>>,
+    tcp: Option<AsyncFd<Socket>>,
+    udp: Option<AsyncFd<Socket>>,
+    udp6: Option<AsyncFd<Socket>>,
+}
+
+impl ReadySockets {
+    fn register(engine: &ProbeEngine) -> Result<Self> {
+        let register = |socket: &Socket| -> Result<AsyncFd<Socket>> {
+            let socket = socket.try_clone()?;
+            socket.set_nonblocking(true)?;
+            AsyncFd::with_interest(socket, Interest::READABLE).context("Failed to register probe socket")
+        };
+        // Queued errors only raise POLLERR; the UDP fallback reads nothing else
+        let icmp_interest = match engine.icmp_kind {
+            IcmpSocketKind::Ping => Interest::READABLE | Interest::ERROR,
+            IcmpSocketKind::UdpErrors => Interest::ERROR,
+            _ => Interest::READABLE,
+        };
+
+        Ok(Self {
+            icmp: register(&engine.icmp_socket)?,
+            icmp_interest,
+            icmp6: engine.icmp6_socket.as_ref().map(register).transpose()?,
+            tcp: engine.tcp_socket.as_ref().map(|tcp| register(&tcp.socket)).transpose()?,
+            udp: engine.reply_socket(ReplySocket::Udp).map(register).transpose()?,
+            udp6: engine.reply_socket(ReplySocket::Udp6).map(register).transpose()?,
+        })
+    }
+
+    /// Sleep until a socket may have data or `deadline` passes
+    ///
+    /// Readiness is cleared on wake-up, before the caller reads, so data that
+    /// arrives while draining raises a fresh event instead of being missed.
+    async fn wait(&self, deadline: Option<Instant>) {
+        tokio::select! {
+            _ = wait_ready(Some(&self.icmp), self.icmp_interest) => {}
+            _ = wait_ready(self.icmp6.as_ref(), Interest::READABLE) => {}
+            _ = wait_ready(self.tcp.as_ref(), Interest::READABLE) => {}
+            _ = wait_ready(self.udp.as_ref(), Interest::READABLE) => {}
+            _ = wait_ready(self.udp6.as_ref(), Interest::READABLE) => {}
+            _ = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                    None => std::future::pending().await,
+                }
+            } => {}
+        }
+    }
+}
+
+async fn wait_ready(socket: Option<&AsyncFd<Socket>>, interest: Interest) {
+    let Some(socket) = socket else {
+        return std::future::pending().await;
+    };
+    if let Ok(mut guard) = socket.ready(interest).await {
+        guard.clear_ready();
+    }
+}
+
+/// A built probe waiting to go out on its socket
+#[derive(Clone)]
+struct OutgoingProbe<'a> {
+    socket: &'a Socket,
+    packet: Vec<u8>,
+    target: SockAddr,
+    ttl: u8,
+}
+
+impl OutgoingProbe<'_> {
+    /// Set the socket's hop limit and send this probe on its own
+    fn send(&self) -> std::io::Result<()> {
+        if self.target.is_ipv6() {
+            self.socket.set_unicast_hops_v6(self.ttl.into())?;
+        } else {
+            self.socket.set_ttl(self.ttl.into())?;
+        }
+        // See send_mmsg for why a failed send is tried again
+        if self.socket.send_to(&self.packet, &self.target).is_err() {
+            self.socket.send_to(&self.packet, &self.target)?;
+        }
+        Ok(())
+    }
+
+    /// Describe this probe as a `msghdr` with the hop limit as ancillary data
+    ///
+    /// The header points into `self`, `iov` and `control`, which must outlive
+    /// the send it is used for.
+    #[cfg(target_os = "linux")]
+    fn ttl_message(&self, iov: &mut libc::iovec, control: &mut [u64; 4]) -> libc::msghdr {
+        let (level, kind) = if self.target.is_ipv6() {
+            (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT)
+        } else {
+            (libc::IPPROTO_IP, libc::IP_TTL)
+        };
+        *iov = libc::iovec {
+            iov_base: self.packet.as_ptr() as *mut libc::c_void,
+            iov_len: self.packet.len(),
+        };
+        // SAFETY: msghdr is plain data, and the control buffer has room for
+        // the single int cmsg written into it
+        unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = self.target.as_ptr() as *mut libc::c_void;
+            msg.msg_namelen = self.target.len();
+            msg.msg_iov = iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<libc::c_int>() as u32) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = level;
+            (*cmsg).cmsg_type = kind;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<libc::c_int>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::c_int>(), self.ttl.into());
+            msg
+        }
+    }
+}
+
+/// Send probes in order, returning one result per probe
+///
+/// On Linux each run of probes sharing a socket goes out with a single
+/// `sendmmsg`, the hop limit riding along as ancillary data. Elsewhere every
+/// probe sets the socket option and is sent on its own.
+fn send_outgoing(probes: &[OutgoingProbe]) -> Vec<std::io::Result<()>> {
+    #[cfg(target_os = "linux")]
+    {
+        probes
+            .chunk_by(|a, b| std::ptr::eq(a.socket, b.socket))
+            .flat_map(|run| send_mmsg(run).unwrap_or_else(|_| run.iter().map(OutgoingProbe::send).collect()))
+            .collect()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        probes.iter().map(OutgoingProbe::send).collect()
+    }
+}
+
+/// Send probes sharing one socket with `sendmmsg`
+///
+/// Failures are reported per probe and the rest of the run is still sent.
+/// The call only fails, with nothing sent, when the kernel rejects the hop
+/// limit as ancillary data, which older kernels do.
+///
+/// On sockets with `IP_RECVERR` the ICMP error an earlier probe provoked is
+/// also reported, once, by the next send, which it fails. Each message is
+/// therefore tried a second time before it counts as refused.
+#[cfg(target_os = "linux")]
+fn send_mmsg(run: &[OutgoingProbe]) -> std::io::Result<Vec<std::io::Result<()>>> {
+    use std::os::fd::AsRawFd;
+
+    let Some(first) = run.first() else {
+        return Ok(Vec::new());
+    };
+    let mut iovecs = vec![libc::iovec { iov_base: std::ptr::null_mut(), iov_len: 0 }; run.len()];
+    let mut controls = vec![[0u64; 4]; run.len()];
+    let mut messages: Vec<libc::mmsghdr> = run
+        .iter()
+        .zip(iovecs.iter_mut().zip(controls.iter_mut()))
+        .map(|(probe, (iov, control))| libc::mmsghdr {
+            msg_hdr: probe.ttl_message(iov, control),
+            msg_len: 0,
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(run.len());
+    let mut retried = false;
+    while results.len() < messages.len() {
+        let offset = results.len();
+        // SAFETY: every message from `offset` on describes valid buffers
+        let sent = unsafe {
+            libc::sendmmsg(
+                first.socket.as_raw_fd(),
+                messages[offset..].as_mut_ptr(),
+                (messages.len() - offset) as _,
+                0,
+            )
+        };
+        if sent > 0 {
+            results.extend((0..sent).map(|_| Ok(())));
+            retried = false;
+        } else {
+            let error = std::io::Error::last_os_error();
+            if offset == 0 && error.raw_os_error() == Some(libc::EINVAL) {
+                return Err(error);
+            }
+            if !retried {
+                retried = true;
+                continue;
+            }
+            // Skip the refused message and carry on with the rest
+            results.push(Err(error));
+            retried = false;
         }
+    }
+    Ok(results)
+}
 
-        // Parse IP header
-        let ip_header_len = ((buf[0] & 0x0f) * 4) as usize;
-        if buf.len() < ip_header_len + 8 {
-            return Ok(None);
+/// Receive buffers reused across collection passes
+///
+/// On Linux a whole batch is read with one `recvmmsg` call; elsewhere the
+/// batch is filled one `recvmsg` at a time. Buffers are allocated on first use.
+#[derive(Default)]
+struct RecvBatch {
+    buffers: Vec<[u8; MAX_MTU]>,
+    received: Vec<(usize, SockAddr, Instant, Option<u8>)>,
+}
+
+impl RecvBatch {
+    fn reserve(&mut self) {
+        if self.buffers.is_empty() {
+            self.buffers = vec![[0u8; MAX_MTU]; RECV_BATCH_SIZE];
         }
+        self.received.clear();
+    }
 
-        let source = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
-        let icmp_data = &buf[ip_header_len..];
+    /// Read up to `RECV_BATCH_SIZE` datagrams, failing with `WouldBlock` if none are queued
+    #[cfg(target_os = "linux")]
+    fn recv(&mut self, socket: &Socket) -> std::io::Result<usize> {
+        use std::os::fd::AsRawFd;
 
-        // Parse ICMP header
-        let icmp_type = icmp_data[0];
-        
-        let response_type = match icmp_type {
-            0 => IcmpResponseType::EchoReply,
-            11 => IcmpResponseType::TimeExceeded,
-            3 => IcmpResponseType::DestinationUnreachable,
-            _ => return Ok(None),
+        self.reserve();
+        let mut iovecs: Vec<libc::iovec> = self
+            .buffers
+            .iter_mut()
+            .map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut_ptr().cast(),
+                iov_len: buffer.len(),
+            })
+            .collect();
+        // SAFETY: sockaddr_storage is plain data
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { std::mem::zeroed() }; RECV_BATCH_SIZE];
+        let mut controls = vec![[0u64; 16]; RECV_BATCH_SIZE];
+        let mut messages: Vec<libc::mmsghdr> = (0..RECV_BATCH_SIZE)
+            .map(|i| {
+                // SAFETY: mmsghdr is plain data; every pointer set below outlives recvmmsg
+                let mut message: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                message.msg_hdr.msg_name = (&mut addrs[i] as *mut libc::sockaddr_storage).cast();
+                message.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                message.msg_hdr.msg_iov = &mut iovecs[i];
+                message.msg_hdr.msg_iovlen = 1;
+                message.msg_hdr.msg_control = controls[i].as_mut_ptr().cast();
+                message.msg_hdr.msg_controllen = std::mem::size_of_val(&controls[i]) as _;
+                message
+            })
+            .collect();
+
+        // SAFETY: every message describes valid, writable buffers
+        let count = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                messages.as_mut_ptr(),
+                RECV_BATCH_SIZE as _,
+                libc::MSG_DONTWAIT as _,
+                std::ptr::null_mut(),
+            )
         };
+        if count < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
 
-        // Extract sequence number
-        let seq = match response_type {
-            IcmpResponseType::EchoReply => {
-                if icmp_data.len() >= 8 {
-                    u16::from_be_bytes([icmp_data[6], icmp_data[7]])
-                } else {
-                    return Ok(None);
-                }
+        for (message, addr) in messages.iter().zip(addrs).take(count as usize) {
+            // SAFETY: the kernel filled in the address and control buffer of this message
+            let (addr, timestamp, reply_ttl) = unsafe {
+                (
+                    SockAddr::new(addr, message.msg_hdr.msg_namelen),
+                    cmsg_timestamp(&message.msg_hdr),
+                    cmsg_reply_ttl(&message.msg_hdr),
+                )
+            };
+            self.received.push((message.msg_len as usize, addr, receive_instant(timestamp), reply_ttl));
+        }
+        Ok(count as usize)
+    }
+
+    /// Read up to `RECV_BATCH_SIZE` datagrams, failing with `WouldBlock` if none are queued
+    #[cfg(not(target_os = "linux"))]
+    fn recv(&mut self, socket: &Socket) -> std::io::Result<usize> {
+        self.reserve();
+        for buffer in self.buffers.iter_mut() {
+            match recv_timestamped(socket, buffer) {
+                Ok(received) => self.received.push(received),
+                Err(e) if self.received.is_empty() => return Err(e),
+                Err(_) => break,
             }
-            IcmpResponseType::TimeExceeded | IcmpResponseType::DestinationUnreachable => {
-                // Extract from original packet in ICMP payload
-                if icmp_data.len() >= 36 {
-                    let orig_icmp_offset = 8 + 20; // ICMP header + IP header
-                    if icmp_data.len() >= orig_icmp_offset + 8 {
-                        u16::from_be_bytes([
-                            icmp_data[orig_icmp_offset + 6],
-                            icmp_data[orig_icmp_offset + 7],
-                        ])
-                    } else {
-                        return Ok(None);
-                    }
-                } else {
-                    return Ok(None);
-                }
+        }
+        Ok(self.received.len())
+    }
+
+    /// The datagrams read by the last `recv`, with their receive time and TTL
+    fn packets(&self, count: usize) -> impl Iterator<Item = (&[u8], &SockAddr, Instant, Option<u8>)> {
+        self.buffers
+            .iter()
+            .zip(&self.received)
+            .take(count)
+            .map(|(buffer, (len, addr, received_at, reply_ttl))| (&buffer[..*len], addr, *received_at, *reply_ttl))
+    }
+}
+
+/// Receive one datagram along with the time the kernel received it
+///
+/// Fails with `WouldBlock` once the socket is drained. The returned instant
+/// comes from the kernel timestamp when the socket has one enabled.
+#[cfg(not(target_os = "linux"))]
+fn recv_timestamped(socket: &Socket, buf: &mut [u8]) -> std::io::Result<(usize, SockAddr, Instant, Option<u8>)> {
+    use std::os::fd::AsRawFd;
+
+    let mut control = [0u64; 16];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut timestamp = None;
+    let mut reply_ttl = None;
+    // SAFETY: msghdr is plain data and every buffer it points to outlives
+    // recvmsg; try_init hands us storage large enough for any address
+    let (len, addr) = unsafe {
+        SockAddr::try_init(|storage, addr_len| {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = storage.cast();
+            msg.msg_namelen = *addr_len;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+            let len = libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_DONTWAIT);
+            if len < 0 {
+                return Err(std::io::Error::last_os_error());
             }
-            _ => return Ok(None),
-        };
+            *addr_len = msg.msg_namelen;
+            timestamp = cmsg_timestamp(&msg);
+            reply_ttl = cmsg_reply_ttl(&msg);
+            Ok(len as usize)
+        })?
+    };
+    Ok((len, addr, receive_instant(timestamp), reply_ttl))
+}
 
-        // Find matching probe
-        if let Some(probe) = self.pending.remove(&seq) {
-            let (rtt, precise_rtt_ns) = probe.get_precise_rtt(Instant::now());
-            Ok(Some(ProbeResponse {
-                hop: probe.hop,
-                seq,
-                source_addr: IpAddr::V4(source),
-                icmp_type: response_type,
-                rtt,
-                send_time: probe.sent_at,
-                receive_time: Instant::now(),
-                precise_rtt_ns,
-            }))
-        } else {
-            Ok(None)
+/// Find the receive timestamp among a message's control messages
+///
+/// # Safety
+/// `msg` must describe a control buffer filled in by `recvmsg`.
+unsafe fn cmsg_timestamp(msg: &libc::msghdr) -> Option<SystemTime> {
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET {
+            #[cfg(target_os = "linux")]
+            if (*cmsg).cmsg_type == libc::SCM_TIMESTAMPNS {
+                let ts = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timespec);
+                return Some(SystemTime::UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32));
+            }
+            #[cfg(not(target_os = "linux"))]
+            if (*cmsg).cmsg_type == libc::SCM_TIMESTAMP {
+                let tv = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timeval);
+                return Some(SystemTime::UNIX_EPOCH + Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000));
+            }
         }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
     }
+    None
+}
 
-    fn parse_icmp6_response(
-        &mut self,
-        buf: &[u8],
-        _addr: socket2::SockAddr,
-    ) -> Result<Option<ProbeResponse>> {
-        // ICMPv6 has a simpler header structure than IPv4
-        if buf.len() < 8 { // Minimum ICMPv6 header size
-            return Ok(None);
+/// Find the reply TTL (IPv4) or hop limit (IPv6) among a message's control messages
+///
+/// # Safety
+/// `msg` must describe a control buffer filled in by `recvmsg`.
+unsafe fn cmsg_reply_ttl(msg: &libc::msghdr) -> Option<u8> {
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        let (level, kind) = ((*cmsg).cmsg_level, (*cmsg).cmsg_type);
+        let data = libc::CMSG_DATA(cmsg);
+        // Linux reports an int for both; BSDs send IPv4 TTLs as a single byte
+        #[cfg(target_os = "linux")]
+        if level == libc::IPPROTO_IP && kind == libc::IP_TTL {
+            return u8::try_from(std::ptr::read_unaligned(data as *const libc::c_int)).ok();
+        }
+        #[cfg(not(target_os = "linux"))]
+        if level == libc::IPPROTO_IP && kind == libc::IP_RECVTTL {
+            return Some(*data);
+        }
+        if level == libc::IPPROTO_IPV6 && kind == libc::IPV6_HOPLIMIT {
+            return u8::try_from(std::ptr::read_unaligned(data as *const libc::c_int)).ok();
         }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    None
+}
 
-        // For ICMPv6, the packet often starts directly with the ICMPv6 header
-        // (no IPv6 header in raw socket read for ICMPv6)
-        let icmp6_type = buf[0];
-        
-        let response_type = match icmp6_type {
-            129 => IcmpResponseType::EchoReply,    // ICMPv6 Echo Reply
-            3 => IcmpResponseType::TimeExceeded,    // ICMPv6 Time Exceeded
-            1 => IcmpResponseType::DestinationUnreachable, // ICMPv6 Destination Unreachable
-            _ => return Ok(None),
-        };
+/// Map a wall-clock kernel timestamp onto the monotonic clock probes are sent on
+///
+/// Only the packet's age is taken from the wall clock, so a receive timestamp
+/// compares correctly with `Instant`-based send times.
+fn receive_instant(kernel_timestamp: Option<SystemTime>) -> Instant {
+    let now = Instant::now();
+    kernel_timestamp
+        .and_then(|timestamp| SystemTime::now().duration_since(timestamp).ok())
+        .filter(|age| *age < MAX_TIMESTAMP_AGE)
+        .and_then(|age| now.checked_sub(age))
+        .unwrap_or(now)
+}
 
-        // Extract sequence number based on message type
-        let seq = match response_type {
-            IcmpResponseType::EchoReply => {
-                if buf.len() >= 8 {
-                    u16::from_be_bytes([buf[6], buf[7]])
-                } else {
-                    return Ok(None);
-                }
+/// Destination port that carries a UDP probe's sequence number
+fn udp_port_for_seq(seq: u16) -> u16 {
+    UDP_BASE_PORT + (seq - INITIAL_SEQUENCE)
+}
+
+/// Sequence number of a UDP probe, if the port is in our range
+fn seq_for_udp_port(port: u16) -> Option<u16> {
+    let offset = port.checked_sub(UDP_BASE_PORT)?;
+    INITIAL_SEQUENCE
+        .checked_add(offset)
+        .filter(|seq| *seq <= MAX_SEQUENCE)
+}
+
+/// TCP sequence number of a SYN probe: our packet id, then the probe sequence
+fn tcp_seq_for_probe(packet_id: u16, seq: u16) -> u32 {
+    (u32::from(packet_id) << 16) | u32::from(seq)
+}
+
+/// Probe sequence from a TCP sequence number, if it carries our packet id
+fn probe_seq_for_tcp(packet_id: u16, tcp_seq: u32) -> Option<u16> {
+    ((tcp_seq >> 16) as u16 == packet_id).then_some(tcp_seq as u16)
+}
+
+/// What identifies our own probes inside a quoted packet
+struct QuotedProbeMatch<'a> {
+    packet_id: u16,
+    udp_src_port: Option<u16>,
+    tcp_src_ports: &'a [u16],
+    paris: bool, // UDP sequence is in the checksum rather than the port
+    cookie: [u8; COOKIE_LEN],
+}
+
+/// Whether an echo payload carries our cookie
+///
+/// Payloads too short to hold one are accepted: probes sized below the cookie
+/// don't carry it, and routers may quote only the first 8 bytes of a probe.
+fn cookie_matches(payload: &[u8], cookie: &[u8; COOKIE_LEN]) -> bool {
+    payload.get(..COOKIE_LEN).is_none_or(|head| head == cookie)
+}
+
+/// Recover the sequence number from a probe quoted in an ICMP error
+///
+/// `quoted` starts at the original IPv4 header. UDP and TCP probes are only
+/// accepted when they came from our probe sockets' source ports.
+fn parse_quoted_ipv4(quoted: &[u8], ours: &QuotedProbeMatch) -> Option<(u16, ProbeProtocol)> {
+    let ip = Ipv4Packet::parse_quoted(quoted).ok()?;
+    parse_quoted_transport(ip.protocol, ip.payload, IPPROTO_ICMP, ours)
+}
+
+/// IPv6 variant of `parse_quoted_ipv4`; `quoted` starts at the original IPv6 header
+fn parse_quoted_ipv6(quoted: &[u8], ours: &QuotedProbeMatch) -> Option<(u16, ProbeProtocol)> {
+    let ip = Ipv6Packet::parse_quoted(quoted).ok()?;
+    parse_quoted_transport(ip.protocol, ip.payload, IPPROTO_ICMPV6, ours)
+}
+
+/// Match a quoted probe, from its transport header on, against our probe formats
+fn parse_quoted_transport(
+    protocol: u8,
+    payload: &[u8],
+    icmp_protocol: u8,
+    ours: &QuotedProbeMatch,
+) -> Option<(u16, ProbeProtocol)> {
+    match protocol {
+        p if p == icmp_protocol => {
+            let icmp = IcmpMessage::parse(payload).ok()?;
+            if p == IPPROTO_ICMP && icmp.icmp_type == ICMP_TIMESTAMP {
+                // Timestamp requests carry no cookie, only our identifier
+                (icmp.identifier() == ours.packet_id).then(|| (icmp.sequence(), ProbeProtocol::Timestamp))
+            } else {
+                cookie_matches(icmp.body, &ours.cookie).then(|| (icmp.sequence(), ProbeProtocol::Icmp))
             }
-            IcmpResponseType::TimeExceeded | IcmpResponseType::DestinationUnreachable => {
-                // For error messages, the original packet is embedded
-                // Skip ICMPv6 header (8 bytes) + IPv6 header (40 bytes) to get to original ICMPv6
-                if buf.len() >= 56 { // 8 + 40 + 8 minimum
-                    let orig_icmp_offset = 8 + 40;
-                    if buf.len() >= orig_icmp_offset + 8 {
-                        u16::from_be_bytes([
-                            buf[orig_icmp_offset + 6],
-                            buf[orig_icmp_offset + 7],
-                        ])
-                    } else {
-                        return Ok(None);
-                    }
-                } else {
-                    return Ok(None);
-                }
+        }
+        IPPROTO_UDP => {
+            let udp = UdpHeader::parse(payload).ok()?;
+            if Some(udp.src_port) != ours.udp_src_port {
+                return None;
             }
-            _ => return Ok(None),
-        };
+            let seq = if ours.paris { udp.checksum } else { seq_for_udp_port(udp.dst_port)? };
+            Some((seq, ProbeProtocol::Udp))
+        }
+        IPPROTO_TCP => {
+            let tcp = QuotedTcp::parse(payload).ok()?;
+            if !ours.tcp_src_ports.contains(&tcp.src_port) {
+                return None;
+            }
+            Some((probe_seq_for_tcp(ours.packet_id, tcp.seq)?, ProbeProtocol::Tcp))
+        }
+        _ => None,
+    }
+}
 
-        // Extract source address from socket address
-        let source_addr = match _addr.as_socket() {
-            Some(SocketAddr::V6(v6_addr)) => IpAddr::V6(*v6_addr.ip()),
-            Some(SocketAddr::V4(v4_addr)) => IpAddr::V4(*v4_addr.ip()), // Shouldn't happen but handle it
-            None => return Ok(None),
-        };
+/// Match a SYN-ACK or RST from the destination to the SYN it answers
+///
+/// `packet` is a full IPv4 packet as read from the raw TCP socket. Returns the
+/// sender, the probe sequence acknowledged and whether the port was open.
+fn parse_tcp_reply(packet: &[u8], src_ports: &[u16], packet_id: u16) -> Option<(Ipv4Addr, u16, PortState)> {
+    let ip = Ipv4Packet::parse(packet).ok().filter(|ip| ip.protocol == IPPROTO_TCP)?;
+    let tcp = TcpHeader::parse(ip.payload).ok()?;
+    if !src_ports.contains(&tcp.dst_port) || tcp.flags & (TCP_RST | TCP_ACK) == 0 {
+        return None;
+    }
 
-        // Find matching probe
-        if let Some(probe) = self.pending.remove(&seq) {
-            let (rtt, precise_rtt_ns) = probe.get_precise_rtt(Instant::now());
-            Ok(Some(ProbeResponse {
-                hop: probe.hop,
-                seq,
-                source_addr,
-                icmp_type: response_type,
-                rtt,
-                send_time: probe.sent_at,
-                receive_time: Instant::now(),
-                precise_rtt_ns,
-            }))
-        } else {
-            Ok(None)
+    // Both SYN-ACK and RST+ACK acknowledge our sequence number plus one
+    let state = if tcp.flags & TCP_RST != 0 { PortState::Closed } else { PortState::Open };
+    Some((ip.source, probe_seq_for_tcp(packet_id, tcp.ack.wrapping_sub(1))?, state))
+}
+
+fn ones_complement_add(a: u16, b: u16) -> u16 {
+    let sum = u32::from(a) + u32::from(b);
+    ((sum & 0xffff) + (sum >> 16)) as u16
+}
+
+/// Echo identifier for a Paris-mode probe
+///
+/// Identifier and sequence always sum to `flow`, so the ICMP checksum is the
+/// same for every probe in the flow.
+fn paris_icmp_id(flow: u16, seq: u16) -> u16 {
+    ones_complement_add(flow, !seq)
+}
+
+/// TCP/UDP pseudo-header for checksum calculation
+fn pseudo_header(src: IpAddr, dst: IpAddr, protocol: u8, length: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(40);
+    match (src, dst) {
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            header.extend_from_slice(&u32::from(length).to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0, protocol]);
+        }
+        _ => {
+            let octets = |addr: IpAddr| match addr {
+                IpAddr::V4(v4) => v4.octets(),
+                IpAddr::V6(v6) => v6.to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED).octets(),
+            };
+            header.extend_from_slice(&octets(src));
+            header.extend_from_slice(&octets(dst));
+            header.extend_from_slice(&[0, protocol]);
+            header.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+    header
+}
+
+/// Build a UDP datagram (header and payload) with a valid checksum
+fn construct_udp_datagram(src: IpAddr, dst: IpAddr, src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let length = (8 + payload.len()) as u16;
+    let mut datagram = Vec::with_capacity(length as usize);
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst_port.to_be_bytes());
+    datagram.extend_from_slice(&length.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]); // Checksum field
+    datagram.extend_from_slice(payload);
+
+    let mut pseudo = pseudo_header(src, dst, IPPROTO_UDP, length);
+    pseudo.extend_from_slice(&datagram);
+    let checksum = calculate_icmp_checksum(&pseudo);
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+    datagram
+}
+
+/// Fill two payload bytes at the even offset `at` so the datagram's UDP checksum equals `seq`
+fn set_udp_checksum_payload(
+    payload: &mut [u8],
+    at: usize,
+    src: IpAddr,
+    dst: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+    seq: u16,
+) {
+    payload[at..at + 2].fill(0);
+    let datagram = construct_udp_datagram(src, dst, src_port, dst_port, payload);
+    let sum = !u16::from_be_bytes([datagram[6], datagram[7]]);
+
+    // checksum = !(sum + x), so x = !seq - sum
+    let adjust = ones_complement_add(!seq, !sum);
+    payload[at..at + 2].copy_from_slice(&adjust.to_be_bytes());
+}
+
+/// Connection ID for a QUIC probe: our cookie, the engine id, then `seq`
+fn quic_connection_id(cookie: &[u8; COOKIE_LEN], packet_id: u16, seq: u16) -> [u8; QUIC_CID_LEN] {
+    let mut cid = [0u8; QUIC_CID_LEN];
+    cid[..4].copy_from_slice(cookie);
+    cid[4..6].copy_from_slice(&packet_id.to_be_bytes());
+    cid[6..].copy_from_slice(&seq.to_be_bytes());
+    cid
+}
+
+/// Build a padded QUIC long-header Initial carrying `cid` as both connection IDs
+///
+/// Nothing past the header is meaningful: servers reject the reserved version
+/// before trying to decrypt the payload.
+fn construct_quic_initial(cid: &[u8; QUIC_CID_LEN]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(QUIC_INITIAL_LEN);
+    packet.push(0xc0); // Long header, fixed bit, Initial, 1-byte packet number
+    packet.extend_from_slice(&QUIC_PROBE_VERSION.to_be_bytes());
+    for _ in 0..2 {
+        packet.push(QUIC_CID_LEN as u8); // Destination, then source connection ID
+        packet.extend_from_slice(cid);
+    }
+    packet.push(0); // Token length
+    let length = (QUIC_INITIAL_LEN - packet.len() - 2) as u16;
+    packet.extend_from_slice(&(0x4000 | length).to_be_bytes()); // Two-byte varint
+    packet.resize(QUIC_INITIAL_LEN, 0); // Packet number and padding
+    packet
+}
+
+/// Encode a domain name in DNS wire format (length-prefixed labels)
+fn encode_dns_name(name: &str) -> Result<Vec<u8>> {
+    let mut wire = Vec::new();
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if !name.is_empty() {
+        for label in name.split('.') {
+            anyhow::ensure!(
+                !label.is_empty() && label.len() <= 63 && label.is_ascii(),
+                "Invalid DNS query name {:?}",
+                name
+            );
+            wire.push(label.len() as u8);
+            wire.extend_from_slice(label.as_bytes());
         }
     }
+    wire.push(0); // Root label
+    anyhow::ensure!(wire.len() <= 255, "DNS query name {:?} is too long", name);
+    Ok(wire)
+}
+
+/// Build a recursive A query with ID `seq` for the wire-format `qname`
+///
+/// The query ends in an EDNS(0) padding option (RFC 7830); returns it along
+/// with the even offset of two padding bytes free to steer the UDP checksum.
+fn construct_dns_query(seq: u16, qname: &[u8]) -> (Vec<u8>, usize) {
+    let mut query = Vec::with_capacity(DNS_HEADER_LEN + qname.len() + 23);
+    query.extend_from_slice(&seq.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00]); // Recursion desired
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 1]); // One question, one additional record
+    query.extend_from_slice(qname);
+    query.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+    query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+
+    // OPT record (root name, type, UDP size, flags, data length), then the
+    // padding option's code and length
+    let padding_at = query.len() + 15;
+    let padding_len = 2 + padding_at % 2;
+    query.push(0);
+    query.extend_from_slice(&DNS_TYPE_OPT.to_be_bytes());
+    query.extend_from_slice(&EDNS_UDP_SIZE.to_be_bytes());
+    query.extend_from_slice(&[0; 4]);
+    query.extend_from_slice(&(4 + padding_len as u16).to_be_bytes());
+    query.extend_from_slice(&EDNS_PADDING.to_be_bytes());
+    query.extend_from_slice(&(padding_len as u16).to_be_bytes());
+    query.resize(query.len() + padding_len, 0);
+    (query, padding_at + padding_len - 2)
+}
+
+/// Extract the query ID (our sequence number) and response code from a DNS response
+fn parse_dns_response(packet: &[u8]) -> Option<(u16, DnsRcode)> {
+    let header = packet.get(..DNS_HEADER_LEN)?;
+    if header[2] & 0x80 == 0 {
+        return None; // A query, not a response
+    }
+    Some((u16::from_be_bytes([header[0], header[1]]), DnsRcode(header[3] & 0x0f)))
+}
+
+/// Extract the sequence number from a QUIC Version Negotiation packet
+///
+/// The server echoes our source connection ID as its destination ID.
+fn parse_version_negotiation(packet: &[u8], cookie: &[u8; COOKIE_LEN], packet_id: u16) -> Option<u16> {
+    if packet.first()? & 0x80 == 0 || packet.get(1..5)? != [0; 4] {
+        return None;
+    }
+    let cid_len = *packet.get(5)? as usize;
+    let cid = packet.get(6..6 + cid_len)?;
+    if cid_len != QUIC_CID_LEN || cid[..4] != cookie[..] || cid[4..6] != packet_id.to_be_bytes() {
+        return None;
+    }
+    Some(u16::from_be_bytes([cid[6], cid[7]]))
+}
+
+/// Build a bare TCP SYN segment (no options) with a valid checksum
+fn construct_tcp_syn(src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16, seq: u32) -> Vec<u8> {
+    let mut segment = vec![0u8; 20];
+    segment[0..2].copy_from_slice(&src_port.to_be_bytes());
+    segment[2..4].copy_from_slice(&dst_port.to_be_bytes());
+    segment[4..8].copy_from_slice(&seq.to_be_bytes());
+    // Acknowledgment number stays zero
+    segment[12] = 5 << 4; // Data offset: 5 words
+    segment[13] = TCP_SYN;
+    segment[14..16].copy_from_slice(&64240u16.to_be_bytes()); // Window
+
+    // Checksum covers the IPv4 pseudo-header followed by the segment
+    let mut pseudo = pseudo_header(src.into(), dst.into(), IPPROTO_TCP, segment.len() as u16);
+    pseudo.extend_from_slice(&segment);
+    let checksum = calculate_icmp_checksum(&pseudo);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+    segment
 }
 
 // Helper function to construct ICMP packet
-fn construct_icmp_packet(seq: u16, id: u16) -> Result<Vec<u8>> {
+fn construct_icmp_packet(seq: u16, id: u16, payload: &[u8]) -> Result<Vec<u8>> {
     let mut packet = vec![0u8; 8];
     
     // ICMP Type (8 = Echo Request)
@@ -470,6 +2642,8 @@ fn construct_icmp_packet(seq: u16, id: u16) -> Result<Vec<u8>> {
     packet[4..6].copy_from_slice(&id.to_be_bytes());
     // Sequence Number
     packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    // Padding, covered by the checksum
+    packet.extend_from_slice(payload);
 
     // Calculate checksum
     let checksum = calculate_icmp_checksum(&packet);
@@ -478,6 +2652,20 @@ fn construct_icmp_packet(seq: u16, id: u16) -> Result<Vec<u8>> {
     Ok(packet)
 }
 
+/// ICMP Timestamp request stamped with the time it is built
+fn construct_icmp_timestamp_packet(seq: u16, id: u16, originate: u32) -> Vec<u8> {
+    let mut packet = vec![0u8; ICMP_TIMESTAMP_LEN];
+    packet[0] = ICMP_TIMESTAMP;
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    // Receive and transmit timestamps are left for the remote host to fill in
+    packet[8..12].copy_from_slice(&originate.to_be_bytes());
+
+    let checksum = calculate_icmp_checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
 fn calculate_icmp_checksum(packet: &[u8]) -> u16 {
     let mut sum = 0u32;
     
@@ -500,7 +2688,7 @@ fn calculate_icmp_checksum(packet: &[u8]) -> u16 {
 }
 
 // Helper function to construct ICMPv6 packet
-fn construct_icmp6_packet(seq: u16, id: u16) -> Result<Vec<u8>> {
+fn construct_icmp6_packet(seq: u16, id: u16, payload: &[u8]) -> Result<Vec<u8>> {
     let mut packet = vec![0u8; 8];
     
     // ICMPv6 Type (128 = Echo Request)
@@ -514,9 +2702,507 @@ fn construct_icmp6_packet(seq: u16, id: u16) -> Result<Vec<u8>> {
     packet[4..6].copy_from_slice(&id.to_be_bytes());
     // Sequence Number
     packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(payload);
 
     // Note: For ICMPv6, the kernel typically calculates the checksum
     // so we don't need to manually calculate it like we do for ICMP
 
     Ok(packet)
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IPv4 header (20 bytes) followed by the first 8 bytes of a UDP probe
+    fn quoted_udp_v4(src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut quoted = vec![0u8; 28];
+        quoted[0] = 0x45;
+        quoted[9] = IPPROTO_UDP;
+        quoted[20..22].copy_from_slice(&src_port.to_be_bytes());
+        quoted[22..24].copy_from_slice(&dst_port.to_be_bytes());
+        quoted
+    }
+
+    #[test]
+    fn test_udp_port_sequence_roundtrip() {
+        for seq in [INITIAL_SEQUENCE, 40000, MAX_SEQUENCE] {
+            assert_eq!(seq_for_udp_port(udp_port_for_seq(seq)), Some(seq));
+        }
+        assert_eq!(udp_port_for_seq(INITIAL_SEQUENCE), UDP_BASE_PORT);
+        assert_eq!(seq_for_udp_port(UDP_BASE_PORT - 1), None);
+        assert_eq!(seq_for_udp_port(u16::MAX), None);
+    }
+
+    fn ours(udp_src_port: Option<u16>, tcp_src_ports: &[u16]) -> QuotedProbeMatch<'_> {
+        QuotedProbeMatch {
+            packet_id: 0x1234,
+            udp_src_port,
+            tcp_src_ports,
+            paris: false,
+            cookie: *b"mtr!",
+        }
+    }
+
+    #[test]
+    fn test_parse_quoted_probes() {
+        let quoted = quoted_udp_v4(50000, udp_port_for_seq(32800));
+        assert_eq!(
+            parse_quoted_ipv4(&quoted, &ours(Some(50000), &[])),
+            Some((32800, ProbeProtocol::Udp))
+        );
+        // Someone else's UDP traffic
+        assert_eq!(parse_quoted_ipv4(&quoted, &ours(Some(50001), &[])), None);
+        assert_eq!(parse_quoted_ipv4(&quoted, &ours(None, &[50000])), None);
+
+        let mut echo = vec![0u8; 28];
+        echo[0] = 0x45;
+        echo[9] = IPPROTO_ICMP;
+        echo[26..28].copy_from_slice(&32769u16.to_be_bytes());
+        assert_eq!(parse_quoted_ipv4(&echo, &ours(None, &[])), Some((32769, ProbeProtocol::Icmp)));
+
+        // Routers that quote the whole echo expose its cookie
+        echo.extend_from_slice(b"mtr!");
+        assert_eq!(parse_quoted_ipv4(&echo, &ours(None, &[])), Some((32769, ProbeProtocol::Icmp)));
+        echo[28..32].copy_from_slice(b"MTR?");
+        assert_eq!(parse_quoted_ipv4(&echo, &ours(None, &[])), None); // Another instance's probe
+
+        let mut udp6 = vec![0u8; 48];
+        udp6[0] = 0x60;
+        udp6[6] = IPPROTO_UDP;
+        udp6[40..42].copy_from_slice(&50000u16.to_be_bytes());
+        udp6[42..44].copy_from_slice(&udp_port_for_seq(32770).to_be_bytes());
+        assert_eq!(
+            parse_quoted_ipv6(&udp6, &ours(Some(50000), &[])),
+            Some((32770, ProbeProtocol::Udp))
+        );
+
+        // Truncated quote
+        assert_eq!(parse_quoted_ipv4(&quoted[..24], &ours(Some(50000), &[])), None);
+    }
+
+    #[test]
+    fn test_reply_sanity_checks() {
+        let mut packet = vec![0u8; 20];
+        packet[0] = 0x45;
+        packet[9] = IPPROTO_ICMP;
+        packet[12..16].copy_from_slice(&[192, 0, 2, 1]);
+        packet.extend_from_slice(&construct_icmp_packet(32772, 0x1234, b"mtr!").unwrap());
+        let total_len = packet.len() as u16;
+        packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+
+        // Built as it goes out, not as a BSD raw socket would hand it over
+        let ip = Ipv4Packet::parse_raw(&packet, false).unwrap();
+        assert_eq!((ip.source, ip.protocol), (Ipv4Addr::new(192, 0, 2, 1), IPPROTO_ICMP));
+        let icmp = IcmpMessage::parse(ip.payload).unwrap();
+        assert_eq!(calculate_icmp_checksum(icmp.bytes), 0);
+        assert!(cookie_matches(icmp.body, b"mtr!"));
+
+        // Link-layer padding past the total length is not part of the message
+        let mut padded = packet.clone();
+        padded.extend_from_slice(&[0xff; 6]);
+        assert_eq!(Ipv4Packet::parse_raw(&padded, false).unwrap().payload, icmp.bytes);
+
+        // A flipped bit breaks the checksum
+        let mut corrupt = packet.clone();
+        corrupt[30] ^= 0x01;
+        assert_ne!(calculate_icmp_checksum(Ipv4Packet::parse_raw(&corrupt, false).unwrap().payload), 0);
+
+        // A header cut off before the ICMP message is complete
+        assert!(IcmpMessage::parse(Ipv4Packet::parse_raw(&packet[..24], false).unwrap().payload).is_err());
+    }
+
+    #[test]
+    fn test_icmp6_filter() {
+        let pass = [ICMP6_DST_UNREACH, ICMP6_ECHO_REPLY];
+        let bsd = icmp6_filter(&pass, false);
+        assert_eq!(bsd[0], 1 << ICMP6_DST_UNREACH);
+        assert_eq!(bsd[ICMP6_ECHO_REPLY as usize >> 5], 1 << (ICMP6_ECHO_REPLY & 31));
+        let linux = icmp6_filter(&pass, true);
+        assert!(linux.iter().zip(&bsd).all(|(l, b)| *l == !b));
+    }
+
+    #[test]
+    fn test_icmp_timestamp_probes() {
+        let request = construct_icmp_timestamp_packet(32771, 0x1234, 1000);
+        assert_eq!(request.len(), ICMP_TIMESTAMP_LEN);
+        assert_eq!(calculate_icmp_checksum(&request), 0);
+
+        // Time exceeded quoting the full request (no cookie to check)
+        let mut quoted = vec![0u8; 20];
+        quoted[0] = 0x45;
+        quoted[9] = IPPROTO_ICMP;
+        quoted.extend_from_slice(&request);
+        assert_eq!(parse_quoted_ipv4(&quoted, &ours(None, &[])), Some((32771, ProbeProtocol::Timestamp)));
+        quoted[24..26].copy_from_slice(&0x4321u16.to_be_bytes());
+        assert_eq!(parse_quoted_ipv4(&quoted, &ours(None, &[])), None); // Another instance's probe
+
+        let timestamps = IcmpTimestamps {
+            originate: MS_PER_DAY - 10,
+            receive: 5,
+            transmit: 6,
+            arrival: 9,
+        };
+        // Readings on either side of midnight
+        assert_eq!(timestamps.forward_ms(), 15);
+        assert_eq!(timestamps.return_ms(), 3);
+        assert_eq!(ms_between(5, MS_PER_DAY - 10), -15);
+        assert_eq!(timestamps.to_string(), "→15ms ←3ms");
+    }
+
+    #[test]
+    fn test_tcp_syn_and_replies() {
+        let src: Ipv4Addr = "192.0.2.1".parse().unwrap();
+        let dst: Ipv4Addr = "198.51.100.7".parse().unwrap();
+        let syn = construct_tcp_syn(src, dst, 40000, TCP_DEFAULT_PORT, tcp_seq_for_probe(0x1234, 32900));
+        assert_eq!(syn[13], TCP_SYN);
+
+        // A correct checksum makes the pseudo-header sum fold to zero
+        let mut pseudo = [src.octets(), dst.octets()].concat();
+        pseudo.extend_from_slice(&[0, IPPROTO_TCP, 0, 20]);
+        pseudo.extend_from_slice(&syn);
+        assert_eq!(calculate_icmp_checksum(&pseudo), 0);
+
+        // Router quotes the SYN in Time Exceeded
+        let mut quoted = vec![0u8; 20];
+        quoted[0] = 0x45;
+        quoted[9] = IPPROTO_TCP;
+        quoted.extend_from_slice(&syn[..8]);
+        assert_eq!(
+            parse_quoted_ipv4(&quoted, &ours(None, &[40000])),
+            Some((32900, ProbeProtocol::Tcp))
+        );
+
+        // Destination answers with SYN-ACK
+        let mut reply = vec![0u8; 40];
+        reply[0] = 0x45;
+        reply[9] = IPPROTO_TCP;
+        reply[12..16].copy_from_slice(&dst.octets());
+        reply[20..22].copy_from_slice(&TCP_DEFAULT_PORT.to_be_bytes());
+        reply[22..24].copy_from_slice(&40000u16.to_be_bytes());
+        reply[28..32].copy_from_slice(&(tcp_seq_for_probe(0x1234, 32900) + 1).to_be_bytes());
+        reply[33] = TCP_SYN | TCP_ACK;
+        assert_eq!(parse_tcp_reply(&reply, &[40000], 0x1234), Some((dst, 32900, PortState::Open)));
+        assert_eq!(parse_tcp_reply(&reply, &[40000], 0x4321), None); // Another process
+        assert_eq!(parse_tcp_reply(&reply, &[40001], 0x1234), None);
+
+        // ...or with RST when nothing listens on the port
+        reply[33] = TCP_RST | TCP_ACK;
+        assert_eq!(parse_tcp_reply(&reply, &[40000], 0x1234), Some((dst, 32900, PortState::Closed)));
+
+        // Our own outgoing SYN, looped back on the raw socket, is ignored
+        reply[33] = TCP_SYN;
+        assert_eq!(parse_tcp_reply(&reply, &[40000], 0x1234), None);
+    }
+
+    #[test]
+    fn test_paris_flow_identifiers() {
+        // ICMP: the checksum is identical for every sequence number
+        let checksums: Vec<_> = [32768u16, 32769, 40000, 60999]
+            .iter()
+            .map(|&seq| construct_icmp_packet(seq, paris_icmp_id(0x1234, seq), &[]).unwrap()[2..4].to_vec())
+            .collect();
+        assert!(checksums.windows(2).all(|pair| pair[0] == pair[1]));
+
+        // UDP: the datagram's checksum is the sequence number
+        let src: IpAddr = "192.0.2.1".parse().unwrap();
+        let dst: IpAddr = "198.51.100.7".parse().unwrap();
+        let mut payload = [0u8; UDP_PAYLOAD_SIZE];
+        set_udp_checksum_payload(&mut payload, 0, src, dst, 50000, UDP_BASE_PORT, 32801);
+        let datagram = construct_udp_datagram(src, dst, 50000, UDP_BASE_PORT, &payload);
+        assert_eq!(u16::from_be_bytes([datagram[6], datagram[7]]), 32801);
+
+        // ...and still valid: summing over the pseudo-header folds to zero
+        let mut pseudo = pseudo_header(src, dst, IPPROTO_UDP, datagram.len() as u16);
+        pseudo.extend_from_slice(&datagram);
+        assert_eq!(calculate_icmp_checksum(&pseudo), 0);
+
+        // The quoted UDP header is matched on its checksum
+        let mut quoted = quoted_udp_v4(50000, UDP_BASE_PORT);
+        quoted[26..28].copy_from_slice(&32801u16.to_be_bytes());
+        let paris = QuotedProbeMatch {
+            paris: true,
+            ..ours(Some(50000), &[])
+        };
+        assert_eq!(parse_quoted_ipv4(&quoted, &paris), Some((32801, ProbeProtocol::Udp)));
+    }
+
+    #[test]
+    fn test_fixed_port_options() {
+        use clap::Parser;
+        let options = |argv: &[&str]| ProbeOptions::from_args(&Args::try_parse_from(argv).unwrap());
+
+        // A fixed UDP port moves the sequence into the checksum; TCP keeps it in the SYN
+        assert!(options(&["mtr-ng", "-P", "udp", "--port", "53", "example.com"]).paris);
+        assert!(!options(&["mtr-ng", "-P", "udp", "example.com"]).paris);
+        assert!(!options(&["mtr-ng", "-P", "tcp", "--port", "443", "example.com"]).paris);
+        // QUIC and DNS always target one port
+        assert!(options(&["mtr-ng", "-P", "quic", "example.com"]).paris);
+        assert!(options(&["mtr-ng", "-P", "dns", "9.9.9.9"]).paris);
+    }
+
+    #[test]
+    fn test_dns_probe_packets() {
+        let qname = encode_dns_name("example.com.").unwrap();
+        assert_eq!(qname, b"\x07example\x03com\x00");
+        assert_eq!(encode_dns_name(".").unwrap(), [0]);
+        assert!(encode_dns_name("bad..name").is_err());
+        assert!(encode_dns_name(&"a".repeat(64)).is_err());
+
+        // Names of either parity leave an even offset for the checksum bytes
+        for name in ["example.com", "example.org.uk"] {
+            let (query, adjust_at) = construct_dns_query(32801, &encode_dns_name(name).unwrap());
+            assert_eq!(adjust_at % 2, 0);
+            assert_eq!(adjust_at + 2, query.len());
+            assert_eq!(query[..4], [0x80, 0x21, 0x01, 0x00]);
+
+            // The OPT record's data length covers the padding option
+            let opt = DNS_HEADER_LEN + name.len() + 2 + 4;
+            assert_eq!(query[opt + 1..opt + 3], DNS_TYPE_OPT.to_be_bytes());
+            assert_eq!(u16::from_be_bytes([query[opt + 9], query[opt + 10]]) as usize, query.len() - opt - 11);
+        }
+
+        // Responses carry the query ID and response code
+        let (mut response, _) = construct_dns_query(32801, &qname);
+        assert_eq!(parse_dns_response(&response), None);
+        response[2] |= 0x80;
+        response[3] = 0x83;
+        assert_eq!(parse_dns_response(&response), Some((32801, DnsRcode(3))));
+        assert_eq!(DnsRcode(3).to_string(), "NXDOMAIN");
+        assert_eq!(DnsRcode(9).to_string(), "RCODE9");
+    }
+
+    #[test]
+    fn test_socket_failure_diagnosis() {
+        let eperm = std::io::Error::from_raw_os_error(libc::EPERM);
+        assert!(explain_raw_error(&eperm).contains("CAP_NET_RAW"));
+
+        let eacces = std::io::Error::from_raw_os_error(libc::EACCES);
+        let reason = explain_ping_error(&eacces, 1000, Some("1\t0\n"));
+        assert!(reason.contains("group 1000"));
+        assert!(reason.contains("(1 0)"));
+        let unsupported = std::io::Error::from(std::io::ErrorKind::Unsupported);
+        assert_eq!(explain_ping_error(&unsupported, 1000, None), "not available on this platform");
+
+        let attempts = [
+            FailedAttempt { mechanism: "raw ICMP socket", reason: explain_raw_error(&eperm) },
+            FailedAttempt { mechanism: "ping socket", reason: "not supported by this kernel".to_string() },
+        ];
+        let message = no_usable_socket(&attempts).to_string();
+        assert_eq!(message.lines().count(), 3);
+        assert!(message.lines().nth(2).unwrap().starts_with("  ping socket: "));
+    }
+
+    #[test]
+    fn test_quic_probe_packets() {
+        let cookie = [0xde, 0xad, 0xbe, 0xef];
+        let cid = quic_connection_id(&cookie, 0x1234, 32801);
+        let packet = construct_quic_initial(&cid);
+        assert_eq!(packet.len(), QUIC_INITIAL_LEN);
+        assert_eq!(packet[0], 0xc0);
+        assert_eq!(packet[1..5], QUIC_PROBE_VERSION.to_be_bytes());
+        assert_eq!(packet[5], 8);
+        assert_eq!(packet[6..14], cid);
+        assert_eq!(packet[15..23], cid);
+        assert_eq!(u16::from_be_bytes([packet[24], packet[25]]) & 0x3fff, (QUIC_INITIAL_LEN - 26) as u16);
+
+        // The padding's last two bytes steer the UDP checksum to the sequence
+        let src: IpAddr = "192.0.2.1".parse().unwrap();
+        let dst: IpAddr = "198.51.100.7".parse().unwrap();
+        let mut payload = packet.clone();
+        set_udp_checksum_payload(&mut payload, QUIC_INITIAL_LEN - 2, src, dst, 50000, QUIC_DEFAULT_PORT, 32801);
+        assert_eq!(payload[..QUIC_INITIAL_LEN - 2], packet[..QUIC_INITIAL_LEN - 2]);
+        let datagram = construct_udp_datagram(src, dst, 50000, QUIC_DEFAULT_PORT, &payload);
+        assert_eq!(u16::from_be_bytes([datagram[6], datagram[7]]), 32801);
+
+        // Version Negotiation echoes our source connection ID as its destination
+        let mut negotiation = vec![0x80, 0, 0, 0, 0, 8];
+        negotiation.extend_from_slice(&[0x11; 8]); // Server-chosen source ID
+        negotiation[6..14].copy_from_slice(&cid);
+        negotiation.extend_from_slice(&[8, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11]);
+        negotiation.extend_from_slice(&1u32.to_be_bytes()); // Supported: QUIC v1
+        assert_eq!(parse_version_negotiation(&negotiation, &cookie, 0x1234), Some(32801));
+        assert_eq!(parse_version_negotiation(&negotiation, &[0; 4], 0x1234), None);
+        assert_eq!(parse_version_negotiation(&negotiation, &cookie, 0x4321), None);
+
+        // A regular long-header packet is not Version Negotiation
+        negotiation[4] = 1;
+        assert_eq!(parse_version_negotiation(&negotiation, &cookie, 0x1234), None);
+    }
+
+    #[test]
+    fn test_flow_cycle_per_ttl() {
+        let mut flows = FlowCycle::new(3);
+        assert_eq!([flows.next(1), flows.next(1), flows.next(2), flows.next(1), flows.next(1)], [0, 1, 0, 2, 0]);
+
+        // Without multipath every probe uses flow 0
+        let mut single = FlowCycle::new(0);
+        assert_eq!([single.next(1), single.next(1)], [0, 0]);
+
+        // Different flows give different but individually constant ICMP checksums
+        let checksum = |flow: u16, seq: u16| {
+            construct_icmp_packet(seq, paris_icmp_id(0x1234u16.wrapping_add(flow), seq), &[]).unwrap()[2..4].to_vec()
+        };
+        assert_eq!(checksum(1, 32768), checksum(1, 32800));
+        assert_ne!(checksum(1, 32768), checksum(2, 32768));
+    }
+
+    #[test]
+    fn test_payload_spec_sizes() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        // Defaults keep the historical probe sizes
+        let default = PayloadSpec::default();
+        assert!(default.bytes(v4, ICMP_HEADER_LEN, 0, 0).is_empty());
+        assert_eq!(default.bytes(v4, UDP_HEADER_LEN, UDP_PAYLOAD_SIZE, 0).len(), UDP_PAYLOAD_SIZE);
+
+        let spec = PayloadSpec {
+            packet_size: Some(1400),
+            pattern: 0xa5,
+        };
+        let payload = spec.bytes(v4, ICMP_HEADER_LEN, 0, 0);
+        assert_eq!(payload.len(), 1400 - 28);
+        assert!(payload.iter().all(|&b| b == 0xa5));
+        assert_eq!(spec.bytes(v6, UDP_HEADER_LEN, UDP_PAYLOAD_SIZE, 0).len(), 1400 - 48);
+
+        // Too small to hold anything, except what Paris UDP needs
+        let tiny = PayloadSpec {
+            packet_size: Some(28),
+            pattern: 0,
+        };
+        assert_eq!(tiny.bytes(v4, UDP_HEADER_LEN, UDP_PAYLOAD_SIZE, 2).len(), 2);
+
+        // The padding is covered by the ICMP checksum
+        let packet = construct_icmp_packet(32768, 0x1234, &payload).unwrap();
+        assert_eq!(packet.len(), 1400 - 20);
+        assert_eq!(calculate_icmp_checksum(&packet), 0);
+    }
+
+    #[test]
+    fn test_kernel_receive_timestamps() {
+        let receiver = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        receiver.bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into()).unwrap();
+        assert!(enable_rx_timestamps(&receiver));
+        let sender = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let target = receiver.local_addr().unwrap().as_socket().unwrap();
+
+        // The kernel turns on arrival stamping lazily, so datagrams sent right
+        // after the first SO_TIMESTAMPNS socket appears may be stamped on read
+        let mut batch = RecvBatch::default();
+        for _ in 0..50 {
+            sender.send_to(b"warm-up", target).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(batch.recv(&receiver).unwrap(), 1);
+            let (_, _, received_at, _) = batch.packets(1).next().unwrap();
+            if received_at.elapsed() >= Duration::from_millis(5) {
+                break;
+            }
+        }
+
+        // Reading late must not inflate the measured receive time
+        sender.send_to(b"probe", target).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let count = batch.recv(&receiver).unwrap();
+        let (data, _, received_at, _) = batch.packets(count).next().unwrap();
+        assert_eq!(data, b"probe");
+        assert!(received_at.elapsed() >= Duration::from_millis(40));
+
+        let err = batch.recv(&receiver).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_batched_receive_timestamps() {
+        let receiver = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        receiver.bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into()).unwrap();
+        assert!(enable_rx_timestamps(&receiver));
+        assert!(enable_reply_ttl(&receiver, Domain::IPV4));
+        let sender = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        sender.set_ttl(42).unwrap();
+        sender.send_to(b"probe", receiver.local_addr().unwrap().as_socket().unwrap()).unwrap();
+
+        sender.send_to(b"second", receiver.local_addr().unwrap().as_socket().unwrap()).unwrap();
+
+        let mut batch = RecvBatch::default();
+        let mut packets = Vec::new();
+        while packets.len() < 2 {
+            match batch.recv(&receiver) {
+                Ok(count) => packets.extend(
+                    batch
+                        .packets(count)
+                        .map(|(data, addr, _, ttl)| (data.to_vec(), addr.as_socket(), ttl)),
+                ),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(Duration::from_millis(1)),
+                Err(e) => panic!("Failed to receive: {}", e),
+            }
+        }
+        assert_eq!(packets[0], (b"probe".to_vec(), Some(sender.local_addr().unwrap()), Some(42)));
+        assert_eq!(packets[1].0, b"second");
+
+        let err = batch.recv(&receiver).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+        // Reading late must not inflate the measured receive time
+        let late = receive_instant(Some(SystemTime::now() - Duration::from_millis(50)));
+        assert!(late.elapsed() >= Duration::from_millis(50));
+
+        // Implausible timestamps fall back to the current time
+        let stale = SystemTime::now() - MAX_TIMESTAMP_AGE * 2;
+        assert!(receive_instant(Some(stale)).elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready_clears_readiness() {
+        let receiver = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        receiver.bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into()).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let target = receiver.local_addr().unwrap();
+        let ready = AsyncFd::new(receiver.try_clone().unwrap()).unwrap();
+        let idle = Duration::from_millis(50);
+
+        // Nothing queued yet
+        assert!(tokio::time::timeout(idle, wait_ready(Some(&ready), Interest::READABLE)).await.is_err());
+
+        let sender = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        sender.send_to(b"reply", target.as_socket().unwrap()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wait_ready(Some(&ready), Interest::READABLE))
+            .await
+            .unwrap();
+
+        // Once drained, the next wait sleeps again
+        let mut batch = RecvBatch::default();
+        assert_eq!(batch.recv(&receiver).unwrap(), 1);
+        assert!(tokio::time::timeout(idle, wait_ready(Some(&ready), Interest::READABLE)).await.is_err());
+        assert!(tokio::time::timeout(idle, wait_ready(None, Interest::READABLE)).await.is_err());
+    }
+
+    #[test]
+    fn test_batched_send() {
+        let receiver = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        receiver.bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into()).unwrap();
+        let sender = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        let target = receiver.local_addr().unwrap();
+
+        let probes: Vec<_> = (1..=3u8)
+            .map(|ttl| OutgoingProbe {
+                socket: &sender,
+                packet: vec![ttl; 4],
+                target: target.clone(),
+                ttl,
+            })
+            .collect();
+        let results = send_outgoing(&probes);
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.is_ok()));
+
+        let mut batch = RecvBatch::default();
+        let mut packets = Vec::new();
+        while packets.len() < 3 {
+            match batch.recv(&receiver) {
+                Ok(count) => packets.extend(batch.packets(count).map(|(data, ..)| data.to_vec())),
+                Err(_) => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        assert_eq!(packets, vec![vec![1; 4], vec![2; 4], vec![3; 4]]);
+    }
+}
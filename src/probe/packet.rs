@@ -0,0 +1,465 @@
+//! Typed views of the packets the probe engine reads
+//!
+//! Raw IPv4 sockets hand us whole packets, header included, and ICMP errors
+//! quote the start of the probe that caused them. Either may be cut short,
+//! carry IPv4 options or IPv6 extension headers, or simply be garbage. The
+//! parsers here check every length before reading a field and borrow from the
+//! buffer, so the engine works with named fields instead of byte offsets.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use thiserror::Error;
+
+use super::{ICMP_HEADER_LEN, IPV4_HEADER_LEN, IPV6_HEADER_LEN, UDP_HEADER_LEN};
+
+/// Whether raw sockets see the IPv4 total length in host byte order with
+/// the header length already subtracted, as macOS and DragonFly deliver it.
+/// Linux, OpenBSD, NetBSD and FreeBSD 11+ pass it on as it was on the wire.
+const RAW_IPV4_LEN_STRIPPED: bool = cfg!(any(target_vendor = "apple", target_os = "dragonfly"));
+
+/// Fixed TCP header length, before options
+const TCP_HEADER_LEN: usize = 20;
+
+/// Bytes of a transport header an ICMP error is guaranteed to quote
+const QUOTED_TRANSPORT_LEN: usize = 8;
+
+/// IPv6 extension headers that may sit between a quoted header and our probe
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+
+/// Why a packet could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub(super) enum ParseError {
+    #[error("truncated {layer} header ({available} of {needed} bytes)")]
+    Truncated {
+        layer: &'static str,
+        needed: usize,
+        available: usize,
+    },
+    #[error("IP version {0} where {1} was expected")]
+    Version(u8, u8),
+    #[error("IPv4 header length {0} is below the minimum")]
+    HeaderLength(usize),
+}
+
+/// The first `needed` bytes of `bytes`, or the error saying what was missing
+fn take<'a>(bytes: &'a [u8], needed: usize, layer: &'static str) -> Result<&'a [u8], ParseError> {
+    bytes.get(..needed).ok_or(ParseError::Truncated {
+        layer,
+        needed,
+        available: bytes.len(),
+    })
+}
+
+fn be16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn be32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// An IPv4 packet: its header fields, options and payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Ipv4Packet<'a> {
+    pub ttl: u8,
+    pub protocol: u8,
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub options: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parse a packet as read from a raw socket
+    ///
+    /// Trailing link-layer padding past the total length is cut off so it
+    /// can't upset a checksum.
+    pub fn parse(packet: &'a [u8]) -> Result<Self, ParseError> {
+        Self::parse_raw(packet, RAW_IPV4_LEN_STRIPPED)
+    }
+
+    /// Parse a packet whose total length is either as sent or, with
+    /// `len_stripped`, in host order less the header, as BSD raw sockets give it
+    pub fn parse_raw(packet: &'a [u8], len_stripped: bool) -> Result<Self, ParseError> {
+        let (mut parsed, header_len) = Self::parse_header(packet)?;
+        let len_field = [packet[2], packet[3]];
+        let total_len = if len_stripped {
+            usize::from(u16::from_ne_bytes(len_field)) + header_len
+        } else {
+            usize::from(u16::from_be_bytes(len_field))
+        };
+        // FreeBSD before 11 strips the length like macOS; only trust the
+        // field when it fits what was read
+        if let Some(packet) = packet.get(..total_len).filter(|_| total_len >= header_len) {
+            parsed.payload = &packet[header_len..];
+        }
+        Ok(parsed)
+    }
+
+    /// Parse the start of a datagram quoted in an ICMP error
+    ///
+    /// Quotes are usually cut short, so the total length is not checked: the
+    /// payload is whatever follows the header.
+    pub fn parse_quoted(quoted: &'a [u8]) -> Result<Self, ParseError> {
+        Self::parse_header(quoted).map(|(parsed, _)| parsed)
+    }
+
+    fn parse_header(bytes: &'a [u8]) -> Result<(Self, usize), ParseError> {
+        let fixed = take(bytes, IPV4_HEADER_LEN, "IPv4")?;
+        let version = fixed[0] >> 4;
+        if version != 4 {
+            return Err(ParseError::Version(version, 4));
+        }
+        let header_len = usize::from(fixed[0] & 0x0f) * 4;
+        if header_len < IPV4_HEADER_LEN {
+            return Err(ParseError::HeaderLength(header_len));
+        }
+        let header = take(bytes, header_len, "IPv4 options")?;
+        let parsed = Self {
+            ttl: header[8],
+            protocol: header[9],
+            source: Ipv4Addr::new(header[12], header[13], header[14], header[15]),
+            destination: Ipv4Addr::new(header[16], header[17], header[18], header[19]),
+            options: &header[IPV4_HEADER_LEN..],
+            payload: &bytes[header_len..],
+        };
+        Ok((parsed, header_len))
+    }
+}
+
+/// A quoted IPv6 packet, with any extension headers skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Ipv6Packet<'a> {
+    pub hop_limit: u8,
+    /// Protocol of the payload, after the extension headers
+    pub protocol: u8,
+    pub source: Ipv6Addr,
+    pub destination: Ipv6Addr,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv6Packet<'a> {
+    /// Parse the start of a datagram quoted in an ICMPv6 error
+    ///
+    /// Raw IPv6 sockets never deliver the header of what they receive, so
+    /// quotes are the only IPv6 headers we see.
+    pub fn parse_quoted(quoted: &'a [u8]) -> Result<Self, ParseError> {
+        let header = take(quoted, IPV6_HEADER_LEN, "IPv6")?;
+        let version = header[0] >> 4;
+        if version != 6 {
+            return Err(ParseError::Version(version, 6));
+        }
+        let address = |at: usize| {
+            let octets: [u8; 16] = header[at..at + 16].try_into().expect("16-byte slice");
+            Ipv6Addr::from(octets)
+        };
+
+        let mut protocol = header[6];
+        let mut payload = &quoted[IPV6_HEADER_LEN..];
+        loop {
+            let extension_len = match protocol {
+                IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS => {
+                    (usize::from(take(payload, 2, "IPv6 extension")?[1]) + 1) * 8
+                }
+                IPV6_FRAGMENT => 8,
+                _ => break,
+            };
+            let extension = take(payload, extension_len, "IPv6 extension")?;
+            protocol = extension[0];
+            payload = &payload[extension_len..];
+        }
+
+        Ok(Self {
+            hop_limit: header[7],
+            protocol,
+            source: address(8),
+            destination: address(24),
+            payload,
+        })
+    }
+}
+
+/// An ICMP or ICMPv6 message, which share the header layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct IcmpMessage<'a> {
+    pub icmp_type: u8,
+    pub code: u8,
+    /// The four bytes after the checksum: identifier and sequence for
+    /// echo messages, lengths and unused space for errors
+    pub rest_of_header: [u8; 4],
+    /// Everything after the 8-byte header
+    pub body: &'a [u8],
+    /// The whole message, header included
+    pub bytes: &'a [u8],
+}
+
+impl<'a> IcmpMessage<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let header = take(bytes, ICMP_HEADER_LEN, "ICMP")?;
+        Ok(Self {
+            icmp_type: header[0],
+            code: header[1],
+            rest_of_header: [header[4], header[5], header[6], header[7]],
+            body: &bytes[ICMP_HEADER_LEN..],
+            bytes,
+        })
+    }
+
+    pub fn identifier(&self) -> u16 {
+        be16(&self.rest_of_header, 0)
+    }
+
+    pub fn sequence(&self) -> u16 {
+        be16(&self.rest_of_header, 2)
+    }
+}
+
+/// A UDP header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct UdpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub length: u16,
+    pub checksum: u16,
+}
+
+impl UdpHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let header = take(bytes, UDP_HEADER_LEN, "UDP")?;
+        Ok(Self {
+            src_port: be16(header, 0),
+            dst_port: be16(header, 2),
+            length: be16(header, 4),
+            checksum: be16(header, 6),
+        })
+    }
+}
+
+/// A full TCP header, as received from the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct TcpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+}
+
+impl TcpHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let header = take(bytes, TCP_HEADER_LEN, "TCP")?;
+        Ok(Self {
+            src_port: be16(header, 0),
+            dst_port: be16(header, 2),
+            seq: be32(header, 4),
+            ack: be32(header, 8),
+            flags: header[13],
+        })
+    }
+}
+
+/// The part of a TCP header an ICMP error is sure to quote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct QuotedTcp {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+}
+
+impl QuotedTcp {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let header = take(bytes, QUOTED_TRANSPORT_LEN, "quoted TCP")?;
+        Ok(Self {
+            src_port: be16(header, 0),
+            dst_port: be16(header, 2),
+            seq: be32(header, 4),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_header(protocol: u8, options: &[u8], payload: &[u8]) -> Vec<u8> {
+        let header_len = IPV4_HEADER_LEN + options.len();
+        let mut packet = vec![0u8; IPV4_HEADER_LEN];
+        packet[0] = 0x40 | (header_len / 4) as u8;
+        packet[2..4].copy_from_slice(&((header_len + payload.len()) as u16).to_be_bytes());
+        packet[8] = 57;
+        packet[9] = protocol;
+        packet[12..16].copy_from_slice(&[192, 0, 2, 1]);
+        packet[16..20].copy_from_slice(&[198, 51, 100, 7]);
+        packet.extend_from_slice(options);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn test_ipv4_packet() {
+        let packet = ipv4_header(1, &[], b"payload!");
+        let parsed = Ipv4Packet::parse_raw(&packet, false).unwrap();
+        assert_eq!(parsed.ttl, 57);
+        assert_eq!(parsed.protocol, 1);
+        assert_eq!(parsed.source, Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(parsed.destination, Ipv4Addr::new(198, 51, 100, 7));
+        assert!(parsed.options.is_empty());
+        assert_eq!(parsed.payload, b"payload!");
+
+        // Record Route option, padded to a word
+        let options = [7, 7, 4, 0, 0, 0, 0, 0];
+        let packet = ipv4_header(1, &options, b"payload!");
+        let parsed = Ipv4Packet::parse_raw(&packet, false).unwrap();
+        assert_eq!(parsed.options, options);
+        assert_eq!(parsed.payload, b"payload!");
+    }
+
+    #[test]
+    fn test_ipv4_total_length() {
+        let packet = ipv4_header(1, &[], b"payload!");
+
+        // Link-layer padding past the total length is not part of the packet
+        let mut padded = packet.clone();
+        padded.extend_from_slice(&[0xff; 6]);
+        assert_eq!(Ipv4Packet::parse_raw(&padded, false).unwrap().payload, b"payload!");
+
+        // macOS and DragonFly: host byte order, header length subtracted
+        let mut stripped = padded.clone();
+        stripped[2..4].copy_from_slice(&8u16.to_ne_bytes());
+        assert_eq!(Ipv4Packet::parse_raw(&stripped, true).unwrap().payload, b"payload!");
+        // Old FreeBSD strips it too; the field doesn't fit and is ignored
+        assert_eq!(Ipv4Packet::parse_raw(&stripped, false).unwrap().payload.len(), 14);
+
+        // Quotes are cut short; their total length is never trusted
+        assert_eq!(Ipv4Packet::parse_quoted(&packet[..24]).unwrap().payload, b"payl");
+    }
+
+    #[test]
+    fn test_ipv4_malformed() {
+        let packet = ipv4_header(1, &[1, 1, 1, 0], b"payload!");
+        assert_eq!(
+            Ipv4Packet::parse_raw(&packet[..12], false),
+            Err(ParseError::Truncated { layer: "IPv4", needed: 20, available: 12 })
+        );
+        assert_eq!(
+            Ipv4Packet::parse_raw(&packet[..22], false),
+            Err(ParseError::Truncated { layer: "IPv4 options", needed: 24, available: 22 })
+        );
+        assert_eq!(
+            Ipv4Packet::parse_raw(&[], false),
+            Err(ParseError::Truncated { layer: "IPv4", needed: 20, available: 0 })
+        );
+
+        let mut wrong_version = packet.clone();
+        wrong_version[0] = 0x65;
+        assert_eq!(Ipv4Packet::parse_raw(&wrong_version, false), Err(ParseError::Version(6, 4)));
+        let mut short_header = packet.clone();
+        short_header[0] = 0x44;
+        assert_eq!(Ipv4Packet::parse_raw(&short_header, false), Err(ParseError::HeaderLength(16)));
+    }
+
+    fn ipv6_header(next_header: u8, rest: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; IPV6_HEADER_LEN];
+        packet[0] = 0x60;
+        packet[4..6].copy_from_slice(&(rest.len() as u16).to_be_bytes());
+        packet[6] = next_header;
+        packet[7] = 3;
+        packet[8..24].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        packet[24..40].copy_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        packet.extend_from_slice(rest);
+        packet
+    }
+
+    #[test]
+    fn test_ipv6_packet() {
+        let packet = ipv6_header(17, b"udp head");
+        let parsed = Ipv6Packet::parse_quoted(&packet).unwrap();
+        assert_eq!(parsed.hop_limit, 3);
+        assert_eq!(parsed.protocol, 17);
+        assert_eq!(parsed.source, "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(parsed.destination, "2001:db8::2".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(parsed.payload, b"udp head");
+    }
+
+    #[test]
+    fn test_ipv6_extension_headers() {
+        // Hop-by-hop (16 bytes), then a fragment header, then UDP
+        let mut rest = vec![IPV6_FRAGMENT, 1];
+        rest.extend_from_slice(&[0; 14]);
+        rest.extend_from_slice(&[17, 0, 0, 0, 0, 0, 0, 0]);
+        rest.extend_from_slice(b"udp head");
+        let packet = ipv6_header(IPV6_HOP_BY_HOP, &rest);
+        let parsed = Ipv6Packet::parse_quoted(&packet).unwrap();
+        assert_eq!(parsed.protocol, 17);
+        assert_eq!(parsed.payload, b"udp head");
+
+        // A quote ending inside an extension header
+        assert_eq!(
+            Ipv6Packet::parse_quoted(&packet[..50]),
+            Err(ParseError::Truncated { layer: "IPv6 extension", needed: 16, available: 10 })
+        );
+        assert!(Ipv6Packet::parse_quoted(&packet[..40 + 1]).is_err());
+    }
+
+    #[test]
+    fn test_ipv6_malformed() {
+        let packet = ipv6_header(17, b"udp head");
+        assert_eq!(
+            Ipv6Packet::parse_quoted(&packet[..39]),
+            Err(ParseError::Truncated { layer: "IPv6", needed: 40, available: 39 })
+        );
+        let mut wrong_version = packet.clone();
+        wrong_version[0] = 0x45;
+        assert_eq!(Ipv6Packet::parse_quoted(&wrong_version), Err(ParseError::Version(4, 6)));
+    }
+
+    #[test]
+    fn test_icmp_message() {
+        let message = [0, 0, 0xab, 0xcd, 0x12, 0x34, 0x80, 0x05, b'm', b't', b'r'];
+        let parsed = IcmpMessage::parse(&message).unwrap();
+        assert_eq!((parsed.icmp_type, parsed.code), (0, 0));
+        assert_eq!(parsed.identifier(), 0x1234);
+        assert_eq!(parsed.sequence(), 0x8005);
+        assert_eq!(parsed.body, b"mtr");
+        assert_eq!(parsed.bytes, message);
+
+        // A bare header has an empty body; anything shorter is truncated
+        assert!(IcmpMessage::parse(&message[..8]).unwrap().body.is_empty());
+        assert_eq!(
+            IcmpMessage::parse(&message[..7]),
+            Err(ParseError::Truncated { layer: "ICMP", needed: 8, available: 7 })
+        );
+    }
+
+    #[test]
+    fn test_transport_headers() {
+        let udp = [0xc3, 0x50, 0x82, 0x9a, 0x00, 0x2c, 0xbe, 0xef];
+        assert_eq!(
+            UdpHeader::parse(&udp).unwrap(),
+            UdpHeader { src_port: 50000, dst_port: 33434, length: 44, checksum: 0xbeef }
+        );
+        assert!(UdpHeader::parse(&udp[..7]).is_err());
+
+        let mut tcp = [0u8; 20];
+        tcp[0..2].copy_from_slice(&80u16.to_be_bytes());
+        tcp[2..4].copy_from_slice(&40000u16.to_be_bytes());
+        tcp[4..8].copy_from_slice(&7u32.to_be_bytes());
+        tcp[8..12].copy_from_slice(&0x1234_8001u32.to_be_bytes());
+        tcp[12] = 0x50;
+        tcp[13] = 0x12;
+        let parsed = TcpHeader::parse(&tcp).unwrap();
+        assert_eq!((parsed.src_port, parsed.dst_port, parsed.seq), (80, 40000, 7));
+        assert_eq!((parsed.ack, parsed.flags), (0x1234_8001, 0x12));
+        assert!(TcpHeader::parse(&tcp[..19]).is_err());
+
+        // Routers need only quote the ports and sequence number
+        assert_eq!(
+            QuotedTcp::parse(&tcp[..8]).unwrap(),
+            QuotedTcp { src_port: 80, dst_port: 40000, seq: 7 }
+        );
+        assert!(QuotedTcp::parse(&tcp[..7]).is_err());
+    }
+}
@@ -0,0 +1,446 @@
+//! io_uring probe backend
+//!
+//! Wraps a [`ProbeEngine`] and moves its socket I/O onto an io_uring
+//! instance. A round of probes is queued as one batch of `SENDMSG` requests,
+//! and every reply socket keeps several `RECVMSG` requests armed so replies
+//! land in their buffers without a syscall per packet. Completions are
+//! signalled through an eventfd watched by the tokio reactor. Building
+//! packets, matching replies and expiring probes stay with the engine.
+
+use super::{
+    cmsg_reply_ttl, cmsg_timestamp, receive_instant, OutgoingProbe, ProbeEngine, ProbeRequest,
+    ProbeResponse, ProbeTransport, ReplySocket, MAX_MTU,
+};
+use crate::args::ProbeProtocol;
+use anyhow::{Context, Result};
+use io_uring::{opcode, squeue, types, IoUring};
+use socket2::SockAddr;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::Duration,
+};
+use tokio::io::{unix::AsyncFd, Interest};
+use tracing::debug;
+
+/// Submission queue depth
+const RING_ENTRIES: u32 = 256;
+
+/// Receive requests kept armed on each reply socket
+const RECVS_PER_SOCKET: usize = 16;
+
+/// `user_data` tags; receive requests use their slot index
+const SEND_TAG: u64 = 1 << 62;
+const POLL_TAG: u64 = 1 << 61;
+const CANCEL_TAG: u64 = 1 << 60;
+
+/// One armed `RECVMSG` request and the buffers it reads into
+///
+/// Slots live in a boxed slice that is never resized, so the pointers handed
+/// to the kernel stay put.
+struct RecvSlot {
+    kind: ReplySocket,
+    fd: RawFd,
+    armed: bool,
+    buffer: [u8; MAX_MTU],
+    addr: libc::sockaddr_storage,
+    iov: libc::iovec,
+    control: [u64; 16],
+    msg: libc::msghdr,
+}
+
+// SAFETY: the raw pointers in `iov` and `msg` only ever point into the slot itself
+unsafe impl Send for RecvSlot {}
+
+impl RecvSlot {
+    /// An unarmed slot; `reset` sets up its header once it is in place
+    fn new(kind: ReplySocket, fd: RawFd) -> Self {
+        // SAFETY: sockaddr_storage, iovec and msghdr are plain data
+        unsafe {
+            Self {
+                kind,
+                fd,
+                armed: false,
+                buffer: [0; MAX_MTU],
+                addr: std::mem::zeroed(),
+                iov: std::mem::zeroed(),
+                control: [0; 16],
+                msg: std::mem::zeroed(),
+            }
+        }
+    }
+
+    /// Point the header back at this slot's buffers
+    ///
+    /// The kernel shrinks the name and control lengths on every receive.
+    fn reset(&mut self) {
+        self.iov = libc::iovec {
+            iov_base: self.buffer.as_mut_ptr().cast(),
+            iov_len: self.buffer.len(),
+        };
+        self.msg.msg_name = (&mut self.addr as *mut libc::sockaddr_storage).cast();
+        self.msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        self.msg.msg_iov = &mut self.iov;
+        self.msg.msg_iovlen = 1;
+        self.msg.msg_control = self.control.as_mut_ptr().cast();
+        self.msg.msg_controllen = std::mem::size_of_val(&self.control) as _;
+    }
+}
+
+/// A `ProbeEngine` whose sends and receives go through io_uring
+pub struct UringProbeEngine {
+    engine: ProbeEngine,
+    ring: IoUring,
+    eventfd: AsyncFd<OwnedFd>,
+    slots: Box<[RecvSlot]>,
+    completed: Vec<(u64, i32)>, // Receive completions reaped while waiting for sends
+    poll_armed: bool,
+}
+
+impl UringProbeEngine {
+    /// Take over the engine's sockets; must be called inside a tokio runtime
+    pub fn new(engine: ProbeEngine) -> Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES).context("Failed to set up io_uring (Linux 5.6 or newer required)")?;
+
+        // SAFETY: eventfd has no memory-safety preconditions; the result is checked below
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to create eventfd");
+        }
+        // SAFETY: fd is a freshly created descriptor nobody else owns
+        let eventfd = unsafe { OwnedFd::from_raw_fd(fd) };
+        ring.submitter()
+            .register_eventfd(eventfd.as_raw_fd())
+            .context("Failed to register io_uring eventfd")?;
+        let eventfd = AsyncFd::with_interest(eventfd, Interest::READABLE).context("Failed to register eventfd")?;
+
+        let mut slots = Vec::new();
+        for kind in ReplySocket::ALL {
+            if let Some(socket) = engine.reply_socket(kind) {
+                slots.extend((0..RECVS_PER_SOCKET).map(|_| RecvSlot::new(kind, socket.as_raw_fd())));
+            }
+        }
+
+        let mut uring = Self {
+            engine,
+            ring,
+            eventfd,
+            slots: slots.into_boxed_slice(),
+            completed: Vec::new(),
+            poll_armed: false,
+        };
+        for index in 0..uring.slots.len() {
+            uring.arm(index)?;
+        }
+        uring.arm_error_poll()?;
+        uring.ring.submit()?;
+        tracing::info!("Using io_uring probe backend");
+        Ok(uring)
+    }
+
+    /// Queue a receive into a slot
+    fn arm(&mut self, index: usize) -> Result<()> {
+        arm_slot(&mut self.ring, &mut self.slots[index], index)
+    }
+
+    /// Queue a receive into every slot that has none, such as those whose
+    /// completions were just handled
+    ///
+    /// A slot that fails is logged and left for the next call, so one full
+    /// queue doesn't cost the others their receives.
+    fn rearm(&mut self) {
+        for index in 0..self.slots.len() {
+            if !self.slots[index].armed {
+                if let Err(e) = self.arm(index) {
+                    debug!("Failed to re-arm io_uring receive {}: {}", index, e);
+                }
+            }
+        }
+    }
+
+    /// Ping sockets and the UDP fallback queue ICMP errors, which only raise POLLERR
+    fn arm_error_poll(&mut self) -> Result<()> {
+        if !self.engine.icmp_kind.queues_errors() || self.poll_armed {
+            return Ok(());
+        }
+        let entry = opcode::PollAdd::new(types::Fd(self.engine.icmp_socket.as_raw_fd()), libc::POLLERR as u32)
+            .build()
+            .user_data(POLL_TAG);
+        push(&mut self.ring, &entry)?;
+        self.poll_armed = true;
+        Ok(())
+    }
+
+    /// Wait until replies have arrived or probes have timed out, and return them
+    pub async fn collect_responses_async(&mut self) -> Result<Vec<ProbeResponse>> {
+        loop {
+            let mut responses = Vec::new();
+            reap(&mut self.ring, &mut self.completed, |_, _| {});
+
+            // Every completion is handled before anything is re-armed, so a
+            // failure to queue can't lose the ones taken off the ring
+            for (tag, result) in std::mem::take(&mut self.completed) {
+                if tag == POLL_TAG {
+                    self.poll_armed = false;
+                    self.engine.drain_error_queue(&mut responses);
+                    continue;
+                }
+
+                let index = tag as usize;
+                let slot = &mut self.slots[index];
+                slot.armed = false;
+                if result >= 0 {
+                    // SAFETY: the kernel filled in the address and control buffer of this slot
+                    let (addr, timestamp, reply_ttl) = unsafe {
+                        (
+                            SockAddr::new(slot.addr, slot.msg.msg_namelen),
+                            cmsg_timestamp(&slot.msg),
+                            cmsg_reply_ttl(&slot.msg),
+                        )
+                    };
+                    let packet = &slot.buffer[..result as usize];
+                    match self.engine.parse_reply(slot.kind, packet, &addr, receive_instant(timestamp), reply_ttl) {
+                        Ok(Some(response)) => responses.push(response),
+                        Ok(None) => {}
+                        Err(e) => debug!("Failed to parse reply: {}", e),
+                    }
+                } else {
+                    debug!("io_uring receive failed: {}", std::io::Error::from_raw_os_error(-result));
+                }
+            }
+            self.rearm();
+            if let Err(e) = self.arm_error_poll() {
+                debug!("Failed to re-arm io_uring error poll: {}", e);
+            }
+            self.ring.submit()?;
+
+            self.engine.expire_probes(&mut responses);
+            if !responses.is_empty() {
+                return Ok(responses);
+            }
+
+            let deadline = self.engine.next_deadline();
+            tokio::select! {
+                ready = self.eventfd.ready(Interest::READABLE) => {
+                    if let Ok(mut guard) = ready {
+                        guard.clear_ready();
+                    }
+                    // Reset the counter; completions posted from here on signal again
+                    let mut counter = [0u8; 8];
+                    // SAFETY: reads at most 8 bytes into `counter`
+                    unsafe { libc::read(self.eventfd.as_raw_fd(), counter.as_mut_ptr().cast(), counter.len()) };
+                }
+                _ = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+            }
+        }
+    }
+}
+
+impl ProbeTransport for UringProbeEngine {
+    fn send_probe_with_protocol(
+        &mut self,
+        hop: usize,
+        dst: SocketAddr,
+        ttl: u8,
+        timeout: Duration,
+        protocol: ProbeProtocol,
+    ) -> Result<u16> {
+        let request = ProbeRequest { hop, dst, ttl, timeout };
+        self.send_probes(&[request], protocol)
+            .pop()
+            .unwrap_or_else(|| Err(anyhow::anyhow!("Probe was not sent")))
+    }
+
+    fn send_probes(&mut self, requests: &[ProbeRequest], protocol: ProbeProtocol) -> Vec<Result<u16>> {
+        self.engine
+            .send_probes_with(requests, protocol, |probes| send_batch(&mut self.ring, &mut self.completed, probes))
+    }
+
+    fn collect_responses_async(&mut self) -> impl Future<Output = Result<Vec<ProbeResponse>>> + Send {
+        UringProbeEngine::collect_responses_async(self)
+    }
+
+    fn expire_all(&mut self) -> Vec<ProbeResponse> {
+        self.engine.expire_all()
+    }
+
+    fn discarded_replies(&self) -> usize {
+        self.engine.discarded_replies()
+    }
+}
+
+impl Drop for UringProbeEngine {
+    /// Cancel the armed receives and wait them out before their buffers are freed
+    fn drop(&mut self) {
+        reap(&mut self.ring, &mut self.completed, |_, _| {});
+        for &(tag, _) in &self.completed {
+            if let Some(slot) = self.slots.get_mut(tag as usize) {
+                slot.armed = false;
+            }
+        }
+
+        let mut outstanding: Vec<u64> = (0..self.slots.len() as u64)
+            .filter(|&index| self.slots[index as usize].armed)
+            .collect();
+        if self.poll_armed {
+            outstanding.push(POLL_TAG);
+        }
+        for &tag in &outstanding {
+            let cancel = opcode::AsyncCancel::new(tag).build().user_data(CANCEL_TAG);
+            if push(&mut self.ring, &cancel).is_err() {
+                break;
+            }
+        }
+
+        while !outstanding.is_empty() {
+            if let Err(e) = self.ring.submit_and_wait(1) {
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    // Leak the buffers rather than free memory the kernel may still write
+                    std::mem::forget(std::mem::take(&mut self.slots));
+                    return;
+                }
+            }
+            let mut done = Vec::new();
+            reap(&mut self.ring, &mut done, |_, _| {});
+            outstanding.retain(|tag| !done.iter().any(|&(finished, _)| finished == *tag));
+        }
+    }
+}
+
+/// Queue a receive into `slot`, tagged with its index
+fn arm_slot(ring: &mut IoUring, slot: &mut RecvSlot, index: usize) -> Result<()> {
+    slot.reset();
+    let entry = opcode::RecvMsg::new(types::Fd(slot.fd), &mut slot.msg)
+        .build()
+        .user_data(index as u64);
+    push(ring, &entry)?;
+    slot.armed = true;
+    Ok(())
+}
+
+/// Queue an entry, flushing the submission queue if it is full
+fn push(ring: &mut IoUring, entry: &squeue::Entry) -> Result<()> {
+    // SAFETY: every entry points into a boxed receive slot or a send batch
+    // that stays alive until its completion has been reaped
+    while unsafe { ring.submission().push(entry) }.is_err() {
+        ring.submit()?;
+    }
+    Ok(())
+}
+
+/// Drain the completion queue
+///
+/// Receive and poll completions are stashed in `completed`; `on_other` sees
+/// everything else, i.e. sends and cancellations.
+fn reap(ring: &mut IoUring, completed: &mut Vec<(u64, i32)>, mut on_other: impl FnMut(u64, i32)) {
+    for cqe in ring.completion() {
+        let tag = cqe.user_data();
+        if tag & (SEND_TAG | CANCEL_TAG) == 0 {
+            completed.push((tag, cqe.result()));
+        } else {
+            on_other(tag, cqe.result());
+        }
+    }
+}
+
+/// Send a batch of probes as `SENDMSG` requests and wait for all of them
+fn send_batch(ring: &mut IoUring, completed: &mut Vec<(u64, i32)>, probes: &[OutgoingProbe]) -> Vec<std::io::Result<()>> {
+    // Owned copies, so a ring failure can leak them instead of freeing
+    // buffers the kernel has not finished with
+    let probes = probes.to_vec();
+    let mut iovecs = vec![libc::iovec { iov_base: std::ptr::null_mut(), iov_len: 0 }; probes.len()];
+    let mut controls = vec![[0u64; 4]; probes.len()];
+    let messages: Vec<libc::msghdr> = probes
+        .iter()
+        .zip(iovecs.iter_mut().zip(controls.iter_mut()))
+        .map(|(probe, (iov, control))| probe.ttl_message(iov, control))
+        .collect();
+
+    let mut results: Vec<Option<std::io::Result<()>>> = (0..probes.len()).map(|_| None).collect();
+    let mut outstanding = 0;
+    for (index, (probe, msg)) in probes.iter().zip(&messages).enumerate() {
+        let entry = opcode::SendMsg::new(types::Fd(probe.socket.as_raw_fd()), msg)
+            .build()
+            .user_data(SEND_TAG | index as u64);
+        match push(ring, &entry) {
+            Ok(()) => outstanding += 1,
+            Err(e) => results[index] = Some(Err(std::io::Error::other(e))),
+        }
+    }
+
+    while outstanding > 0 {
+        if let Err(e) = ring.submit_and_wait(1) {
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                std::mem::forget((probes, iovecs, controls, messages));
+                return results
+                    .into_iter()
+                    .map(|result| result.unwrap_or_else(|| Err(std::io::Error::new(e.kind(), e.to_string()))))
+                    .collect();
+            }
+        }
+        reap(ring, completed, |tag, result| {
+            if tag & SEND_TAG != 0 {
+                let index = (tag & !SEND_TAG) as usize;
+                results[index] = Some(if result < 0 {
+                    Err(std::io::Error::from_raw_os_error(-result))
+                } else {
+                    Ok(())
+                });
+                outstanding -= 1;
+            }
+        });
+    }
+
+    results.into_iter().map(|result| result.unwrap_or(Ok(()))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recv_slot_reset() {
+        let mut slot = Box::new(RecvSlot::new(ReplySocket::Icmp, -1));
+        slot.reset();
+        slot.msg.msg_namelen = 0;
+        slot.msg.msg_controllen = 0;
+        slot.reset();
+
+        assert_eq!(slot.msg.msg_iov, &mut slot.iov as *mut libc::iovec);
+        assert_eq!(slot.iov.iov_base, slot.buffer.as_mut_ptr().cast());
+        assert_eq!(slot.iov.iov_len, MAX_MTU);
+        assert_eq!(slot.msg.msg_namelen as usize, std::mem::size_of::<libc::sockaddr_storage>());
+        assert_eq!(slot.msg.msg_control, slot.control.as_mut_ptr().cast());
+        assert_ne!(slot.msg.msg_controllen, 0);
+    }
+
+    #[test]
+    fn test_recv_slot_completion() {
+        let receiver = std::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let sender = std::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut ring = IoUring::new(8).unwrap();
+        let mut slots = vec![RecvSlot::new(ReplySocket::Icmp, receiver.as_raw_fd())].into_boxed_slice();
+        arm_slot(&mut ring, &mut slots[0], 0).unwrap();
+        ring.submit().unwrap();
+        assert!(slots[0].armed);
+
+        sender.send_to(b"reply", receiver.local_addr().unwrap()).unwrap();
+        let mut completed = Vec::new();
+        while completed.is_empty() {
+            ring.submit_and_wait(1).unwrap();
+            reap(&mut ring, &mut completed, |_, _| {});
+        }
+        assert_eq!(completed, [(0, 5)]);
+        let slot = &slots[0];
+        assert_eq!(&slot.buffer[..5], b"reply");
+        // SAFETY: the kernel filled in the address of the completed slot
+        let addr = unsafe { SockAddr::new(slot.addr, slot.msg.msg_namelen) };
+        assert_eq!(addr.as_socket(), Some(sender.local_addr().unwrap()));
+    }
+}
@@ -0,0 +1,109 @@
+//! Per-probe event log (--probe-log)
+//!
+//! One line is appended for every probe once its outcome is known, so there
+//! is a record of exactly when loss happened to hold up against anyone's
+//! monitoring. The fields are separated by single spaces and never change
+//! order, with `-` for a value that doesn't apply:
+//!
+//! `<sent> <target> <hop> <seq> <outcome> <rtt_ms> <responder>`
+//!
+//! `sent` is when the probe went out, RFC 3339 in UTC to the microsecond.
+//! `outcome` is one of `ttl-exceeded`, `reply` (from the target),
+//! `unreachable` or `lost`.
+
+use crate::probe::IcmpResponseType;
+use crate::{utils, Result};
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::{
+    fs::File,
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+#[derive(Debug)]
+pub struct ProbeLog {
+    file: Mutex<File>,
+    target: IpAddr,
+}
+
+impl ProbeLog {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path, target: IpAddr) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+            target,
+        })
+    }
+
+    /// Log a probe sent at `send_time`; `reply` is None when it was lost
+    pub fn probe(&self, send_time: Instant, hop: u8, seq: u16, reply: Option<(IcmpResponseType, IpAddr, Duration)>) {
+        let sent = Utc::now() - send_time.elapsed();
+        let line = format_line(sent, self.target, hop, seq, reply);
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("Failed to write probe log: {}", e);
+        }
+    }
+}
+
+fn format_line(
+    sent: DateTime<Utc>,
+    target: IpAddr,
+    hop: u8,
+    seq: u16,
+    reply: Option<(IcmpResponseType, IpAddr, Duration)>,
+) -> String {
+    let sent = sent.to_rfc3339_opts(SecondsFormat::Micros, true);
+    match reply {
+        Some((kind, responder, rtt)) => {
+            let outcome = match kind {
+                IcmpResponseType::TimeExceeded => "ttl-exceeded",
+                IcmpResponseType::EchoReply => "reply",
+                IcmpResponseType::DestinationUnreachable => "unreachable",
+                IcmpResponseType::Timeout => "lost",
+            };
+            format!(
+                "{} {} {} {} {} {:.3} {}\n",
+                sent,
+                target,
+                hop,
+                seq,
+                outcome,
+                utils::time::duration_to_ms_f64(rtt),
+                responder
+            )
+        }
+        None => format!("{} {} {} {} lost - -\n", sent, target, hop, seq),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_format() {
+        let sent = DateTime::parse_from_rfc3339("2026-03-01T12:00:00.123456Z").unwrap().with_timezone(&Utc);
+        let target: IpAddr = "192.0.2.1".parse().unwrap();
+        let responder: IpAddr = "198.51.100.7".parse().unwrap();
+
+        let reply = Some((IcmpResponseType::TimeExceeded, responder, Duration::from_micros(12345)));
+        assert_eq!(
+            format_line(sent, target, 3, 33001, reply),
+            "2026-03-01T12:00:00.123456Z 192.0.2.1 3 33001 ttl-exceeded 12.345 198.51.100.7\n"
+        );
+        assert_eq!(
+            format_line(sent, target, 4, 33002, None),
+            "2026-03-01T12:00:00.123456Z 192.0.2.1 4 33002 lost - -\n"
+        );
+    }
+}
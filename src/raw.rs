@@ -0,0 +1,106 @@
+//! Raw output (-l), mtr's line protocol for driving other frontends
+//!
+//! Every line is a record about one hop, numbered from 0 for TTL 1 as mtr
+//! numbers them:
+//!
+//! - `x <pos> <seq>`: a probe was sent
+//! - `h <pos> <addr>`: the hop answered from a new address
+//! - `d <pos> <name>`: the hop's address resolved to a name
+//! - `p <pos> <usec> <seq>`: a reply came back after this many microseconds
+//!
+//! Timeouts print nothing; a reader counts a probe lost when its `x` never
+//! gets a `p`.
+
+use crate::{utils, HopStats};
+use std::{collections::HashMap, io::Write, net::IpAddr, sync::Mutex, time::Duration};
+use tracing::debug;
+
+#[derive(Debug, Default)]
+struct Announced {
+    addr: Option<IpAddr>,
+    named: bool,
+}
+
+/// Records written to stdout as probes go out and replies come in
+#[derive(Debug, Default)]
+pub struct RawWriter {
+    announced: Mutex<HashMap<u8, Announced>>, // What the reader knows of each hop, by TTL
+}
+
+impl RawWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn transmit(&self, ttl: u8, seq: u16) {
+        write_lines(&format!("x {} {}\n", position(ttl), seq));
+    }
+
+    /// A reply to probe `seq` from `addr`, recorded in `hop`
+    pub fn reply(&self, hop: &HopStats, addr: IpAddr, rtt: Duration, seq: u16) {
+        write_lines(&self.reply_lines(hop, addr, rtt, seq));
+    }
+
+    fn reply_lines(&self, hop: &HopStats, addr: IpAddr, rtt: Duration, seq: u16) -> String {
+        let pos = position(hop.hop);
+        let mut announced = self.announced.lock().unwrap();
+        let known = announced.entry(hop.hop).or_default();
+        let mut lines = String::new();
+        if known.addr != Some(addr) {
+            lines.push_str(&format!("h {} {}\n", pos, addr));
+            *known = Announced {
+                addr: Some(addr),
+                named: false,
+            };
+        }
+        // Names resolve in the background, so one turns up with a later reply
+        if let (false, Some(name)) = (known.named, hop.hostname.as_ref().filter(|_| hop.addr == Some(addr))) {
+            lines.push_str(&format!("d {} {}\n", pos, name));
+            known.named = true;
+        }
+        lines.push_str(&format!("p {} {} {}\n", pos, utils::time::duration_to_us_u64(rtt), seq));
+        lines
+    }
+}
+
+/// mtr's position of a hop: its TTL less one
+fn position(ttl: u8) -> u8 {
+    ttl.saturating_sub(1)
+}
+
+fn write_lines(lines: &str) {
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = stdout.write_all(lines.as_bytes()).and_then(|_| stdout.flush()) {
+        debug!("Failed to write raw output: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_records() {
+        let raw = RawWriter::new();
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut hop = HopStats::new(3);
+        hop.addr = Some(first);
+
+        let rtt = Duration::from_micros(12345);
+        assert_eq!(raw.reply_lines(&hop, first, rtt, 33000), "h 2 192.0.2.1\np 2 12345 33000\n");
+        assert_eq!(raw.reply_lines(&hop, first, rtt, 33001), "p 2 12345 33001\n");
+
+        hop.hostname = Some("core1.example.net".to_string());
+        assert_eq!(
+            raw.reply_lines(&hop, first, rtt, 33002),
+            "d 2 core1.example.net\np 2 12345 33002\n"
+        );
+        assert_eq!(raw.reply_lines(&hop, first, rtt, 33003), "p 2 12345 33003\n");
+
+        // Another address is announced, and its name is still to come
+        let second: IpAddr = "192.0.2.2".parse().unwrap();
+        hop.addr = Some(second);
+        hop.hostname = None;
+        assert_eq!(raw.reply_lines(&hop, second, rtt, 33004), "h 2 192.0.2.2\np 2 12345 33004\n");
+    }
+}
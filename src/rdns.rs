@@ -0,0 +1,265 @@
+//! Reverse DNS lookups and their cache
+//!
+//! Every round brings replies from the same hop addresses, and often the same
+//! address answers for several hops (everything past the target, for one).
+//! PTR answers are therefore kept per address until their TTL runs out, so
+//! each address is looked up once rather than once per reply. Failures are
+//! cached as well: an address without a PTR record for as long as the negative
+//! answer allows, one whose lookup timed out or failed for a short while.
+//!
+//! Lookups run on a pool of background tasks, never on the path that records
+//! replies: asking for a name that isn't cached queues a lookup and returns
+//! nothing, and the name is handed to the pool's `on_resolved` once it is
+//! known, so even a hop that has gone silent by then gets it.
+
+use hickory_resolver::{
+    error::{ResolveError, ResolveErrorKind},
+    TokioAsyncResolver,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc, Semaphore},
+    time,
+};
+use tracing::debug;
+
+/// Shortest time an answer is kept, so TTL 0 records aren't queried every round
+const MIN_TTL: Duration = Duration::from_secs(60);
+
+/// How long "no such name" is kept when the server gives no negative TTL
+const NEGATIVE_TTL: Duration = Duration::from_secs(300);
+
+/// How long to wait before retrying a lookup that timed out or failed
+const RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// How often `wait_idle` checks for outstanding lookups
+const IDLE_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone)]
+struct CachedName {
+    hostname: Option<String>,
+    expires: Instant,
+}
+
+/// PTR answers, positive and negative, by address
+#[derive(Debug, Clone, Default)]
+pub struct ReverseDnsCache {
+    entries: HashMap<IpAddr, CachedName>,
+}
+
+impl ReverseDnsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cached answer for an address; `Some(None)` means it has no name
+    pub fn get(&self, addr: IpAddr, now: Instant) -> Option<Option<&str>> {
+        self.entries
+            .get(&addr)
+            .filter(|entry| entry.expires > now)
+            .map(|entry| entry.hostname.as_deref())
+    }
+
+    /// Remember a name, or its absence, for `ttl`
+    pub fn insert(&mut self, addr: IpAddr, hostname: Option<String>, ttl: Duration, now: Instant) {
+        self.entries.retain(|_, entry| entry.expires > now);
+        self.entries.insert(
+            addr,
+            CachedName {
+                hostname,
+                expires: now + ttl.max(MIN_TTL),
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    cache: ReverseDnsCache,
+    pending: HashSet<IpAddr>, // Queued or in flight
+}
+
+/// Reverse lookups on a bounded pool of background tasks
+#[derive(Debug, Clone)]
+pub struct ReverseResolver {
+    state: Arc<Mutex<State>>,
+    queue: mpsc::UnboundedSender<IpAddr>,
+}
+
+impl ReverseResolver {
+    /// Start the pool, which runs at most `workers` lookups at a time and
+    /// gives each `timeout`; must be called within a Tokio runtime
+    ///
+    /// `on_resolved` is called with every name found, outside the pool's lock.
+    pub fn spawn(
+        resolver: TokioAsyncResolver,
+        workers: usize,
+        timeout: Duration,
+        on_resolved: impl Fn(IpAddr, &str) + Send + Sync + 'static,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (queue, requests) = mpsc::unbounded_channel();
+        let pool = Pool {
+            state: Arc::clone(&state),
+            workers: workers.max(1),
+            timeout,
+            on_resolved: Arc::new(on_resolved),
+        };
+        tokio::spawn(run_pool(resolver, requests, pool));
+        Self { state, queue }
+    }
+
+    /// The name of an address if it is known, queueing a lookup if it isn't
+    ///
+    /// Each address is queued once, however often it is asked for while its
+    /// lookup is outstanding.
+    pub fn lookup(&self, addr: IpAddr) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(cached) = state.cache.get(addr, Instant::now()) {
+            return cached.map(str::to_string);
+        }
+        if state.pending.insert(addr) {
+            let _ = self.queue.send(addr);
+        }
+        None
+    }
+
+    /// Wait until no lookups are outstanding, or `limit` has passed
+    pub async fn wait_idle(&self, limit: Duration) {
+        let deadline = time::Instant::now() + limit;
+        while !self.state.lock().unwrap().pending.is_empty() && time::Instant::now() < deadline {
+            time::sleep(IDLE_POLL).await;
+        }
+    }
+}
+
+/// Told of every name a lookup finds
+type OnResolved = Arc<dyn Fn(IpAddr, &str) + Send + Sync>;
+
+/// What the lookup tasks share
+struct Pool {
+    state: Arc<Mutex<State>>,
+    workers: usize,
+    timeout: Duration,
+    on_resolved: OnResolved,
+}
+
+async fn run_pool(resolver: TokioAsyncResolver, mut requests: mpsc::UnboundedReceiver<IpAddr>, pool: Pool) {
+    let permits = Arc::new(Semaphore::new(pool.workers));
+    let timeout = pool.timeout;
+    while let Some(addr) = requests.recv().await {
+        let Ok(permit) = Arc::clone(&permits).acquire_owned().await else {
+            break;
+        };
+        let resolver = resolver.clone();
+        let state = Arc::clone(&pool.state);
+        let on_resolved = Arc::clone(&pool.on_resolved);
+        tokio::spawn(async move {
+            let (hostname, ttl) = match time::timeout(timeout, resolver.reverse_lookup(addr)).await {
+                Ok(Ok(lookup)) => (
+                    lookup.iter().next().map(|name| name.to_string()),
+                    lookup.valid_until().saturating_duration_since(Instant::now()),
+                ),
+                Ok(Err(e)) => {
+                    debug!("Reverse lookup of {} failed: {}", addr, e);
+                    (None, negative_ttl(&e))
+                }
+                Err(_) => {
+                    debug!("Reverse lookup of {} timed out after {:?}", addr, timeout);
+                    (None, RETRY_AFTER)
+                }
+            };
+            if let Some(ref hostname) = hostname {
+                debug!("Resolved {} to {}", addr, hostname);
+            }
+            drop(permit);
+
+            {
+                let mut state = state.lock().unwrap();
+                state.pending.remove(&addr);
+                state.cache.insert(addr, hostname.clone(), ttl, Instant::now());
+            }
+            if let Some(hostname) = hostname {
+                on_resolved(addr, &hostname);
+            }
+        });
+    }
+}
+
+/// How long a failed reverse lookup should be remembered
+pub fn negative_ttl(error: &ResolveError) -> Duration {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound {
+            negative_ttl: Some(ttl),
+            ..
+        } => Duration::from_secs(u64::from(*ttl)),
+        ResolveErrorKind::NoRecordsFound { .. } => NEGATIVE_TTL,
+        _ => RETRY_AFTER,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::{
+        op::{Query, ResponseCode},
+        rr::{Name, RecordType},
+    };
+
+    #[test]
+    fn test_cache_expiry() {
+        let mut cache = ReverseDnsCache::new();
+        let now = Instant::now();
+        let router: IpAddr = "192.0.2.1".parse().unwrap();
+        let unnamed: IpAddr = "192.0.2.2".parse().unwrap();
+
+        cache.insert(router, Some("r1.example.".to_string()), Duration::from_secs(3600), now);
+        cache.insert(unnamed, None, NEGATIVE_TTL, now);
+        assert_eq!(cache.get(router, now), Some(Some("r1.example.")));
+        assert_eq!(cache.get(unnamed, now), Some(None));
+        assert_eq!(cache.get("192.0.2.3".parse().unwrap(), now), None);
+
+        // The negative answer runs out first
+        let later = now + NEGATIVE_TTL;
+        assert_eq!(cache.get(unnamed, later), None);
+        assert_eq!(cache.get(router, later), Some(Some("r1.example.")));
+
+        // Expired entries are dropped on the next insert
+        cache.insert("192.0.2.3".parse().unwrap(), None, RETRY_AFTER, later);
+        assert_eq!(cache.len(), 2);
+
+        // A TTL of zero still holds for a while
+        cache.insert(router, Some("r1.example.".to_string()), Duration::ZERO, later);
+        assert_eq!(cache.get(router, later + MIN_TTL / 2), Some(Some("r1.example.")));
+    }
+
+    #[test]
+    fn test_negative_ttl() {
+        let no_name = |negative_ttl| -> ResolveError {
+            ResolveErrorKind::NoRecordsFound {
+                query: Box::new(Query::query(Name::root(), RecordType::PTR)),
+                soa: None,
+                negative_ttl,
+                response_code: ResponseCode::NXDomain,
+                trusted: true,
+            }
+            .into()
+        };
+        assert_eq!(negative_ttl(&no_name(Some(900))), Duration::from_secs(900));
+        assert_eq!(negative_ttl(&no_name(None)), NEGATIVE_TTL);
+        assert_eq!(negative_ttl(&ResolveErrorKind::Timeout.into()), RETRY_AFTER);
+    }
+}
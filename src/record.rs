@@ -0,0 +1,294 @@
+//! SQLite session recording (--record)
+//!
+//! Every probe result and, after every round, each hop's statistics are
+//! appended to a database that outlives the run, so a path can be queried
+//! and baselined over weeks of runs:
+//!
+//! - `targets`: one row per traced name and address
+//! - `rounds`: one row per hop per round, with the hop's running totals
+//! - `probes`: one row per probe, its RTT NULL when it was lost
+//!
+//! Rows carry the run's start time, telling apart runs against the same
+//! target. Timestamps are RFC 3339 in UTC, which SQLite's date functions
+//! read as they are. Probes are buffered and written with their round in a
+//! single transaction, so recording costs one commit per round.
+//!
+//! The writes happen on a thread of the recorder's own: a busy or slow
+//! database never blocks the tasks that probe and count replies.
+
+use crate::{utils, HopStats, Result};
+use anyhow::Context;
+use chrono::{SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+use std::{
+    net::IpAddr,
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS targets (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    addr TEXT NOT NULL,
+    UNIQUE (name, addr)
+);
+CREATE TABLE IF NOT EXISTS rounds (
+    id INTEGER PRIMARY KEY,
+    target_id INTEGER NOT NULL REFERENCES targets (id),
+    run_started TEXT NOT NULL,
+    round INTEGER NOT NULL,
+    timestamp TEXT NOT NULL,
+    hop INTEGER NOT NULL,
+    addr TEXT,
+    hostname TEXT,
+    sent INTEGER NOT NULL,
+    received INTEGER NOT NULL,
+    loss_percent REAL NOT NULL,
+    last_ms REAL,
+    avg_ms REAL,
+    best_ms REAL,
+    worst_ms REAL,
+    jitter_ms REAL
+);
+CREATE TABLE IF NOT EXISTS probes (
+    id INTEGER PRIMARY KEY,
+    target_id INTEGER NOT NULL REFERENCES targets (id),
+    run_started TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    hop INTEGER NOT NULL,
+    addr TEXT,
+    rtt_ms REAL
+);
+CREATE INDEX IF NOT EXISTS rounds_target ON rounds (target_id, timestamp);
+CREATE INDEX IF NOT EXISTS probes_target ON probes (target_id, timestamp);
+";
+
+/// How long to wait for another writer, such as the other session of --dual-stack
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct Probe {
+    timestamp: String,
+    hop: u8,
+    addr: Option<IpAddr>,
+    rtt: Option<Duration>,
+}
+
+enum Message {
+    Probe(Probe),
+    Round(usize, Vec<HopStats>),
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    tx: Sender<Message>,
+}
+
+/// The writer thread's end: the connection and what it still has to write
+struct Writer {
+    conn: Connection,
+    target_id: i64,
+    run_started: String,
+    pending: Vec<Probe>, // Probes since the last round, written with the next
+    failing: bool,       // Set once a failed write was warned about; repeats only go to debug
+}
+
+impl Recorder {
+    /// Open (creating if needed) the database at `path` for a run against `target`
+    pub fn open(path: &Path, target: &str, target_addr: IpAddr) -> Result<Self> {
+        let conn = Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::with_connection(conn, target, target_addr)
+            .with_context(|| format!("Failed to set up {}", path.display()))
+    }
+
+    fn with_connection(conn: Connection, target: &str, target_addr: IpAddr) -> Result<Self> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO targets (name, addr) VALUES (?1, ?2)",
+            params![target, target_addr.to_string()],
+        )?;
+        let target_id = conn.query_row(
+            "SELECT id FROM targets WHERE name = ?1 AND addr = ?2",
+            params![target, target_addr.to_string()],
+            |row| row.get(0),
+        )?;
+        let writer = Writer {
+            conn,
+            target_id,
+            run_started: now(),
+            pending: Vec::new(),
+            failing: false,
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("mtr-ng-record".to_string())
+            .spawn(move || writer.run(rx))
+            .context("Failed to start the database writer")?;
+        Ok(Self { tx })
+    }
+
+    /// Note a probe's reply, or with `reply` None its loss
+    pub fn probe(&self, hop: u8, reply: Option<(IpAddr, Duration)>) {
+        let _ = self.tx.send(Message::Probe(Probe {
+            timestamp: now(),
+            hop,
+            addr: reply.map(|(addr, _)| addr),
+            rtt: reply.map(|(_, rtt)| rtt),
+        }));
+    }
+
+    /// Write the round's hop statistics along with the probes noted since the last
+    pub fn round(&self, round: usize, hops: &[HopStats]) {
+        let probed = hops.iter().filter(|hop| hop.sent > 0).cloned().collect();
+        let _ = self.tx.send(Message::Round(round, probed));
+    }
+
+    /// Write the probes noted since the last round, such as replies that came in after it,
+    /// and wait until they are committed
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(Message::Flush(done_tx)).is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+impl Writer {
+    /// Write until every Recorder handle is gone, then write what is left
+    fn run(mut self, rx: Receiver<Message>) {
+        for message in rx {
+            match message {
+                Message::Probe(probe) => self.pending.push(probe),
+                Message::Round(round, hops) => {
+                    let result = self.write(Some((round, &hops)));
+                    self.report(result);
+                }
+                Message::Flush(done) => {
+                    let result = self.write(None);
+                    self.report(result);
+                    let _ = done.send(());
+                }
+            }
+        }
+        let result = self.write(None);
+        self.report(result);
+    }
+
+    fn write(&mut self, round: Option<(usize, &[HopStats])>) -> Result<()> {
+        let probes = std::mem::take(&mut self.pending);
+        if probes.is_empty() && round.is_none() {
+            return Ok(());
+        }
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO probes (target_id, run_started, timestamp, hop, addr, rtt_ms) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for probe in &probes {
+                insert.execute(params![
+                    self.target_id,
+                    self.run_started,
+                    probe.timestamp,
+                    probe.hop,
+                    probe.addr.map(|addr| addr.to_string()),
+                    probe.rtt.map(utils::time::duration_to_ms_f64),
+                ])?;
+            }
+        }
+        if let Some((round, hops)) = round {
+            let timestamp = now();
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO rounds (target_id, run_started, round, timestamp, hop, addr, hostname, sent, \
+                 received, loss_percent, last_ms, avg_ms, best_ms, worst_ms, jitter_ms) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            )?;
+            let ms = |rtt: Option<Duration>| rtt.map(utils::time::duration_to_ms_f64);
+            for hop in hops {
+                insert.execute(params![
+                    self.target_id,
+                    self.run_started,
+                    round as i64,
+                    timestamp,
+                    hop.hop,
+                    hop.addr.map(|addr| addr.to_string()),
+                    hop.hostname,
+                    hop.sent as i64,
+                    hop.received as i64,
+                    hop.loss_percent,
+                    ms(hop.last_rtt),
+                    ms(hop.avg_rtt),
+                    ms(hop.best_rtt),
+                    ms(hop.worst_rtt),
+                    ms(hop.jitter_avg),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn report(&mut self, result: Result<()>) {
+        match result {
+            Ok(()) => self.failing = false,
+            Err(e) if !self.failing => {
+                warn!("Failed to record to the database: {}", e);
+                self.failing = true;
+            }
+            Err(e) => debug!("Failed to record to the database: {}", e),
+        }
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_probes_and_rounds_are_recorded() {
+        let path = std::env::temp_dir().join(format!("mtr-ng-record-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let target: IpAddr = "192.0.2.1".parse().unwrap();
+        let recorder = Recorder::open(&path, "example.com", target).unwrap();
+
+        let mut hops = vec![HopStats::new(1), HopStats::new(2)];
+        hops[0].addr = Some("198.51.100.1".parse().unwrap());
+        hops[0].increment_sent();
+        hops[0].add_rtt(Duration::from_millis(12));
+        hops[1].increment_sent();
+        hops[1].add_timeout();
+        recorder.probe(1, Some(("198.51.100.1".parse().unwrap(), Duration::from_millis(12))));
+        recorder.probe(2, None);
+        recorder.round(1, &hops);
+        recorder.probe(2, Some((target, Duration::from_millis(30))));
+        recorder.flush().await;
+
+        let conn = Connection::open(&path).unwrap();
+        let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(count("SELECT COUNT(*) FROM targets"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM probes"), 3);
+        assert_eq!(count("SELECT COUNT(*) FROM probes WHERE rtt_ms IS NULL"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM rounds WHERE round = 1"), 2);
+        let avg: f64 = conn
+            .query_row("SELECT avg_ms FROM rounds WHERE hop = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(avg, 12.0);
+        let loss: f64 = conn
+            .query_row("SELECT loss_percent FROM rounds WHERE hop = 2", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(loss, 100.0);
+        drop(conn);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,6 +1,25 @@
-use crate::args::Column;
+use crate::alerts::Thresholds;
+use crate::args::{Args, Column, RttUnit};
+use crate::config::{self, Config};
+use crate::output::Event;
+use crate::ui::visualization::{create_sparkline_spans, detect_color_support, spans_to_ansi, ColorSupport};
 use crate::utils;
-use crate::{MtrSession, Result};
+use crate::{merge, persist::SavedSession};
+use crate::{HopStats, MtrSession, Result};
+use anyhow::Context;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write as _};
+use tokio::sync::watch;
+use tracing::warn;
+
+mod html;
+mod snapshot;
+mod xml;
+
+pub use snapshot::{export_snapshot, ExportFormat};
+
+/// How many of a hop's latest probes --graph draws
+const GRAPH_WIDTH: usize = 40;
 
 fn format_column_headers(columns: &[Column]) -> String {
     let mut header = String::new();
@@ -13,6 +32,7 @@ fn format_column_headers(columns: &[Column]) -> String {
             Column::Host => {} // No header padding needed
             Column::Loss => header.push_str("Loss%"),
             Column::Sent => header.push_str(" Snt"),
+            Column::Avail => header.push_str("Avail%"),
             Column::Last => header.push_str("   Last"),
             Column::Avg => header.push_str("    Avg"),
             Column::Ema => header.push_str("   EMA"),
@@ -20,18 +40,20 @@ fn format_column_headers(columns: &[Column]) -> String {
             Column::JitterAvg => header.push_str("  JAvg"),
             Column::Best => header.push_str("  Best"),
             Column::Worst => header.push_str("  Wrst"),
-            Column::Graph => header.push_str("StDev"), // Use StDev for report mode instead of graph
+            Column::P50 | Column::P90 | Column::P95 | Column::P99 => {
+                header.push_str(&format!("{:>7}", column.header()))
+            }
+            Column::Trend => header.push_str(&format!("{:>4}", "Trd")),
+            Column::ReplyTtl => header.push_str("rTTL"),
+            Column::Location => header.push_str(&format!("{:<21}", "Location")),
+            Column::StDev => header.push_str("StDev"),
+            Column::Graph => {} // Left out of text reports, see report_columns
         }
     }
     header
 }
 
-fn format_row_data(
-    hop: &crate::HopStats,
-    hostname: &str,
-    columns: &[Column],
-    stddev: f64,
-) -> String {
+fn format_row_data(hop: &crate::HopStats, hostname: &str, columns: &[Column], rtt_unit: RttUnit) -> String {
     let mut row = String::new();
     for (i, column) in columns.iter().enumerate() {
         if i > 0 {
@@ -42,120 +64,496 @@ fn format_row_data(
             Column::Host => row.push_str(&format!(" {:20}", hostname)),
             Column::Loss => row.push_str(&format!(" {:5.1}%", hop.loss_percent)),
             Column::Sent => row.push_str(&format!(" {:4}", hop.sent)),
+            Column::Avail => match hop.availability_percent() {
+                Some(avail) => row.push_str(&format!(" {:5.1}%", avail)),
+                None => row.push_str("    ???"),
+            },
             Column::Last => {
                 if let Some(rtt) = hop.last_rtt {
-                    row.push_str(&format!(" {:6.1}", utils::time::duration_to_ms_f64(rtt)));
+                    row.push_str(&format!(" {:>6}", utils::time::format_rtt(rtt, rtt_unit)));
                 } else {
                     row.push_str("   ???");
                 }
             }
             Column::Avg => {
                 if let Some(rtt) = hop.avg_rtt {
-                    row.push_str(&format!(" {:6.1}", utils::time::duration_to_ms_f64(rtt)));
+                    row.push_str(&format!(" {:>6}", utils::time::format_rtt(rtt, rtt_unit)));
                 } else {
                     row.push_str("   ???");
                 }
             }
             Column::Ema => {
                 if let Some(rtt) = hop.ema_rtt {
-                    row.push_str(&format!(" {:5.1}", utils::time::duration_to_ms_f64(rtt)));
+                    row.push_str(&format!(" {:>5}", utils::time::format_rtt(rtt, rtt_unit)));
                 } else {
                     row.push_str("   ???");
                 }
             }
             Column::Jitter => {
                 if let Some(jitter) = hop.last_jitter {
-                    row.push_str(&format!(" {:5.1}", utils::time::duration_to_ms_f64(jitter)));
+                    row.push_str(&format!(" {:>5}", utils::time::format_rtt(jitter, rtt_unit)));
                 } else {
                     row.push_str("   ???");
                 }
             }
             Column::JitterAvg => {
                 if let Some(jitter) = hop.jitter_avg {
-                    row.push_str(&format!(" {:5.1}", utils::time::duration_to_ms_f64(jitter)));
+                    row.push_str(&format!(" {:>5}", utils::time::format_rtt(jitter, rtt_unit)));
                 } else {
                     row.push_str("   ???");
                 }
             }
             Column::Best => {
                 if let Some(rtt) = hop.best_rtt {
-                    row.push_str(&format!(" {:5.1}", utils::time::duration_to_ms_f64(rtt)));
+                    row.push_str(&format!(" {:>5}", utils::time::format_rtt(rtt, rtt_unit)));
                 } else {
                     row.push_str("   ???");
                 }
             }
             Column::Worst => {
                 if let Some(rtt) = hop.worst_rtt {
-                    row.push_str(&format!(" {:5.1}", utils::time::duration_to_ms_f64(rtt)));
+                    row.push_str(&format!(" {:>5}", utils::time::format_rtt(rtt, rtt_unit)));
+                } else {
+                    row.push_str("   ???");
+                }
+            }
+            Column::P50 | Column::P90 | Column::P95 | Column::P99 => {
+                let percentile = column.percentile().and_then(|p| hop.rtt_percentile(p));
+                if let Some(rtt) = percentile {
+                    row.push_str(&format!(" {:>6}", utils::time::format_rtt(rtt, rtt_unit)));
                 } else {
                     row.push_str("   ???");
                 }
             }
-            Column::Graph => {
-                row.push_str(&format!(" {:5.1}", stddev));
+            Column::Trend => {
+                let symbol = hop.trend().map(|t| t.symbol()).unwrap_or(" ");
+                row.push_str(&format!("  {} ", symbol));
+            }
+            Column::ReplyTtl => match hop.reply_ttl {
+                Some(ttl) => row.push_str(&format!(" {:>3}", ttl)),
+                None => row.push_str("    "),
+            },
+            Column::Location => {
+                let location = hop.geo.as_ref().map(|geo| geo.to_string()).unwrap_or_default();
+                let location: String = location.chars().take(20).collect();
+                row.push_str(&format!(" {:20}", location));
             }
+            Column::StDev => {
+                let stddev = hop.rtt_stddev().unwrap_or_default();
+                row.push_str(&format!(" {:>5}", utils::time::format_rtt(stddev, rtt_unit)));
+            }
+            Column::Graph => {}
         }
     }
     row
 }
 
+/// Stop the sessions cleanly on Ctrl-C, so the report covers the rounds so far
+///
+/// A second Ctrl-C, say during the wait for late replies, quits at once.
+fn shutdown_on_interrupt(sessions: &[&MtrSession]) {
+    let tokens: Vec<_> = sessions.iter().map(|session| session.cancel.clone()).collect();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            for token in tokens {
+                token.cancel();
+            }
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            // The conventional status for SIGINT
+            std::process::exit(130);
+        }
+    });
+}
+
+/// Follow --config while the sessions trace
+///
+/// Returns the arguments as the latest reload left them, for the snapshots
+/// and the final report. Without --config they never change.
+fn spawn_config_reloader(sessions: &[&MtrSession]) -> Result<watch::Receiver<Args>> {
+    let mut args = sessions[0].args.clone();
+    let (tx, rx) = watch::channel(args.clone());
+    let Some(path) = args.config.clone() else {
+        return Ok(rx);
+    };
+    let mut reloads = config::spawn_reloader(path)?;
+    let sessions: Vec<MtrSession> = sessions.iter().map(|session| (*session).clone()).collect();
+    tokio::spawn(async move {
+        while let Some(config) = reloads.recv().await {
+            apply_config(&sessions, &mut args, &config);
+            if tx.send(args.clone()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Apply a reloaded config to running sessions
+///
+/// The thresholds reach the --on-alert commands at once; the display
+/// settings only change `args`.
+fn apply_config(sessions: &[MtrSession], args: &mut Args, config: &Config) {
+    config.apply_to_args(args);
+    let thresholds = Thresholds::from_args(args);
+    for session in sessions {
+        if let Some(ref alert) = session.alert {
+            alert.set_thresholds(thresholds);
+        }
+        if let Some(ref path) = args.config {
+            session.publish(&Event::config_reloaded(path));
+        }
+    }
+}
+
+/// Print a snapshot of the report every --report-every rounds while the trace runs
+///
+/// Returns the task, to be aborted once the trace is over and the final
+/// report takes over.
+fn spawn_snapshots(
+    session: &MtrSession,
+    settings: &watch::Receiver<Args>,
+) -> Result<Option<tokio::task::JoinHandle<()>>> {
+    let Some(every) = session.args.report_every else {
+        return Ok(None);
+    };
+    let mut file = match session.args.report_file {
+        Some(ref path) => Some(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let mut rounds = session.hooks.on_round_complete();
+    let mut session = session.clone();
+    let settings = settings.clone();
+    Ok(Some(tokio::spawn(async move {
+        while let Some(event) = rounds.recv().await {
+            if event.round % every.get() != 0 {
+                continue;
+            }
+            session.args = settings.borrow().clone();
+            let snapshot = format!("Round {}\n{}\n", event.round, render_report(&session));
+            let written = match file {
+                Some(ref mut file) => file.write_all(snapshot.as_bytes()),
+                None => {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(snapshot.as_bytes()).and_then(|_| stdout.flush())
+                }
+            };
+            if let Err(e) = written {
+                warn!("Failed to write report snapshot: {}", e);
+            }
+        }
+    })))
+}
+
 pub async fn run_report(mut session: MtrSession) -> Result<()> {
+    shutdown_on_interrupt(&[&session]);
+    let settings = spawn_config_reloader(&[&session])?;
+    let snapshots = spawn_snapshots(&session, &settings)?;
     session.run_trace().await?;
+    if let Some(snapshots) = snapshots {
+        snapshots.abort();
+    }
+    session.args = settings.borrow().clone();
+    print_output(&session);
+    write_html(&[&session])?;
+    session.save_state()
+}
 
-    let columns = session.args.get_columns();
-
-    println!(
-        "Start: {}",
-        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
-    );
-    println!(
-        "HOST: localhost → {} ({})",
-        session.target, session.target_addr
-    );
-    println!();
-    println!(
-        "                             {}",
-        format_column_headers(&columns)
-    );
+/// Report on sessions saved with --save, combined into one (--merge)
+pub async fn run_merge(args: Args) -> Result<()> {
+    let saved = args
+        .merge
+        .iter()
+        .map(|path| SavedSession::load(path))
+        .collect::<Result<Vec<_>>>()?;
+    let session = MtrSession::from_saved(args, merge::merge(saved)?)?;
+    print_output(&session);
+    write_html(&[&session])
+}
 
-    // Determine how many hops to display based on discovery or organic growth  
-    let max_hops_to_display = if session.num_hosts > 0 {
-        session.num_hosts
+/// Trace both address families of a host at once, then report each in turn
+pub async fn run_dual_stack_report(mut ipv4: MtrSession, mut ipv6: MtrSession) -> Result<()> {
+    shutdown_on_interrupt(&[&ipv4, &ipv6]);
+    let settings = spawn_config_reloader(&[&ipv4, &ipv6])?;
+    let snapshots = [spawn_snapshots(&ipv4, &settings)?, spawn_snapshots(&ipv6, &settings)?];
+    tokio::try_join!(ipv4.run_trace(), ipv6.run_trace())?;
+    for snapshots in snapshots.into_iter().flatten() {
+        snapshots.abort();
+    }
+    ipv4.args = settings.borrow().clone();
+    ipv6.args = settings.borrow().clone();
+    print_output(&ipv4);
+    // A blank line between the two tables; line-oriented output runs on unbroken
+    if prints_table(&ipv4) {
+        println!();
+    }
+    print_output(&ipv6);
+    write_html(&[&ipv4, &ipv6])
+}
+
+/// Write the --html report of the first session's arguments, if one was asked for
+pub fn write_html(sessions: &[&MtrSession]) -> Result<()> {
+    match sessions.first().and_then(|session| session.args.html.as_ref()) {
+        Some(path) => html::write(sessions, path),
+        None => Ok(()),
+    }
+}
+
+/// Print a finished session in the format asked for
+fn print_output(session: &MtrSession) {
+    if session.args.jsonl.is_some() || session.args.raw || session.args.split {
+        // Written out as the trace ran
+    } else if session.args.xml {
+        print!("{}", xml::render(session));
     } else {
-        // Show all hops that have been probed
-        session.hops.iter()
-            .enumerate()
-            .rev()
-            .find(|(_, hop)| hop.sent > 0)
-            .map(|(i, _)| i + 1)
-            .unwrap_or(0)
-    };
+        print_report(session);
+    }
+}
+
+/// Whether a finished session is printed as the report table
+fn prints_table(session: &MtrSession) -> bool {
+    !(session.args.jsonl.is_some() || session.args.raw || session.args.split || session.args.xml)
+}
+
+/// A hop's name as the report shows it: its address with --numeric, else its hostname
+fn hop_name(session: &MtrSession, hop: &HopStats) -> String {
+    if session.args.numeric {
+        utils::network::format_optional_ip(hop.addr)
+    } else {
+        utils::network::format_hostname_with_fallback(hop.hostname.clone(), hop.addr)
+    }
+}
+
+/// Standard deviation of a hop's recent RTTs in milliseconds, 0 with fewer than two
+fn rtt_stddev_ms(hop: &HopStats) -> f64 {
+    hop.rtt_stddev().map_or(0.0, utils::time::duration_to_ms_f64)
+}
+
+/// Text made safe for XML and HTML element content and attribute values
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Sparklines at the end of report rows (--graph), scaled alike as in the UI
+struct Graph {
+    rtt_range: (u64, u64),
+    scale: crate::SparklineScale,
+    color: Option<ColorSupport>, // Only a terminal gets color codes
+}
+
+impl Graph {
+    fn new(hops: &[HopStats], scale: crate::SparklineScale) -> Self {
+        let rtts: Vec<u64> = hops
+            .iter()
+            .flat_map(|hop| hop.rtts.iter())
+            .map(|rtt| utils::time::duration_to_ms_u64(*rtt))
+            .collect();
+        Self {
+            rtt_range: (
+                rtts.iter().min().copied().unwrap_or(1),
+                rtts.iter().max().copied().unwrap_or(1),
+            ),
+            scale,
+            color: std::io::stdout().is_terminal().then(detect_color_support),
+        }
+    }
+
+    fn render(&self, hop: &HopStats) -> String {
+        let (min, max) = self.rtt_range;
+        let width = hop.packet_history.len().min(GRAPH_WIDTH);
+        let color_support = self.color.unwrap_or(ColorSupport::None);
+        let spans = create_sparkline_spans(hop, min, max, self.scale, color_support, width);
+        if self.color.is_some() {
+            spans_to_ansi(&spans)
+        } else {
+            spans.iter().map(|span| span.content.as_ref()).collect()
+        }
+    }
+}
+
+/// The columns of a text report, which has no room for graphs
+///
+/// The default Graph column gives way to StDev, as in mtr's report; a
+/// Graph asked for with --fields is left out.
+fn report_columns(args: &Args) -> Vec<Column> {
+    let columns = args.get_columns();
+    if args.fields.is_none() && !args.show_all {
+        columns
+            .into_iter()
+            .map(|column| if column == Column::Graph { Column::StDev } else { column })
+            .collect()
+    } else {
+        columns.into_iter().filter(|column| *column != Column::Graph).collect()
+    }
+}
+
+fn print_report(session: &MtrSession) {
+    print!("{}", render_report(session));
+    if session.args.baseline {
+        if let Err(e) = session.save_baselines() {
+            eprintln!("Warning: failed to save baselines: {:#}", e);
+        }
+    }
+}
+
+/// The text report of a session as it stands
+fn render_report(session: &MtrSession) -> String {
+    let columns = report_columns(&session.args);
+    let mut out = String::new();
+
+    let _ = writeln!(out, "Start: {}", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"));
+    let source = session
+        .args
+        .address
+        .map_or_else(|| "localhost".to_string(), |source| source.to_string());
+    let _ = writeln!(out, "HOST: {} → {} ({})", source, session.target, session.target_addr);
+    if session.args.rtt_unit != RttUnit::Ms {
+        let _ = writeln!(out, "RTT unit: {}", session.args.rtt_unit.name());
+    }
+    out.push('\n');
+    let _ = writeln!(out, "                             {}", format_column_headers(&columns));
+
+    // Determine how many hops to display based on discovery or organic growth  
+    let max_hops_to_display = session.display_hop_count();
     
-    for hop in session.hops.iter().take(max_hops_to_display) {
+    let blocked_hop = session.blocked_hop();
+    let hops = session.stats.snapshot_range(max_hops_to_display);
+    let graph = session.args.graph.then(|| Graph::new(&hops, session.args.sparkline_scale));
+
+    for (index, hop) in hops.iter().enumerate() {
         if hop.sent == 0 {
             continue;
         }
 
-        let hostname = if session.args.numeric {
-            utils::network::format_optional_ip(hop.addr)
+        let hostname = if blocked_hop == Some(index) {
+            hop.blocked_description()
         } else {
-            utils::network::format_hostname_with_fallback(hop.hostname.clone(), hop.addr)
+            hop_name(session, hop)
         };
-
-        let stddev = if hop.received > 1 && hop.rtts.len() > 1 {
-            let mean = utils::time::duration_to_ms_f64(hop.avg_rtt.unwrap());
-            let rtt_values_ms: Vec<f64> = hop
-                .rtts
-                .iter()
-                .map(|rtt| utils::time::duration_to_ms_f64(*rtt))
-                .collect();
-            utils::math::calculate_stddev(&rtt_values_ms, mean)
-        } else {
-            0.0
+        let hostname = match hop.port_state {
+            Some(state) => format!("{} [{}]", hostname, state),
+            None => hostname,
+        };
+        let hostname = match hop.timestamps {
+            Some(timestamps) => format!("{} [{}]", hostname, timestamps),
+            None => hostname,
+        };
+        let hostname = match hop.dns_rcode {
+            Some(rcode) => format!("{} [DNS {}]", hostname, rcode),
+            None => hostname,
         };
 
-        println!("{}", format_row_data(hop, &hostname, &columns, stddev));
+        let mut row = format_row_data(hop, &hostname, &columns, session.args.rtt_unit);
+        if let Some(ref graph) = graph {
+            row.push_str("  ");
+            row.push_str(&graph.render(hop));
+        }
+        let _ = writeln!(out, "{}", row);
+        // ICMP extensions reported by the hop, as mtr prints them
+        if let Some(ref extensions) = hop.extensions {
+            for label in &extensions.mpls {
+                let _ = writeln!(out, "    [{}]", label);
+            }
+            for interface in &extensions.interfaces {
+                let _ = writeln!(out, "    [{}]", interface);
+            }
+        }
+        if let Some(ref whois) = hop.whois {
+            let _ = writeln!(out, "    [{}]", whois);
+        }
+    }
+
+    let route_history = session.stats.route_history();
+    if route_history.total() > 0 {
+        out.push('\n');
+        let _ = writeln!(out, "Route changes ({}):", route_history.total());
+        for change in route_history.events() {
+            let _ = writeln!(out, "  {}", change);
+        }
+    }
+
+    if session.args.baseline {
+        let deviations: Vec<_> = (0..max_hops_to_display)
+            .filter_map(|index| session.baseline_deviation(index).map(|d| (index, d)))
+            .collect();
+        if !deviations.is_empty() {
+            out.push('\n');
+            let _ = writeln!(out, "Deviations from baseline:");
+            for (index, deviation) in deviations {
+                let hop = &hops[index];
+                let _ = writeln!(
+                    out,
+                    "{:2}. {:20} {}",
+                    hop.hop,
+                    utils::network::format_optional_ip(hop.addr),
+                    deviation
+                );
+            }
+        }
     }
+    out
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_report_columns() {
+        let args = Args::try_parse_from(["mtr-ng", "-r", "example.com"]).unwrap();
+        let columns = report_columns(&args);
+        assert!(columns.contains(&Column::StDev));
+        assert!(!columns.contains(&Column::Graph));
+
+        let args = Args::try_parse_from(["mtr-ng", "-r", "--fields", "hop,stdev,graph", "example.com"]).unwrap();
+        assert_eq!(report_columns(&args), vec![Column::Hop, Column::StDev]);
+    }
+
+    #[tokio::test]
+    async fn test_config_reload_changes_thresholds() {
+        let args = Args::try_parse_from([
+            "mtr-ng", "-r", "-n", "--loss-threshold", "60", "--on-alert", "true", "192.0.2.1",
+        ])
+        .unwrap();
+        let session = MtrSession::new(args).await.unwrap();
+        {
+            let mut hop = session.stats.hop_mut(0);
+            hop.increment_sent();
+            hop.add_rtt(std::time::Duration::from_millis(20));
+            hop.increment_sent();
+            hop.add_timeout();
+        }
+        let alert = session.alert.clone().unwrap();
+        assert_eq!(alert.check(&session), None);
+
+        let mut args = session.args.clone();
+        let config = Config::parse(r#"{"loss-threshold": 20.0, "fields": ["hop", "host", "loss"]}"#).unwrap();
+        apply_config(std::slice::from_ref(&session), &mut args, &config);
+        assert_eq!(args.loss_threshold, Some(20.0));
+        assert_eq!(report_columns(&args), vec![Column::Hop, Column::Host, Column::Loss]);
+        assert_eq!(alert.check(&session).unwrap().hop, 1);
+    }
+
+    #[test]
+    fn test_trend_header_matches_row() {
+        let mut hop = HopStats::new(1);
+        hop.increment_sent();
+        hop.add_rtt(std::time::Duration::from_millis(12));
+        let columns = [Column::Avg, Column::Trend, Column::Last];
+        let header = format_column_headers(&columns);
+        let row = format_row_data(&hop, "192.0.2.1", &columns, RttUnit::Ms);
+        assert_eq!(header.chars().count(), row.chars().count());
+        assert_eq!(header.find("Last").unwrap() + 4, row.chars().count());
+    }
 }
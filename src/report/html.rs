@@ -0,0 +1,170 @@
+//! Self-contained HTML report (--html)
+//!
+//! One file with no outside references, so it survives being attached to a
+//! ticket: the hop table of every session, and beside each hop an SVG
+//! sparkline of its recent RTTs and a strip of its recent probes, red where
+//! they were lost.
+
+use super::{escape, hop_name, rtt_stddev_ms};
+use crate::{hop_stats::PacketOutcome, utils, HopStats, MtrSession, Result};
+use anyhow::Context;
+use std::{fmt::Write, path::Path, time::Duration};
+
+const SPARKLINE_WIDTH: f64 = 160.0;
+const SPARKLINE_HEIGHT: f64 = 24.0;
+/// Width of one probe in the loss strip
+const PROBE_WIDTH: f64 = 4.0;
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+h2 { margin-bottom: 0.2em; }
+.meta { color: #666; margin-top: 0; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { padding: 0.3em 0.8em; text-align: right; border-bottom: 1px solid #ddd; }
+th { background: #f4f4f4; }
+td.host { text-align: left; font-family: ui-monospace, monospace; }
+td.chart { padding: 0.2em 0.8em; }
+tr.lossy td.loss { color: #c0392b; font-weight: bold; }
+";
+
+/// Write the report for `sessions` to `path`
+pub(crate) fn write(sessions: &[&MtrSession], path: &Path) -> Result<()> {
+    std::fs::write(path, render(sessions)).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn render(sessions: &[&MtrSession]) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    let title = sessions.iter().map(|session| session.target.as_str()).collect::<Vec<_>>().join(", ");
+    let _ = writeln!(out, "<title>mtr-ng report: {}</title>", escape(&title));
+    let _ = writeln!(out, "<style>\n{}</style>\n</head>\n<body>", STYLE);
+    for session in sessions {
+        render_session(&mut out, session);
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn render_session(out: &mut String, session: &MtrSession) {
+    let _ = writeln!(
+        out,
+        "<h2>{} ({})</h2>\n<p class=\"meta\">{} probes, {}</p>",
+        escape(&session.target),
+        session.target_addr,
+        format!("{:?}", session.args.protocol).to_uppercase(),
+        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+    );
+    out.push_str(
+        "<table>\n<tr><th>Hop</th><th>Host</th><th>Loss%</th><th>Snt</th><th>Last</th>\
+         <th>Avg</th><th>Best</th><th>Wrst</th><th>StDev</th><th>Latency</th><th>Probes</th></tr>\n",
+    );
+    let hops = session.stats.snapshot_range(session.display_hop_count());
+    for hop in hops.iter().filter(|hop| hop.sent > 0) {
+        let ms = |rtt: Option<Duration>| {
+            rtt.map_or_else(|| "-".to_string(), |rtt| format!("{:.1}", utils::time::duration_to_ms_f64(rtt)))
+        };
+        let _ = writeln!(
+            out,
+            "<tr{}><td>{}</td><td class=\"host\">{}</td><td class=\"loss\">{:.1}</td><td>{}</td>\
+             <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}</td>\
+             <td class=\"chart\">{}</td><td class=\"chart\">{}</td></tr>",
+            if hop.loss_percent > 0.0 { " class=\"lossy\"" } else { "" },
+            hop.hop,
+            escape(&hop_name(session, hop)),
+            hop.loss_percent,
+            hop.sent,
+            ms(hop.last_rtt),
+            ms(hop.avg_rtt),
+            ms(hop.best_rtt),
+            ms(hop.worst_rtt),
+            rtt_stddev_ms(hop),
+            latency_sparkline(hop),
+            loss_strip(hop),
+        );
+    }
+    out.push_str("</table>\n");
+}
+
+/// The hop's recent RTTs as a line, scaled to its own worst
+fn latency_sparkline(hop: &HopStats) -> String {
+    let rtts: Vec<f64> = hop.rtts.iter().map(|rtt| utils::time::duration_to_ms_f64(*rtt)).collect();
+    if rtts.len() < 2 {
+        return String::new();
+    }
+    let max = rtts.iter().cloned().fold(f64::MIN_POSITIVE, f64::max);
+    let step = SPARKLINE_WIDTH / (rtts.len() - 1) as f64;
+    let points: Vec<String> = rtts
+        .iter()
+        .enumerate()
+        .map(|(i, rtt)| {
+            let y = SPARKLINE_HEIGHT - 1.0 - rtt / max * (SPARKLINE_HEIGHT - 2.0);
+            format!("{:.1},{:.1}", i as f64 * step, y)
+        })
+        .collect();
+    format!(
+        "<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\"><title>up to {max:.1} ms</title>\
+         <polyline fill=\"none\" stroke=\"#2980b9\" stroke-width=\"1.5\" points=\"{points}\"/></svg>",
+        w = SPARKLINE_WIDTH,
+        h = SPARKLINE_HEIGHT,
+        max = max,
+        points = points.join(" "),
+    )
+}
+
+/// One mark per recent probe: green answered, red lost, grey still out
+fn loss_strip(hop: &HopStats) -> String {
+    let width = hop.packet_history.len() as f64 * PROBE_WIDTH;
+    let mut svg = format!(
+        "<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = width,
+        h = SPARKLINE_HEIGHT,
+    );
+    for (i, outcome) in hop.packet_history.iter().enumerate() {
+        let color = match outcome {
+            PacketOutcome::Received(_) => "#27ae60",
+            PacketOutcome::Lost => "#c0392b",
+            PacketOutcome::Pending => "#bbb",
+        };
+        let _ = write!(
+            svg,
+            "<rect x=\"{}\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            i as f64 * PROBE_WIDTH,
+            PROBE_WIDTH - 1.0,
+            SPARKLINE_HEIGHT,
+            color,
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_html_report() {
+        let args = Args::try_parse_from(["mtr-ng", "-n", "192.0.2.1"]).unwrap();
+        let session = MtrSession::new(args).await.unwrap();
+        session.stats.set_num_hosts(1);
+        {
+            let mut hop = session.stats.hop_mut(0);
+            hop.addr = Some("198.51.100.1".parse().unwrap());
+            for ms in [10, 20, 15] {
+                hop.increment_sent();
+                hop.add_rtt(Duration::from_millis(ms));
+            }
+            hop.increment_sent();
+            hop.add_timeout();
+        }
+
+        let html = render(&[&session]);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("http")); // Nothing fetched from elsewhere
+        assert!(html.contains("<td class=\"host\">198.51.100.1</td><td class=\"loss\">25.0</td>"));
+        assert_eq!(html.matches("<polyline").count(), 1);
+        assert_eq!(html.matches("#c0392b\"/>").count(), 1); // The lost probe
+    }
+}
@@ -0,0 +1,162 @@
+//! Snapshots written from the TUI
+//!
+//! The statistics of every session as they stand, saved without ending the
+//! run: as the text report, as a JSON `output::Snapshot` carrying the
+//! schema version of the `--jsonl` events and their hops' shape, or as CSV
+//! with one row per hop.
+
+use super::render_report;
+use crate::output::{HopSnapshot, SessionSnapshot, Snapshot};
+use crate::{MtrSession, Result};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+/// What a snapshot is written as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "target,hop,addr,hostname,sent,received,loss_percent,last_ms,avg_ms,best_ms,worst_ms,jitter_ms";
+
+/// Write a snapshot of `sessions` into `dir`, named after the first target and the time
+///
+/// Returns the path written.
+pub fn export_snapshot(sessions: &[&MtrSession], format: ExportFormat, dir: &Path) -> Result<PathBuf> {
+    let now = Utc::now();
+    let target = sessions.first().map_or("mtr-ng", |session| session.target.as_str());
+    let name: String = target
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    let path = dir.join(format!("mtr-ng-{}-{}.{}", name, now.format("%Y%m%d-%H%M%S"), format.extension()));
+
+    let contents = match format {
+        ExportFormat::Text => sessions.iter().map(|session| render_report(session)).collect::<Vec<_>>().join("\n"),
+        ExportFormat::Json => render_json(sessions, now)?,
+        ExportFormat::Csv => render_csv(sessions),
+    };
+    std::fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// The hops of a session that have been probed, as far as the display goes
+fn hop_snapshots(session: &MtrSession) -> Vec<HopSnapshot> {
+    session
+        .stats
+        .snapshot_range(session.display_hop_count())
+        .iter()
+        .filter(|hop| hop.sent > 0)
+        .map(HopSnapshot::from)
+        .collect()
+}
+
+/// The sessions as a versioned `output::Snapshot` document
+fn render_json(sessions: &[&MtrSession], timestamp: DateTime<Utc>) -> Result<String> {
+    let sessions = sessions
+        .iter()
+        .map(|session| SessionSnapshot {
+            target: session.target.clone(),
+            target_addr: session.target_addr,
+            timestamp,
+            hops: hop_snapshots(session),
+        })
+        .collect();
+    Snapshot { sessions }.to_json()
+}
+
+fn render_csv(sessions: &[&MtrSession]) -> String {
+    let mut out = format!("{}\n", CSV_HEADER);
+    let ms = |value: Option<f64>| value.map_or_else(String::new, |ms| format!("{:.3}", ms));
+    for session in sessions {
+        for hop in hop_snapshots(session) {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{},{:.1},{},{},{},{},{}",
+                csv_field(&session.target),
+                hop.hop,
+                hop.addr.map_or_else(String::new, |addr| addr.to_string()),
+                csv_field(hop.hostname.as_deref().unwrap_or("")),
+                hop.sent,
+                hop.received,
+                hop.loss_percent,
+                ms(hop.last_ms),
+                ms(hop.avg_ms),
+                ms(hop.best_ms),
+                ms(hop.worst_ms),
+                ms(hop.jitter_ms),
+            );
+        }
+    }
+    out
+}
+
+/// Quote a field that would otherwise break the row
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_csv_and_json_snapshots() {
+        let args = Args::try_parse_from(["mtr-ng", "-n", "192.0.2.1"]).unwrap();
+        let session = MtrSession::new(args).await.unwrap();
+        session.stats.set_num_hosts(2);
+        {
+            let mut hop = session.stats.hop_mut(0);
+            hop.addr = Some("198.51.100.1".parse().unwrap());
+            hop.hostname = Some("edge, west".to_string());
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_micros(12500));
+        }
+        {
+            let mut hop = session.stats.hop_mut(1);
+            hop.increment_sent();
+            hop.add_timeout();
+        }
+
+        let csv = render_csv(&[&session]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "192.0.2.1,1,198.51.100.1,\"edge, west\",1,1,0.0,12.500,12.500,12.500,12.500,"
+        );
+        assert_eq!(lines[2], "192.0.2.1,2,,,1,0,100.0,,,,,");
+
+        let json = render_json(&[&session], Utc::now()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema_version"], crate::output::SCHEMA_VERSION);
+        let snapshot = Snapshot::from_json(&json).unwrap();
+        assert_eq!(snapshot.sessions[0].target, "192.0.2.1");
+        assert_eq!(snapshot.sessions[0].hops.len(), 2);
+        assert_eq!(snapshot.sessions[0].hops[0].avg_ms, Some(12.5));
+    }
+}
@@ -0,0 +1,156 @@
+//! XML report (-x), laid out exactly as classic mtr's
+//!
+//! Element names, order and the printf-style padding of the values follow
+//! mtr's `report_xml()` with its default fields, so scripts written against
+//! mtr's output parse ours unchanged. That includes the `<Loss%>` element,
+//! which strict XML parsers reject just as they do mtr's.
+//!
+//! mtr names only one responder per hop. Where several answered (ECMP), the
+//! others follow the fields as `<PATH>` elements, which tools written for
+//! mtr's output never see otherwise.
+
+use super::{escape, hop_name, rtt_stddev_ms};
+use crate::{utils, HopStats, MtrSession};
+use std::{fmt::Write, time::Duration};
+
+/// mtr's default packet size, reported when --psize isn't given
+const DEFAULT_PSIZE: u16 = 64;
+
+pub(super) fn render(session: &MtrSession) -> String {
+    let args = &session.args;
+    let source = args
+        .address
+        .map_or_else(utils::network::local_hostname, |source| source.to_string());
+    let hops = session.stats.snapshot_range(session.display_hop_count());
+    // Without --count the trace ran until interrupted; report what was sent
+    let tests = args
+        .count
+        .unwrap_or_else(|| hops.iter().map(|hop| hop.sent).max().unwrap_or(0));
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        out,
+        "<MTR SRC=\"{}\" DST=\"{}\" TOS=\"0x{:X}\" PSIZE=\"{}\" BITPATTERN=\"0x{:02X}\" TESTS=\"{}\">",
+        escape(&source),
+        escape(&session.target),
+        args.tos.unwrap_or(0),
+        args.psize.unwrap_or(DEFAULT_PSIZE),
+        args.bitpattern,
+        tests,
+    );
+    for hop in hops.iter().filter(|hop| hop.sent > 0) {
+        let _ = writeln!(out, "    <HUB COUNT=\"{}\" HOST=\"{}\">", hop.hop, escape(&hop_name(session, hop)));
+        for (title, value) in fields(hop) {
+            let _ = writeln!(out, "        <{}>{}</{}>", title, value, title);
+        }
+        for path in hop.get_alternate_paths() {
+            let host = match path.hostname {
+                Some(ref hostname) if !args.numeric => hostname.clone(),
+                _ => path.addr.to_string(),
+            };
+            let ms = |rtt: Option<Duration>| format!("{:.1}", rtt.map_or(0.0, utils::time::duration_to_ms_f64));
+            let _ = writeln!(
+                out,
+                "        <PATH HOST=\"{}\" COUNT=\"{}\" PCT=\"{:.1}\" LAST=\"{}\" AVG=\"{}\"/>",
+                escape(&host),
+                path.frequency,
+                hop.get_path_percentage(path),
+                ms(path.last_rtt),
+                ms(path.avg_rtt),
+            );
+        }
+        out.push_str("    </HUB>\n");
+    }
+    out.push_str("</MTR>\n");
+    out
+}
+
+/// mtr's default report fields (LSNABWV) with their printf formats
+fn fields(hop: &HopStats) -> [(&'static str, String); 7] {
+    let ms = |rtt: Option<Duration>| format!(" {:5.1}", rtt.map_or(0.0, utils::time::duration_to_ms_f64));
+    [
+        ("Loss%", format!(" {:4.1}%", hop.loss_percent)),
+        ("Snt", format!(" {:5}", hop.sent)),
+        ("Last", ms(hop.last_rtt)),
+        ("Avg", ms(hop.avg_rtt)),
+        ("Best", ms(hop.best_rtt)),
+        ("Wrst", ms(hop.worst_rtt)),
+        ("StDev", format!(" {:5.1}", rtt_stddev_ms(hop))),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_xml_matches_mtr_layout() {
+        let args = Args::try_parse_from([
+            "mtr-ng", "-x", "-n", "-c", "2", "-a", "192.0.2.10", "-Q", "184", "192.0.2.1",
+        ])
+        .unwrap();
+        let session = MtrSession::new(args).await.unwrap();
+        session.stats.set_num_hosts(2);
+        {
+            let mut hop = session.stats.hop_mut(0);
+            hop.addr = Some("198.51.100.1".parse().unwrap());
+            for ms in [10, 20] {
+                hop.increment_sent();
+                hop.add_rtt(Duration::from_millis(ms));
+            }
+        }
+        {
+            let mut hop = session.stats.hop_mut(1);
+            hop.increment_sent();
+            hop.add_timeout();
+        }
+
+        let expected = "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<MTR SRC=\"192.0.2.10\" DST=\"192.0.2.1\" TOS=\"0xB8\" PSIZE=\"64\" BITPATTERN=\"0x00\" TESTS=\"2\">
+    <HUB COUNT=\"1\" HOST=\"198.51.100.1\">
+        <Loss%>  0.0%</Loss%>
+        <Snt>     2</Snt>
+        <Last>  20.0</Last>
+        <Avg>  15.0</Avg>
+        <Best>  10.0</Best>
+        <Wrst>  20.0</Wrst>
+        <StDev>   7.1</StDev>
+    </HUB>
+    <HUB COUNT=\"2\" HOST=\"???\">
+        <Loss%> 100.0%</Loss%>
+        <Snt>     1</Snt>
+        <Last>   0.0</Last>
+        <Avg>   0.0</Avg>
+        <Best>   0.0</Best>
+        <Wrst>   0.0</Wrst>
+        <StDev>   0.0</StDev>
+    </HUB>
+</MTR>
+";
+        assert_eq!(render(&session), expected);
+    }
+
+    #[tokio::test]
+    async fn test_alternate_paths() {
+        let args = Args::try_parse_from(["mtr-ng", "-x", "-n", "192.0.2.1"]).unwrap();
+        let session = MtrSession::new(args).await.unwrap();
+        session.stats.set_num_hosts(1);
+        {
+            let mut hop = session.stats.hop_mut(0);
+            let (primary, other) = ("198.51.100.1".parse().unwrap(), "198.51.100.2".parse().unwrap());
+            for (addr, ms) in [(primary, 10), (primary, 10), (primary, 10), (other, 30)] {
+                hop.increment_sent();
+                hop.add_rtt_from_addr(addr, Duration::from_millis(ms));
+            }
+        }
+
+        let xml = render(&session);
+        assert!(xml.contains(
+            "        <StDev>   0.0</StDev>\n        <PATH HOST=\"198.51.100.2\" COUNT=\"1\" PCT=\"25.0\" LAST=\"30.0\" AVG=\"30.0\"/>\n    </HUB>"
+        ));
+    }
+}
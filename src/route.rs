@@ -0,0 +1,98 @@
+//! Route change history
+//!
+//! A TTL is normally answered by the same router round after round. When a
+//! different address answers than the one before it, the route through that
+//! hop has changed; each change is kept with its time, so intermittent
+//! reroutes can be lined up against loss spikes. Load-balanced hops alternate
+//! between responders by themselves and are counted too; `--paris` keeps the
+//! probes on one path.
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt, net::IpAddr};
+
+/// Changes kept for the events view; the total count keeps going
+const MAX_EVENTS: usize = 1000;
+
+/// A different router answering at a hop
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteChange {
+    pub timestamp: DateTime<Utc>,
+    pub hop: u8,
+    pub from: IpAddr,
+    pub to: IpAddr,
+}
+
+impl RouteChange {
+    pub fn new(hop: u8, from: IpAddr, to: IpAddr) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            hop,
+            from,
+            to,
+        }
+    }
+}
+
+impl fmt::Display for RouteChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hop {}: {} → {}",
+            self.timestamp.with_timezone(&Local).format("%H:%M:%S"),
+            self.hop,
+            self.from,
+            self.to
+        )
+    }
+}
+
+/// Every route change seen during a session, oldest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RouteHistory {
+    events: VecDeque<RouteChange>,
+    total: usize,
+}
+
+impl RouteHistory {
+    pub fn record(&mut self, change: RouteChange) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(change);
+        self.total += 1;
+    }
+
+    /// Changes seen, including ones too old to be kept
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Kept changes, oldest first
+    pub fn events(&self) -> impl DoubleEndedIterator<Item = &RouteChange> {
+        self.events.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_keeps_recent_changes() {
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+        let mut history = RouteHistory::default();
+        for i in 0..MAX_EVENTS + 5 {
+            let (from, to) = if i % 2 == 0 { (a, b) } else { (b, a) };
+            history.record(RouteChange::new(4, from, to));
+        }
+
+        assert_eq!(history.total(), MAX_EVENTS + 5);
+        assert_eq!(history.events().count(), MAX_EVENTS);
+        // The newest change is last: an odd index, back to the first router
+        let latest = history.events().next_back().unwrap();
+        assert_eq!((latest.from, latest.to), (a, b));
+        assert!(latest.to_string().ends_with("hop 4: 192.0.2.1 → 192.0.2.2"));
+    }
+}
@@ -1,22 +1,53 @@
-use crate::{Args, HopStats, Result, utils};
-use crate::probe::{ProbeEngine, ProbeResponse, IcmpResponseType};
+use crate::{hop_stats::PacketOutcome, netns, Args, HopStats, Result, systemd, utils};
+use crate::adaptive::ProbeBudget;
+use crate::alerts::{AlertCommand, Thresholds};
+use crate::output::Event;
+use crate::stream::{self, EventStream, JsonlWriter};
+use crate::baseline::{BaselineStore, Deviation};
+use crate::builder::{HopUpdate, MtrSessionBuilder};
+use crate::config::Config;
+use crate::fault::{FaultTransport, Scenario};
+use crate::geoip::GeoIpDb;
+use crate::graphite::GraphiteSink;
+use crate::hooks::{HopLossExceeded, Hooks, RoundComplete, TargetReached};
+use crate::persist::SavedSession;
+use crate::probe_log::ProbeLog;
+use crate::raw::RawWriter;
+use crate::record::Recorder;
+use crate::rdns::ReverseResolver;
+use crate::route::{RouteChange, RouteHistory};
+use crate::split::SplitWriter;
+use crate::stats::SharedStats;
+use crate::whois::WhoisClient;
+use crate::probe::{ProbeEngine, ProbeOptions, ProbeRequest, ProbeResponse, ProbeTransport, IcmpResponseType};
 use anyhow::anyhow;
-use hickory_resolver::{config::{ResolverConfig, ResolverOpts}, TokioAsyncResolver};
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
 use rand;
 
 use std::{
     collections::HashMap,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use tokio::sync::mpsc;
 use tokio::time;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
 const MIN_SEQUENCE: u16 = 33000;
 const MAX_SEQUENCE: u16 = 65535;
 
+/// Rounds between probes one hop past the destination, checking the path hasn't grown
+const VERIFY_INTERVAL: usize = 10;
+
 // Add callback type for real-time updates
 pub type UpdateCallback = Arc<dyn Fn() + Send + Sync>;
 
@@ -34,21 +65,40 @@ pub struct SequenceEntry {
 pub struct MtrSession {
     pub target: String,
     pub target_addr: IpAddr,
-    pub hops: Vec<HopStats>,
+    pub stats: Arc<SharedStats>, // Per-hop statistics, shared with clones of the session
     pub args: Args,
     pub resolver: TokioAsyncResolver,
     pub packet_id: u16,
     pub next_sequence: u16,
     pub sequence_table: HashMap<u16, SequenceEntry>, // sequence -> entry (like original mtr)
     pub batch_at: usize,  // current hop index being sent (like original mtr)
-    pub num_hosts: usize, // number of active hops
     pub update_callback: Option<UpdateCallback>, // callback for real-time updates
+    pub hop_updates: Option<mpsc::UnboundedSender<HopUpdate>>, // Per-hop results for MtrSessionBuilder callers
+    pub cancel: CancellationToken, // Cancelled by shutdown(), ending the trace after its in-flight probes
+    pub abandon: CancellationToken, // Cancelled by abandon(), giving up on the in-flight probes as lost
+    pub hooks: Arc<Hooks>, // Typed event subscribers (on_target_reached etc.)
+    pub alert: Option<Arc<AlertCommand>>, // Command run on threshold breaches (--on-alert)
+    pub notifier: Option<Arc<systemd::Notifier>>, // systemd READY/WATCHDOG notifications
+    pub interval_ms: Arc<AtomicU64>, // live probe interval, adjustable while running
+    pub events: Option<EventStream>, // NDJSON event stream publisher
+    pub jsonl: Option<JsonlWriter>, // Events written to stdout (--jsonl)
+    pub graphite: Option<GraphiteSink>, // Per-round metrics for Carbon (--graphite)
+    pub raw: Option<Arc<RawWriter>>, // mtr's raw line protocol on stdout (-l)
+    pub split: Option<Arc<SplitWriter>>, // Changed hops on stdout after each round (-p)
+    pub recorder: Option<Arc<Recorder>>, // Probe and round history in SQLite (--record)
+    pub probe_log: Option<Arc<ProbeLog>>, // A line per probe outcome (--probe-log)
+    pub baselines: Option<Arc<BaselineStore>>, // History from previous runs (--baseline)
+    pub geoip: Option<Arc<GeoIpDb>>, // Location database (--geoip)
+    pub whois: Option<Arc<WhoisClient>>, // Registry lookups (--whois)
+    rdns: Option<ReverseResolver>, // Background hop name lookups, None with --numeric
+    budget: Option<Arc<ProbeBudget>>, // Shares of each round's probes (--adaptive)
+    preopened: Arc<std::sync::Mutex<Option<Result<ProbeEngine>>>>, // Opened before dropping privileges
 }
 
 impl MtrSession {
-    pub async fn new(args: Args) -> Result<Self> {
-        let resolver =
-            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
+    pub async fn new(mut args: Args) -> Result<Self> {
+        Self::prepare_args(&mut args)?;
+        let resolver = Self::create_resolver(&args);
 
         // Resolve target hostname to IP
         let target_addr = if let Ok(ip) = args.target.parse::<IpAddr>() {
@@ -61,42 +111,272 @@ impl MtrSession {
                 .ok_or_else(|| anyhow!("Failed to resolve hostname"))?
         };
 
-        let mut hops: Vec<HopStats> = (1..=args.max_hops).map(HopStats::new).collect();
+        Self::with_target(args, resolver, target_addr)
+    }
+
+    /// One session per address family of a dual-stack host (`--dual-stack`)
+    ///
+    /// Returns the IPv4 session first. Both share the probe interval, so
+    /// interval changes made while running apply to the pair.
+    pub async fn new_dual_stack(mut args: Args) -> Result<(Self, Self)> {
+        Self::prepare_args(&mut args)?;
+        if args.target.parse::<IpAddr>().is_ok() {
+            anyhow::bail!("--dual-stack needs a hostname, not an address");
+        }
+        if args.stream_socket.is_some() {
+            anyhow::bail!("--stream-socket cannot be combined with --dual-stack");
+        }
+        if args.graphite.is_some() {
+            anyhow::bail!("--graphite cannot be combined with --dual-stack");
+        }
+        if args.address.is_some() {
+            anyhow::bail!("--address selects one address family and cannot be combined with --dual-stack");
+        }
+        if args.save.is_some() || args.load.is_some() {
+            anyhow::bail!("--save and --load cannot be combined with --dual-stack");
+        }
+        let resolver = Self::create_resolver(&args);
+
+        let (v4, v6) = tokio::join!(resolver.ipv4_lookup(&args.target), resolver.ipv6_lookup(&args.target));
+        let v4 = v4
+            .ok()
+            .and_then(|lookup| lookup.iter().next().map(|a| IpAddr::V4(a.0)))
+            .ok_or_else(|| anyhow!("{} has no IPv4 address", args.target))?;
+        let v6 = v6
+            .ok()
+            .and_then(|lookup| lookup.iter().next().map(|aaaa| IpAddr::V6(aaaa.0)))
+            .ok_or_else(|| anyhow!("{} has no IPv6 address", args.target))?;
+
+        let ipv4 = Self::with_target(args.clone(), resolver.clone(), v4)?;
+        let mut ipv6 = Self::with_target(args, resolver, v6)?;
+        ipv6.interval_ms = Arc::clone(&ipv4.interval_ms);
+        Ok((ipv4, ipv6))
+    }
+
+    /// The resolver for the target and for reverse lookups of hops
+    ///
+    /// `--dns-server` sends every query to that server. Otherwise the system
+    /// configuration (resolv.conf and the hosts file) applies, as it does for
+    /// every other program; hickory's public defaults are the last resort.
+    fn create_resolver(args: &Args) -> TokioAsyncResolver {
+        if let Some(server) = args.dns_server {
+            let servers = NameServerConfigGroup::from_ips_clear(&[server], 53, true);
+            let config = ResolverConfig::from_parts(None, Vec::new(), servers);
+            return TokioAsyncResolver::tokio(config, ResolverOpts::default());
+        }
+        TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            warn!("Cannot use the system DNS configuration ({}), falling back to public resolvers", e);
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        })
+    }
+
+    /// Apply the config file and reject option combinations that can't work
+    fn prepare_args(args: &mut Args) -> Result<()> {
+        if args.io_uring && !cfg!(all(feature = "io-uring", target_os = "linux")) {
+            anyhow::bail!("--io-uring needs a Linux build with the `io-uring` feature enabled");
+        }
+        if let Some(ref path) = args.config {
+            Config::load(path)?.apply_to_args(args);
+        }
+        use crate::args::ProbeProtocol;
+        if args.port.is_some() && matches!(args.protocol, ProbeProtocol::Icmp | ProbeProtocol::Timestamp) {
+            anyhow::bail!("--port only applies to UDP, TCP, QUIC and DNS probes");
+        }
+        if args.dns_query.is_some() && args.protocol != ProbeProtocol::Dns {
+            anyhow::bail!("--dns-query only applies to DNS probes (-P dns)");
+        }
+        if let Some(ref vrf) = args.vrf {
+            if !cfg!(target_os = "linux") {
+                anyhow::bail!("--vrf is only supported on Linux");
+            }
+            if crate::probe::is_vrf_device(vrf) == Some(false) {
+                warn!("{} is not a VRF device - probes are bound to it like --interface", vrf);
+            }
+        }
+        if args.first_ttl > args.max_hops {
+            anyhow::bail!(
+                "First TTL {} is beyond the maximum of {} hops",
+                args.first_ttl,
+                args.max_hops
+            );
+        }
+        if args.on_alert.is_some() && !Thresholds::from_args(args).is_enabled() {
+            anyhow::bail!("--on-alert needs --loss-threshold or --latency-threshold");
+        }
+        Ok(())
+    }
+
+    /// A session holding saved statistics instead of probing, to report on them (--merge)
+    pub fn from_saved(mut args: Args, mut saved: SavedSession) -> Result<Self> {
+        Self::prepare_args(&mut args)?;
+        args.target = saved.target.clone();
+        let resolver = Self::create_resolver(&args);
+        let session = Self::with_target(args, resolver, saved.target_addr)?;
+        for change in std::mem::take(&mut saved.route_history).events() {
+            session.stats.record_route_change(change.clone());
+        }
+        let mut hops = session.stats.snapshot();
+        saved.restore_into(&mut hops);
+        for (index, hop) in hops.into_iter().enumerate() {
+            *session.stats.hop_mut(index) = hop;
+        }
+        Ok(session)
+    }
+
+    fn with_target(args: Args, resolver: TokioAsyncResolver, target_addr: IpAddr) -> Result<Self> {
+        if args.protocol == crate::args::ProbeProtocol::Timestamp && target_addr.is_ipv6() {
+            anyhow::bail!("ICMP timestamp probes are IPv4 only; {} is an IPv6 address", target_addr);
+        }
+        let mut hops: Vec<HopStats> = (args.first_ttl..=args.max_hops).map(HopStats::new).collect();
 
         // Configure EMA alpha for all hops from command line args
         for hop in &mut hops {
             hop.set_ema_alpha(args.ema_alpha);
         }
+        let mut route_history = RouteHistory::default();
+        if let Some(ref path) = args.load {
+            let mut saved = SavedSession::load(path)?;
+            if saved.target != args.target {
+                anyhow::bail!("{} holds a session for {}, not {}", path.display(), saved.target, args.target);
+            }
+            if saved.target_addr != target_addr {
+                warn!(
+                    "{} now resolves to {} rather than {} as in the saved session",
+                    args.target, target_addr, saved.target_addr
+                );
+            }
+            info!("Restoring session saved {} from {}", saved.saved_at, path.display());
+            route_history = std::mem::take(&mut saved.route_history);
+            saved.restore_into(&mut hops);
+        }
         let packet_id = std::process::id() as u16;
 
+        let events = match args.stream_socket {
+            Some(ref path) => {
+                let events = EventStream::new();
+                stream::serve_unix(path, events.clone(), Event::start(&args.target, target_addr))?;
+                Some(events)
+            }
+            None => None,
+        };
+
+        let baselines = if args.baseline {
+            let path = BaselineStore::default_path()
+                .ok_or_else(|| anyhow!("Cannot locate data directory for baselines (HOME not set)"))?;
+            Some(Arc::new(BaselineStore::load(&path)?))
+        } else {
+            None
+        };
+        let geoip = match args.geoip {
+            Some(ref path) => Some(Arc::new(GeoIpDb::open(path)?)),
+            None => None,
+        };
+        let recorder = match args.record {
+            Some(ref path) => Some(Arc::new(Recorder::open(path, &args.target, target_addr)?)),
+            None => None,
+        };
+        let probe_log = match args.probe_log {
+            Some(ref path) => Some(Arc::new(ProbeLog::open(path, target_addr)?)),
+            None => None,
+        };
+        let whois = args.whois.then(|| Arc::new(WhoisClient::spawn()));
+        let hop_count = hops.len();
+        let stats = Arc::new(SharedStats::new(hops, route_history));
+        let rdns = (!args.numeric).then(|| {
+            let stats = Arc::clone(&stats);
+            ReverseResolver::spawn(
+                resolver.clone(),
+                args.dns_workers as usize,
+                Duration::from_millis(args.dns_timeout),
+                move |addr, hostname| {
+                    if hostname != addr.to_string() {
+                        stats.name_responder(addr, hostname);
+                    }
+                },
+            )
+        });
+
         Ok(Self {
             target: args.target.clone(),
             target_addr,
-            hops,
-            args,
+            stats,
             resolver,
             packet_id,
             next_sequence: MIN_SEQUENCE,
             sequence_table: HashMap::new(),
-            batch_at: 0,   // Start at hop 1 (index 0)
-            num_hosts: 10, // Initial estimate
+            batch_at: 0, // Start at hop 1 (index 0)
             update_callback: None,
+            hop_updates: None,
+            cancel: CancellationToken::new(),
+            abandon: CancellationToken::new(),
+            hooks: Arc::default(),
+            alert: AlertCommand::from_args(&args).map(Arc::new),
+            notifier: systemd::Notifier::from_env().map(Arc::new),
+            interval_ms: Arc::new(AtomicU64::new(args.interval)),
+            events,
+            jsonl: args.jsonl.map(JsonlWriter::new),
+            graphite: args.graphite.clone().map(GraphiteSink::spawn),
+            raw: args.raw.then(|| Arc::new(RawWriter::new())),
+            split: args.split.then(|| Arc::new(SplitWriter::new())),
+            recorder,
+            probe_log,
+            baselines,
+            geoip,
+            whois,
+            rdns,
+            budget: args.adaptive.then(|| Arc::new(ProbeBudget::new(hop_count))),
+            preopened: Arc::default(),
+            args,
         })
     }
 
     pub async fn run_trace(&mut self) -> Result<()> {
         info!("Starting trace to {} ({})", self.target, self.target_addr);
-
-        match self.target_addr {
-            IpAddr::V4(ipv4) => self.run_ipv4_trace(ipv4).await,
-            IpAddr::V6(_) => {
-                warn!("IPv6 not yet implemented, falling back to simulation");
-                self.run_simulated_trace().await
+        if let Some(source) = self.args.address {
+            if source.is_ipv6() != self.target_addr.is_ipv6() {
+                anyhow::bail!("Source address {} cannot reach {}", source, self.target_addr);
             }
         }
+        if let Some(ref jsonl) = self.jsonl {
+            jsonl.write(&Event::start(&self.target, self.target_addr));
+        }
+
+        let result = self.run_probe_trace(self.target_addr).await;
+        if result.is_ok() {
+            self.resolve_remaining_hostnames().await;
+        }
+
+        if let Some(ref notifier) = self.notifier {
+            notifier.stopping();
+        }
+        self.finish_outputs().await;
+        result
+    }
+
+    /// Announce the end of the trace, remove the stream socket and wait for queued metrics and recordings
+    pub async fn finish_outputs(&self) {
+        self.publish(&Event::stop());
+        if let Some(ref path) = self.args.stream_socket {
+            stream::remove_unix(path);
+        }
+        if let Some(ref graphite) = self.graphite {
+            graphite.flush().await;
+        }
+        if let Some(ref recorder) = self.recorder {
+            recorder.flush().await;
+        }
     }
 
-    async fn run_ipv4_trace(&mut self, target: Ipv4Addr) -> Result<()> {
+    /// Open the probe engine, inside the requested network namespace if any
+    pub fn open_probe_engine(args: &Args) -> Result<ProbeEngine> {
+        let options = ProbeOptions::from_args(args);
+        match args.netns {
+            Some(ref name) => netns::run_in_namespace(name, move || ProbeEngine::with_options(options)),
+            None => ProbeEngine::with_options(options),
+        }
+    }
+
+    async fn run_probe_trace(&mut self, target: IpAddr) -> Result<()> {
         if self.args.simulate || self.args.force_simulate {
             let reason = if self.args.force_simulate {
                 "--force-simulate flag enabled"
@@ -104,20 +384,32 @@ impl MtrSession {
                 "--simulate flag enabled"
             };
             info!("Running in simulation mode ({})", reason);
+            if let Some(kind) = self.args.scenario {
+                info!("Injecting faults from {:?} scenario", kind);
+                return self.run_trace_with_transport(FaultTransport::new(Scenario::builtin(kind))).await;
+            }
             return self.run_simulated_trace().await;
         }
 
         // Try to create ProbeEngine for modern ICMP handling
-        match ProbeEngine::new() {
+        let preopened = self.preopened.lock().unwrap().take();
+        match preopened.unwrap_or_else(|| Self::open_probe_engine(&self.args)) {
             Ok(probe_engine) => {
                 info!("Using ProbeEngine for real traceroute");
+                if let Some(ref notifier) = self.notifier {
+                    notifier.ready();
+                }
+                #[cfg(all(feature = "io-uring", target_os = "linux"))]
+                if self.args.io_uring {
+                    let probe_engine = crate::probe::UringProbeEngine::new(probe_engine)?;
+                    return self.run_mtr_algorithm_with_probe_engine(target, probe_engine).await;
+                }
                 self.run_mtr_algorithm_with_probe_engine(target, probe_engine)
                     .await
             }
             Err(e) => {
                 anyhow::bail!(
-                    "Failed to create ProbeEngine: {}. This usually means insufficient permissions. \
-                    Try running with sudo, or use --simulate for demo mode.", e
+                    "Failed to create ProbeEngine: {:#}\nUse --simulate for demo mode.", e
                 );
             }
         }
@@ -125,11 +417,19 @@ impl MtrSession {
 
 
 
+    /// Run the MTR algorithm over a caller-supplied transport
+    pub async fn run_trace_with_transport(&mut self, transport: impl ProbeTransport) -> Result<()> {
+        if let Some(ref notifier) = self.notifier {
+            notifier.ready();
+        }
+        self.run_mtr_algorithm_with_probe_engine(self.target_addr, transport).await
+    }
+
     // Modern ProbeEngine implementation 
     async fn run_mtr_algorithm_with_probe_engine(
         &mut self,
-        target: Ipv4Addr,
-        mut probe_engine: ProbeEngine,
+        target: IpAddr,
+        mut probe_engine: impl ProbeTransport,
     ) -> Result<()> {
         info!("Starting MTR algorithm with ProbeEngine");
         let mut round = 0;
@@ -140,18 +440,28 @@ impl MtrSession {
                     break;
                 }
             }
+            if self.cancel.is_cancelled() {
+                info!("Trace shut down after {} rounds", round);
+                break;
+            }
 
             let round_start = Instant::now();
 
             // Send probes for all active hops (like net_send_batch)
-            let restart = self.net_send_batch_with_probe_engine(target, &mut probe_engine).await?;
+            let restart = self.net_send_batch_with_probe_engine(target, &mut probe_engine, round).await?;
 
             // Collect responses efficiently
             let collect_duration = Duration::from_millis(self.args.interval);
-            self.net_process_return_with_probe_engine(&mut probe_engine, target, collect_duration).await;
+            self.net_process_return_with_probe_engine(&mut probe_engine, collect_duration).await;
 
             if restart {
                 round += 1;
+                if let Some(ref notifier) = self.notifier {
+                    // A completed round proves the engine is still sending and collecting
+                    notifier.watchdog();
+                    notifier.status(&format!("Round {} to {}", round, self.target_addr));
+                }
+                self.publish_round(round);
                 if let Some(count) = self.args.count {
                     debug!("Completed round {}/{}, restarting batch", round, count);
                 } else {
@@ -163,193 +473,295 @@ impl MtrSession {
                     let elapsed = round_start.elapsed();
                     let target_interval = Duration::from_millis(self.args.interval);
                     if elapsed < target_interval {
-                        tokio::time::sleep(target_interval - elapsed).await;
+                        tokio::select! {
+                            _ = tokio::time::sleep(target_interval - elapsed) => {}
+                            _ = self.cancel.cancelled() => {}
+                        }
                     }
                 }
             }
         }
 
+        self.await_late_replies(&mut probe_engine).await;
+        // The last replies may have ended the path short of the last round's range
+        self.stats.set_num_hosts(self.probe_range());
+        log_discarded_replies(&probe_engine);
         Ok(())
     }
+
+    /// Keep collecting after the last round until every probe has been
+    /// answered or timed out, for at most --grace-period
+    async fn await_late_replies(&mut self, probe_engine: &mut impl ProbeTransport) {
+        let deadline = time::Instant::now() + Duration::from_secs_f64(self.args.grace_period);
+        while self.outstanding_probes() > 0 {
+            match time::timeout_at(deadline, probe_engine.collect_responses_async()).await {
+                Ok(Ok(responses)) => {
+                    for response in responses {
+                        self.process_probe_response(response).await;
+                    }
+                }
+                Ok(Err(e)) => {
+                    debug!("Response collection failed: {}", e);
+                    break;
+                }
+                Err(_) => {
+                    debug!("Grace period over with {} probes outstanding", self.outstanding_probes());
+                    break;
+                }
+            }
+        }
+        // Whatever is still out counts, and is logged, as lost
+        for response in probe_engine.expire_all() {
+            self.process_probe_response(response).await;
+        }
+    }
+
+    /// Probes sent that have been neither answered nor timed out yet
+    fn outstanding_probes(&self) -> usize {
+        (0..self.stats.len())
+            .map(|index| {
+                self.stats
+                    .hop(index)
+                    .packet_history
+                    .iter()
+                    .filter(|outcome| matches!(outcome, PacketOutcome::Pending))
+                    .count()
+            })
+            .sum()
+    }
     
     // ProbeEngine-based equivalent of net_send_batch - send to all hops in parallel
     async fn net_send_batch_with_probe_engine(
         &mut self,
-        target: Ipv4Addr,
-        probe_engine: &mut ProbeEngine,
+        target: IpAddr,
+        probe_engine: &mut impl ProbeTransport,
+        round: usize,
     ) -> Result<bool> {
-        // Send probes to all hops in parallel (like simulation mode)
-        // This is the correct MTR algorithm - not incremental discovery
-        let max_hops = if self.num_hosts > 0 {
-            utils::math::min_with_safety(self.num_hosts, self.args.max_hops as usize)
-            } else {
-            utils::math::min_with_safety(10, self.args.max_hops as usize) // Start with reasonable number
-        };
-
-        // Send all probes rapidly in succession
-        for i in 0..max_hops {
-            self.net_send_query_with_probe_engine(target, probe_engine, i)?;
+        // Send probes to all hops in range in parallel; the range grows as the
+        // path is discovered and stops at the target
+        let max_hops = self.plan_round(round);
+
+        // --burst probes per hop, all in one batch unless --spread spaces them out
+        let counts = self.probes_per_hop(max_hops);
+        let mut slots = Vec::with_capacity(counts.iter().sum()); // Hop index of each probe
+        for (i, &probes) in counts.iter().enumerate() {
+            if probes == 0 {
+                continue; // --adaptive left this hop out of the round
+            }
+            self.stats.hop_mut(i).begin_round();
+            slots.extend(std::iter::repeat_n(i, probes));
+        }
+        let round_start = time::Instant::now();
+        let window = spread_window(self.current_interval(), self.args.spread);
+        for (offset, range) in schedule_batches(slots.len(), window, self.args.jitter) {
+            if !offset.is_zero() {
+                // Replies to the probes already sent arrive in the meantime
+                self.collect_until(probe_engine, round_start + offset).await;
+            }
+            // Sequence numbers are taken just before sending, so RTTs start here
+            let requests: Vec<ProbeRequest> = slots[range]
+                .iter()
+                .map(|&index| self.net_prepare_query(target, index))
+                .collect();
+            for (request, result) in requests.iter().zip(probe_engine.send_probes(&requests, self.args.protocol)) {
+                let seq = result?;
+                debug!("Sent {:?} probe with TTL {}, seq={}", self.args.protocol, request.ttl, seq);
+                if let Some(ref raw) = self.raw {
+                    raw.transmit(request.ttl, seq);
+                }
+            }
         }
 
         // Always restart after sending batch (that's how MTR works)
         Ok(true)
     }
 
-    // ProbeEngine-based equivalent of net_send_query
-    fn net_send_query_with_probe_engine(
-        &mut self,
-        target: Ipv4Addr,
-        probe_engine: &mut ProbeEngine,
-        index: usize,
-    ) -> Result<()> {
-        let time_to_live = (index + 1) as u8;
+    // ProbeEngine-based equivalent of net_send_query, minus the send itself
+    fn net_prepare_query(&mut self, target: IpAddr, index: usize) -> ProbeRequest {
+        let (time_to_live, timeout) = {
+            let hop = self.stats.hop(index);
+            (hop.hop, hop.probe_timeout())
+        };
         let seq = self.prepare_sequence(index);
         let send_time = Instant::now();
 
         self.save_sequence_with_send_time(index, seq, send_time);
 
-        let target_addr = std::net::SocketAddr::from((target, 33434)); // Standard traceroute port for UDP/TCP
-        let timeout = Duration::from_millis(200); // Short timeout per individual probe (like original MTR)
-
-        // Send probe using ProbeEngine with selected protocol
-        probe_engine.send_probe_with_protocol(
-            index, 
-            target_addr, 
-            time_to_live, 
+        ProbeRequest {
+            hop: index,
+            dst: SocketAddr::new(target, self.args.port.unwrap_or(0)),
+            ttl: time_to_live,
             timeout,
-            self.args.protocol
-        )?;
-
-        debug!("Sent {:?} probe to hop {} (TTL={}), seq={}", 
-               self.args.protocol, index + 1, time_to_live, seq);
-        Ok(())
+        }
     }
 
     // Event-driven response collection (no polling!)
     async fn net_process_return_with_probe_engine(
         &mut self,
-        probe_engine: &mut ProbeEngine,
-        target: Ipv4Addr,
+        probe_engine: &mut impl ProbeTransport,
         _collect_duration: Duration,
     ) {
         let start_collect = Instant::now();
         let max_wait = Duration::from_millis(50);
-        let mut total_responses = 0;
+        let total_responses = self
+            .collect_until(probe_engine, time::Instant::from_std(start_collect + max_wait))
+            .await;
+        debug!("Collected {} responses in {:?} (event-driven)", total_responses, start_collect.elapsed());
+    }
 
-        // Use tokio::select for event-driven I/O instead of polling
-        loop {
-            if start_collect.elapsed() >= max_wait {
-                break;
-            }
+    /// Process replies as they arrive until `deadline`, returning how many there were
+    async fn collect_until(&mut self, probe_engine: &mut impl ProbeTransport, deadline: time::Instant) -> usize {
+        let mut total_responses = 0;
 
-            tokio::select! {
-                // Event-driven response collection
-                result = probe_engine.collect_responses_async() => {
-                    match result {
-                        Ok(batch_responses) => {
-                            if batch_responses.is_empty() {
-                                // No responses available, yield briefly to other tasks
-                                tokio::task::yield_now().await;
-                            } else {
-                                total_responses += batch_responses.len();
-                                for response in batch_responses {
-                                    self.process_probe_response(response, target).await;
-                                }
-                                // Continue immediately if we got responses
-                                continue;
-                            }
-                        }
-                        Err(_) => {
-                            // Error in collection, yield and continue
-                            tokio::task::yield_now().await;
-                        }
+        // The engine sleeps until replies arrive; stop once the window closes
+        while let Ok(result) = time::timeout_at(deadline, probe_engine.collect_responses_async()).await {
+            match result {
+                Ok(batch_responses) => {
+                    total_responses += batch_responses.len();
+                    for response in batch_responses {
+                        self.process_probe_response(response).await;
                     }
                 }
-                // Timeout fallback to prevent infinite waiting
-                _ = tokio::time::sleep(Duration::from_micros(500)) => {
-                    // Cooperative yielding instead of busy polling
-                    tokio::task::yield_now().await;
+                Err(e) => {
+                    debug!("Response collection failed: {}", e);
+                    break;
                 }
             }
         }
-        
-        debug!("Collected {} responses in {:?} (event-driven)", total_responses, start_collect.elapsed());
+        total_responses
     }
 
     // Process individual probe responses
-    async fn process_probe_response(&mut self, response: ProbeResponse, target: Ipv4Addr) {
+    async fn process_probe_response(&mut self, response: ProbeResponse) {
+        // Trigger real-time UI update when a response arrives
+        if self.record_response(response) {
+            if let Some(ref callback) = self.update_callback {
+                callback();
+            }
+        }
+    }
+
+    /// Fold a probe's reply, or its timeout, into the statistics of its hop
+    ///
+    /// Returns false for a reply to a hop outside the table.
+    fn record_response(&self, mut response: ProbeResponse) -> bool {
         let hop_index = response.hop;
-        
-        if hop_index >= self.hops.len() {
-            return; // Invalid hop index
+        if hop_index >= self.stats.len() {
+            return false;
         }
+        let destination = self.destination_index();
 
-        match response.icmp_type {
-            IcmpResponseType::TimeExceeded => {
-                // Intermediate hop response - update RTT and address
-                self.hops[hop_index].add_rtt_from_addr(response.source_addr, response.rtt);
-                debug!("Got TimeExceeded from {} for hop {} (RTT: {:?})", 
-                       response.source_addr, hop_index + 1, response.rtt);
-                
-                // DNS lookup if needed
-        if !self.args.numeric {
-                    self.perform_dns_lookup(hop_index, response.source_addr).await;
-                }
+        let hop_number = {
+            let mut hop = self.stats.hop_mut(hop_index);
+            if let Some(extensions) = response.extensions.take() {
+                hop.set_extensions(extensions);
             }
-            IcmpResponseType::EchoReply => {
-                // Direct response - update stats and check if target
-                self.hops[hop_index].add_rtt_from_addr(response.source_addr, response.rtt);
-                
-                // Check if we reached the target
-                if let IpAddr::V4(source_ipv4) = response.source_addr {
-                    if source_ipv4 == target {
-                        info!("Reached target {} at hop {}", target, hop_index + 1);
+            if response.port_state.is_some() {
+                hop.port_state = response.port_state;
+            }
+            if response.reply_ttl.is_some() {
+                hop.reply_ttl = response.reply_ttl;
+            }
+            if response.timestamps.is_some() {
+                hop.timestamps = response.timestamps;
+            }
+            if response.dns_rcode.is_some() {
+                hop.dns_rcode = response.dns_rcode;
+            }
+
+            match response.icmp_type {
+                IcmpResponseType::TimeExceeded | IcmpResponseType::EchoReply => {
+                    // RTT is calculated in ProbeEngine when the reply arrives
+                    hop.add_rtt_from_addr(response.source_addr, response.rtt);
+                    debug!("Hop {} RTT: {:?} from {} (precise: {}ns)",
+                           hop.hop, response.rtt, response.source_addr, response.precise_rtt_ns);
+                    if response.source_addr == self.target_addr {
+                        info!("Reached target {} at hop {}", self.target_addr, hop.hop);
                     }
                 }
-                
-                // DNS lookup if needed
-                if !self.args.numeric {
-                    self.perform_dns_lookup(hop_index, response.source_addr).await;
+                IcmpResponseType::DestinationUnreachable => {
+                    // ICMP error - mark hop with error but still update address for display
+                    hop.set_unreachable(response.source_addr, response.icmp_code);
+                    debug!("Got DestinationUnreachable from {} for hop {}",
+                           response.source_addr, hop.hop);
                 }
-            }
-            IcmpResponseType::DestinationUnreachable => {
-                // ICMP error - mark hop with error but still update address for display
-                self.hops[hop_index].set_icmp_error();
-                // Still set the address so it shows up instead of "???"
-                if self.hops[hop_index].addr.is_none() {
-                    self.hops[hop_index].addr = Some(response.source_addr);
+                IcmpResponseType::Timeout => {
+                    hop.add_timeout();
+                    debug!("Timeout for hop {}", hop.hop);
                 }
-                debug!("Got DestinationUnreachable from {} for hop {}", 
-                       response.source_addr, hop_index + 1);
             }
-            IcmpResponseType::Timeout => {
-                // Timeout - just increment timeout count
-                debug!("Timeout for hop {}", hop_index + 1);
+            hop.hop
+        };
+
+        // Written out with the hop's lock released
+        let reply = (response.icmp_type != IcmpResponseType::Timeout).then_some((response.source_addr, response.rtt));
+        if let Some(ref jsonl) = self.jsonl {
+            let (addr, rtt) = reply.unzip();
+            jsonl.write(&Event::probe(self.target_addr, hop_number, addr, rtt));
+        }
+        if let Some(ref recorder) = self.recorder {
+            recorder.probe(hop_number, reply);
+        }
+        if let Some(ref probe_log) = self.probe_log {
+            let reply = reply.map(|(addr, rtt)| (response.icmp_type, addr, rtt));
+            probe_log.probe(response.send_time, hop_number, response.seq, reply);
+        }
+
+        if matches!(response.icmp_type, IcmpResponseType::TimeExceeded | IcmpResponseType::EchoReply) {
+            self.resolve_hostname(hop_index, response.source_addr);
+        }
+        if let (Some(ref raw), false) = (&self.raw, response.icmp_type == IcmpResponseType::Timeout) {
+            raw.reply(&self.stats.hop(hop_index), response.source_addr, response.rtt, response.seq);
+        }
+        if response.icmp_type != IcmpResponseType::Timeout {
+            self.track_route(hop_index, response.source_addr);
+            if response.source_addr == self.target_addr && self.destination_index() != destination {
+                self.hooks.target_reached(TargetReached {
+                    hop: self.stats.hop(hop_index).hop,
+                    rtt: response.rtt,
+                });
             }
         }
+        self.annotate_hop(hop_index);
+        self.hooks.check_loss(&self.stats.hop(hop_index));
+        self.notify_hop(hop_index);
+        true
+    }
 
-        // Trigger real-time UI update when a response arrives
-        if let Some(ref callback) = self.update_callback {
-            callback();
+    /// Send a hop's current statistics to the results stream, if there is one
+    fn notify_hop(&self, index: usize) {
+        if let Some(ref hop_updates) = self.hop_updates {
+            let _ = hop_updates.send(HopUpdate::new(index, self.stats.hop(index).clone()));
         }
     }
 
-    // DNS lookup functionality
-    async fn perform_dns_lookup(&mut self, hop_index: usize, addr: IpAddr) {
-        if hop_index >= self.hops.len() {
+    /// Name a hop's responder, if its reverse lookup has completed
+    ///
+    /// Lookups run in the background (see `rdns`); a name that isn't known
+    /// yet is written into every hop it answers for once the lookup is done.
+    fn resolve_hostname(&self, hop_index: usize, addr: IpAddr) {
+        let Some(ref rdns) = self.rdns else {
             return;
+        };
+        if let Some(hostname) = rdns.lookup(addr).filter(|hostname| *hostname != addr.to_string()) {
+            self.stats.hop_mut(hop_index).set_hostname_for_addr(addr, hostname);
         }
+    }
 
-        if let Ok(lookup_result) = self
-            .resolver
-            .reverse_lookup(addr)
-            .await
-        {
-            if let Some(hostname) = lookup_result.iter().next() {
-                let hostname_str = hostname.to_string();
-                if hostname_str != addr.to_string() {
-                    debug!("Resolved {} to {}", addr, hostname_str);
-                    self.hops[hop_index].set_hostname_for_addr(addr, hostname_str);
+    /// Let outstanding reverse lookups finish and name every responder
+    ///
+    /// Run once probing is over, so the last replies don't leave hops unnamed.
+    async fn resolve_remaining_hostnames(&self) {
+        let Some(ref rdns) = self.rdns else {
+            return;
+        };
+        rdns.wait_idle(Duration::from_millis(self.args.dns_timeout)).await;
+        for index in 0..self.stats.len() {
+            let mut hop = self.stats.hop_mut(index);
+            let addrs: Vec<IpAddr> = hop.addr.into_iter().chain(hop.alternate_paths.keys().copied()).collect();
+            for addr in addrs {
+                if let Some(hostname) = rdns.lookup(addr).filter(|hostname| *hostname != addr.to_string()) {
+                    hop.set_hostname_for_addr(addr, hostname);
                 }
             }
         }
@@ -362,7 +774,7 @@ impl MtrSession {
         if self.next_sequence == MAX_SEQUENCE {
             self.next_sequence = MIN_SEQUENCE;
         }
-        self.hops[index].increment_sent();
+        self.stats.hop_mut(index).increment_sent();
         
         // Trigger UI update immediately when packet is sent (shows waiting state)
         if let Some(ref callback) = self.update_callback {
@@ -376,7 +788,7 @@ impl MtrSession {
         let entry = SequenceEntry {
             index,
             transit: true,
-            saved_seq: self.hops[index].sent as u32,
+            saved_seq: self.stats.hop(index).sent as u32,
             send_time,
         };
         self.sequence_table.insert(seq, entry);
@@ -384,12 +796,25 @@ impl MtrSession {
 
     async fn run_simulated_trace(&mut self) -> Result<()> {
         info!("Running simulated traceroute (use sudo for real network tracing)");
+        if let Some(ref notifier) = self.notifier {
+            notifier.ready();
+        }
 
+        let mut seq = MIN_SEQUENCE; // Only for output that shows sequence numbers
         for round in 0..self.args.count.unwrap_or(10) {
+            if self.cancel.is_cancelled() {
+                break;
+            }
             debug!("Simulation Round {}", round + 1);
 
-            for hop in &mut self.hops {
+            for index in 0..self.stats.len() {
+                let mut hop = self.stats.hop_mut(index);
+                hop.begin_round();
                 hop.increment_sent();
+                seq = seq.wrapping_add(1);
+                if let Some(ref raw) = self.raw {
+                    raw.transmit(hop.hop, seq);
+                }
 
                 // Simulate realistic network behavior
                 let base_latency = hop.hop as u64 * 10 + 20; // Base latency increases with hops
@@ -420,17 +845,42 @@ impl MtrSession {
                                     None
                                 };
                             }
-                            _ => {
-                                let final_octet = if hop.hop >= 8 { 8 } else { hop.hop };
-                                hop.addr = Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, final_octet)));
+                            4..=7 => {
+                                hop.addr = Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, hop.hop)));
                                 hop.hostname = if !self.args.numeric {
                                     Some("dns.google".to_string())
                                 } else {
                                     None
                                 };
                             }
+                            _ => {
+                                // The target answers for itself
+                                hop.addr = Some(self.target_addr);
+                                hop.hostname = (!self.args.numeric).then(|| self.target.clone());
+                            }
                         }
                     }
+                    if let Some(addr) = hop.addr {
+                        hop.record_responder(addr);
+                    }
+
+                    if let Some(ref jsonl) = self.jsonl {
+                        jsonl.write(&Event::probe(self.target_addr, hop.hop, hop.addr, Some(rtt)));
+                    }
+                    if let (Some(ref recorder), Some(addr)) = (&self.recorder, hop.addr) {
+                        recorder.probe(hop.hop, Some((addr, rtt)));
+                    }
+                    if let (Some(ref probe_log), Some(addr)) = (&self.probe_log, hop.addr) {
+                        let kind = if addr == self.target_addr {
+                            IcmpResponseType::EchoReply
+                        } else {
+                            IcmpResponseType::TimeExceeded
+                        };
+                        probe_log.probe(Instant::now() - rtt, hop.hop, seq, Some((kind, addr, rtt)));
+                    }
+                    if let (Some(ref raw), Some(addr)) = (&self.raw, hop.addr) {
+                        raw.reply(&hop, addr, rtt, seq);
+                    }
 
                     // Stop at target (simulate reaching destination)
                     if hop.hop >= 8 {
@@ -438,28 +888,322 @@ impl MtrSession {
                     }
                 } else {
                     hop.add_timeout();
+                    if let Some(ref jsonl) = self.jsonl {
+                        jsonl.write(&Event::probe(self.target_addr, hop.hop, None, None));
+                    }
+                    if let Some(ref recorder) = self.recorder {
+                        recorder.probe(hop.hop, None);
+                    }
+                    if let Some(ref probe_log) = self.probe_log {
+                        probe_log.probe(Instant::now(), hop.hop, seq, None);
+                    }
                 }
             }
 
-            time::sleep(Duration::from_millis(self.args.interval)).await;
+            if let Some(ref notifier) = self.notifier {
+                notifier.watchdog();
+            }
+            self.publish_round(round + 1);
+
+            tokio::select! {
+                _ = time::sleep(Duration::from_millis(self.args.interval)) => {}
+                _ = self.cancel.cancelled() => {}
+            }
         }
 
         Ok(())
     }
 
+    /// Index of the hop that blocked the trace, if any
+    ///
+    /// The trace counts as blocked when the furthest hop that answered did so
+    /// with a destination unreachable and isn't the target itself (a port
+    /// unreachable from the target is a normal end of trace).
+    pub fn blocked_hop(&self) -> Option<usize> {
+        let last = self.stats.rposition(|hop| hop.received > 0 || hop.has_icmp_error())?;
+        let hop = self.stats.hop(last);
+        if hop.unreachable_code.is_some() && hop.addr != Some(self.target_addr) {
+            Some(last)
+        } else {
+            None
+        }
+    }
+
+    /// Current probe interval (may differ from `args.interval` while running)
+    pub fn current_interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.load(Ordering::Relaxed))
+    }
+
+    /// Change the probe interval of a running trace
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval_ms
+            .store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Set the displayed range for a round and return how many hops to probe
+    ///
+    /// Once the destination is known, every `VERIFY_INTERVAL` rounds one probe
+    /// also goes a hop past it. If a router rather than the target answers
+    /// there, the path has grown and the range follows (see `track_route`).
+    fn plan_round(&self, round: usize) -> usize {
+        let range = self.probe_range();
+        self.stats.set_num_hosts(range);
+        if self.destination_index().is_some() && round % VERIFY_INTERVAL == VERIFY_INTERVAL - 1 {
+            (range + 1).min(self.stats.len())
+        } else {
+            range
+        }
+    }
+
+    /// Probes to send each of the first `count` hops this round: --burst,
+    /// or with --adaptive more to unstable hops and fewer to stable ones
+    fn probes_per_hop(&self, count: usize) -> Vec<usize> {
+        let burst = usize::from(self.args.burst);
+        match self.budget {
+            Some(ref budget) => budget.allocate(&self.stats.snapshot_range(count), burst),
+            None => vec![burst; count],
+        }
+    }
+
+    /// Number of hops to probe in the next round
+    ///
+    /// The range ends at the destination (see `destination_index`), or at a
+    /// hop that reported it unreachable. Until one has answered it reaches `--max-unknown` hops
+    /// past the furthest hop that answered, so every round discovers more of
+    /// the path while a path that goes silent isn't probed out to `--max-hops`.
+    fn probe_range(&self) -> usize {
+        if let Some(destination) = self.destination_index() {
+            return destination + 1;
+        }
+        let mut answered = 0; // Hops up to and including the furthest that answered
+        for index in 0..self.stats.len() {
+            let hop = self.stats.hop(index);
+            if hop.has_icmp_error() {
+                return index + 1;
+            }
+            if hop.received > 0 {
+                answered = index + 1;
+            }
+        }
+        (answered + usize::from(self.args.max_unknown)).min(self.stats.len())
+    }
+
+    /// Number of hops worth displaying: the discovered path length, or every probed hop
+    pub fn display_hop_count(&self) -> usize {
+        self.stats.display_count()
+    }
+
+    /// Record a change of responder at a hop as a route change event
+    ///
+    /// Also retires the hops past the destination, so a route that got
+    /// shorter doesn't leave stale hops at the end of the table.
+    fn track_route(&self, index: usize, addr: IpAddr) {
+        let (ttl, previous) = {
+            let mut hop = self.stats.hop_mut(index);
+            (hop.hop, hop.record_responder(addr))
+        };
+        if let Some(previous) = previous {
+            let change = RouteChange::new(ttl, previous, addr);
+            info!("Route changed at hop {}: {} -> {}", change.hop, previous, addr);
+            self.publish(&Event::route_changed(&change));
+            self.hooks.path_changed(&change);
+            self.stats.record_route_change(change);
+        }
+        if let Some(destination) = self.destination_index() {
+            if index > destination && addr != self.target_addr {
+                // A router answered past the destination: the path got longer
+                info!("Path extends past hop {}", self.stats.hop(destination).hop);
+                for earlier in 0..index {
+                    let mut hop = self.stats.hop_mut(earlier);
+                    if hop.last_responder == Some(self.target_addr) {
+                        hop.last_responder = None;
+                    }
+                }
+            } else {
+                self.retire_hops_beyond(destination);
+            }
+        }
+    }
+
+    /// Index of the first hop whose latest reply came from the target
+    ///
+    /// Probes with a TTL past the path length are answered by the target too,
+    /// so this is where the path currently ends. It moves in when the route
+    /// gets shorter and out again when the hop starts answering from a router.
+    pub fn destination_index(&self) -> Option<usize> {
+        self.stats.position(|hop| hop.last_responder == Some(self.target_addr))
+    }
+
+    /// TTL of the destination hop (see `destination_index`)
+    pub fn destination_ttl(&self) -> Option<u8> {
+        self.destination_index().map(|index| self.stats.hop(index).hop)
+    }
+
+    /// Reset every hop past `destination` that holds statistics
+    fn retire_hops_beyond(&self, destination: usize) {
+        for index in destination + 1..self.stats.len() {
+            let mut hop = self.stats.hop_mut(index);
+            if hop.sent > 0 || hop.received > 0 {
+                debug!("Retiring hop {} beyond the destination", hop.hop);
+                let mut fresh = HopStats::new(hop.hop);
+                fresh.set_ema_alpha(hop.ema_alpha);
+                *hop = fresh;
+            }
+        }
+    }
+
+    /// Attach GeoIP and whois details for a hop's current address
+    fn annotate_hop(&self, index: usize) {
+        let mut hop = self.stats.hop_mut(index);
+        let Some(addr) = hop.addr else {
+            return;
+        };
+        if let Some(ref geoip) = self.geoip {
+            if hop.geo.as_ref().map(|geo| geo.addr) != Some(addr) {
+                hop.geo = geoip.lookup(addr);
+            }
+        }
+        if let Some(ref whois) = self.whois {
+            if hop.whois.as_ref().map(|whois| whois.addr) != Some(addr) {
+                hop.whois = whois.lookup(addr);
+            }
+        }
+    }
+
+    /// How a hop differs from its recorded baseline, if it does
+    pub fn baseline_deviation(&self, index: usize) -> Option<Deviation> {
+        let baselines = self.baselines.as_ref()?;
+        if index >= self.stats.len() {
+            return None;
+        }
+        baselines.deviation(&self.target, &self.stats.hop(index))
+    }
+
+    /// Fold this run into the stored baselines
+    ///
+    /// Re-reads the file first so concurrent runs against other targets aren't lost.
+    pub fn save_baselines(&self) -> Result<()> {
+        if self.baselines.is_none() {
+            return Ok(());
+        }
+        let path = BaselineStore::default_path()
+            .ok_or_else(|| anyhow!("Cannot locate data directory for baselines (HOME not set)"))?;
+        let mut store = BaselineStore::load(&path)?;
+        store.record(&self.target, &self.stats.snapshot_range(self.display_hop_count()));
+        store.save(&path)?;
+        info!("Saved baselines to {}", path.display());
+        Ok(())
+    }
+
+    /// Write the session's statistics to the --save file, if one was given
+    pub fn save_state(&self) -> Result<()> {
+        let Some(ref path) = self.args.save else {
+            return Ok(());
+        };
+        SavedSession::new(&self.target, self.target_addr, &self.stats.snapshot(), &self.stats.route_history()).save(path)?;
+        info!("Saved session to {}", path.display());
+        Ok(())
+    }
+
+    /// Publish an event to stream subscribers and --jsonl, if enabled
+    pub fn publish(&self, event: &Event) {
+        if let Some(ref events) = self.events {
+            events.publish(event);
+        }
+        if let Some(ref jsonl) = self.jsonl {
+            jsonl.write(event);
+        }
+    }
+
+    fn publish_round(&self, round: usize) {
+        self.stats.complete_round();
+        if self.events.is_some()
+            || self.jsonl.is_some()
+            || self.graphite.is_some()
+            || self.split.is_some()
+            || self.recorder.is_some()
+        {
+            let hops = self.stats.snapshot_range(self.display_hop_count());
+            self.publish(&Event::round(round, &hops));
+            if let Some(ref graphite) = self.graphite {
+                graphite.send_round(&self.target, &hops);
+            }
+            if let Some(ref split) = self.split {
+                split.round(&hops, self.args.numeric);
+            }
+            if let Some(ref recorder) = self.recorder {
+                recorder.round(round, &hops);
+            }
+        }
+        self.hooks
+            .round_complete(round, || self.stats.snapshot_range(self.display_hop_count()));
+        if let Some(ref alert) = self.alert {
+            alert.check(self);
+        }
+    }
+
+    /// Stop a running trace cleanly
+    ///
+    /// No further rounds start; replies to the probes already sent are
+    /// still awaited, for at most --grace-period, before the trace returns.
+    pub fn shutdown(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Stop waiting for the replies of a trace that was shut down
+    ///
+    /// The probes still outstanding are counted and logged as lost, and the
+    /// trace returns.
+    pub fn abandon(&self) {
+        self.abandon.cancel();
+    }
+
+    /// Events when the target answers, see `hooks`
+    pub fn on_target_reached(&self) -> mpsc::UnboundedReceiver<TargetReached> {
+        self.hooks.on_target_reached()
+    }
+
+    /// Events when a hop's loss goes above `threshold` percent
+    pub fn on_hop_loss_exceeded(&self, threshold: f64) -> mpsc::UnboundedReceiver<HopLossExceeded> {
+        self.hooks.on_hop_loss_exceeded(threshold)
+    }
+
+    /// Events for every route change
+    pub fn on_path_change(&self) -> mpsc::UnboundedReceiver<RouteChange> {
+        self.hooks.on_path_change()
+    }
+
+    /// Events at the end of each probing round
+    pub fn on_round_complete(&self) -> mpsc::UnboundedReceiver<RoundComplete> {
+        self.hooks.on_round_complete()
+    }
+
+    /// Start building a session for library use, see `MtrSessionBuilder`
+    pub fn builder(target: impl Into<String>) -> MtrSessionBuilder {
+        MtrSessionBuilder::new(target)
+    }
+
     pub fn set_update_callback(&mut self, callback: UpdateCallback) {
         self.update_callback = Some(callback);
     }
 
+    /// Trace with an engine opened earlier, before privileges were dropped
+    ///
+    /// A failed attempt is reported as the reason the trace can't start,
+    /// rather than opening the engine again.
+    pub fn set_probe_engine(&mut self, engine: Result<ProbeEngine>) {
+        *self.preopened.lock().unwrap() = Some(engine);
+    }
+
          // Real-time updates with ProbeEngine integration
     pub async fn run_trace_with_realtime_updates(
-        session_arc: std::sync::Arc<std::sync::Mutex<Self>>,
+        session_arc: Arc<Self>,
     ) -> Result<()> {
          // Extract target and args from session
-        let (target_addr, args) = {
-            let session = session_arc.lock().unwrap();
-            (session.target_addr, session.args.clone())
-        };
+        let target_addr = session_arc.target_addr;
+        let args = session_arc.args.clone();
+        let notifier = session_arc.notifier.clone();
+        let preopened = session_arc.preopened.lock().unwrap().take();
 
         info!("Starting real-time trace to {}", target_addr);
 
@@ -470,41 +1214,50 @@ impl MtrSession {
                  "--simulate flag enabled"
              };
              info!("Running in simulation mode ({})", reason);
+             if let Some(ref notifier) = notifier {
+                 notifier.ready();
+             }
+             if let Some(kind) = args.scenario {
+                 info!("Injecting faults from {:?} scenario", kind);
+                 let transport = FaultTransport::new(Scenario::builtin(kind));
+                 return Self::run_real_trace_realtime(session_arc, target_addr, transport, args, notifier).await;
+             }
              return Self::run_simulated_trace_realtime(session_arc, args).await;
          }
 
-        match target_addr {
-             IpAddr::V4(ipv4) => {
-                 // Try real network tracing first
-                 match ProbeEngine::new() {
-                     Ok(probe_engine) => {
-                         info!("Using ProbeEngine for real-time traceroute");
-                         Self::run_real_trace_realtime(session_arc, ipv4, probe_engine, args).await
-                     }
-                     Err(e) => {
-                         warn!("Failed to create ProbeEngine: {}. Falling back to simulation.", e);
-                         Self::run_simulated_trace_realtime(session_arc, args).await
-                     }
-                 }
-             }
-            IpAddr::V6(_) => {
-                warn!("IPv6 not yet implemented, falling back to simulation");
+        // Try real network tracing first
+        match preopened.unwrap_or_else(|| Self::open_probe_engine(&args)) {
+            Ok(probe_engine) => {
+                info!("Using ProbeEngine for real-time traceroute");
+                if let Some(ref notifier) = notifier {
+                    notifier.ready();
+                }
+                #[cfg(all(feature = "io-uring", target_os = "linux"))]
+                if args.io_uring {
+                    let probe_engine = crate::probe::UringProbeEngine::new(probe_engine)?;
+                    return Self::run_real_trace_realtime(session_arc, target_addr, probe_engine, args, notifier).await;
+                }
+                Self::run_real_trace_realtime(session_arc, target_addr, probe_engine, args, notifier).await
+            }
+            Err(e) => {
+                warn!("Failed to create ProbeEngine: {}. Falling back to simulation.", e);
                 Self::run_simulated_trace_realtime(session_arc, args).await
             }
         }
     }
 
      async fn run_real_trace_realtime(
-        session_arc: std::sync::Arc<std::sync::Mutex<Self>>,
-        target: Ipv4Addr,
-         probe_engine: ProbeEngine,
+        session_arc: Arc<Self>,
+        target: IpAddr,
+         probe_engine: impl ProbeTransport + 'static,
         args: Args,
+        notifier: Option<Arc<systemd::Notifier>>,
     ) -> Result<()> {
          info!("Starting real network trace with channels (real-time UI)");
          
          // Create channels for communication between probe task and UI
          let (response_tx, response_rx) = mpsc::unbounded_channel::<ProbeResponse>();
-         let (sent_tx, sent_rx) = mpsc::unbounded_channel::<usize>(); // hop index when packet sent
+         let (sent_tx, sent_rx) = mpsc::unbounded_channel::<(usize, bool)>(); // (hop index, first of round) when packet sent
          
          // Clone session for probe task
          let probe_session_arc = Arc::clone(&session_arc);
@@ -512,7 +1265,7 @@ impl MtrSession {
          
          // Spawn probe task that runs independently
          let probe_handle = tokio::spawn(async move {
-             Self::run_probe_task(probe_session_arc, target, probe_engine, probe_args, response_tx, sent_tx).await
+             Self::run_probe_task(probe_session_arc, target, probe_engine, probe_args, response_tx, sent_tx, notifier).await
          });
          
          // UI task processes both sent notifications and responses
@@ -531,23 +1284,30 @@ impl MtrSession {
      // Probe task - continuously sends probes and async listens for responses
      #[allow(unused_mut)]
      async fn run_probe_task(
-         _session_arc: std::sync::Arc<std::sync::Mutex<Self>>,
-        target: Ipv4Addr,
-         mut probe_engine: ProbeEngine,
+         session_arc: Arc<Self>,
+        target: IpAddr,
+         mut probe_engine: impl ProbeTransport + 'static,
         args: Args,
          response_tx: mpsc::UnboundedSender<ProbeResponse>,
-         sent_tx: mpsc::UnboundedSender<usize>,
+         sent_tx: mpsc::UnboundedSender<(usize, bool)>,
+         notifier: Option<Arc<systemd::Notifier>>,
     ) -> Result<()> {
-         let max_hops = utils::math::min_with_safety(10, args.max_hops as usize);
-         info!("Probe task starting with {} max hops", max_hops);
+         info!("Probe task starting with up to {} hops", args.max_hops);
+         let interval_ms = Arc::clone(&session_arc.interval_ms);
          
          // Spawn continuous response listener task
          #[allow(unused_mut)]
          let (probe_tx, probe_rx) = mpsc::unbounded_channel();
          let listener_response_tx = response_tx.clone();
          
+         let protocol = args.protocol;
+         let grace_period = Duration::from_secs_f64(args.grace_period);
+         let abandon = session_arc.abandon.clone();
          let listener_handle = tokio::spawn(async move {
-             Self::run_response_listener(probe_engine, probe_rx, listener_response_tx).await
+             Self::run_response_listener(
+                 probe_engine, protocol, probe_rx, listener_response_tx, notifier, grace_period, abandon,
+             )
+             .await
          });
          
          // Main probe sending loop
@@ -555,33 +1315,77 @@ impl MtrSession {
              let mut round = 0;
                  
              loop {
+                 // Replies to the previous round had the interval to arrive
+                 if round > 0 {
+                     session_arc.publish_round(round);
+                 }
+
                  if let Some(count) = args.count {
                      if round >= count {
                          break;
                      }
                  }
+                 if session_arc.cancel.is_cancelled() {
+                     info!("Trace shut down after {} rounds", round);
+                     break;
+                 }
                      
-                 // Send all probes for this round
-                 for i in 0..max_hops {
-                     // Notify UI that packet is being sent (shows waiting state)
-                     if sent_tx.send(i).is_err() {
-                         return Ok::<(), anyhow::Error>(());
+                 // Each hop's timeout follows its own RTT history
+                 let count = session_arc.plan_round(round);
+                 let hops: Vec<(u8, Duration)> = (0..count)
+                     .map(|index| {
+                         let hop = session_arc.stats.hop(index);
+                         (hop.hop, hop.probe_timeout())
+                     })
+                     .collect();
+                 let counts = session_arc.probes_per_hop(hops.len());
+
+                 // All probes for this round, --burst of them per hop, paired
+                 // with whether they are the first of the round to their hop
+                 let mut requests = Vec::with_capacity(counts.iter().sum());
+                 for (i, (&(ttl, timeout), &probes)) in hops.iter().zip(&counts).enumerate() {
+                     for probe in 0..probes {
+                         let request = ProbeRequest {
+                             hop: i,
+                             dst: SocketAddr::new(target, args.port.unwrap_or(0)),
+                             ttl,
+                             timeout,
+                         };
+                         requests.push((request, probe == 0));
                      }
-                     
-                     let dest = SocketAddr::new(target.into(), 0);
-                     let ttl = (i + 1) as u8;
-                     let timeout = Duration::from_millis(5000);
-                     
-                     // Send probe request to listener task
-                     if probe_tx.send((i, dest, ttl, timeout, round)).is_err() {
+                 }
+
+                 // Hand the round to the listener task as one batch, or as
+                 // several spaced out over the --spread window
+                 let interval = Duration::from_millis(interval_ms.load(Ordering::Relaxed));
+                 let round_start = time::Instant::now();
+                 let batches = schedule_batches(requests.len(), spread_window(interval, args.spread), args.jitter);
+                 for (offset, range) in batches {
+                     if !offset.is_zero() {
+                         tokio::select! {
+                             _ = time::sleep_until(round_start + offset) => {}
+                             _ = session_arc.cancel.cancelled() => break,
+                         }
+                     }
+                     // Notify UI that packets are being sent (shows waiting state)
+                     for &(ref request, first_of_round) in &requests[range.clone()] {
+                         if sent_tx.send((request.hop, first_of_round)).is_err() {
+                             return Ok::<(), anyhow::Error>(());
+                         }
+                     }
+                     let batch = requests[range].iter().map(|&(request, _)| request).collect();
+                     if probe_tx.send((batch, round)).is_err() {
                          return Ok::<(), anyhow::Error>(());
                      }
                  }
                  
-                 debug!("Sent {} probes for round {}", max_hops, round + 1);
+                 debug!("Sent {} probes for round {}", requests.len(), round + 1);
                  round += 1;
                  
-                 tokio::time::sleep(Duration::from_millis(args.interval)).await;
+                 tokio::select! {
+                     _ = time::sleep_until(round_start + interval) => {}
+                     _ = session_arc.cancel.cancelled() => {}
+                 }
              }
              
              info!("Probe sender completed {} rounds", round);
@@ -598,31 +1402,54 @@ impl MtrSession {
      
      // Pure event-driven async response listener (zero polling!)
      async fn run_response_listener(
-         mut probe_engine: ProbeEngine,
-         mut probe_rx: mpsc::UnboundedReceiver<(usize, SocketAddr, u8, Duration, usize)>, // (hop, dest, ttl, timeout, round)
+         mut probe_engine: impl ProbeTransport,
+         protocol: crate::args::ProbeProtocol,
+         mut probe_rx: mpsc::UnboundedReceiver<(Vec<ProbeRequest>, usize)>, // (probes, round)
          response_tx: mpsc::UnboundedSender<ProbeResponse>,
+         notifier: Option<Arc<systemd::Notifier>>,
+         grace_period: Duration,
+         abandon: CancellationToken,
      ) -> Result<()> {
          use std::collections::HashMap;
          
          let mut sent_sequences: HashMap<u16, (usize, usize)> = HashMap::new(); // seq -> (hop, round)
+         // Set once the sender is done: replies are awaited until then
+         let mut grace_deadline: Option<time::Instant> = None;
          
          loop {
              tokio::select! {
                  // Handle probe send requests (channel-driven)
-                 probe_request = probe_rx.recv() => {
-                     if let Some((hop, dest, ttl, timeout, round)) = probe_request {
-                         match probe_engine.send_probe(hop, dest, ttl, timeout) {
-                             Ok(seq) => {
-                                 sent_sequences.insert(seq, (hop, round));
-                                 debug!("Sent probe: hop={}, round={}, seq={}", hop + 1, round + 1, seq);
+                 probe_request = probe_rx.recv(), if grace_deadline.is_none() => {
+                     if let Some((requests, round)) = probe_request {
+                         let results = probe_engine.send_probes(&requests, protocol);
+                         for (request, result) in requests.iter().zip(results) {
+                             match result {
+                                 Ok(seq) => {
+                                     sent_sequences.insert(seq, (request.hop, round));
+                                     debug!("Sent probe: hop={}, round={}, seq={}", request.hop + 1, round + 1, seq);
+                                     // Only a live engine gets here, so this doubles as the health signal
+                                     if let Some(ref notifier) = notifier {
+                                         notifier.watchdog();
+                                     }
+                                 }
+                                 Err(e) => debug!("Failed to send probe: {}", e),
                              }
-                             Err(e) => debug!("Failed to send probe: {}", e),
                          }
                      } else {
-                         // Sender dropped, time to exit
-                         break;
+                         // Sender done; wait out the grace period for the last replies
+                         grace_deadline = Some(time::Instant::now() + grace_period);
                      }
                  }
+
+                 _ = time::sleep_until(grace_deadline.unwrap_or_else(time::Instant::now)), if grace_deadline.is_some() => {
+                     debug!("Grace period over with {} probes outstanding", sent_sequences.len());
+                     break;
+                 }
+
+                 _ = abandon.cancelled() => {
+                     debug!("Abandoned with {} probes outstanding", sent_sequences.len());
+                     break;
+                 }
                  
                  // Event-driven response collection (no sleep!)
                  result = probe_engine.collect_responses_async() => {
@@ -654,47 +1481,62 @@ impl MtrSession {
                      }
                  }
              }
+
+             if grace_deadline.is_some() && sent_sequences.is_empty() {
+                 break; // Every probe answered or timed out
+             }
+         }
+
+         // Whatever is still out counts, and is logged, as lost
+         for response in probe_engine.expire_all() {
+             if sent_sequences.remove(&response.seq).is_some() && response_tx.send(response).is_err() {
+                 break;
+             }
          }
          
+         log_discarded_replies(&probe_engine);
          info!("Response listener finished");
          Ok(())
      }
      
      // UI processor - handles both sent notifications and responses for real-time updates
      async fn run_ui_processor_with_sent_notifications(
-         session_arc: std::sync::Arc<std::sync::Mutex<Self>>,
+         session_arc: Arc<Self>,
          mut response_rx: mpsc::UnboundedReceiver<ProbeResponse>,
-         mut sent_rx: mpsc::UnboundedReceiver<usize>,
+         mut sent_rx: mpsc::UnboundedReceiver<(usize, bool)>,
      ) -> Result<()> {
                     let mut _probe_count = 0;
+         let mut sending = true;
          
          loop {
              tokio::select! {
+                 // A probe's sent notification is queued before the probe goes
+                 // out, so taking it first keeps replies from outrunning it
+                 biased;
+
                  // Handle packet sent notifications (shows waiting state)
-                 sent_hop = sent_rx.recv() => {
+                 sent_hop = sent_rx.recv(), if sending => {
                      match sent_hop {
-                         Some(hop_index) => {
-                             let should_update = {
-                                 let mut session = session_arc.lock().unwrap();
-                                 if hop_index < session.hops.len() {
-                                     session.hops[hop_index].increment_sent();
-                                     session.update_callback.is_some()
-                                 } else {
-                                     false
+                         Some((hop_index, first_of_round)) => {
+                             if hop_index < session_arc.stats.len() {
+                                 {
+                                     let mut hop = session_arc.stats.hop_mut(hop_index);
+                                     if first_of_round {
+                                         hop.begin_round();
+                                     }
+                                     hop.increment_sent();
                                  }
-                             };
-                             
-                             // Trigger UI update immediately when packet is sent
-                             if should_update {
-                                 let session = session_arc.lock().unwrap();
-                                 if let Some(ref callback) = session.update_callback {
+
+                                 // Trigger UI update immediately when packet is sent
+                                 if let Some(ref callback) = session_arc.update_callback {
                                      callback();
                                  }
                              }
                          }
                          None => {
                              // Sender closed, but continue processing responses
-                             break;
+                             // to the probes still in flight
+                             sending = false;
                          }
                      }
                  }
@@ -703,43 +1545,11 @@ impl MtrSession {
                  response_result = response_rx.recv() => {
                      match response_result {
                          Some(response) => {
-                             let should_trigger_update = {
-                                 let mut session = session_arc.lock().unwrap();
-                                 let hop_index = response.hop;
-                                 
-                                 if hop_index < session.hops.len() {
-                                     match response.icmp_type {
-                                         IcmpResponseType::TimeExceeded | IcmpResponseType::EchoReply => {
-                                             // RTT is calculated in ProbeEngine when response arrives - no timing corruption!
-                                             session.hops[hop_index].add_rtt_from_addr(response.source_addr, response.rtt);
-                                             debug!("UI: Hop {} RTT: {:?} from {} (precise: {}ns)", 
-                                                   hop_index + 1, response.rtt, response.source_addr, response.precise_rtt_ns);
-                                         }
-                                         IcmpResponseType::DestinationUnreachable => {
-                                             session.hops[hop_index].set_icmp_error();
-                                             if session.hops[hop_index].addr.is_none() {
-                                                 session.hops[hop_index].addr = Some(response.source_addr);
-                                             }
-                                             debug!("UI: Hop {} destination unreachable from {}", hop_index + 1, response.source_addr);
-                                         }
-                                         IcmpResponseType::Timeout => {
-                                             debug!("UI: Hop {} timeout", hop_index + 1);
-                                         }
-                                     }
-                                     
-                                                                            _probe_count += 1;
-                                     
-                                     // Always trigger update for every response - real-time feel
-                                     session.update_callback.is_some()
-                                 } else {
-                                     false
-                                 }
-                             };
+                             if session_arc.record_response(response) {
+                                 _probe_count += 1;
 
-                             // Trigger UI update after processing each response (moved outside lock)
-                             if should_trigger_update {
-                                 let session = session_arc.lock().unwrap();
-                                 if let Some(ref callback) = session.update_callback {
+                                 // Always trigger update for every response - real-time feel
+                                 if let Some(ref callback) = session_arc.update_callback {
                                      callback();
                                  }
                              }
@@ -761,52 +1571,56 @@ impl MtrSession {
      }
 
     async fn run_simulated_trace_realtime(
-        session_arc: std::sync::Arc<std::sync::Mutex<Self>>,
+        session_arc: Arc<Self>,
         args: Args,
     ) -> Result<()> {
         info!("Running simulated traceroute (real-time individual packet responses)");
 
         // Extract the numeric flag once to avoid borrow conflicts
         let numeric = args.numeric;
-        let max_hops = {
-            let session = session_arc.lock().unwrap();
-            session.hops.len()
-        };
+        let max_hops = session_arc.stats.len();
+        let interval_ms = Arc::clone(&session_arc.interval_ms);
 
         for round in 0..args.count.unwrap_or(1000) {
-            debug!("Simulation Round {} (interval: {}ms)", round + 1, args.interval);
+            // A round already under way runs to the end, like probes in flight
+            if session_arc.cancel.is_cancelled() {
+                info!("Trace shut down after {} rounds", round);
+                break;
+            }
+            let interval = interval_ms.load(Ordering::Relaxed);
+            debug!("Simulation Round {} (interval: {}ms)", round + 1, interval);
             let round_start = tokio::time::Instant::now();
 
             // PHASE 1: Send all packets immediately (shows waiting state)
-            {
-                let mut session = session_arc.lock().unwrap();
-                for hop_index in 0..max_hops {
-                    session.hops[hop_index].increment_sent();
-                }
-                
-                // Trigger UI update to show all hops in waiting state
-                if let Some(ref callback) = session.update_callback {
-                    callback();
-                }
+            for hop_index in 0..max_hops {
+                let mut hop = session_arc.stats.hop_mut(hop_index);
+                hop.begin_round();
+                hop.increment_sent();
+            }
+
+            // Trigger UI update to show all hops in waiting state
+            if let Some(ref callback) = session_arc.update_callback {
+                callback();
             }
             
             // PHASE 2: Simulate responses arriving individually with realistic delays
             for hop_index in 0..max_hops {
+                let ttl = u64::from(args.first_ttl) + hop_index as u64;
+
                 // Simulate network transit time for this hop
-                let base_transit_time = (hop_index + 1) as u64 * 15 + 10; // 25ms, 40ms, 55ms, etc.
+                let base_transit_time = ttl * 15 + 10; // 25ms, 40ms, 55ms, etc.
                 let jitter = rand::random::<u64>() % 30; // 0-30ms jitter
                 let transit_time = Duration::from_millis(base_transit_time + jitter);
                 
                 // Wait for the simulated transit time
                 tokio::time::sleep(transit_time).await;
 
-                let should_update_ui = {
-                    let mut session = session_arc.lock().unwrap();
-                    let hop = &mut session.hops[hop_index];
+                {
+                    let mut hop = session_arc.stats.hop_mut(hop_index);
                     
                     // Don't increment sent again - already done in phase 1
 
-                    let base_latency = (hop_index + 1) as u64 * 15 + 20; // Realistic latency progression
+                    let base_latency = ttl * 15 + 20; // Realistic latency progression
                     let rtt_jitter = rand::random::<u64>() % 20;
                     let packet_loss_chance = utils::math::clamp_f64(hop_index as f64 * 0.03, 0.0, 0.20);
 
@@ -832,46 +1646,61 @@ impl MtrSession {
                                         None
                                     };
                                 }
-                                _ => {
-                                    let final_octet = if hop.hop >= 8 { 8 } else { hop.hop };
-                                    hop.addr = Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, final_octet)));
+                                4..=7 => {
+                                    hop.addr = Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, hop.hop)));
                                     hop.hostname = if !numeric {
                                         Some("dns.google".to_string())
                                     } else {
                                         None
                                     };
                                 }
+                                _ => {
+                                    // The target answers for itself
+                                    hop.addr = Some(session_arc.target_addr);
+                                    hop.hostname = (!numeric).then(|| session_arc.target.clone());
+                                }
                             }
                         }
+                        if let Some(addr) = hop.addr {
+                            hop.record_responder(addr);
+                        }
                     } else {
                         hop.add_timeout();
                     }
-
-                    session.update_callback.is_some()
-                };
+                }
+                session_arc.hooks.check_loss(&session_arc.stats.hop(hop_index));
+                session_arc.notify_hop(hop_index);
 
                 // Trigger UI update immediately when this packet response "arrives"
-                if should_update_ui {
-                    let session = session_arc.lock().unwrap();
-                    if let Some(ref callback) = session.update_callback {
-                        callback();
-                    }
+                if let Some(ref callback) = session_arc.update_callback {
+                    callback();
                 }
 
-                // Stop at target (simulate reaching destination)
-                if hop_index + 1 >= 8 {
+                // Stop at target (simulate reaching destination); nothing answers beyond it
+                if ttl >= 8 {
+                    for index in hop_index + 1..max_hops {
+                        session_arc.stats.hop_mut(index).add_timeout();
+                    }
                     break;
                 }
             }
 
+            if let Some(ref notifier) = session_arc.notifier {
+                notifier.watchdog();
+            }
+            session_arc.publish_round(round + 1);
+
             // Wait for the remainder of the interval before starting the next round
             // This maintains the specified interval timing while showing individual responses
             let elapsed = round_start.elapsed();
-            let interval_duration = Duration::from_millis(args.interval);
+            let interval_duration = Duration::from_millis(interval);
             if elapsed < interval_duration {
                 let remaining = interval_duration - elapsed;
                 debug!("Round {} completed in {:?}, waiting {:?} more", round + 1, elapsed, remaining);
-                tokio::time::sleep(remaining).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(remaining) => {}
+                    _ = session_arc.cancel.cancelled() => {}
+                }
             }
         }
 
@@ -881,6 +1710,45 @@ impl MtrSession {
 
 
 
+/// The part of the interval a round's probes are spread over
+fn spread_window(interval: Duration, spread: u8) -> Duration {
+    interval * u32::from(spread) / 100
+}
+
+/// When to send each of a round's `probes` within `window`, as batches of
+/// probes that go out together: (offset from the round's start, probe indices)
+///
+/// Probes are evenly spaced, or with `jitter` each gets a random time, still
+/// in order. An empty window sends the whole round at once.
+fn schedule_batches(probes: usize, window: Duration, jitter: bool) -> Vec<(Duration, Range<usize>)> {
+    if window.is_zero() || probes <= 1 {
+        return vec![(Duration::ZERO, 0..probes)];
+    }
+    let mut offsets: Vec<Duration> = if jitter {
+        (0..probes).map(|_| window.mul_f64(rand::random::<f64>())).collect()
+    } else {
+        (0..probes).map(|i| window * i as u32 / probes as u32).collect()
+    };
+    offsets.sort();
+
+    let mut batches: Vec<(Duration, Range<usize>)> = Vec::new();
+    for (i, offset) in offsets.into_iter().enumerate() {
+        match batches.last_mut() {
+            Some((last, range)) if *last == offset => range.end = i + 1,
+            _ => batches.push((offset, i..i + 1)),
+        }
+    }
+    batches
+}
+
+/// Report replies the transport threw away as corrupt, if there were any
+fn log_discarded_replies(transport: &impl ProbeTransport) {
+    let discarded = transport.discarded_replies();
+    if discarded > 0 {
+        warn!("Discarded {} corrupt or malformed replies", discarded);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -903,6 +1771,60 @@ mod tests {
             force_simulate: false,
             timing: false,
             quiet: false,
+            loss_threshold: None,
+            latency_threshold: None,
+            unfocused_redraw: None,
+            unfocused_interval: None,
+            netns: None,
+            fwmark: None,
+            rtt_unit: crate::RttUnit::Ms,
+            stream_socket: None,
+            scenario: None,
+            config: None,
+            baseline: false,
+            paris: false,
+            multipath: None,
+            tos: None,
+            psize: None,
+            bitpattern: 0,
+            address: None,
+            interface: None,
+            io_uring: false,
+            first_ttl: 1,
+            port: None,
+            dual_stack: false,
+            dns_query: None,
+            vrf: None,
+            keep_privileges: false,
+            geoip: None,
+            whois: false,
+            dns_server: None,
+            dns_workers: 4,
+            dns_timeout: 3000,
+            save: None,
+            load: None,
+            burst: 1,
+            max_unknown: 5,
+            grace_period: 5.0,
+            on_alert: None,
+            no_bell: false,
+            no_images: false,
+            spread: 0,
+            jitter: false,
+            adaptive: false,
+            xml: false,
+            jsonl: None,
+            graphite: None,
+            raw: false,
+            split: false,
+            html: None,
+            record: None,
+            graph: false,
+            report_every: None,
+            report_file: None,
+            order: None,
+            probe_log: None,
+            merge: Vec::new(),
         };
 
         let session = MtrSession::new(args).await;
@@ -911,7 +1833,7 @@ mod tests {
         let session = session.unwrap();
         assert_eq!(session.target, "192.168.1.1");
         assert_eq!(session.target_addr.to_string(), "192.168.1.1");
-        assert_eq!(session.hops.len(), 20);
+        assert_eq!(session.stats.len(), 20);
         assert_eq!(session.args.count, Some(5));
         assert_eq!(session.args.interval, 500);
     }
@@ -934,6 +1856,60 @@ mod tests {
             force_simulate: false,
             timing: false,
             quiet: false,
+            loss_threshold: None,
+            latency_threshold: None,
+            unfocused_redraw: None,
+            unfocused_interval: None,
+            netns: None,
+            fwmark: None,
+            rtt_unit: crate::RttUnit::Ms,
+            stream_socket: None,
+            scenario: None,
+            config: None,
+            baseline: false,
+            paris: false,
+            multipath: None,
+            tos: None,
+            psize: None,
+            bitpattern: 0,
+            address: None,
+            interface: None,
+            io_uring: false,
+            first_ttl: 1,
+            port: None,
+            dual_stack: false,
+            dns_query: None,
+            vrf: None,
+            keep_privileges: false,
+            geoip: None,
+            whois: false,
+            dns_server: None,
+            dns_workers: 4,
+            dns_timeout: 3000,
+            save: None,
+            load: None,
+            burst: 1,
+            max_unknown: 5,
+            grace_period: 5.0,
+            on_alert: None,
+            no_bell: false,
+            no_images: false,
+            spread: 0,
+            jitter: false,
+            adaptive: false,
+            xml: false,
+            jsonl: None,
+            graphite: None,
+            raw: false,
+            split: false,
+            html: None,
+            record: None,
+            graph: false,
+            report_every: None,
+            report_file: None,
+            order: None,
+            probe_log: None,
+            merge: Vec::new(),
         };
 
         let session = MtrSession::new(args).await;
@@ -941,7 +1917,7 @@ mod tests {
 
         let session = session.unwrap();
         assert_eq!(session.target, "localhost");
-        assert_eq!(session.hops.len(), 15);
+        assert_eq!(session.stats.len(), 15);
         assert!(session.args.report);
         assert!(!session.args.numeric);
     }
@@ -964,6 +1940,60 @@ mod tests {
             force_simulate: false,
             timing: false,
             quiet: false,
+            loss_threshold: None,
+            latency_threshold: None,
+            unfocused_redraw: None,
+            unfocused_interval: None,
+            netns: None,
+            fwmark: None,
+            rtt_unit: crate::RttUnit::Ms,
+            stream_socket: None,
+            scenario: None,
+            config: None,
+            baseline: false,
+            paris: false,
+            multipath: None,
+            tos: None,
+            psize: None,
+            bitpattern: 0,
+            address: None,
+            interface: None,
+            io_uring: false,
+            first_ttl: 1,
+            port: None,
+            dual_stack: false,
+            dns_query: None,
+            vrf: None,
+            keep_privileges: false,
+            geoip: None,
+            whois: false,
+            dns_server: None,
+            dns_workers: 4,
+            dns_timeout: 3000,
+            save: None,
+            load: None,
+            burst: 1,
+            max_unknown: 5,
+            grace_period: 5.0,
+            on_alert: None,
+            no_bell: false,
+            no_images: false,
+            spread: 0,
+            jitter: false,
+            adaptive: false,
+            xml: false,
+            jsonl: None,
+            graphite: None,
+            raw: false,
+            split: false,
+            html: None,
+            record: None,
+            graph: false,
+            report_every: None,
+            report_file: None,
+            order: None,
+            probe_log: None,
+            merge: Vec::new(),
         };
 
         // We can't easily test MtrSession::new in sync context due to async resolver,
@@ -973,5 +2003,394 @@ mod tests {
         assert_eq!(args.target, args_clone.target);
         assert_eq!(args.count, args_clone.count);
     }
+
+    #[tokio::test]
+    async fn test_trace_with_fault_transport() {
+        use crate::fault::{Delay, HopProfile, Loss};
+
+        let args = Args {
+            target: "192.0.2.1".to_string(),
+            count: Some(3),
+            interval: 20,
+            max_hops: 10,
+            report: true,
+            numeric: true,
+            sparkline_scale: crate::SparklineScale::Logarithmic,
+            ema_alpha: 0.1,
+            fields: None,
+            show_all: false,
+            simulate: true,
+            protocol: crate::args::ProbeProtocol::Icmp,
+            force_simulate: false,
+            timing: false,
+            quiet: false,
+            loss_threshold: None,
+            latency_threshold: None,
+            unfocused_redraw: None,
+            unfocused_interval: None,
+            netns: None,
+            fwmark: None,
+            rtt_unit: crate::RttUnit::Ms,
+            stream_socket: None,
+            scenario: None,
+            config: None,
+            baseline: false,
+            paris: false,
+            multipath: None,
+            tos: None,
+            psize: None,
+            bitpattern: 0,
+            address: None,
+            interface: None,
+            io_uring: false,
+            first_ttl: 1,
+            port: None,
+            dual_stack: false,
+            dns_query: None,
+            vrf: None,
+            keep_privileges: false,
+            geoip: None,
+            whois: false,
+            dns_server: None,
+            dns_workers: 4,
+            dns_timeout: 3000,
+            save: None,
+            load: None,
+            burst: 1,
+            max_unknown: 5,
+            grace_period: 0.1,
+            on_alert: None,
+            no_bell: false,
+            no_images: false,
+            spread: 0,
+            jitter: false,
+            adaptive: false,
+            xml: false,
+            jsonl: None,
+            graphite: None,
+            raw: false,
+            split: false,
+            html: None,
+            record: None,
+            graph: false,
+            report_every: None,
+            report_file: None,
+            order: None,
+            probe_log: None,
+            merge: Vec::new(),
+        };
+        let mut session = MtrSession::new(args).await.unwrap();
+
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(2))).with_loss(Loss::Random(1.0)),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(3))),
+        ])
+        .with_duplicates(1.0);
+        let mut reached = session.on_target_reached();
+        let mut rounds = session.on_round_complete();
+        session
+            .run_trace_with_transport(FaultTransport::new(scenario))
+            .await
+            .unwrap();
+
+        let event = reached.try_recv().unwrap();
+        assert_eq!((event.hop, event.rtt), (3, Duration::from_millis(3)));
+        assert!(reached.try_recv().is_err()); // Only when it first answers
+        let rounds: Vec<usize> = std::iter::from_fn(|| rounds.try_recv().ok()).map(|event| event.round).collect();
+        assert_eq!(rounds, [1, 2, 3]);
+
+        assert_eq!(session.stats.hop(0).received, 3); // Duplicates not double counted
+        assert_eq!(session.stats.hop(0).addr, Some("10.255.0.1".parse().unwrap()));
+        assert_eq!(session.stats.hop(1).sent, 3);
+        assert_eq!(session.stats.hop(1).received, 0);
+        assert_eq!(session.stats.hop(2).addr, Some(session.target_addr));
+        assert_eq!(session.stats.hop(2).avg_rtt, Some(Duration::from_millis(3)));
+        assert_eq!(session.stats.hop(2).reply_ttl, Some(62));
+        assert_eq!(session.stats.hop(1).reply_ttl, None);
+    }
+
+    #[tokio::test]
+    async fn test_trace_with_first_ttl() {
+        use crate::fault::{Delay, HopProfile};
+        use clap::Parser;
+
+        let args = Args::try_parse_from([
+            "mtr-ng", "-f", "2", "-M", "10", "-c", "2", "-i", "20", "-n", "192.0.2.1",
+        ])
+        .unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        assert_eq!(session.stats.len(), 9);
+        assert_eq!(session.stats.hop(0).hop, 2);
+
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(2))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(3))),
+        ]);
+        session
+            .run_trace_with_transport(FaultTransport::new(scenario))
+            .await
+            .unwrap();
+
+        // Index 0 is probed with TTL 2, so the first hop never shows up
+        assert_eq!(session.stats.hop(0).addr, Some("10.255.0.2".parse().unwrap()));
+        assert_eq!(session.stats.hop(0).avg_rtt, Some(Duration::from_millis(2)));
+        assert_eq!(session.stats.hop(1).addr, Some(session.target_addr));
+
+        let args = Args::try_parse_from(["mtr-ng", "-f", "12", "-M", "10", "192.0.2.1"]).unwrap();
+        assert!(MtrSession::new(args).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_trace_with_burst() {
+        use crate::fault::{Delay, HopProfile};
+        use crate::hop_stats::PacketOutcome;
+        use clap::Parser;
+
+        let args = Args::try_parse_from([
+            "mtr-ng", "--burst", "4", "-c", "2", "-i", "20", "-n", "192.0.2.1",
+        ])
+        .unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(2))),
+        ]);
+        session
+            .run_trace_with_transport(FaultTransport::new(scenario))
+            .await
+            .unwrap();
+
+        // Every probe of a burst is accounted for, but the hop sees two rounds
+        let hop = session.stats.hop(0);
+        assert_eq!((hop.sent, hop.received), (8, 8));
+        assert_eq!(hop.packet_history.len(), 8);
+        assert!(hop.packet_history.iter().all(|outcome| matches!(outcome, PacketOutcome::Received(_))));
+        assert_eq!(hop.rounds_completed, 1); // The last round is still open
+    }
+
+    #[tokio::test]
+    async fn test_trace_with_spread() {
+        use crate::fault::{Delay, HopProfile};
+        use clap::Parser;
+
+        let args = Args::try_parse_from([
+            "mtr-ng", "--burst", "3", "--spread", "100", "-c", "2", "-i", "60", "-n", "192.0.2.1",
+        ])
+        .unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(2))),
+        ]);
+        let start = Instant::now();
+        session
+            .run_trace_with_transport(FaultTransport::new(scenario))
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(60));
+
+        // Spacing the probes out loses none of them
+        for index in 0..2 {
+            let hop = session.stats.hop(index);
+            assert_eq!((hop.sent, hop.received), (6, 6));
+        }
+    }
+
+    #[test]
+    fn test_schedule_batches() {
+        // No window: the round goes out as one batch
+        assert_eq!(schedule_batches(6, Duration::ZERO, false), vec![(Duration::ZERO, 0..6)]);
+
+        let window = spread_window(Duration::from_millis(1000), 40);
+        assert_eq!(window, Duration::from_millis(400));
+        let even = schedule_batches(4, window, false);
+        let offsets: Vec<u64> = even.iter().map(|(offset, _)| offset.as_millis() as u64).collect();
+        assert_eq!(offsets, vec![0, 100, 200, 300]);
+        assert_eq!(even[3].1, 3..4);
+
+        // Jittered times stay inside the window, in order, covering every probe
+        let jittered = schedule_batches(50, window, true);
+        assert!(jittered.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(jittered.iter().all(|(offset, _)| *offset < window));
+        assert_eq!(jittered.iter().map(|(_, range)| range.len()).sum::<usize>(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_probe_range_follows_discovery() {
+        use crate::fault::{Delay, HopProfile, Loss};
+        use clap::Parser;
+
+        // Only the first hop answers; everything further stays silent
+        let args = Args::try_parse_from(["mtr-ng", "-U", "2", "-G", "0.1", "-c", "3", "-i", "20", "-n", "192.0.2.1"]).unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(2))).with_loss(Loss::Random(1.0)),
+        ]);
+        session
+            .run_trace_with_transport(FaultTransport::new(scenario))
+            .await
+            .unwrap();
+        let sent: Vec<usize> = session.stats.snapshot_range(5).iter().map(|hop| hop.sent).collect();
+        assert_eq!(sent, [3, 3, 2, 0, 0]);
+        assert_eq!(session.display_hop_count(), 3);
+
+        // Probing stops at the target once it answers
+        let args = Args::try_parse_from(["mtr-ng", "-c", "3", "-i", "20", "-n", "192.0.2.1"]).unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(2))),
+        ]);
+        session
+            .run_trace_with_transport(FaultTransport::new(scenario))
+            .await
+            .unwrap();
+        let sent: Vec<usize> = session.stats.snapshot_range(6).iter().map(|hop| hop.sent).collect();
+        assert_eq!(sent, [3, 3, 0, 0, 0, 0]); // Probed once, then retired
+        assert_eq!(session.display_hop_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shorter_route_retires_trailing_hops() {
+        use clap::Parser;
+
+        let args = Args::try_parse_from(["mtr-ng", "-n", "192.0.2.1"]).unwrap();
+        let session = MtrSession::new(args).await.unwrap();
+        let mut changes = session.on_path_change();
+        let target = session.target_addr;
+        let reply = |session: &MtrSession, index: usize, addr: IpAddr| {
+            {
+                let mut hop = session.stats.hop_mut(index);
+                hop.increment_sent();
+                hop.add_rtt_from_addr(addr, Duration::from_millis(5));
+            }
+            session.track_route(index, addr);
+        };
+
+        for index in 0..4 {
+            reply(&session, index, IpAddr::from([10, 0, 0, index as u8 + 1]));
+        }
+        reply(&session, 4, target);
+        assert_eq!(session.destination_index(), Some(4));
+        assert_eq!(session.probe_range(), 5);
+
+        // The target now answers two hops earlier
+        reply(&session, 2, target);
+        assert_eq!(session.destination_index(), Some(2));
+        assert_eq!(session.destination_ttl(), Some(3));
+        assert_eq!(session.probe_range(), 3);
+        assert_eq!(session.stats.hop(3).sent, 0);
+        assert_eq!(session.stats.hop(4).sent, 0);
+        assert_eq!(session.stats.hop(2).route_changes, 1);
+        let change = changes.try_recv().unwrap();
+        assert_eq!((change.hop, change.to), (3, target));
+
+        // Now and then a probe checks one hop further, without showing it
+        assert_eq!(session.plan_round(0), 3);
+        assert_eq!(session.plan_round(VERIFY_INTERVAL - 1), 4);
+        assert_eq!(session.stats.num_hosts(), 3);
+
+        // A router answering there means the path grew again
+        reply(&session, 3, IpAddr::from([10, 0, 0, 9]));
+        assert_eq!(session.destination_index(), None);
+        assert!(session.probe_range() > 4);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_probes_in_flight() {
+        use crate::fault::{Delay, HopProfile};
+        use clap::Parser;
+
+        // No count: only the shutdown ends this trace
+        let args = Args::try_parse_from(["mtr-ng", "-i", "20", "-n", "192.0.2.1"]).unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(80))),
+        ]);
+        let cancel = session.cancel.clone();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+        time::timeout(Duration::from_secs(5), session.run_trace_with_transport(FaultTransport::new(scenario)))
+            .await
+            .expect("shutdown ends the trace")
+            .unwrap();
+        assert_eq!(session.outstanding_probes(), 0);
+        let hop = session.stats.hop(1);
+        assert_eq!(hop.sent, hop.received);
+    }
+
+    #[tokio::test]
+    async fn test_grace_period_collects_late_replies() {
+        use crate::fault::{Delay, HopProfile};
+        use clap::Parser;
+
+        // Replies take longer than the interval, so only the grace period sees them
+        for (grace, received) in [("0", 0), ("1", 1)] {
+            let args = Args::try_parse_from(["mtr-ng", "-G", grace, "-c", "1", "-i", "20", "-n", "192.0.2.1"]).unwrap();
+            let mut session = MtrSession::new(args).await.unwrap();
+            let scenario = Scenario::new(vec![HopProfile::new(Delay::Fixed(Duration::from_millis(150)))]);
+            session
+                .run_trace_with_transport(FaultTransport::new(scenario))
+                .await
+                .unwrap();
+            assert_eq!(session.stats.hop(0).received, received, "grace period {}", grace);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trace_ipv6_target() {
+        use crate::fault::{Delay, HopProfile};
+        use clap::Parser;
+
+        let args = Args::try_parse_from(["mtr-ng", "-c", "2", "-i", "20", "-n", "2001:db8::1"]).unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        let scenario = Scenario::new(vec![
+            HopProfile::new(Delay::Fixed(Duration::from_millis(1))),
+            HopProfile::new(Delay::Fixed(Duration::from_millis(2))),
+        ]);
+        session
+            .run_trace_with_transport(FaultTransport::new(scenario))
+            .await
+            .unwrap();
+
+        assert_eq!(session.stats.hop(0).addr, Some("fd00::1".parse().unwrap()));
+        assert_eq!(session.stats.hop(1).addr, Some(session.target_addr));
+        assert_eq!(session.stats.hop(1).received, 2);
+    }
+
+    #[tokio::test]
+    async fn test_preopen_failure_is_reported() {
+        use clap::Parser;
+
+        let args = Args::try_parse_from(["mtr-ng", "-n", "-c", "1", "192.0.2.1"]).unwrap();
+        let mut session = MtrSession::new(args).await.unwrap();
+        session.set_probe_engine(Err(anyhow!("raw socket: Operation not permitted")));
+        let err = session.run_trace().await.unwrap_err();
+        assert!(format!("{:#}", err).contains("raw socket: Operation not permitted"));
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_needs_hostname() {
+        use clap::Parser;
+
+        let args = Args::try_parse_from(["mtr-ng", "--dual-stack", "192.0.2.1"]).unwrap();
+        assert!(MtrSession::new_dual_stack(args).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_rejects_graphite() {
+        use clap::Parser;
+
+        let args =
+            Args::try_parse_from(["mtr-ng", "--dual-stack", "--graphite", "carbon:2003", "example.com"]).unwrap();
+        let err = MtrSession::new_dual_stack(args).await.err().unwrap();
+        assert!(err.to_string().contains("--graphite"));
+    }
 }
 
@@ -0,0 +1,97 @@
+//! Split output (-p), mtr's compact per-hop lines for external UIs
+//!
+//! After every round each hop whose figures changed gets one line:
+//!
+//! `<ttl> <host> <loss> <received> <sent> <best> <avg> <worst>`
+//!
+//! with the loss in thousandths of a percent and the RTTs in whole
+//! milliseconds, as mtr prints them. A hop that hasn't answered is
+//! `<ttl> ???`. Unchanged hops print nothing, so a log of the output is a
+//! diff of the path over time.
+
+use crate::{utils, HopStats};
+use std::{collections::HashMap, io::Write, sync::Mutex, time::Duration};
+use tracing::debug;
+
+#[derive(Debug, Default)]
+pub struct SplitWriter {
+    lines: Mutex<HashMap<u8, String>>, // Last line printed for each hop, by TTL
+}
+
+impl SplitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Print the hops that changed since the last round
+    pub fn round(&self, hops: &[HopStats], numeric: bool) {
+        let out = self.changed_lines(hops, numeric);
+        if out.is_empty() {
+            return;
+        }
+        let mut stdout = std::io::stdout().lock();
+        if let Err(e) = stdout.write_all(out.as_bytes()).and_then(|_| stdout.flush()) {
+            debug!("Failed to write split output: {}", e);
+        }
+    }
+
+    fn changed_lines(&self, hops: &[HopStats], numeric: bool) -> String {
+        let mut printed = self.lines.lock().unwrap();
+        let mut out = String::new();
+        for hop in hops.iter().filter(|hop| hop.sent > 0) {
+            let line = hop_line(hop, numeric);
+            if printed.get(&hop.hop) != Some(&line) {
+                out.push_str(&format!("{} {}\n", hop.hop, line));
+                printed.insert(hop.hop, line);
+            }
+        }
+        out
+    }
+}
+
+fn hop_line(hop: &HopStats, numeric: bool) -> String {
+    let Some(addr) = hop.addr else {
+        return "???".to_string();
+    };
+    let host = match hop.hostname {
+        Some(ref hostname) if !numeric => hostname.clone(),
+        _ => addr.to_string(),
+    };
+    let ms = |rtt: Option<Duration>| rtt.map_or(0, utils::time::duration_to_ms_u64);
+    format!(
+        "{} {} {} {} {} {} {}",
+        host,
+        (hop.loss_percent * 1000.0).round() as u64,
+        hop.received,
+        hop.sent,
+        ms(hop.best_rtt),
+        ms(hop.avg_rtt),
+        ms(hop.worst_rtt),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_changed_hops_are_printed() {
+        let split = SplitWriter::new();
+        let mut hops = vec![HopStats::new(1), HopStats::new(2)];
+        hops[0].addr = Some("192.0.2.1".parse().unwrap());
+        hops[0].hostname = Some("gw.example.net".to_string());
+        for ms in [10, 30] {
+            hops[0].increment_sent();
+            hops[0].add_rtt(Duration::from_millis(ms));
+        }
+        hops[1].increment_sent();
+        hops[1].add_timeout();
+
+        assert_eq!(split.changed_lines(&hops, false), "1 gw.example.net 0 2 2 10 20 30\n2 ???\n");
+        assert_eq!(split.changed_lines(&hops, false), "");
+
+        hops[0].increment_sent();
+        hops[0].add_timeout();
+        assert_eq!(split.changed_lines(&hops, true), "1 192.0.2.1 33333 2 3 10 20 30\n");
+    }
+}
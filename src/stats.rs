@@ -0,0 +1,198 @@
+//! Hop statistics shared between the probe path and its readers
+//!
+//! A session's configuration doesn't change once it is running, so only the
+//! statistics need to be shared, and they sit behind one lock per hop rather
+//! than a lock around the whole session. Recording a reply locks the hop it
+//! belongs to; the UI copies the table out with `snapshot`, holding each hop
+//! only while it is cloned, and renders from the copy without holding
+//! anything.
+
+use crate::{route::RouteChange, route::RouteHistory, HopStats};
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::Instant,
+};
+
+/// Guesses how many hops to show before the first round has planned a range
+const INITIAL_HOSTS: usize = 10;
+
+#[derive(Debug)]
+pub struct SharedStats {
+    hops: Vec<RwLock<HopStats>>,
+    route_history: RwLock<RouteHistory>,
+    num_hosts: AtomicUsize,  // Hops in the probed range, 0 for every probed hop
+    rounds: AtomicUsize,     // Rounds completed since the start or the last reset
+    started: Mutex<Instant>, // When the statistics started, or were last reset
+}
+
+impl SharedStats {
+    pub fn new(hops: Vec<HopStats>, route_history: RouteHistory) -> Self {
+        Self {
+            hops: hops.into_iter().map(RwLock::new).collect(),
+            route_history: RwLock::new(route_history),
+            num_hosts: AtomicUsize::new(INITIAL_HOSTS),
+            rounds: AtomicUsize::new(0),
+            started: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Number of hops between the first TTL and --max-hops; never changes
+    pub fn len(&self) -> usize {
+        self.hops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hops.is_empty()
+    }
+
+    /// Read one hop; panics if `index` is out of range, like indexing
+    pub fn hop(&self, index: usize) -> RwLockReadGuard<'_, HopStats> {
+        self.hops[index].read().unwrap()
+    }
+
+    /// Update one hop; panics if `index` is out of range, like indexing
+    pub fn hop_mut(&self, index: usize) -> RwLockWriteGuard<'_, HopStats> {
+        self.hops[index].write().unwrap()
+    }
+
+    /// A copy of every hop, taken one hop at a time
+    pub fn snapshot(&self) -> Vec<HopStats> {
+        self.hops.iter().map(|hop| hop.read().unwrap().clone()).collect()
+    }
+
+    /// A copy of the first `count` hops
+    pub fn snapshot_range(&self, count: usize) -> Vec<HopStats> {
+        self.hops
+            .iter()
+            .take(count)
+            .map(|hop| hop.read().unwrap().clone())
+            .collect()
+    }
+
+    /// Index of the last hop matching `predicate`, checking one hop at a time
+    pub fn rposition(&self, predicate: impl Fn(&HopStats) -> bool) -> Option<usize> {
+        self.hops.iter().rposition(|hop| predicate(&hop.read().unwrap()))
+    }
+
+    /// Index of the first hop matching `predicate`, checking one hop at a time
+    pub fn position(&self, predicate: impl Fn(&HopStats) -> bool) -> Option<usize> {
+        self.hops.iter().position(|hop| predicate(&hop.read().unwrap()))
+    }
+
+    pub fn route_history(&self) -> RwLockReadGuard<'_, RouteHistory> {
+        self.route_history.read().unwrap()
+    }
+
+    pub fn record_route_change(&self, change: RouteChange) {
+        self.route_history.write().unwrap().record(change);
+    }
+
+    pub fn num_hosts(&self) -> usize {
+        self.num_hosts.load(Ordering::Relaxed)
+    }
+
+    pub fn set_num_hosts(&self, count: usize) {
+        self.num_hosts.store(count, Ordering::Relaxed);
+    }
+
+    /// Number of hops worth displaying: the discovered path length, or every probed hop
+    pub fn display_count(&self) -> usize {
+        let num_hosts = self.num_hosts();
+        if num_hosts > 0 {
+            num_hosts.min(self.len())
+        } else {
+            self.rposition(|hop| hop.sent > 0).map(|i| i + 1).unwrap_or(0)
+        }
+    }
+
+    /// Count a completed round
+    pub fn complete_round(&self) {
+        self.rounds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Rounds completed since the statistics started
+    pub fn rounds(&self) -> usize {
+        self.rounds.load(Ordering::Relaxed)
+    }
+
+    /// Name every hop `addr` answers for, as its responder or an alternate one
+    pub fn name_responder(&self, addr: IpAddr, hostname: &str) {
+        for hop in &self.hops {
+            let mut hop = hop.write().unwrap();
+            if hop.addr == Some(addr) || hop.alternate_paths.contains_key(&addr) {
+                hop.set_hostname_for_addr(addr, hostname.to_string());
+            }
+        }
+    }
+
+    /// When the statistics started, so rates can be taken over the same span as the counts
+    pub fn started(&self) -> Instant {
+        *self.started.lock().unwrap()
+    }
+
+    /// Start every hop over, keeping its TTL and smoothing factor
+    pub fn reset(&self) {
+        for hop in &self.hops {
+            let mut hop = hop.write().unwrap();
+            let mut fresh = HopStats::new(hop.hop);
+            fresh.set_ema_alpha(hop.ema_alpha);
+            *hop = fresh;
+        }
+        *self.route_history.write().unwrap() = RouteHistory::default();
+        self.rounds.store(0, Ordering::Relaxed);
+        *self.started.lock().unwrap() = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_snapshot_and_reset() {
+        let mut hops: Vec<HopStats> = (1..=3).map(HopStats::new).collect();
+        hops[2].set_ema_alpha(0.3);
+        let stats = SharedStats::new(hops, RouteHistory::default());
+
+        {
+            let mut hop = stats.hop_mut(1);
+            hop.increment_sent();
+            hop.add_rtt(Duration::from_millis(7));
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot[1].received, 1);
+        assert_eq!(stats.rposition(|hop| hop.received > 0), Some(1));
+
+        // A snapshot doesn't follow later updates
+        stats.hop_mut(1).add_timeout();
+        assert_eq!(snapshot[1].sent, 1);
+
+        stats.complete_round();
+        assert_eq!(stats.rounds(), 1);
+
+        stats.reset();
+        assert_eq!(stats.rounds(), 0);
+        assert_eq!(stats.hop(1).sent, 0);
+        assert_eq!(stats.hop(1).hop, 2);
+        assert_eq!(stats.hop(2).ema_alpha, 0.3);
+        assert_eq!(stats.snapshot_range(2).len(), 2);
+    }
+
+    #[test]
+    fn test_name_responder() {
+        let stats = SharedStats::new((1..=3).map(HopStats::new).collect(), RouteHistory::default());
+        let router: IpAddr = "192.0.2.1".parse().unwrap();
+        stats.hop_mut(0).add_rtt_from_addr(router, Duration::from_millis(3));
+        stats.hop_mut(2).add_rtt_from_addr(router, Duration::from_millis(9));
+
+        stats.name_responder(router, "r1.example.");
+        assert_eq!(stats.hop(0).hostname.as_deref(), Some("r1.example."));
+        assert_eq!(stats.hop(1).hostname, None);
+        assert_eq!(stats.hop(2).hostname.as_deref(), Some("r1.example."));
+    }
+}
@@ -0,0 +1,236 @@
+//! NDJSON event stream
+//!
+//! The session publishes one JSON object per line describing the trace as it
+//! progresses, in the schema of [`crate::output`]. Events fan out over a
+//! broadcast channel so any number of consumers (currently the Unix domain
+//! socket server) can follow along without slowing down the probe loop.
+//! `--jsonl` writes the same events to stdout instead, plus one per probe
+//! result if asked for.
+
+use crate::{args::JsonlMode, output::Event};
+use anyhow::{bail, Context, Result};
+use std::{io::Write, os::unix::fs::FileTypeExt, path::Path, sync::Arc};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{UnixListener, UnixStream},
+    sync::broadcast::{self, error::RecvError},
+};
+use tracing::{debug, info, warn};
+
+/// Events buffered per client before slow readers start losing them
+const STREAM_CAPACITY: usize = 256;
+
+/// Publisher side of the event stream
+#[derive(Debug, Clone)]
+pub struct EventStream {
+    tx: broadcast::Sender<Arc<str>>,
+}
+
+impl EventStream {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(STREAM_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.tx.subscribe()
+    }
+
+    /// Publish an event to all current subscribers
+    pub fn publish(&self, event: &Event) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        match event.to_ndjson() {
+            Ok(line) => {
+                let _ = self.tx.send(line.into());
+            }
+            Err(e) => warn!("Failed to serialize stream event: {}", e),
+        }
+    }
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Events written to stdout as they happen (--jsonl)
+///
+/// Lines are written and flushed straight away, so a pipeline reading them
+/// sees each one as soon as the session does.
+#[derive(Debug, Clone, Copy)]
+pub struct JsonlWriter {
+    mode: JsonlMode,
+}
+
+impl JsonlWriter {
+    pub fn new(mode: JsonlMode) -> Self {
+        Self { mode }
+    }
+
+    /// Write `event` if it belongs in this output: probe events in probe mode,
+    /// round events in round mode, and every other event in both
+    pub fn write(&self, event: &Event) {
+        if !self.wants(event) {
+            return;
+        }
+        match event.to_ndjson() {
+            Ok(line) => {
+                let mut stdout = std::io::stdout().lock();
+                if let Err(e) = stdout.write_all(line.as_bytes()).and_then(|_| stdout.flush()) {
+                    debug!("Failed to write JSON line: {}", e);
+                }
+            }
+            Err(e) => warn!("Failed to serialize JSON line: {}", e),
+        }
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        match event {
+            Event::Probe { .. } => self.mode == JsonlMode::Probe,
+            Event::Round { .. } => self.mode == JsonlMode::Round,
+            _ => true,
+        }
+    }
+}
+
+/// Serve the event stream to clients connecting on a Unix domain socket
+///
+/// Binds immediately and spawns the accept loop. A socket left behind by a
+/// process that is gone is replaced; one that still accepts connections, or
+/// anything that isn't a socket, is an error. Each client first receives
+/// `greeting`, then every event published from that point on.
+pub fn serve_unix(path: &Path, events: EventStream, greeting: Event) -> Result<()> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path.display());
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("Stream socket {} is already in use", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind stream socket {}", path.display()))?;
+    let greeting = greeting.to_ndjson()?;
+    info!("Streaming events on {}", path.display());
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((client, _)) => {
+                    debug!("Stream client connected");
+                    tokio::spawn(serve_client(client, greeting.clone(), events.subscribe()));
+                }
+                Err(e) => {
+                    warn!("Stream socket accept failed: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Remove the socket `serve_unix` bound, once the session is over
+pub fn remove_unix(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => debug!("Removed stream socket {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove stream socket {}: {}", path.display(), e),
+    }
+}
+
+async fn serve_client(mut client: UnixStream, greeting: String, mut rx: broadcast::Receiver<Arc<str>>) {
+    if client.write_all(greeting.as_bytes()).await.is_err() {
+        return;
+    }
+    loop {
+        match rx.recv().await {
+            Ok(line) => {
+                if client.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Stream client fell behind, dropped {} events", skipped);
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    debug!("Stream client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    #[test]
+    fn test_jsonl_modes() {
+        let probe = Event::probe("192.0.2.9".parse().unwrap(), 2, None, None);
+        let value: serde_json::Value = serde_json::from_str(&probe.to_ndjson().unwrap()).unwrap();
+        assert_eq!(value["event"], "probe");
+        assert!(value["rtt_ms"].is_null()); // A timeout
+
+        let round = Event::round(1, &[]);
+        let per_probe = JsonlWriter::new(JsonlMode::Probe);
+        let per_round = JsonlWriter::new(JsonlMode::Round);
+        assert!(per_probe.wants(&probe) && !per_probe.wants(&round));
+        assert!(per_round.wants(&round) && !per_round.wants(&probe));
+        assert!(per_probe.wants(&Event::stop()) && per_round.wants(&Event::stop()));
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_stream() {
+        let path = std::env::temp_dir().join(format!("mtr-ng-stream-{}.sock", std::process::id()));
+        let events = EventStream::new();
+        let target: IpAddr = "192.0.2.1".parse().unwrap();
+        serve_unix(&path, events.clone(), Event::start("example", target)).unwrap();
+
+        let client = UnixStream::connect(&path).await.unwrap();
+        let mut lines = BufReader::new(client).lines();
+        let greeting = lines.next_line().await.unwrap().unwrap();
+        assert!(greeting.contains("\"event\":\"start\""));
+
+        events.publish(&Event::stop());
+        let stop = lines.next_line().await.unwrap().unwrap();
+        assert!(stop.contains("\"event\":\"stop\""));
+
+        remove_unix(&path);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_in_use() {
+        let path = std::env::temp_dir().join(format!("mtr-ng-stream-{}-busy.sock", std::process::id()));
+        let target: IpAddr = "192.0.2.1".parse().unwrap();
+        serve_unix(&path, EventStream::new(), Event::start("example", target)).unwrap();
+        let err = serve_unix(&path, EventStream::new(), Event::start("example", target)).unwrap_err();
+        assert!(err.to_string().contains("already in use"));
+
+        // A socket nobody listens on any more is taken over
+        let stale = std::env::temp_dir().join(format!("mtr-ng-stream-{}-stale.sock", std::process::id()));
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        serve_unix(&stale, EventStream::new(), Event::start("example", target)).unwrap();
+
+        remove_unix(&path);
+        remove_unix(&stale);
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_keeps_other_files() {
+        let path = std::env::temp_dir().join(format!("mtr-ng-stream-{}.txt", std::process::id()));
+        std::fs::write(&path, "not a socket").unwrap();
+        let target: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(serve_unix(&path, EventStream::new(), Event::start("example", target)).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,204 @@
+//! systemd service integration
+//!
+//! Implements the `sd_notify` datagram protocol so mtr-ng can run as a
+//! `Type=notify` service. READY is signalled once the probe engine is up, and
+//! WATCHDOG keep-alives are sent from the probe loop itself: if the engine
+//! hangs, the pings stop and systemd restarts the unit instead of letting it
+//! report stale data.
+
+use std::{
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+/// Handle to the systemd notification socket
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    path: PathBuf,
+    watchdog_interval: Option<Duration>,
+    last_ping: Mutex<Option<Instant>>,
+}
+
+impl Notifier {
+    /// Create a notifier from the `NOTIFY_SOCKET` / `WATCHDOG_USEC` environment
+    ///
+    /// Returns `None` when not running under systemd (or when the watchdog is
+    /// addressed to a different process via `WATCHDOG_PID`).
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+
+        let watchdog_for_us = std::env::var("WATCHDOG_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .map(|pid| pid == std::process::id())
+            .unwrap_or(true);
+
+        let watchdog_interval = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && watchdog_for_us)
+            .map(Duration::from_micros);
+
+        match Self::new(PathBuf::from(path), watchdog_interval) {
+            Ok(notifier) => {
+                info!(
+                    "systemd notification enabled (watchdog: {:?})",
+                    notifier.watchdog_interval
+                );
+                Some(notifier)
+            }
+            Err(e) => {
+                warn!("Failed to open systemd notify socket: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Create a notifier for an explicit socket path
+    pub fn new(path: PathBuf, watchdog_interval: Option<Duration>) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            path,
+            watchdog_interval,
+            last_ping: Mutex::new(None),
+        })
+    }
+
+    /// Configured watchdog timeout, if the unit has `WatchdogSec=` set
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    /// Tell systemd the service finished starting up
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    /// Update the free-form status line shown by `systemctl status`
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={}", status));
+    }
+
+    /// Tell systemd the service is shutting down
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// Send a watchdog keep-alive
+    ///
+    /// Called from the probe loop on every unit of progress; pings are rate
+    /// limited to half the watchdog interval so hot loops don't flood systemd.
+    pub fn watchdog(&self) {
+        let Some(interval) = self.watchdog_interval else {
+            return;
+        };
+
+        let mut last_ping = self.last_ping.lock().unwrap();
+        if let Some(last) = *last_ping {
+            if last.elapsed() < interval / 2 {
+                return;
+            }
+        }
+        *last_ping = Some(Instant::now());
+        drop(last_ping);
+
+        self.notify("WATCHDOG=1");
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.send(state) {
+            debug!("sd_notify '{}' failed: {}", state, e);
+        }
+    }
+
+    fn send(&self, state: &str) -> std::io::Result<()> {
+        let path = self.path.as_os_str().as_encoded_bytes();
+
+        // Abstract namespace sockets are announced with a leading '@'
+        if let Some(name) = path.strip_prefix(b"@") {
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                self.socket.send_to_addr(state.as_bytes(), &addr)?;
+                return Ok(());
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = name;
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "abstract sockets are only supported on Linux",
+                ));
+            }
+        }
+
+        self.socket.send_to(state.as_bytes(), &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound_socket(name: &str) -> (UnixDatagram, PathBuf) {
+        let path = std::env::temp_dir().join(format!("mtr-ng-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        (socket, path)
+    }
+
+    #[test]
+    fn test_notifier_sends_ready_and_status() {
+        let (receiver, path) = bound_socket("notify");
+        let notifier = Notifier::new(path.clone(), None).unwrap();
+
+        let mut buf = [0u8; 128];
+        notifier.ready();
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        notifier.status("Round 3");
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STATUS=Round 3");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_watchdog_is_rate_limited() {
+        let (receiver, path) = bound_socket("watchdog");
+        receiver.set_nonblocking(true).unwrap();
+        let notifier = Notifier::new(path.clone(), Some(Duration::from_secs(60))).unwrap();
+
+        notifier.watchdog();
+        notifier.watchdog();
+
+        let mut buf = [0u8; 128];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+        assert!(receiver.recv(&mut buf).is_err()); // Second ping suppressed
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_watchdog_disabled_without_interval() {
+        let (receiver, path) = bound_socket("nowatchdog");
+        receiver.set_nonblocking(true).unwrap();
+        let notifier = Notifier::new(path.clone(), None).unwrap();
+
+        notifier.watchdog();
+
+        let mut buf = [0u8; 128];
+        assert!(receiver.recv(&mut buf).is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -3,11 +3,176 @@
 //! This module handles all keyboard input events and user interactions
 //! for the mtr-ng terminal user interface.
 
-use crate::{HopStats, MtrSession};
-use crossterm::event::{KeyCode, KeyModifiers};
-use std::sync::{Arc, Mutex};
+use crate::args::Column;
+use crate::report::{self, ExportFormat};
+use crate::MtrSession;
+use crossterm::event::{KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::Position;
+use std::{path::Path, sync::Arc, time::Duration};
 
-use super::state::UiState;
+use super::state::{ColumnWidths, TableLayout, UiState};
+
+/// Table rows one notch of the scroll wheel moves
+const SCROLL_ROWS: isize = 3;
+
+/// Cells `[ ]` and `{ }` resize a column by
+const WIDTH_STEP: i16 = 2;
+
+/// Probe intervals `+` and `-` step through, in milliseconds
+const INTERVAL_STEPS: [u64; 11] = [100, 200, 250, 500, 1000, 2000, 5000, 10000, 15000, 30000, 60000];
+
+/// What a key does outside popups
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Quit,
+    Reset,
+    ToggleScale,
+    CycleColors,
+    ToggleColumn,
+    ColumnSelector,
+    CycleVisualization,
+    ToggleHostnames,
+    ToggleTree,
+    CycleRttUnit,
+    Help,
+    SelectHop,
+    HopDetail,
+    HopGraph,
+    CollapsePaths,
+    CollapseAllPaths,
+    Freeze,
+    Faster,
+    Slower,
+    Sort,
+    TtlOrder,
+    RouteEvents,
+    Snapshot,
+    NarrowHost,
+    WidenHost,
+    NarrowGraph,
+    WidenGraph,
+    FitColumns,
+}
+
+/// Keys bound to an action, with the line the help overlay shows for them
+pub struct Binding {
+    pub keys: &'static [KeyCode],
+    pub action: Action,
+    pub description: &'static str,
+}
+
+/// Every key of the main view, in the order the help lists them
+pub const KEYMAP: &[Binding] = &[
+    Binding { keys: &[KeyCode::Char('q'), KeyCode::Esc], action: Action::Quit, description: "Quit" },
+    Binding { keys: &[KeyCode::Char('?')], action: Action::Help, description: "Toggle this help" },
+    Binding { keys: &[KeyCode::Char('r')], action: Action::Reset, description: "Reset statistics" },
+    Binding { keys: &[KeyCode::Up, KeyCode::Down], action: Action::SelectHop, description: "Select hop" },
+    Binding {
+        keys: &[KeyCode::Enter],
+        action: Action::HopDetail,
+        description: "Show details of the selected hop",
+    },
+    Binding {
+        keys: &[KeyCode::Char('g')],
+        action: Action::HopGraph,
+        description: "Chart the selected hop full screen",
+    },
+    Binding {
+        keys: &[KeyCode::Char('p')],
+        action: Action::CollapsePaths,
+        description: "Collapse/expand the selected hop's alternate paths",
+    },
+    Binding {
+        keys: &[KeyCode::Char('P')],
+        action: Action::CollapseAllPaths,
+        description: "Collapse/expand every hop's alternate paths",
+    },
+    Binding {
+        keys: &[KeyCode::Char(' ')],
+        action: Action::Freeze,
+        description: "Freeze the display (probing continues)",
+    },
+    Binding { keys: &[KeyCode::Char('+'), KeyCode::Char('=')], action: Action::Faster, description: "Probe faster" },
+    Binding { keys: &[KeyCode::Char('-')], action: Action::Slower, description: "Probe slower" },
+    Binding {
+        keys: &[KeyCode::Char('<'), KeyCode::Char('>')],
+        action: Action::Sort,
+        description: "Sort by previous/next column",
+    },
+    Binding { keys: &[KeyCode::Char('n')], action: Action::TtlOrder, description: "Back to TTL order" },
+    Binding { keys: &[KeyCode::Char('[')], action: Action::NarrowHost, description: "Narrow the Host column" },
+    Binding { keys: &[KeyCode::Char(']')], action: Action::WidenHost, description: "Widen the Host column" },
+    Binding { keys: &[KeyCode::Char('{')], action: Action::NarrowGraph, description: "Narrow the Graph column" },
+    Binding { keys: &[KeyCode::Char('}')], action: Action::WidenGraph, description: "Widen the Graph column" },
+    Binding {
+        keys: &[KeyCode::Char('\\')],
+        action: Action::FitColumns,
+        description: "Fit Host and Graph to the table again",
+    },
+    Binding {
+        keys: &[KeyCode::Char('s')],
+        action: Action::ToggleScale,
+        description: "Toggle sparkline scale (Linear/Log)",
+    },
+    Binding { keys: &[KeyCode::Char('c')], action: Action::CycleColors, description: "Cycle color modes" },
+    Binding { keys: &[KeyCode::Char('f')], action: Action::ToggleColumn, description: "Toggle column fields" },
+    Binding { keys: &[KeyCode::Char('o')], action: Action::ColumnSelector, description: "Open column selector" },
+    Binding {
+        keys: &[KeyCode::Char('v')],
+        action: Action::CycleVisualization,
+        description: "Cycle graph modes",
+    },
+    Binding {
+        keys: &[KeyCode::Char('h')],
+        action: Action::ToggleHostnames,
+        description: "Toggle hostname display",
+    },
+    Binding {
+        keys: &[KeyCode::Char('u')],
+        action: Action::CycleRttUnit,
+        description: "Cycle RTT units (ms/μs/auto)",
+    },
+    Binding { keys: &[KeyCode::Char('t')], action: Action::ToggleTree, description: "Toggle topology tree view" },
+    Binding { keys: &[KeyCode::Char('e')], action: Action::RouteEvents, description: "Show route change events" },
+    Binding {
+        keys: &[KeyCode::Char('w')],
+        action: Action::Snapshot,
+        description: "Write a snapshot (text/JSON/CSV), w since e shows route events",
+    },
+];
+
+/// Keys of the column selector, as `handle_column_selector_input` reads them
+pub const COLUMN_SELECTOR_KEYS: &[(&str, &str)] = &[
+    ("↑/↓", "Navigate columns"),
+    ("Space", "Toggle column visibility"),
+    ("←/→", "Reorder columns"),
+    ("Shift+↑/↓", "Alternative column reordering"),
+    ("Esc", "Close"),
+];
+
+/// The action bound to a key in [`KEYMAP`]
+pub fn action_for(key_code: KeyCode) -> Option<Action> {
+    KEYMAP
+        .iter()
+        .find(|binding| binding.keys.contains(&key_code))
+        .map(|binding| binding.action)
+}
+
+/// How the help overlay writes a binding's keys, e.g. "q/Esc"
+pub fn key_label(keys: &[KeyCode]) -> String {
+    keys.iter()
+        .map(|key| match key {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Up => "↑".to_string(),
+            KeyCode::Down => "↓".to_string(),
+            KeyCode::Enter => "Enter".to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            other => format!("{:?}", other),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
 
 /// Event handler for processing keyboard input and user interactions
 pub struct EventHandler;
@@ -76,76 +241,236 @@ impl EventHandler {
         }
     }
 
+    /// Handle keyboard input while asking which format to write a snapshot in
+    ///
+    /// `t`, `j` or `c` write the sessions as text, JSON or CSV into the
+    /// current directory; any other key cancels. The outcome is left as a
+    /// notice for the status line.
+    pub fn handle_export_input(&mut self, key_code: KeyCode, ui_state: &mut UiState, sessions: &[Arc<MtrSession>]) {
+        ui_state.toggle_export_prompt();
+        let format = match key_code {
+            KeyCode::Char('t') => ExportFormat::Text,
+            KeyCode::Char('j') => ExportFormat::Json,
+            KeyCode::Char('c') => ExportFormat::Csv,
+            _ => return,
+        };
+        let sessions: Vec<&MtrSession> = sessions.iter().map(|session| session.as_ref()).collect();
+        ui_state.notice = Some(match report::export_snapshot(&sessions, format, Path::new(".")) {
+            Ok(path) => format!("✓ Snapshot written to {}", path.display()),
+            Err(e) => format!("⚠ {:#}", e),
+        });
+    }
+
+    /// Handle keyboard input while the help overlay is open
+    ///
+    /// The arrow and paging keys scroll it and Esc closes it. Returns false for
+    /// any other key, which goes on to `handle_normal_input`.
+    pub fn handle_help_input(&mut self, key_code: KeyCode, ui_state: &mut UiState) -> bool {
+        let lines = super::widgets::help_lines().len();
+        let page = ui_state.help_rows.max(1) as isize;
+        match key_code {
+            KeyCode::Esc => ui_state.toggle_help(),
+            KeyCode::Up => ui_state.scroll_help(-1, lines),
+            KeyCode::Down => ui_state.scroll_help(1, lines),
+            KeyCode::PageUp => ui_state.scroll_help(-page, lines),
+            KeyCode::PageDown => ui_state.scroll_help(page, lines),
+            KeyCode::Home => ui_state.scroll_help(isize::MIN, lines),
+            KeyCode::End => ui_state.scroll_help(isize::MAX, lines),
+            _ => return false,
+        }
+        true
+    }
+
     /// Handle keyboard input during normal operation (non-popup mode)
-    /// 
-    /// Returns true if the application should continue running, false to quit
+    ///
+    /// Keys are looked up in [`KEYMAP`]. Returns true if the application
+    /// should continue running, false to quit
     pub fn handle_normal_input(
         &mut self,
         key_code: KeyCode,
         ui_state: &mut UiState,
-        session: &Arc<Mutex<MtrSession>>,
+        sessions: &[Arc<MtrSession>],
     ) -> bool {
-        match key_code {
-            KeyCode::Char('q') | KeyCode::Esc => {
-                // Quit application
-                false
-            }
-            KeyCode::Char('r') => {
-                // Reset statistics
-                self.reset_statistics(session);
-                true
-            }
-            KeyCode::Char('s') => {
-                // Toggle sparkline scale
-                ui_state.toggle_sparkline_scale();
-                true
+        let Some(action) = action_for(key_code) else {
+            // Unknown key, continue running
+            return true;
+        };
+        match action {
+            Action::Quit => return false,
+            Action::Reset => self.reset_statistics(sessions),
+            Action::ToggleScale => ui_state.toggle_sparkline_scale(),
+            Action::CycleColors => ui_state.cycle_color_mode(),
+            Action::ToggleColumn => ui_state.toggle_column(),
+            Action::ColumnSelector => ui_state.toggle_column_selector(),
+            Action::CycleVisualization => ui_state.toggle_visualization_mode(),
+            Action::ToggleHostnames => ui_state.toggle_hostnames(),
+            Action::ToggleTree => ui_state.toggle_view_mode(),
+            Action::CycleRttUnit => ui_state.cycle_rtt_unit(),
+            Action::Help => ui_state.toggle_help(),
+            Action::SelectHop => ui_state.select_hop(&selectable_hops(sessions, &ui_state.table_layout), key_code == KeyCode::Down),
+            Action::HopDetail => {
+                // Selecting the first hop if none is
+                if ui_state.selected_hop.is_none() {
+                    ui_state.select_hop(&selectable_hops(sessions, &ui_state.table_layout), true);
+                }
+                ui_state.toggle_hop_detail();
             }
-            KeyCode::Char('c') => {
-                // Cycle color mode
-                ui_state.cycle_color_mode();
-                true
+            Action::HopGraph => {
+                if ui_state.selected_hop.is_none() {
+                    ui_state.select_hop(&selectable_hops(sessions, &ui_state.table_layout), true);
+                }
+                ui_state.toggle_hop_graph();
             }
-            KeyCode::Char('f') => {
-                // Toggle column fields
-                ui_state.toggle_column();
-                true
+            Action::CollapsePaths => {
+                // Every hop's when none is selected
+                let ttl = ui_state
+                    .selected_hop
+                    .and_then(|index| sessions.first().map(|session| session.stats.hop(index).hop));
+                match ttl {
+                    Some(ttl) => ui_state.toggle_paths(ttl),
+                    None => ui_state.toggle_all_paths(),
+                }
             }
-            KeyCode::Char('o') => {
-                // Open column selector
-                ui_state.toggle_column_selector();
-                true
+            Action::CollapseAllPaths => ui_state.toggle_all_paths(),
+            Action::Freeze => {
+                ui_state.toggle_frozen(|| sessions.iter().map(|session| session.stats.snapshot()).collect())
             }
-            KeyCode::Char('v') => {
-                // Toggle visualization mode
-                ui_state.toggle_visualization_mode();
-                true
+            Action::Faster => self.step_interval(sessions, true),
+            Action::Slower => self.step_interval(sessions, false),
+            Action::Sort => ui_state.cycle_sort_column(key_code == KeyCode::Char('>')),
+            Action::TtlOrder => ui_state.sort_column = None,
+            Action::RouteEvents => ui_state.toggle_route_events(),
+            Action::Snapshot => ui_state.toggle_export_prompt(),
+            Action::NarrowHost => self.resize_column(ui_state, Column::Host, -WIDTH_STEP),
+            Action::WidenHost => self.resize_column(ui_state, Column::Host, WIDTH_STEP),
+            Action::NarrowGraph => self.resize_column(ui_state, Column::Graph, -WIDTH_STEP),
+            Action::WidenGraph => self.resize_column(ui_state, Column::Graph, WIDTH_STEP),
+            Action::FitColumns => {
+                ui_state.column_widths = ColumnWidths::default();
+                save_column_widths(ui_state);
             }
-            KeyCode::Char('h') => {
-                // Toggle hostnames/IP addresses
-                ui_state.toggle_hostnames();
-                true
+        }
+        true
+    }
+
+    /// Handle a mouse event on the hop table
+    ///
+    /// A click on a row selects its hop and a click on a column header sorts
+    /// by that column (or, clicked again, goes back to TTL order); the wheel
+    /// scrolls. Returns whether anything changed. Popups take no clicks;
+    /// while the help is open, the wheel scrolls it.
+    pub fn handle_mouse(&mut self, mouse: MouseEvent, ui_state: &mut UiState) -> bool {
+        if ui_state.show_help {
+            // The wheel scrolls the help instead
+            let lines = super::widgets::help_lines().len();
+            match mouse.kind {
+                MouseEventKind::ScrollDown => ui_state.scroll_help(SCROLL_ROWS, lines),
+                MouseEventKind::ScrollUp => ui_state.scroll_help(-SCROLL_ROWS, lines),
+                _ => return false,
             }
-            KeyCode::Char('?') => {
-                // Toggle help overlay
-                ui_state.toggle_help();
-                true
+            return true;
+        }
+        if ui_state.show_column_selector
+            || ui_state.show_hop_detail
+            || ui_state.show_route_events
+            || ui_state.show_hop_graph
+            || ui_state.show_export_prompt
+        {
+            return false;
+        }
+        let layout = &ui_state.table_layout;
+        match mouse.kind {
+            MouseEventKind::ScrollDown => ui_state.scroll_table(SCROLL_ROWS),
+            MouseEventKind::ScrollUp => ui_state.scroll_table(-SCROLL_ROWS),
+            MouseEventKind::Down(MouseButton::Left) => {
+                let area = layout.area;
+                if !area.contains(Position::new(mouse.column, mouse.row)) {
+                    return false;
+                }
+                if mouse.row == area.y {
+                    let Some(&(column, _, _)) = layout
+                        .columns
+                        .iter()
+                        .find(|&&(_, x, width)| mouse.column >= x && mouse.column < x + width)
+                    else {
+                        return false;
+                    };
+                    ui_state.sort_by(column);
+                } else {
+                    let row = layout.offset + (mouse.row - area.y - 1) as usize;
+                    let Some(&Some(hop)) = layout.rows.get(row) else {
+                        return false;
+                    };
+                    ui_state.selected_hop = Some(hop);
+                }
             }
-            _ => {
-                // Unknown key, continue running
-                true
+            _ => return false,
+        }
+        true
+    }
+
+    /// Move the probe interval of all sessions one step shorter or longer
+    ///
+    /// An interval between two steps (as set with -i) moves to the nearest
+    /// step in that direction; the ends of the range stay where they are.
+    fn step_interval(&self, sessions: &[Arc<MtrSession>], faster: bool) {
+        let Some(current) = sessions.first().map(|session| session.current_interval().as_millis() as u64) else {
+            return;
+        };
+        let next = if faster {
+            INTERVAL_STEPS.iter().rev().find(|&&step| step < current)
+        } else {
+            INTERVAL_STEPS.iter().find(|&&step| step > current)
+        };
+        if let Some(&next) = next {
+            for session in sessions {
+                session.set_interval(Duration::from_millis(next));
             }
         }
     }
 
+    /// Widen or narrow a column, keeping the width for the next run
+    fn resize_column(&self, ui_state: &mut UiState, column: Column, delta: i16) {
+        if ui_state.resize_column(column, delta) {
+            save_column_widths(ui_state);
+        }
+    }
+
     /// Reset all hop statistics
-    fn reset_statistics(&self, session: &Arc<Mutex<MtrSession>>) {
-        let mut session_guard = session.lock().unwrap();
-        for hop in &mut session_guard.hops {
-            *hop = HopStats::new(hop.hop);
+    fn reset_statistics(&self, sessions: &[Arc<MtrSession>]) {
+        for session in sessions {
+            session.stats.reset();
         }
     }
 }
 
+/// Save the Host and Graph widths, saying so in the status line if that fails
+fn save_column_widths(ui_state: &mut UiState) {
+    let Some(path) = ColumnWidths::default_path() else {
+        return;
+    };
+    if let Err(e) = ui_state.column_widths.save(&path) {
+        ui_state.notice = Some(format!("⚠ {:#}", e));
+    }
+}
+
+/// Indices of the hops shown for the first session, which the selection moves over
+///
+/// They come in the order the table last showed them, sorted or not; path
+/// order before the first table is drawn and in the tree view.
+fn selectable_hops(sessions: &[Arc<MtrSession>], layout: &TableLayout) -> Vec<usize> {
+    let Some(session) = sessions.first() else {
+        return Vec::new();
+    };
+    let hops = session.stats.snapshot_range(session.display_hop_count());
+    let probed = |index: &usize| hops.get(*index).is_some_and(|hop| hop.sent > 0);
+    if layout.rows.is_empty() {
+        (0..hops.len()).filter(probed).collect()
+    } else {
+        layout.rows.iter().flatten().copied().filter(probed).collect()
+    }
+}
+
 impl Default for EventHandler {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,391 @@
+//! Inline Image Graphs
+//!
+//! On a terminal that can show pictures, the sparkline graph column and the
+//! full-screen hop chart are drawn as images instead of block characters,
+//! putting every RTT at its own height rather than one of eight. iTerm2's
+//! inline image protocol is the only one spoken so far; it is detected from
+//! `TERM_PROGRAM`, or `LC_TERMINAL` which iTerm2 passes on over ssh.
+//!
+//! ratatui knows nothing of the images: the cells under them are left blank
+//! and the images are sent after each frame, only those that changed.
+
+use crate::hop_stats::PacketOutcome;
+use crate::ui::visualization::{calculate_rtt_ratio, colors, ColorSupport};
+use crate::{utils, HopStats, SparklineScale};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crossterm::{cursor::MoveTo, queue};
+use ratatui::{
+    backend::Backend,
+    buffer::{Buffer, Cell},
+    layout::Rect,
+    style::Color,
+};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// Pixels of a terminal cell in the images, which the terminal scales to the real cell
+const CELL_WIDTH: u32 = 8;
+const CELL_HEIGHT: u32 = 16;
+
+/// How the terminal can show pictures, if at all
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphicsSupport {
+    None,   // Text only
+    Iterm2, // iTerm2 inline images (OSC 1337)
+}
+
+pub fn detect_graphics_support() -> GraphicsSupport {
+    use std::env;
+
+    // tmux and screen don't pass the images through as they are
+    if env::var_os("TMUX").is_some() || env::var("TERM").is_ok_and(|term| term.starts_with("screen")) {
+        return GraphicsSupport::None;
+    }
+    if env::var("TERM_PROGRAM").is_ok_and(|program| program == "iTerm.app")
+        || env::var("LC_TERMINAL").is_ok_and(|terminal| terminal == "iTerm2")
+    {
+        return GraphicsSupport::Iterm2;
+    }
+    GraphicsSupport::None
+}
+
+/// A PNG to be shown over an area of the screen, stretched to fill it
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineImage {
+    pub area: Rect,
+    pub png: Vec<u8>,
+}
+
+/// The images on screen, so that unchanged ones aren't sent again
+#[derive(Debug, Clone, Default)]
+pub struct ImageScreen {
+    area: Rect,
+    shown: Vec<(InlineImage, Vec<Cell>)>, // With the cells ratatui had drawn under each
+}
+
+/// The cells of a frame under the images on screen and those about to be,
+/// which is all of the frame `ImageScreen::update` looks at
+#[derive(Debug, Clone, Default)]
+pub struct FrameCells {
+    area: Rect,
+    cells: HashMap<(u16, u16), Cell>,
+}
+
+impl FrameCells {
+    fn get(&self, x: u16, y: u16) -> Cell {
+        self.cells.get(&(x, y)).cloned().unwrap_or_default()
+    }
+}
+
+impl ImageScreen {
+    /// Copy out of `buffer` the cells `update` will need for `images`
+    pub fn capture(&self, buffer: &Buffer, images: &[InlineImage]) -> FrameCells {
+        let areas = self.shown.iter().map(|(image, _)| image.area).chain(images.iter().map(|image| image.area));
+        let cells = areas
+            .flat_map(|area| area.intersection(buffer.area).positions())
+            .map(|position| ((position.x, position.y), buffer[position].clone()))
+            .collect();
+        FrameCells {
+            area: buffer.area,
+            cells,
+        }
+    }
+
+    /// Bring the terminal up to date with the images of the frame `frame` was captured from
+    ///
+    /// Images no longer wanted where they are get painted over with the cells
+    /// ratatui drew there; then the new and changed ones are sent, along with
+    /// any whose cells ratatui has drawn over since, such as for a row that
+    /// was selected.
+    pub fn update<B: Backend + Write>(
+        &mut self,
+        backend: &mut B,
+        frame: &FrameCells,
+        images: Vec<InlineImage>,
+    ) -> io::Result<()> {
+        if frame.area != self.area {
+            // A resize redraws the whole screen, images gone
+            self.area = frame.area;
+            self.shown.clear();
+        }
+        let stale: Vec<(u16, u16, Cell)> = self
+            .shown
+            .iter()
+            .filter(|(old, _)| !images.iter().any(|new| new.area == old.area))
+            .flat_map(|(old, _)| old.area.intersection(frame.area).positions())
+            .map(|position| (position.x, position.y, frame.get(position.x, position.y)))
+            .collect();
+        backend.draw(stale.iter().map(|(x, y, cell)| (*x, *y, cell)))?;
+
+        let shown: Vec<(InlineImage, Vec<Cell>)> = images
+            .into_iter()
+            .map(|image| {
+                let cells = image
+                    .area
+                    .intersection(frame.area)
+                    .positions()
+                    .map(|p| frame.get(p.x, p.y))
+                    .collect();
+                (image, cells)
+            })
+            .collect();
+        for (image, _) in shown.iter().filter(|&entry| !self.shown.contains(entry)) {
+            queue!(backend, MoveTo(image.area.x, image.area.y))?;
+            write!(
+                backend,
+                "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=0;doNotMoveCursor=1:{}\x07",
+                image.png.len(),
+                image.area.width,
+                image.area.height,
+                STANDARD.encode(&image.png)
+            )?;
+        }
+        Write::flush(backend)?;
+        self.shown = shown;
+        Ok(())
+    }
+}
+
+/// A hop's recent RTTs as bars, one to a cell like the text sparkline
+///
+/// Each bar is as tall as its RTT on the shared scale; lost probes are a red
+/// dot and probes still out a blue one.
+pub fn sparkline_image(
+    hop: &HopStats,
+    global_min_rtt: u64,
+    global_max_rtt: u64,
+    scale: SparklineScale,
+    cells: u16,
+) -> Vec<u8> {
+    let mut canvas = Canvas::new(u32::from(cells) * CELL_WIDTH, CELL_HEIGHT);
+    let skip = hop.packet_history.len().saturating_sub(cells as usize);
+    for (i, outcome) in hop.packet_history.iter().skip(skip).enumerate() {
+        let x = i as u32 * CELL_WIDTH + 1;
+        let dot = CELL_WIDTH / 2;
+        match outcome {
+            PacketOutcome::Received(rtt) => {
+                let ratio = calculate_rtt_ratio(
+                    utils::time::duration_to_ms_u64(*rtt),
+                    global_min_rtt,
+                    global_max_rtt,
+                    scale,
+                );
+                let color = colors::get_smooth_gradient_color(ratio, ColorSupport::TrueColor);
+                let height = ((ratio * CELL_HEIGHT as f64).round() as u32).clamp(1, CELL_HEIGHT);
+                canvas.fill(x, CELL_HEIGHT - height, CELL_WIDTH - 2, height, rgb(color));
+            }
+            PacketOutcome::Lost => canvas.fill(x + 1, (CELL_HEIGHT - dot) / 2, dot, dot, rgb(Color::Red)),
+            PacketOutcome::Pending => canvas.fill(x + 1, (CELL_HEIGHT - dot) / 2, dot, dot, rgb(Color::Blue)),
+        }
+    }
+    canvas.png()
+}
+
+/// What the full-screen chart plots, RTTs in milliseconds
+pub struct Plot<'a> {
+    pub x_max: f64,                     // Index of the newest probe, at the right edge
+    pub rtt_range: (f64, f64),          // RTTs at the bottom and top edges
+    pub gridlines: &'a [f64],           // RTTs of the faint lines across
+    pub references: &'a [(Color, f64)], // Best, average and worst, drawn across
+    pub replies: &'a [(f64, f64)],      // Probe index and RTT of each reply
+    pub lost: &'a [f64],                // Probe index of each lost probe
+}
+
+/// The chart of a hop's RTT history, to fill `cols` by `rows` cells
+///
+/// The replies are joined by a line over the gridlines and reference
+/// lines, with lost probes marked along the top.
+pub fn chart_image(plot: &Plot, cols: u16, rows: u16) -> Vec<u8> {
+    let mut canvas = Canvas::new(u32::from(cols) * CELL_WIDTH, u32::from(rows) * CELL_HEIGHT);
+    let (bottom, top) = plot.rtt_range;
+    let (width, height) = (canvas.width as f64 - 1.0, canvas.height as f64 - 1.0);
+    let x = |index: f64| (index / plot.x_max.max(1.0) * width).round() as i64;
+    let y = |rtt: f64| ((top - rtt) / (top - bottom) * height).round() as i64;
+
+    for &rtt in plot.gridlines {
+        canvas.line((0, y(rtt)), (width as i64, y(rtt)), rgb(Color::DarkGray));
+    }
+    for &(color, rtt) in plot.references {
+        canvas.line((0, y(rtt)), (width as i64, y(rtt)), rgb(color));
+    }
+    for pair in plot.replies.windows(2) {
+        let (from, to) = ((x(pair[0].0), y(pair[0].1)), (x(pair[1].0), y(pair[1].1)));
+        // Twice as thick as the lines behind it
+        canvas.line(from, to, rgb(Color::Cyan));
+        canvas.line((from.0, from.1 + 1), (to.0, to.1 + 1), rgb(Color::Cyan));
+    }
+    if let [(index, rtt)] = plot.replies {
+        canvas.fill(
+            x(*index).max(1) as u32 - 1,
+            y(*rtt).max(1) as u32 - 1,
+            3,
+            3,
+            rgb(Color::Cyan),
+        );
+    }
+    for &index in plot.lost {
+        canvas.fill((x(index) - 2).max(0) as u32, 0, 5, 5, rgb(Color::Red));
+    }
+    canvas.png()
+}
+
+/// The RGB of a chart color
+fn rgb(color: Color) -> [u8; 3] {
+    match color {
+        Color::Rgb(r, g, b) => [r, g, b],
+        Color::Red => [220, 50, 47],
+        Color::Green => [80, 200, 80],
+        Color::Yellow => [230, 200, 40],
+        Color::Blue => [100, 100, 255],
+        Color::Cyan => [40, 200, 220],
+        Color::DarkGray => [80, 80, 80],
+        _ => [255, 255, 255],
+    }
+}
+
+/// A transparent RGBA picture to draw on
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    /// Color a pixel, if it is on the canvas
+    fn set(&mut self, x: i64, y: i64, [r, g, b]: [u8; 3]) {
+        if x < 0 || y < 0 || x >= i64::from(self.width) || y >= i64::from(self.height) {
+            return;
+        }
+        let offset = ((y as u32 * self.width + x as u32) * 4) as usize;
+        self.pixels[offset..offset + 4].copy_from_slice(&[r, g, b, 255]);
+    }
+
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 3]) {
+        for py in y..y + height {
+            for px in x..x + width {
+                self.set(i64::from(px), i64::from(py), color);
+            }
+        }
+    }
+
+    /// Draw a straight line between two pixels (Bresenham)
+    fn line(&mut self, (mut x, mut y): (i64, i64), (x1, y1): (i64, i64), color: [u8; 3]) {
+        let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+        let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+        let mut error = dx + dy;
+        loop {
+            self.set(x, y, color);
+            if (x, y) == (x1, y1) {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += sx;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn png(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::Fast);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .expect("a PNG of a non-empty canvas encodes into memory");
+        png
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::CrosstermBackend;
+
+    /// Run `update` over a frame of `buffer`, returning what it wrote and how many images were sent
+    fn update(screen: &mut ImageScreen, buffer: &Buffer, images: &[InlineImage]) -> (String, usize) {
+        let mut written = Vec::new();
+        let cells = screen.capture(buffer, images);
+        screen
+            .update(&mut CrosstermBackend::new(&mut written), &cells, images.to_vec())
+            .unwrap();
+        let out = String::from_utf8(written).unwrap();
+        let sent = out.matches("1337;File").count();
+        (out, sent)
+    }
+
+    #[test]
+    fn test_image_screen_update() {
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 10, 3));
+        let image = InlineImage {
+            area: Rect::new(2, 1, 4, 1),
+            png: vec![1, 2, 3],
+        };
+        let mut screen = ImageScreen::default();
+        assert_eq!(update(&mut screen, &buffer, std::slice::from_ref(&image)).1, 1);
+        assert_eq!(update(&mut screen, &buffer, std::slice::from_ref(&image)).1, 0); // Unchanged
+        let image = InlineImage {
+            png: vec![4, 5, 6],
+            ..image
+        };
+        assert_eq!(update(&mut screen, &buffer, std::slice::from_ref(&image)).1, 1);
+
+        // ratatui drew over it, as for a row that was selected
+        buffer[(3, 1)].set_symbol("x");
+        assert_eq!(update(&mut screen, &buffer, std::slice::from_ref(&image)).1, 1);
+
+        // No longer wanted: its cells are painted over with what ratatui drew there
+        let (out, sent) = update(&mut screen, &buffer, &[]);
+        assert_eq!(sent, 0);
+        assert!(out.contains('x'));
+
+        // A resize clears the screen, so an unchanged image goes again
+        assert_eq!(update(&mut screen, &buffer, std::slice::from_ref(&image)).1, 1);
+        let resized = Buffer::empty(Rect::new(0, 0, 12, 3));
+        assert_eq!(update(&mut screen, &resized, &[image]).1, 1);
+    }
+
+    #[test]
+    fn test_chart_image() {
+        let replies = [(0.0, 44.0), (1.0, 48.0), (2.0, 52.0), (3.0, 39.0)];
+        let references = [(Color::Green, 39.0), (Color::Yellow, 45.75), (Color::Red, 52.0)];
+        let plot = Plot {
+            x_max: 3.0,
+            rtt_range: (31.2, 57.2),
+            gridlines: &[38.0, 44.0, 50.0],
+            references: &references,
+            replies: &replies,
+            lost: &[1.0],
+        };
+        let png = chart_image(&plot, 93, 20);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        // IHDR: width and height in pixels
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 93 * CELL_WIDTH);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 20 * CELL_HEIGHT);
+
+        let mut canvas = Canvas::new(10, 10);
+        canvas.line((0, 0), (9, 3), rgb(Color::Cyan));
+        canvas.line((9, 9), (2, 4), rgb(Color::Cyan));
+        let lit = |x: u32, y: u32| canvas.pixels[((y * 10 + x) * 4 + 3) as usize] == 255;
+        assert!(lit(0, 0) && lit(9, 3) && lit(9, 9) && lit(2, 4));
+        assert!(!lit(0, 9));
+    }
+}
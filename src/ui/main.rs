@@ -4,35 +4,51 @@
 //! It includes colorblind-friendly visualizations, sparkline graphs, interactive controls,
 //! and support for various terminal color modes.
 
+use crate::alerts::Thresholds;
+use crate::config::{self, Config};
 use crate::args::Column;
 use crate::ui::events::EventHandler;
-use crate::ui::state::UiState;
+use crate::ui::graphics::{self, GraphicsSupport, InlineImage, Plot};
+use crate::ui::state::{ColumnWidths, TableLayout, UiState, ViewMode};
+use crate::ui::topology;
 use crate::ui::visualization::{
-    create_heatmap_spans, create_sparkline_spans, VisualizationMode,
+    create_heatmap_spans, create_histogram_spans, create_jitter_spans, create_loss_spans, create_sparkline_spans,
+    VisualizationMode,
 };
 use crate::ui::widgets;
 use crate::utils;
-use crate::{MtrSession, Result};
+use crate::hop_stats::PacketOutcome;
+use crate::{HopStats, MtrSession, Result};
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, Event},
+    event::{
+        DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture, Event, KeyCode,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
 
-    layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style},
-    widgets::{Cell, Clear, Paragraph, Row, Table},
+    layout::{Alignment, Constraint, Direction, Flex, Layout, Rect},
+    style::{Color, Modifier, Style},
+    symbols::Marker,
+    text::{Line, Span},
+    widgets::{Axis, Block, Borders, Cell, Chart, Clear, Dataset, GraphType, LegendPosition, Paragraph, Row, Table},
     Frame, Terminal,
 };
 use std::{
-    io,
-    sync::{Arc, Mutex},
-    time::Duration,
+    io::{self, Write},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::sync::mpsc;
-use tracing::debug;
+use tracing::{debug, warn};
+use unicode_width::UnicodeWidthStr;
+
+/// Longest a quit waits for outstanding replies before they are given up on
+const SHUTDOWN_WAIT: Duration = Duration::from_secs(1);
+/// Longest a quit then waits for the lost probes to be counted before the traces are aborted
+const ABANDON_WAIT: Duration = Duration::from_millis(200);
 
 // ========================================
 // Main UI Rendering
@@ -80,7 +96,24 @@ use tracing::debug;
 /// 3. Scale widget - Shows RTT scale with gradient and labeled axis
 ///
 /// The function also handles the help overlay when toggled by the user.
-pub fn render_ui(f: &mut Frame, session: &MtrSession, ui_state: &UiState) {
+/// With several sessions (`--dual-stack`) the table area is split between
+/// them, each under a label naming its address; the status line follows
+/// the first. Each session's hops are copied out once per frame, so drawing
+/// never holds a lock the probe path is waiting on; while the display is
+/// frozen the copies taken when it froze are drawn instead.
+///
+/// Returns where the first session's table was drawn, for mouse handling;
+/// graphs drawn as inline images are added to `images`, to be sent after.
+pub fn render_ui(
+    f: &mut Frame,
+    sessions: &[&MtrSession],
+    ui_state: &UiState,
+    images: &mut Vec<InlineImage>,
+) -> TableLayout {
+    let mut table_layout = TableLayout::default();
+    let Some(&session) = sessions.first() else {
+        return table_layout;
+    };
     let area = f.area();
 
     // Minimum size check
@@ -90,7 +123,7 @@ pub fn render_ui(f: &mut Frame, session: &MtrSession, ui_state: &UiState) {
             area.width, area.height
         ));
         f.render_widget(fallback, area);
-        return;
+        return table_layout;
     }
 
     // Compact layout - no margins, minimal spacing
@@ -103,184 +136,79 @@ pub fn render_ui(f: &mut Frame, session: &MtrSession, ui_state: &UiState) {
         ])
         .split(area);
 
-    // Get RTT range for scaling
-    let rtt_values: Vec<u64> = session
-        .hops
+    let hops: Vec<Vec<HopStats>> = match ui_state.frozen {
+        Some(ref frozen) => frozen.clone(),
+        None => sessions.iter().map(|session| session.stats.snapshot()).collect(),
+    };
+
+    // Get RTT range for scaling, shared so the tables are comparable; the
+    // jitter graph and the scale under it go by the jitter instead, leaving
+    // out the sub-millisecond values that can't anchor a log scale
+    let jitter_mode = matches!(ui_state.visualization_mode, VisualizationMode::Jitter);
+    let rtt_values: Vec<u64> = hops
         .iter()
+        .flatten()
         .filter(|hop| hop.sent > 0)
-        .flat_map(|hop| hop.rtts.iter())
+        .flat_map(|hop| if jitter_mode { hop.jitters.iter() } else { hop.rtts.iter() })
         .map(|d| utils::time::duration_to_ms_u64(*d))
+        .filter(|&ms| ms > 0 || !jitter_mode)
         .collect();
 
     let global_max_rtt = rtt_values.iter().max().copied().unwrap_or(1);
     let global_min_rtt = rtt_values.iter().min().copied().unwrap_or(1);
 
     // Status line (no borders)
-    let status_line = widgets::create_status_text(session, ui_state);
+    let status_line = widgets::create_status_text(session, &hops[0], ui_state);
     let status = Paragraph::new(vec![status_line]);
     f.render_widget(status, chunks[0]);
 
-    // Main table
-    let header_cells = ui_state.columns.iter().map(|col| match col {
-        Column::Loss
-        | Column::Sent
-        | Column::Last
-        | Column::Avg
-        | Column::Ema
-        | Column::Jitter
-        | Column::JitterAvg
-        | Column::Best
-        | Column::Worst => Cell::from(format!("{:>width$}", col.header(), width = col.width())),
-        _ => Cell::from(col.header()),
-    });
-
-    let header = Row::new(header_cells).style(Style::default().fg(Color::Yellow));
-
-    let mut rows = Vec::new();
-
-    // Determine how many hops to display based on discovery or organic growth
-    let max_hops_to_display = if session.num_hosts > 0 {
-        session.num_hosts
-    } else {
-        // Organic discovery: show hops up to the furthest one with data
-        session.hops.iter()
-            .enumerate()
-            .rev()
-            .find(|(_, hop)| hop.sent > 0 || hop.addr.is_some())
-            .map(|(i, _)| i + 1)
-            .unwrap_or(0)
-            .max(8) // Show at least 8 hops to see progress
-    };
-    
-    for hop in session.hops.iter().take(max_hops_to_display).filter(|hop| hop.sent > 0) {
-        let hostname = widgets::format_hostname(session, hop, ui_state);
-        let graph_width = widgets::calculate_graph_width(&chunks[1], &ui_state.columns);
-
-        let graph_spans = match ui_state.visualization_mode {
-            VisualizationMode::Sparkline => create_sparkline_spans(
-                hop,
-                global_min_rtt,
-                global_max_rtt,
-                ui_state.current_sparkline_scale,
-                ui_state.color_support,
-                graph_width,
-            ),
-            VisualizationMode::Heatmap => create_heatmap_spans(
-                hop,
-                global_min_rtt,
-                global_max_rtt,
-                ui_state.current_sparkline_scale,
-                ui_state.color_support,
-                graph_width,
-            ),
+    let areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Ratio(1, sessions.len() as u32); sessions.len()])
+        .split(chunks[1]);
+    for (i, ((&session, hops), &area)) in sessions.iter().zip(&hops).zip(areas.iter()).enumerate() {
+        let area = if sessions.len() > 1 {
+            let [label, rest] = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(area);
+            f.render_widget(widgets::create_session_label(session), label);
+            rest
+        } else {
+            area
         };
 
-        let cells = widgets::create_table_cells(
-            hop,
-            &hostname,
-            &graph_spans,
-            &ui_state.columns,
-        );
-
-        rows.push(Row::new(cells));
-
-        // Add alternate paths if multi-path is detected
-        if hop.has_multiple_paths() {
-            for alt_path in hop.get_alternate_paths() {
-                let percentage = hop.get_path_percentage(alt_path);
-
-                // Format hostname with proper length, including percentage
-                let alt_hostname = if let Some(hostname) = &alt_path.hostname {
-                    let full_name =
-                        format!("  ↳ {} ({}) ({:.0}%)", hostname, alt_path.addr, percentage);
-                    if full_name.len() > 50 {
-                        format!(
-                            "  ↳ {}...{} ({:.0}%)",
-                            &hostname[..15],
-                            alt_path.addr,
-                            percentage
-                        )
-                    } else {
-                        full_name
-                    }
-                } else {
-                    format!("  ↳ {} ({:.0}%)", alt_path.addr, percentage)
-                };
-
-                let _alt_rtt = utils::time::duration_to_ms_f64(alt_path.last_rtt.unwrap_or_default());
-
-                // Create cells for each column, focusing on key info
-                let mut alt_cells = Vec::new();
-                for column in &ui_state.columns {
-                    match column {
-                        Column::Hop => alt_cells.push(Cell::from("")),
-                        Column::Host => alt_cells.push(Cell::from(alt_hostname.clone())),
-                        Column::Loss => alt_cells.push(Cell::from("")), // Empty - percentage is now in hostname
-                        Column::Sent => alt_cells.push(Cell::from("")),
-                        Column::Last => {
-                            if let Some(rtt) = alt_path.last_rtt {
-                                let formatted = if utils::time::duration_to_us_f64(rtt) < 1000.0 {
-                                    utils::time::format_duration_us(rtt)
-                                } else {
-                                    format!("{:.1}", utils::time::duration_to_ms_f64(rtt))
-                                };
-                                alt_cells.push(Cell::from(formatted));
-                            } else {
-                                alt_cells.push(Cell::from("???"));
-                            }
-                        },
-                        Column::Avg => alt_cells.push(Cell::from("")),
-                        Column::Ema => alt_cells.push(Cell::from("")),
-                        Column::Best => alt_cells.push(Cell::from("")),
-                        Column::Worst => alt_cells.push(Cell::from("")),
-                        Column::Jitter => alt_cells.push(Cell::from("")),
-                        Column::JitterAvg => alt_cells.push(Cell::from("")),
-                        Column::Graph => alt_cells.push(Cell::from("")),
-                    }
-                }
-
-                let alt_row = Row::new(alt_cells);
-                rows.push(alt_row);
+        if ui_state.view_mode == ViewMode::Tree {
+            f.render_widget(topology::create_topology_tree(session, hops, ui_state), area);
+        } else {
+            // The selection and the mouse follow the first session's table
+            let selected = if i == 0 { ui_state.selected_hop } else { None };
+            let layout = render_table(f, session, hops, ui_state, area, selected, (global_min_rtt, global_max_rtt));
+            images.extend(table_images(&layout, hops, ui_state, (global_min_rtt, global_max_rtt)));
+            if i == 0 {
+                table_layout = layout;
             }
         }
     }
 
-    let constraints = widgets::create_column_constraints(&ui_state.columns);
-    let table = Table::new(rows, &constraints).header(header);
-
-    f.render_widget(table, chunks[1]);
-
     // Compact scale visualization
     let scale_widget = widgets::create_scale_widget(
         global_min_rtt,
         global_max_rtt,
         ui_state.current_sparkline_scale,
         ui_state.color_support,
+        ui_state.rtt_unit,
         chunks[2].width as usize,
     );
     f.render_widget(scale_widget, chunks[2]);
 
     // Show help overlay if enabled
     if ui_state.show_help {
-        let area = f.area();
-        // Center the help overlay
-        let (help_width, help_height) = utils::layout::calculate_popup_dimensions(
-            area.width, area.height, 50, 12
-        );
-        let (help_x, help_y) = utils::layout::center_popup(
-            area.width, area.height, help_width, help_height
-        );
-
-        let help_area = Rect {
-            x: help_x,
-            y: help_y,
-            width: help_width,
-            height: help_height,
-        };
+        let lines = widgets::help_lines();
+        let help_area = help_popup_area(f.area(), &lines);
+        let rows = help_area.height.saturating_sub(2) as usize;
+        let scroll = ui_state.help_scroll.min(lines.len().saturating_sub(rows));
 
         // Clear the background and render help
         f.render_widget(Clear, help_area);
-        f.render_widget(widgets::create_help_overlay(), help_area);
+        f.render_widget(widgets::create_help_overlay(lines, scroll, rows), help_area);
     }
 
     // Show column selector popup if enabled
@@ -309,49 +237,635 @@ pub fn render_ui(f: &mut Frame, session: &MtrSession, ui_state: &UiState) {
             popup_area,
         );
     }
+
+    // Show details of the selected hop if requested
+    if let Some(index) = ui_state.selected_hop.filter(|_| ui_state.show_hop_detail) {
+        if let Some(hop) = hops[0].get(index) {
+            let area = f.area();
+            let lines = widgets::hop_detail_lines(session, hop, ui_state);
+            let (popup_width, popup_height) =
+                utils::layout::calculate_popup_dimensions(area.width, area.height, 72, lines.len() as u16 + 2);
+            let (popup_x, popup_y) =
+                utils::layout::center_popup(area.width, area.height, popup_width, popup_height);
+            let popup_area = Rect {
+                x: popup_x,
+                y: popup_y,
+                width: popup_width,
+                height: popup_height,
+            };
+
+            f.render_widget(Clear, popup_area);
+            f.render_widget(widgets::create_hop_detail_popup(hop, lines), popup_area);
+        }
+    }
+
+    // Show the route change events if requested
+    if ui_state.show_route_events {
+        let area = f.area();
+        let (popup_width, popup_height) =
+            utils::layout::calculate_popup_dimensions(area.width, area.height, 72, 20);
+        let (popup_x, popup_y) =
+            utils::layout::center_popup(area.width, area.height, popup_width, popup_height);
+        let popup_area = Rect {
+            x: popup_x,
+            y: popup_y,
+            width: popup_width,
+            height: popup_height,
+        };
+
+        f.render_widget(Clear, popup_area);
+        f.render_widget(
+            widgets::create_route_events_popup(session, popup_height.saturating_sub(2) as usize),
+            popup_area,
+        );
+    }
+
+    // Text drawn over an image would leave holes in it, so popups put the images away
+    if ui_state.show_help || ui_state.show_column_selector || ui_state.show_hop_detail || ui_state.show_route_events {
+        images.clear();
+    }
+
+    // The selected hop's chart covers the table and its popups
+    if let Some(index) = ui_state.selected_hop.filter(|_| ui_state.show_hop_graph) {
+        if let Some(hop) = hops[0].get(index) {
+            f.render_widget(Clear, area);
+            images.clear();
+            images.extend(render_hop_graph(f, session, hop, ui_state, area));
+        }
+    }
+
+    // Ask which format to write a snapshot in
+    if ui_state.show_export_prompt {
+        let area = f.area();
+        let (popup_width, popup_height) = utils::layout::calculate_popup_dimensions(area.width, area.height, 36, 4);
+        let (popup_x, popup_y) = utils::layout::center_popup(area.width, area.height, popup_width, popup_height);
+        let popup_area = Rect {
+            x: popup_x,
+            y: popup_y,
+            width: popup_width,
+            height: popup_height,
+        };
+
+        f.render_widget(Clear, popup_area);
+        f.render_widget(widgets::create_export_prompt(), popup_area);
+        images.clear();
+    }
+
+    table_layout
+}
+
+/// Chart a hop's RTT history over the whole terminal
+///
+/// Each reply in the packet history is a point, oldest on the left; lost
+/// probes are marked along the top. Gridlines run at the labelled RTTs, with
+/// the best, average and worst RTT drawn across as reference lines. With
+/// inline images the plot is a picture inside the same labels, returned to
+/// be sent once the frame is drawn.
+fn render_hop_graph(
+    f: &mut Frame,
+    session: &MtrSession,
+    hop: &HopStats,
+    ui_state: &UiState,
+    area: Rect,
+) -> Option<InlineImage> {
+    let ms = |rtt: Duration| utils::time::duration_to_ms_f64(rtt);
+    let history = hop.packet_history.len();
+    let mut replies = Vec::new();
+    let mut lost = Vec::new();
+    for (i, outcome) in hop.packet_history.iter().enumerate() {
+        match outcome {
+            PacketOutcome::Received(rtt) => replies.push((i as f64, ms(*rtt))),
+            PacketOutcome::Lost => lost.push(i as f64),
+            PacketOutcome::Pending => {}
+        }
+    }
+
+    // Start the axis a little under the fastest reply, so the jitter isn't flattened against it
+    let top = replies.iter().map(|&(_, rtt)| rtt).fold(0.0, f64::max).max(1.0) * 1.1;
+    let bottom = replies.iter().map(|&(_, rtt)| rtt).fold(top, f64::min) * 0.8;
+    let x_bounds = [0.0, history.saturating_sub(1).max(1) as f64];
+    type Segment = [(f64, f64); 2];
+    let across = |rtt: f64| -> Segment { [(x_bounds[0], rtt), (x_bounds[1], rtt)] };
+
+    const GRIDLINES: usize = 4;
+    let levels: Vec<f64> = (0..=GRIDLINES)
+        .map(|i| bottom + (top - bottom) * i as f64 / GRIDLINES as f64)
+        .collect();
+    let level_labels: Vec<String> = levels
+        .iter()
+        .map(|&rtt| utils::time::format_rtt_label(Duration::from_secs_f64(rtt / 1000.0), ui_state.rtt_unit))
+        .collect();
+    let references: Vec<(String, Color, f64)> = [
+        ("best", Color::Green, hop.best_rtt),
+        ("avg", Color::Yellow, hop.avg_rtt),
+        ("worst", Color::Red, hop.worst_rtt),
+    ]
+    .into_iter()
+    .filter_map(|(name, color, rtt)| {
+        let rtt = rtt?;
+        let label = format!("{} {}", name, utils::time::format_rtt(rtt, ui_state.rtt_unit));
+        Some((label, color, ms(rtt)))
+    })
+    .collect();
+    let lost_label = format!("lost {}", lost.len());
+    let oldest_label = format!("{} ago", history.saturating_sub(1));
+
+    let host = widgets::format_hostname(session, hop, ui_state);
+    let title = format!(
+        " Hop {}: {} | {:.1}% loss of {} sent | Esc to return ",
+        hop.hop, host, hop.loss_percent, hop.sent
+    );
+    let block = Block::default().borders(Borders::ALL).title(title).title_alignment(Alignment::Center);
+
+    if ui_state.graphics != GraphicsSupport::None {
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+        let [legend, middle, x_labels] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(1), Constraint::Length(1)]).areas(inner);
+        let label_width = level_labels.iter().map(|label| label.width()).max().unwrap_or(0) as u16 + 1;
+        let [y_labels, plot_area] =
+            Layout::horizontal([Constraint::Length(label_width), Constraint::Min(1)]).areas(middle);
+
+        let mut legend_spans: Vec<Span> = references
+            .iter()
+            .map(|(label, color, _)| Span::styled(format!("━ {}  ", label), Style::default().fg(*color)))
+            .collect();
+        legend_spans.push(Span::styled("━ RTT  ", Style::default().fg(Color::Cyan)));
+        legend_spans.push(Span::styled(format!("■ {}", lost_label), Style::default().fg(Color::Red)));
+        f.render_widget(Paragraph::new(Line::from(legend_spans)), legend);
+
+        for (label, &rtt) in level_labels.iter().zip(&levels) {
+            // On the row its gridline is drawn in
+            let row = ((top - rtt) / (top - bottom) * plot_area.height as f64) as u16;
+            let row_area = Rect {
+                y: y_labels.y + row.min(plot_area.height.saturating_sub(1)),
+                height: 1,
+                ..y_labels
+            };
+            f.render_widget(Paragraph::new(format!("{} ", label)).alignment(Alignment::Right), row_area);
+        }
+        let x_axis = Rect {
+            x: plot_area.x,
+            width: plot_area.width,
+            ..x_labels
+        };
+        f.render_widget(Paragraph::new(oldest_label), x_axis);
+        f.render_widget(Paragraph::new("probes").alignment(Alignment::Center), x_axis);
+        f.render_widget(Paragraph::new("now").alignment(Alignment::Right), x_axis);
+
+        let lines: Vec<(Color, f64)> = references.iter().map(|&(_, color, rtt)| (color, rtt)).collect();
+        let plot = Plot {
+            x_max: x_bounds[1],
+            rtt_range: (bottom, top),
+            gridlines: &levels[1..GRIDLINES],
+            references: &lines,
+            replies: &replies,
+            lost: &lost,
+        };
+        let png = graphics::chart_image(&plot, plot_area.width, plot_area.height);
+        return Some(InlineImage { area: plot_area, png });
+    }
+
+    let lost: Vec<(f64, f64)> = lost.into_iter().map(|i| (i, top)).collect();
+    let gridlines: Vec<Segment> = levels[1..GRIDLINES].iter().map(|&rtt| across(rtt)).collect();
+    let reference_lines: Vec<Segment> = references.iter().map(|&(_, _, rtt)| across(rtt)).collect();
+
+    // The legend doesn't blank what it covers, so equal-width names keep the lines out of it
+    let width = references.iter().map(|(label, ..)| label.chars().count()).fold(lost_label.len(), usize::max);
+    let pad = |name: &str| format!("{:<width$}", name);
+
+    let line = |data| {
+        Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .data(data)
+    };
+    let mut datasets: Vec<Dataset> = gridlines
+        .iter()
+        .map(|gridline| line(gridline).style(Style::default().fg(Color::DarkGray)))
+        .collect();
+    for ((label, color, _), data) in references.iter().zip(&reference_lines) {
+        datasets.push(line(data).name(pad(label)).style(Style::default().fg(*color)));
+    }
+    datasets.push(line(&replies).name(pad("RTT")).style(Style::default().fg(Color::Cyan)));
+    datasets.push(
+        Dataset::default()
+            .name(pad(&lost_label))
+            .marker(Marker::Dot)
+            .graph_type(GraphType::Scatter)
+            .style(Style::default().fg(Color::Red))
+            .data(&lost),
+    );
+
+    let chart = Chart::new(datasets)
+        .block(block)
+        .x_axis(
+            Axis::default()
+                .title("probes")
+                .bounds(x_bounds)
+                .labels([oldest_label, "now".to_string()]),
+        )
+        .y_axis(
+            Axis::default()
+                .title(ui_state.rtt_unit.name())
+                .bounds([bottom, top])
+                .labels(level_labels),
+        )
+        .legend_position(Some(LegendPosition::TopLeft))
+        .hidden_legend_constraints((Constraint::Ratio(1, 3), Constraint::Ratio(1, 3)));
+    f.render_widget(chart, area);
+    None
+}
+
+/// Render the hop table, with alternate ECMP responders as indented sub-rows
+/// unless they are collapsed
+///
+/// The hops are in TTL order unless a sort column was picked, and the rows
+/// start `ui_state.scroll` down. Returns where everything ended up.
+fn render_table(
+    f: &mut Frame,
+    session: &MtrSession,
+    hops: &[HopStats],
+    ui_state: &UiState,
+    area: Rect,
+    selected: Option<usize>,
+    (global_min_rtt, global_max_rtt): (u64, u64),
+) -> TableLayout {
+    let titles: Vec<(String, Style)> = ui_state
+        .columns
+        .iter()
+        .map(|col| {
+            // The sort column is marked with the direction it orders in
+            if ui_state.sort_column == Some(*col) {
+                let arrow = if matches!(col, Column::Host | Column::Location) { "▲" } else { "▼" };
+                (
+                    format!("{}{}", arrow, col.header()),
+                    Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+                )
+            } else if *col == Column::Graph {
+                (ui_state.visualization_mode.graph_title().to_string(), Style::default())
+            } else {
+                (col.header().to_string(), Style::default())
+            }
+        })
+        .collect();
+
+    // Determine how many hops to display based on discovery or organic growth
+    let max_hops_to_display = if session.stats.num_hosts() > 0 {
+        session.stats.num_hosts()
+    } else {
+        // Organic discovery: show hops up to the furthest one with data
+        hops.iter()
+            .enumerate()
+            .rev()
+            .find(|(_, hop)| hop.sent > 0 || hop.addr.is_some())
+            .map(|(i, _)| i + 1)
+            .unwrap_or(0)
+            .max(8) // Show at least 8 hops to see progress
+    };
+    
+    let blocked_hop = session.blocked_hop();
+    let destination = session.destination_ttl();
+
+    let mut shown: Vec<(usize, &HopStats)> = hops
+        .iter()
+        .enumerate()
+        .take(max_hops_to_display)
+        .filter(|(_, hop)| hop.sent > 0)
+        .collect();
+    if let Some(column) = ui_state.sort_column {
+        widgets::sort_hops(&mut shown, column);
+    }
+
+    // Every row's text first, so the columns can be fitted to it
+    let mut fitted: Vec<u16> = titles.iter().map(|(title, _)| title.width() as u16).collect();
+    let mut fit = |texts: &[String]| {
+        for (fitted, text) in fitted.iter_mut().zip(texts) {
+            *fitted = (*fitted).max(text.width() as u16);
+        }
+    };
+    let mut hop_rows = Vec::new();
+    for (index, hop) in shown {
+        let hostname = widgets::format_hostname(session, hop, ui_state);
+        let texts = widgets::cell_texts(hop, &hostname, &ui_state.columns, ui_state.rtt_unit);
+        fit(&texts);
+
+        // Add alternate paths if multi-path is detected
+        let mut alt_rows = Vec::new();
+        if hop.has_multiple_paths() && !ui_state.paths_collapsed(hop.hop) {
+            for alt_path in hop.get_alternate_paths() {
+                let percentage = hop.get_path_percentage(alt_path);
+                let alt_hostname = match &alt_path.hostname {
+                    Some(hostname) => format!("  ↳ {} ({}) ({:.0}%)", hostname, alt_path.addr, percentage),
+                    None => format!("  ↳ {} ({:.0}%)", alt_path.addr, percentage),
+                };
+                // Only the name and the last RTT are the alternate's own
+                let alt_texts: Vec<String> = ui_state
+                    .columns
+                    .iter()
+                    .map(|column| match column {
+                        Column::Host => alt_hostname.clone(),
+                        Column::Last => utils::time::format_optional_rtt(alt_path.last_rtt, ui_state.rtt_unit),
+                        _ => String::new(),
+                    })
+                    .collect();
+                fit(&alt_texts);
+                alt_rows.push(alt_texts);
+            }
+        }
+        hop_rows.push((index, hop, texts, alt_rows));
+    }
+
+    let widths = widgets::column_widths(&ui_state.columns, &fitted, ui_state.column_widths, area.width);
+    let width_of = |column: Column| {
+        ui_state
+            .columns
+            .iter()
+            .position(|&shown| shown == column)
+            .map_or(0, |index| widths[index] as usize)
+    };
+    let graph_width = width_of(Column::Graph);
+    // Names too long for the Host column end in "..." rather than just stopping
+    let fit_host = |texts: &mut Vec<String>| {
+        if let Some(index) = ui_state.columns.iter().position(|&column| column == Column::Host) {
+            texts[index] = utils::network::truncate_hostname(&texts[index], width_of(Column::Host));
+        }
+    };
+
+    let header_cells = ui_state.columns.iter().zip(titles).map(|(&col, (title, style))| {
+        widgets::aligned_cell(col, title).style(style)
+    });
+    let header = Row::new(header_cells).style(Style::default().fg(Color::Yellow));
+
+    let mut rows = Vec::new();
+    let mut row_hops = Vec::new();
+    for (index, hop, mut texts, alt_rows) in hop_rows {
+        let graph_spans = match ui_state.visualization_mode {
+            // Left blank for the image drawn over it (see `table_images`)
+            VisualizationMode::Sparkline if ui_state.graphics != GraphicsSupport::None => Vec::new(),
+            VisualizationMode::Sparkline => create_sparkline_spans(
+                hop,
+                global_min_rtt,
+                global_max_rtt,
+                ui_state.current_sparkline_scale,
+                ui_state.color_support,
+                graph_width,
+            ),
+            VisualizationMode::Heatmap => create_heatmap_spans(
+                hop,
+                global_min_rtt,
+                global_max_rtt,
+                ui_state.current_sparkline_scale,
+                ui_state.color_support,
+                graph_width,
+            ),
+            VisualizationMode::Histogram => create_histogram_spans(
+                hop,
+                global_min_rtt,
+                global_max_rtt,
+                ui_state.current_sparkline_scale,
+                ui_state.color_support,
+                graph_width,
+            ),
+            VisualizationMode::Loss => create_loss_spans(hop, ui_state.color_support, graph_width),
+            VisualizationMode::Jitter => create_jitter_spans(
+                hop,
+                global_min_rtt,
+                global_max_rtt,
+                ui_state.current_sparkline_scale,
+                ui_state.color_support,
+                graph_width,
+            ),
+        };
+
+        fit_host(&mut texts);
+        let cells = widgets::create_table_cells(hop, texts, &graph_spans, &ui_state.columns);
+
+        let mut style = if blocked_hop == Some(index) {
+            Style::default().fg(Color::Red)
+        } else if session.baseline_deviation(index).is_some() {
+            Style::default().fg(Color::Yellow)
+        } else if destination == Some(hop.hop) {
+            Style::default().fg(Color::Green)
+        } else if destination.is_some_and(|ttl| hop.hop > ttl) {
+            Style::default().fg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        if selected == Some(index) {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        rows.push(Row::new(cells).style(style));
+        row_hops.push(Some(index));
+
+        for mut alt_texts in alt_rows {
+            fit_host(&mut alt_texts);
+            let alt_cells: Vec<Cell> = ui_state
+                .columns
+                .iter()
+                .zip(alt_texts)
+                .map(|(&column, text)| widgets::aligned_cell(column, text))
+                .collect();
+            rows.push(Row::new(alt_cells));
+            row_hops.push(None);
+        }
+    }
+
+    let constraints: Vec<Constraint> = widths.iter().map(|&width| Constraint::Length(width)).collect();
+    let mut layout = TableLayout {
+        area,
+        columns: Vec::new(),
+        rows: row_hops,
+        offset: 0,
+    };
+    layout.offset = ui_state.scroll.min(rows.len().saturating_sub(layout.visible_rows()));
+    // Laid out as the table lays out its columns
+    let column_areas = Layout::horizontal(&constraints).flex(Flex::Start).spacing(1).split(area);
+    layout.columns = ui_state
+        .columns
+        .iter()
+        .zip(column_areas.iter())
+        .map(|(&column, column_area)| (column, column_area.x, column_area.width))
+        .collect();
+
+    let table = Table::new(rows.into_iter().skip(layout.offset), &constraints).header(header);
+    f.render_widget(table, area);
+    layout
 }
 
 
 
+/// The graph column of a table as inline images, one for each hop row in view
+///
+/// Only the sparkline mode is drawn with images; the others stay text.
+fn table_images(
+    layout: &TableLayout,
+    hops: &[HopStats],
+    ui_state: &UiState,
+    (global_min_rtt, global_max_rtt): (u64, u64),
+) -> Vec<InlineImage> {
+    if ui_state.graphics == GraphicsSupport::None
+        || !matches!(ui_state.visualization_mode, VisualizationMode::Sparkline)
+    {
+        return Vec::new();
+    }
+    let Some(&(_, x, width)) = layout.columns.iter().find(|&&(column, _, _)| column == Column::Graph) else {
+        return Vec::new();
+    };
+    if width == 0 {
+        return Vec::new();
+    }
+    layout
+        .rows
+        .iter()
+        .enumerate()
+        .skip(layout.offset)
+        .take(layout.visible_rows())
+        .filter_map(|(row, index)| {
+            let hop = hops.get((*index)?)?;
+            let area = Rect {
+                x,
+                y: layout.area.y + 1 + (row - layout.offset) as u16,
+                width,
+                height: 1,
+            };
+            let png = graphics::sparkline_image(
+                hop,
+                global_min_rtt,
+                global_max_rtt,
+                ui_state.current_sparkline_scale,
+                width,
+            );
+            Some(InlineImage { area, png })
+        })
+        .collect()
+}
+
 // ========================================
 // Interactive Event Loop
 // ========================================
 
 pub async fn run_interactive(session: MtrSession) -> Result<()> {
+    run_sessions(vec![session]).await
+}
+
+/// Trace a host's IPv4 and IPv6 addresses together, one table above the other
+pub async fn run_dual_stack_interactive(ipv4: MtrSession, ipv6: MtrSession) -> Result<()> {
+    run_sessions(vec![ipv4, ipv6]).await
+}
+
+/// Draw every session in one frame, remembering where the table went
+fn redraw(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    sessions: &[Arc<MtrSession>],
+    ui_state: &mut UiState,
+) -> Result<()> {
+    let sessions: Vec<&MtrSession> = sessions.iter().map(|session| &**session).collect();
+    let mut table_layout = TableLayout::default();
+    let mut images = Vec::new();
+    let frame = terminal.draw(|f| table_layout = render_ui(f, &sessions, ui_state, &mut images))?;
+    ui_state.help_rows = help_popup_area(frame.area, &widgets::help_lines()).height.saturating_sub(2) as usize;
+    ui_state.table_layout = table_layout;
+    if ui_state.graphics != GraphicsSupport::None {
+        let cells = ui_state.image_screen.capture(frame.buffer, &images);
+        ui_state.image_screen.update(terminal.backend_mut(), &cells, images)?;
+    }
+    Ok(())
+}
+
+/// Where the help overlay goes: centered, as big as its lines, within the terminal
+fn help_popup_area(area: Rect, lines: &[Line]) -> Rect {
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 2;
+    let (help_width, help_height) =
+        utils::layout::calculate_popup_dimensions(area.width, area.height, width, lines.len() as u16 + 2);
+    let (help_x, help_y) = utils::layout::center_popup(area.width, area.height, help_width, help_height);
+    Rect {
+        x: help_x,
+        y: help_y,
+        width: help_width,
+        height: help_height,
+    }
+}
+
+/// Ring the terminal bell
+fn ring_bell(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
+    let backend = terminal.backend_mut();
+    backend.write_all(b"\x07")?;
+    backend.flush()?;
+    Ok(())
+}
+
+/// Run the interactive UI over one or more sessions
+///
+/// The first session is the primary: its arguments drive the UI settings,
+/// thresholds and config reloading. The sessions themselves stay as they
+/// were configured; a reloaded config only changes the UI's copy of them
+/// and the thresholds their --on-alert commands check.
+async fn run_sessions(mut sessions: Vec<MtrSession>) -> Result<()> {
+    let mut args = sessions[0].args.clone();
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture, EnableFocusChange)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let session_arc = Arc::new(Mutex::new(session.clone()));
-    let session_clone = Arc::clone(&session_arc);
-
-    let mut ui_state = UiState::new(
-        session.args.sparkline_scale,
-        session.args.get_columns(),
-    );
-
-    let mut event_handler = EventHandler::new();
-
     let (update_tx, mut update_rx) = mpsc::unbounded_channel::<()>();
-
-    {
-        let mut session_guard = session_arc.lock().unwrap();
+    for session in &mut sessions {
         let update_tx_for_callback = update_tx.clone();
-        session_guard.set_update_callback(Arc::new(move || {
+        session.set_update_callback(Arc::new(move || {
             let _ = update_tx_for_callback.send(());
         }));
     }
+    let sessions: Vec<Arc<MtrSession>> = sessions.into_iter().map(Arc::new).collect();
+
+    let mut ui_state = UiState::new(
+        args.sparkline_scale,
+        args.get_columns(),
+        args.rtt_unit,
+    );
+    if args.no_images {
+        ui_state.graphics = GraphicsSupport::None;
+    }
+    if let Some(path) = ColumnWidths::default_path() {
+        match ColumnWidths::load(&path) {
+            Ok(widths) => ui_state.column_widths = widths,
+            Err(e) => warn!("{:#}", e),
+        }
+    }
+    if args.multipath.is_some() {
+        // The tree is what multipath probing is for
+        ui_state.view_mode = ViewMode::Tree;
+    }
 
-    let trace_handle = {
-        let session_for_trace = Arc::clone(&session_clone);
-        tokio::spawn(async move {
-            if let Err(e) = MtrSession::run_trace_with_realtime_updates(session_for_trace).await {
-                debug!("Real-time trace failed: {}", e);
+    let mut event_handler = EventHandler::new();
+
+    let mut thresholds = Thresholds::from_args(&args);
+    let mut config_rx = match args.config {
+        Some(ref path) => {
+            // Colors only live in the UI, so pick them up from the initial file here
+            if let Ok(config) = Config::load(path) {
+                ui_state.apply_config(&config);
             }
-        })
+            config::spawn_reloader(path.clone())?
+        }
+        None => mpsc::unbounded_channel().1,
     };
+    let unfocused_redraw = args.unfocused_redraw.map(Duration::from_millis);
+    let unfocused_interval = args.unfocused_interval.map(Duration::from_millis);
+    let mut focused_interval = sessions[0].current_interval(); // What to go back to on refocus, maybe changed with +/-
+    let mut last_draw = Instant::now();
+
+    let trace_handles: Vec<_> = sessions
+        .iter()
+        .map(|session| {
+            let session_for_trace = Arc::clone(session);
+            tokio::spawn(async move {
+                if let Err(e) = MtrSession::run_trace_with_realtime_updates(session_for_trace).await {
+                    debug!("Real-time trace failed: {}", e);
+                }
+            })
+        })
+        .collect();
 
     // Create a channel for keyboard input events
     let (input_tx, mut input_rx) = mpsc::unbounded_channel::<crossterm::event::Event>();
@@ -385,16 +899,78 @@ pub async fn run_interactive(session: MtrSession) -> Result<()> {
                     break;
                 }
                 
+                // Sound the alarm as the end-to-end hop goes over a threshold
+                let breach = sessions.iter().find_map(|session| thresholds.check_session(session));
+                if ui_state.update_breach(breach) && !args.no_bell {
+                    ring_bell(&mut terminal)?;
+                }
+
+                if !ui_state.focused {
+                    // Throttle redraws while nobody is looking
+                    if let Some(min_gap) = unfocused_redraw {
+                        if last_draw.elapsed() < min_gap {
+                            continue;
+                        }
+                    }
+                }
+
+                // A frozen display keeps showing what it had
+                if ui_state.frozen.is_some() {
+                    continue;
+                }
+
                 // Update UI immediately when new data arrives
-                terminal.draw(|f| {
-                    let session_guard = session_clone.lock().unwrap();
-                    render_ui(f, &session_guard, &ui_state)
-                })?;
+                redraw(&mut terminal, &sessions, &mut ui_state)?;
+                last_draw = Instant::now();
             }
             
+            // Apply a reloaded config file in place
+            Some(config) = config_rx.recv() => {
+                config.apply_to_args(&mut args);
+                thresholds = Thresholds::from_args(&args);
+                for session in &sessions {
+                    if let Some(ref alert) = session.alert {
+                        alert.set_thresholds(thresholds);
+                    }
+                }
+                if let Some(ref path) = args.config {
+                    for session in &sessions {
+                        session.publish(&crate::output::Event::config_reloaded(path));
+                    }
+                }
+                ui_state.apply_config(&config);
+                ui_state.notice = Some("↻ Configuration reloaded".to_string());
+
+                redraw(&mut terminal, &sessions, &mut ui_state)?;
+                last_draw = Instant::now();
+            }
+
             // Handle keyboard input events immediately
             input_event = input_rx.recv() => {
-                if let Some(Event::Key(key)) = input_event {
+                if let Some(Event::FocusLost) = input_event {
+                    ui_state.focus_lost();
+                    if let Some(interval) = unfocused_interval {
+                        focused_interval = sessions[0].current_interval();
+                        for session in &sessions {
+                            session.set_interval(interval);
+                        }
+                    }
+                } else if let Some(Event::FocusGained) = input_event {
+                    if unfocused_interval.is_some() {
+                        for session in &sessions {
+                            session.set_interval(focused_interval);
+                        }
+                    }
+                    if ui_state.focus_gained() && !args.no_bell {
+                        // Ring the terminal bell to flag what happened while away
+                        ring_bell(&mut terminal)?;
+                    }
+                    redraw(&mut terminal, &sessions, &mut ui_state)?;
+                    last_draw = Instant::now();
+                } else if let Some(Event::Key(key)) = input_event {
+                    // Any key acknowledges a pending notice
+                    ui_state.notice = None;
+
                     // Handle column selector popup inputs first
                     if ui_state.show_column_selector {
                         event_handler.handle_column_selector_input(
@@ -402,12 +978,24 @@ pub async fn run_interactive(session: MtrSession) -> Result<()> {
                             key.modifiers,
                             &mut ui_state,
                         );
+                    } else if ui_state.show_help && event_handler.handle_help_input(key.code, &mut ui_state) {
+                        // Scrolled or closed the help
+                    } else if ui_state.show_export_prompt {
+                        event_handler.handle_export_input(key.code, &mut ui_state, &sessions);
+                    } else if ui_state.show_hop_graph && key.code == KeyCode::Esc {
+                        // Esc goes back to the table rather than quitting
+                        ui_state.toggle_hop_graph();
+                    } else if ui_state.show_hop_detail && key.code == KeyCode::Esc {
+                        // Esc closes the hop details rather than quitting
+                        ui_state.toggle_hop_detail();
+                    } else if ui_state.show_route_events && key.code == KeyCode::Esc {
+                        ui_state.toggle_route_events();
                     } else {
                         // Handle normal keyboard shortcuts
                         let should_continue = event_handler.handle_normal_input(
                             key.code,
                             &mut ui_state,
-                            &session_clone,
+                            &sessions,
                         );
                         if !should_continue {
                             break;
@@ -415,10 +1003,14 @@ pub async fn run_interactive(session: MtrSession) -> Result<()> {
                     }
                     
                     // ALWAYS redraw UI immediately after keyboard input
-                    terminal.draw(|f| {
-                        let session_guard = session_clone.lock().unwrap();
-                        render_ui(f, &session_guard, &ui_state)
-                    })?;
+                    redraw(&mut terminal, &sessions, &mut ui_state)?;
+                    last_draw = Instant::now();
+                } else if let Some(Event::Mouse(mouse)) = input_event {
+                    // Motion is reported too, so only redraw for what was handled
+                    if event_handler.handle_mouse(mouse, &mut ui_state) {
+                        redraw(&mut terminal, &sessions, &mut ui_state)?;
+                        last_draw = Instant::now();
+                    }
                 } else if input_event.is_none() {
                     // Input channel closed
                     break;
@@ -428,14 +1020,44 @@ pub async fn run_interactive(session: MtrSession) -> Result<()> {
     }
 
     input_handle.abort();
-    trace_handle.abort();
+    // Let the probes in flight come back so the final statistics are settled,
+    // but don't keep the user waiting on a slow path
+    for session in &sessions {
+        session.shutdown();
+    }
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_WAIT;
+    let mut unfinished = Vec::new();
+    for (session, mut handle) in sessions.iter().zip(trace_handles) {
+        if tokio::time::timeout_at(deadline, &mut handle).await.is_err() {
+            session.abandon();
+            unfinished.push(handle);
+        }
+    }
+    let deadline = tokio::time::Instant::now() + ABANDON_WAIT;
+    for handle in unfinished {
+        let abort = handle.abort_handle();
+        if tokio::time::timeout_at(deadline, handle).await.is_err() {
+            abort.abort();
+        }
+    }
+    let mut saved = crate::report::write_html(&sessions.iter().map(|session| &**session).collect::<Vec<_>>());
+    for session in &sessions {
+        session.finish_outputs().await;
+        if let Err(e) = session.save_baselines() {
+            warn!("Failed to save baselines: {:#}", e);
+        }
+        if saved.is_ok() {
+            saved = session.save_state();
+        }
+    }
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
-        DisableMouseCapture
+        DisableMouseCapture,
+        DisableFocusChange
     )?;
     terminal.show_cursor()?;
 
-    Ok(())
+    saved
 }
@@ -3,13 +3,15 @@
 //! This module provides terminal-based user interface components for mtr-ng.
 
 pub mod events;
+pub mod graphics;
 pub mod state;
+pub mod topology;
 pub mod visualization;
 pub mod widgets;
 
 // Re-export commonly used types
 pub use events::EventHandler;
-pub use state::UiState;
+pub use state::{UiState, ViewMode};
 pub use visualization::{ColorSupport, VisualizationMode};
 pub use widgets::ColumnSelectorState;
 
@@ -3,43 +3,190 @@
 //! This module manages all UI state including display settings, column configuration,
 //! and user interface modes for the mtr-ng terminal application.
 
+use crate::alerts::Breach;
 use crate::args::Column;
+use crate::config::Config;
+use crate::ui::graphics::{detect_graphics_support, GraphicsSupport, ImageScreen};
 use crate::ui::visualization::{detect_color_support, ColorSupport, VisualizationMode};
 use crate::ui::widgets::ColumnSelectorState;
-use crate::SparklineScale;
+use crate::{utils, HopStats, Result, RttUnit, SparklineScale};
+use ratatui::layout::Rect;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
 // ========================================
 // UI State Management
 // ========================================
 
+/// How the hop list is laid out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewMode {
+    Table, // One row per hop, alternates as indented sub-rows
+    Tree,  // Route topology tree with a branch per ECMP responder
+}
+
+/// Where the hop table was last drawn, so mouse clicks can be mapped onto it
+#[derive(Debug, Clone, Default)]
+pub struct TableLayout {
+    pub area: Rect,                       // Header line included
+    pub columns: Vec<(Column, u16, u16)>, // Each column with its x and width
+    pub rows: Vec<Option<usize>>,         // Hop index of every row, None for alternate paths
+    pub offset: usize,                    // Rows scrolled past at the top
+}
+
+impl TableLayout {
+    /// Rows that fit below the header
+    pub fn visible_rows(&self) -> usize {
+        self.area.height.saturating_sub(1) as usize
+    }
+}
+
+/// Widths picked for the Host and Graph columns, kept between runs
+///
+/// None leaves a column to the table: Host fits its names, Graph takes the
+/// space that's left.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnWidths {
+    pub host: Option<u16>,
+    pub graph: Option<u16>,
+}
+
+impl ColumnWidths {
+    /// `$XDG_DATA_HOME/mtr-ng/columns.json`, falling back to `~/.local/share`
+    pub fn default_path() -> Option<PathBuf> {
+        Some(utils::paths::data_dir()?.join("columns.json"))
+    }
+
+    /// Load the widths, treating a missing file as no choice made
+    pub fn load(path: &Path) -> Result<Self> {
+        utils::paths::load_json_or_default(path, "column width")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        utils::paths::write_atomic(path, &serde_json::to_string_pretty(self)?)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UiState {
     pub current_sparkline_scale: SparklineScale,
     pub color_support: ColorSupport,
+    pub graphics: GraphicsSupport, // Whether graphs are drawn as inline images
+    pub image_screen: ImageScreen, // Inline images on screen after the last frame
     pub columns: Vec<Column>,
     pub current_column_index: usize,
     pub show_help: bool,
+    pub help_scroll: usize, // Help lines scrolled past at the top
+    pub help_rows: usize,   // Help lines that fit in the last frame
     pub visualization_mode: VisualizationMode,
     pub show_hostnames: bool, // Toggle between hostnames and IP addresses
     pub show_column_selector: bool, // Show column selection popup
     pub column_selector_state: ColumnSelectorState, // State for column selector
+    pub focused: bool, // Whether the terminal window currently has focus
+    pub away_breach: Option<Breach>, // First threshold breach seen while unfocused
+    pub away_bell: bool, // A breach began while unfocused, so the bell already rang for it
+    pub breach: Option<Breach>, // Threshold breach going on now, flagged in the status line
+    pub notice: Option<String>, // One-shot message shown in the status line
+    pub rtt_unit: RttUnit, // Unit used for RTT values
+    pub view_mode: ViewMode, // Table or topology tree
+    pub selected_hop: Option<usize>, // Hop index picked with the arrow keys
+    pub show_hop_detail: bool, // Show the detail popup for the selected hop
+    pub show_route_events: bool, // Show the route change event list
+    pub show_hop_graph: bool, // Full-screen RTT chart of the selected hop
+    pub collapse_paths: bool, // Fold alternate paths into their hop's row by default
+    pub path_toggles: HashSet<u8>, // TTLs of hops doing the opposite of collapse_paths
+    pub show_export_prompt: bool, // Asking which format to write a snapshot in
+    pub frozen: Option<Vec<Vec<HopStats>>>, // Hops on screen while the display is frozen
+    pub sort_column: Option<Column>, // Column the table is sorted by, None for TTL order
+    pub column_widths: ColumnWidths, // Host and Graph widths set with [ ] { }
+    pub scroll: usize, // Table rows scrolled past at the top
+    pub table_layout: TableLayout, // Where the table went in the last frame
 }
 
 impl UiState {
     /// Create a new UI state with default settings
-    pub fn new(scale: SparklineScale, columns: Vec<Column>) -> Self {
+    pub fn new(scale: SparklineScale, columns: Vec<Column>, rtt_unit: RttUnit) -> Self {
         let column_selector_state = ColumnSelectorState::new(&columns);
         Self {
             current_sparkline_scale: scale,
             color_support: detect_color_support(),
+            graphics: detect_graphics_support(),
+            image_screen: ImageScreen::default(),
             columns,
             current_column_index: 0,
             show_help: false,
+            help_scroll: 0,
+            help_rows: 0,
             visualization_mode: VisualizationMode::Sparkline,
             show_hostnames: true, // Start with hostnames enabled by default
             show_column_selector: false,
             column_selector_state,
+            focused: true,
+            away_breach: None,
+            away_bell: false,
+            breach: None,
+            notice: None,
+            rtt_unit,
+            view_mode: ViewMode::Table,
+            selected_hop: None,
+            show_hop_detail: false,
+            show_route_events: false,
+            show_hop_graph: false,
+            collapse_paths: false,
+            path_toggles: HashSet::new(),
+            show_export_prompt: false,
+            frozen: None,
+            sort_column: None,
+            column_widths: ColumnWidths::default(),
+            scroll: 0,
+            table_layout: TableLayout::default(),
+        }
+    }
+
+    // ========================================
+    // Focus and Alert Tracking
+    // ========================================
+
+    /// Record that the terminal lost focus
+    pub fn focus_lost(&mut self) {
+        self.focused = false;
+        self.away_breach = None;
+        self.away_bell = false;
+    }
+
+    /// Record that the terminal regained focus
+    ///
+    /// When a threshold was breached while the user was away a notice is
+    /// queued for the status line. Returns true when the bell should flag it
+    /// too, which is when it didn't already ring as the breach began.
+    pub fn focus_gained(&mut self) -> bool {
+        self.focused = true;
+        match self.away_breach.take() {
+            Some(breach) => {
+                self.notice = Some(format!("⚠ While away: {}", breach));
+                !self.away_bell
+            }
+            None => false,
+        }
+    }
+
+    /// Note the current threshold breach, if any, remembering the first seen while unfocused
+    ///
+    /// Returns true when a breach has just begun, which is when the bell
+    /// rings; it doesn't ring again until the path is back within limits.
+    pub fn update_breach(&mut self, breach: Option<Breach>) -> bool {
+        let started = self.breach.is_none() && breach.is_some();
+        if !self.focused {
+            self.away_bell |= started;
+            if self.away_breach.is_none() {
+                self.away_breach = breach.clone();
+            }
         }
+        self.breach = breach;
+        started
     }
 
     // ========================================
@@ -49,6 +196,13 @@ impl UiState {
     /// Toggle the help overlay visibility
     pub fn toggle_help(&mut self) {
         self.show_help = !self.show_help;
+        self.help_scroll = 0;
+    }
+
+    /// Scroll the help by `delta` lines, keeping the last of its `lines` at the bottom
+    pub fn scroll_help(&mut self, delta: isize, lines: usize) {
+        let max = lines.saturating_sub(self.help_rows);
+        self.help_scroll = self.help_scroll.saturating_add_signed(delta).min(max);
     }
 
     /// Toggle the column selector popup visibility
@@ -62,6 +216,119 @@ impl UiState {
         self.show_column_selector = !self.show_column_selector;
     }
 
+    /// Toggle the detail popup for the selected hop
+    pub fn toggle_hop_detail(&mut self) {
+        self.show_hop_detail = !self.show_hop_detail && self.selected_hop.is_some();
+    }
+
+    /// Toggle the full-screen chart of the selected hop
+    pub fn toggle_hop_graph(&mut self) {
+        self.show_hop_graph = !self.show_hop_graph && self.selected_hop.is_some();
+    }
+
+    /// Whether the alternate paths of the hop with this TTL are folded into its row
+    pub fn paths_collapsed(&self, ttl: u8) -> bool {
+        self.collapse_paths != self.path_toggles.contains(&ttl)
+    }
+
+    /// Collapse or expand the alternate paths of one hop
+    pub fn toggle_paths(&mut self, ttl: u8) {
+        if !self.path_toggles.remove(&ttl) {
+            self.path_toggles.insert(ttl);
+        }
+    }
+
+    /// Collapse or expand the alternate paths of every hop, forgetting per-hop choices
+    pub fn toggle_all_paths(&mut self) {
+        self.collapse_paths = !self.collapse_paths;
+        self.path_toggles.clear();
+    }
+
+    /// Toggle the prompt for a snapshot's format
+    pub fn toggle_export_prompt(&mut self) {
+        self.show_export_prompt = !self.show_export_prompt;
+    }
+
+    /// Toggle the route change event list
+    pub fn toggle_route_events(&mut self) {
+        self.show_route_events = !self.show_route_events;
+    }
+
+    /// Freeze the display on the hops `snapshot` returns, or let it follow the trace again
+    ///
+    /// Probing carries on while frozen; the statistics just aren't shown
+    /// until the display is unfrozen.
+    pub fn toggle_frozen(&mut self, snapshot: impl FnOnce() -> Vec<Vec<HopStats>>) {
+        self.frozen = match self.frozen {
+            Some(_) => None,
+            None => Some(snapshot()),
+        };
+    }
+
+    /// Move the hop selection to the next or previous of the given hop indices
+    ///
+    /// With nothing selected yet, this picks the first (or last) hop.
+    pub fn select_hop(&mut self, hops: &[usize], forward: bool) {
+        let position = self
+            .selected_hop
+            .and_then(|selected| hops.iter().position(|&index| index == selected));
+        self.selected_hop = match (position, forward) {
+            (None, true) => hops.first(),
+            (None, false) => hops.last(),
+            (Some(position), true) => hops.get(position + 1).or(hops.last()),
+            (Some(position), false) => hops.get(position.saturating_sub(1)),
+        }
+        .copied();
+        self.scroll_to_selected_hop();
+    }
+
+    /// Scroll the table just far enough to show the selected hop
+    fn scroll_to_selected_hop(&mut self) {
+        let layout = &self.table_layout;
+        let Some(row) = layout.rows.iter().position(|&hop| hop.is_some() && hop == self.selected_hop) else {
+            return;
+        };
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + layout.visible_rows() {
+            self.scroll = row + 1 - layout.visible_rows();
+        }
+    }
+
+    /// Scroll the table by `rows`, up for negative values
+    pub fn scroll_table(&mut self, rows: isize) {
+        let layout = &self.table_layout;
+        let last = layout.rows.len().saturating_sub(layout.visible_rows());
+        self.scroll = layout.offset.saturating_add_signed(rows).min(last);
+    }
+
+    /// Sort the table by `column`, or go back to TTL order if it already is
+    pub fn sort_by(&mut self, column: Column) {
+        self.sort_column = match column {
+            Column::Hop | Column::Graph => None,
+            column if self.sort_column == Some(column) => None,
+            column => Some(column),
+        };
+    }
+
+    /// Sort by the next (or previous) shown column, TTL order coming between the last and the first
+    pub fn cycle_sort_column(&mut self, forward: bool) {
+        let mut order: Vec<Option<Column>> = vec![None];
+        order.extend(
+            self.columns
+                .iter()
+                .filter(|column| !matches!(column, Column::Hop | Column::Graph))
+                .map(|&column| Some(column)),
+        );
+        let position = order.iter().position(|&column| column == self.sort_column).unwrap_or(0);
+        let next = if forward {
+            (position + 1) % order.len()
+        } else {
+            (position + order.len() - 1) % order.len()
+        };
+        self.sort_column = order[next];
+    }
+
     // ========================================
     // Column Selector Immediate Update Methods
     // ========================================
@@ -103,11 +370,22 @@ impl UiState {
     // Display Mode Management
     // ========================================
 
-    /// Toggle between sparkline and heatmap visualization modes
+    /// Cycle through the sparkline, heatmap, histogram, loss and jitter visualization modes
     pub fn toggle_visualization_mode(&mut self) {
         self.visualization_mode = match self.visualization_mode {
             VisualizationMode::Sparkline => VisualizationMode::Heatmap,
-            VisualizationMode::Heatmap => VisualizationMode::Sparkline,
+            VisualizationMode::Heatmap => VisualizationMode::Histogram,
+            VisualizationMode::Histogram => VisualizationMode::Loss,
+            VisualizationMode::Loss => VisualizationMode::Jitter,
+            VisualizationMode::Jitter => VisualizationMode::Sparkline,
+        };
+    }
+
+    /// Toggle between the hop table and the topology tree
+    pub fn toggle_view_mode(&mut self) {
+        self.view_mode = match self.view_mode {
+            ViewMode::Table => ViewMode::Tree,
+            ViewMode::Tree => ViewMode::Table,
         };
     }
 
@@ -124,6 +402,32 @@ impl UiState {
         };
     }
 
+    /// Apply the display settings from a (re)loaded config file
+    pub fn apply_config(&mut self, config: &Config) {
+        if let Some(ref fields) = config.fields {
+            self.columns = fields.clone();
+            self.column_selector_state = ColumnSelectorState::new(&self.columns);
+        }
+        if let Some(color) = config.color {
+            self.color_support = color;
+        }
+        if let Some(scale) = config.sparkline_scale {
+            self.current_sparkline_scale = scale;
+        }
+        if let Some(unit) = config.rtt_unit {
+            self.rtt_unit = unit;
+        }
+    }
+
+    /// Cycle through RTT display units
+    pub fn cycle_rtt_unit(&mut self) {
+        self.rtt_unit = match self.rtt_unit {
+            RttUnit::Ms => RttUnit::Us,
+            RttUnit::Us => RttUnit::Auto,
+            RttUnit::Auto => RttUnit::Ms,
+        };
+    }
+
     /// Cycle through available color support modes
     pub fn cycle_color_mode(&mut self) {
         self.color_support = match self.color_support {
@@ -165,6 +469,23 @@ impl UiState {
         }
     }
 
+    /// Widen (positive `delta`) or narrow the Host or Graph column
+    ///
+    /// Starts from the width it had in the last frame, so the first step is
+    /// away from the fitted width. Returns false when the column isn't shown.
+    pub fn resize_column(&mut self, column: Column, delta: i16) -> bool {
+        let layout = &self.table_layout;
+        let Some(&(_, _, width)) = layout.columns.iter().find(|&&(shown, _, _)| shown == column) else {
+            return false;
+        };
+        let width = Some(width.saturating_add_signed(delta).clamp(1, layout.area.width.max(1)));
+        match column {
+            Column::Graph => self.column_widths.graph = width,
+            _ => self.column_widths.host = width,
+        }
+        true
+    }
+
     /// Remove a column from the display
     pub fn remove_column(&mut self, column: Column) {
         if let Some(pos) = self.columns.iter().position(|&c| c == column) {
@@ -174,34 +495,4 @@ impl UiState {
             }
         }
     }
-
-    // ========================================
-    // Header Generation (Legacy)
-    // ========================================
-
-    /// Generate header string for the current column configuration
-    /// 
-    /// Note: This method is legacy and may be removed in favor of widget-based headers
-    pub fn get_header(&self) -> String {
-        let mut header = String::from("  ");
-        for (i, column) in self.columns.iter().enumerate() {
-            if i > 0 {
-                header.push(' ');
-            }
-            match column {
-                Column::Hop => {} // No header for hop number column (3 chars: "XX.")
-                Column::Host => header.push_str(&format!("{:21}", column.header())), // 21 chars
-                Column::Loss => header.push_str(&format!("{:>7}", column.header())), // 7 chars for "XX.X%"
-                Column::Sent => header.push_str(&format!("{:>4}", column.header())), // 4 chars
-                Column::Last | Column::Avg | Column::Ema | Column::Best | Column::Worst => {
-                    header.push_str(&format!("{:>9}", column.header())); // 9 chars for "XXX.Xms"
-                }
-                Column::Jitter | Column::JitterAvg => {
-                    header.push_str(&format!("{:>9}", column.header())); // 9 chars for "XXX.Xms"
-                }
-                Column::Graph => header.push_str(column.header()), // Variable width
-            }
-        }
-        header
-    }
 } 
\ No newline at end of file
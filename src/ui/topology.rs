@@ -0,0 +1,204 @@
+//! Route Topology Tree View
+//!
+//! Renders the discovered path as a tree instead of a table. Hops answered by a
+//! single router form the trunk; where ECMP load balancing makes several
+//! routers answer for the same TTL, the hop splits into one branch per
+//! responder with its share of the replies and its own average RTT.
+
+use crate::hop_stats::HopStats;
+use crate::ui::state::UiState;
+use crate::ui::widgets;
+use crate::utils;
+use crate::MtrSession;
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::Paragraph,
+};
+use std::{net::IpAddr, time::Duration};
+
+/// Width reserved for host names in the tree
+const NAME_WIDTH: usize = 40;
+
+/// One responder at a hop
+#[derive(Debug, Clone, PartialEq)]
+struct Branch {
+    name: String,
+    share: Option<f64>,
+    avg_rtt: Option<Duration>,
+}
+
+/// Build the tree view for every displayed hop
+pub fn create_topology_tree(session: &MtrSession, hops: &[HopStats], ui_state: &UiState) -> Paragraph<'static> {
+    let mut lines = vec![Line::from(Span::styled(
+        format!(
+            "{:>3} {:<width$} {:>6} {:>9}",
+            "",
+            "Host",
+            "Share",
+            "Avg",
+            width = NAME_WIDTH + 3
+        ),
+        Style::default().fg(Color::Yellow),
+    ))];
+
+    for hop in hops
+        .iter()
+        .take(session.display_hop_count())
+        .filter(|hop| hop.sent > 0)
+    {
+        lines.extend(hop_lines(hop, &hop_branches(session, hop, ui_state), ui_state));
+    }
+
+    Paragraph::new(lines)
+}
+
+/// Responders at a hop, most frequent first
+fn hop_branches(session: &MtrSession, hop: &HopStats, ui_state: &UiState) -> Vec<Branch> {
+    if !hop.has_multiple_paths() {
+        return vec![Branch {
+            name: utils::network::truncate_hostname(&widgets::format_hostname(session, hop, ui_state), NAME_WIDTH),
+            share: None,
+            avg_rtt: hop.avg_rtt,
+        }];
+    }
+
+    let show_names = ui_state.show_hostnames && !session.args.numeric;
+    let mut branches = Vec::new();
+    if let Some(addr) = hop.addr {
+        branches.push((
+            hop.get_primary_path_percentage(),
+            Branch {
+                name: branch_name(addr, hop.hostname.as_deref(), show_names),
+                share: None,
+                avg_rtt: hop.avg_rtt,
+            },
+        ));
+    }
+    for path in hop.get_alternate_paths() {
+        branches.push((
+            hop.get_path_percentage(path),
+            Branch {
+                name: branch_name(path.addr, path.hostname.as_deref(), show_names),
+                share: None,
+                avg_rtt: path.avg_rtt,
+            },
+        ));
+    }
+
+    branches.sort_by(|a, b| b.0.total_cmp(&a.0));
+    branches
+        .into_iter()
+        .map(|(share, branch)| Branch {
+            share: Some(share),
+            ..branch
+        })
+        .collect()
+}
+
+fn branch_name(addr: IpAddr, hostname: Option<&str>, show_names: bool) -> String {
+    let name = match hostname {
+        Some(hostname) if show_names => format!("{} ({})", hostname, addr),
+        _ => addr.to_string(),
+    };
+    if name.chars().count() > NAME_WIDTH {
+        let truncated: String = name.chars().take(NAME_WIDTH - 3).collect();
+        format!("{}...", truncated)
+    } else {
+        name
+    }
+}
+
+/// Render a hop: a trunk node, or a split with one line per branch
+fn hop_lines(hop: &HopStats, branches: &[Branch], ui_state: &UiState) -> Vec<Line<'static>> {
+    let branch_style = Style::default().fg(Color::Cyan);
+    let last = branches.len().saturating_sub(1);
+
+    branches
+        .iter()
+        .enumerate()
+        .map(|(i, branch)| {
+            let label = if i == 0 {
+                format!("{:>2}.", hop.hop)
+            } else {
+                "   ".to_string()
+            };
+            let glyph = match (branches.len(), i) {
+                (1, _) => "●─ ",
+                (_, 0) => "┬─ ",
+                (_, i) if i == last => "└─ ",
+                _ => "├─ ",
+            };
+            let share = branch
+                .share
+                .map(|share| format!("{:.0}%", share))
+                .unwrap_or_default();
+
+            Line::from(vec![
+                Span::raw(format!("{} ", label)),
+                Span::styled(glyph, if branches.len() > 1 { branch_style } else { Style::default() }),
+                Span::raw(format!("{:<width$}", branch.name, width = NAME_WIDTH)),
+                Span::styled(format!(" {:>6}", share), Style::default().fg(Color::Yellow)),
+                Span::raw(format!(
+                    " {:>9}",
+                    utils::time::format_optional_rtt(branch.avg_rtt, ui_state.rtt_unit)
+                )),
+            ])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Column;
+    use crate::{RttUnit, SparklineScale};
+
+    fn line_text(line: &Line) -> String {
+        line.spans.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn test_split_hop_renders_branches() {
+        let ui_state = UiState::new(SparklineScale::Linear, vec![Column::Host], RttUnit::Ms);
+        let mut hop = HopStats::new(3);
+        hop.increment_sent();
+        hop.add_rtt_from_addr("10.1.0.1".parse().unwrap(), Duration::from_millis(8));
+
+        let branches = vec![
+            Branch {
+                name: "10.1.0.1".to_string(),
+                share: Some(62.0),
+                avg_rtt: Some(Duration::from_millis(8)),
+            },
+            Branch {
+                name: "10.1.0.2".to_string(),
+                share: Some(30.0),
+                avg_rtt: Some(Duration::from_millis(9)),
+            },
+            Branch {
+                name: "10.1.0.3".to_string(),
+                share: Some(8.0),
+                avg_rtt: None,
+            },
+        ];
+        let lines: Vec<String> = hop_lines(&hop, &branches, &ui_state).iter().map(line_text).collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(" 3. ┬─ 10.1.0.1"));
+        assert!(lines[0].contains("62%"));
+        assert!(lines[1].starts_with("    ├─ 10.1.0.2"));
+        assert!(lines[2].starts_with("    └─ 10.1.0.3"));
+        assert!(lines[2].ends_with("???"));
+    }
+
+    #[test]
+    fn test_branch_name_truncation() {
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(branch_name(addr, Some("r1.example"), true), "r1.example (192.0.2.1)");
+        assert_eq!(branch_name(addr, Some("r1.example"), false), "192.0.2.1");
+
+        let long = "a".repeat(60);
+        assert_eq!(branch_name(addr, Some(&long), true).chars().count(), NAME_WIDTH);
+    }
+}
@@ -5,6 +5,7 @@
 
 use crate::utils;
 use crate::{HopStats, SparklineScale};
+use serde::Deserialize;
 use ratatui::{
     style::Style,
     text::Span,
@@ -14,7 +15,8 @@ use ratatui::{
 // Public Types
 // ========================================
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum ColorSupport {
     None,      // No color support
     Basic,     // 16 colors
@@ -26,6 +28,21 @@ pub enum ColorSupport {
 pub enum VisualizationMode {
     Sparkline, // Variable height characters (▁▂▃▄▅▆▇█)
     Heatmap,   // Full height blocks (█) with colors only
+    Histogram, // How many recent RTTs fell at each point of the RTT scale
+    Loss,      // Share of probes lost over time, whatever the RTT of the rest
+    Jitter,    // RTT change from one reply to the next
+}
+
+impl VisualizationMode {
+    /// Header of the graph column in this mode
+    pub fn graph_title(&self) -> &'static str {
+        match self {
+            VisualizationMode::Sparkline | VisualizationMode::Heatmap => "RTT History",
+            VisualizationMode::Histogram => "RTT Distribution",
+            VisualizationMode::Loss => "Loss History",
+            VisualizationMode::Jitter => "Jitter History",
+        }
+    }
 }
 
 // ========================================
@@ -231,6 +248,20 @@ pub fn create_sparkline_spans(
     spans
 }
 
+/// Render spans as text with ANSI color codes, for drawing outside the UI
+pub fn spans_to_ansi(spans: &[Span]) -> String {
+    use crossterm::style::{ResetColor, SetForegroundColor};
+
+    let mut out = String::new();
+    for span in spans {
+        match span.style.fg {
+            Some(color) => out.push_str(&format!("{}{}{}", SetForegroundColor(color.into()), span.content, ResetColor)),
+            None => out.push_str(&span.content),
+        }
+    }
+    out
+}
+
 /// Generate colored heatmap spans for RTT visualization (full-height blocks)
 pub fn create_heatmap_spans(
     hop: &HopStats,
@@ -285,6 +316,145 @@ pub fn create_heatmap_spans(
     spans
 }
 
+/// Generate histogram spans: the recent RTTs counted into one bucket per column
+///
+/// The buckets follow the scale the sparklines use, shared by all hops, so
+/// bars in the same place on different rows mean the same RTT and take its
+/// color on the scale below the table. Bar height is the bucket's share of the hop's fullest one;
+/// two humps mean two kinds of delay, such as queueing on top of the path.
+pub fn create_histogram_spans(
+    hop: &HopStats,
+    global_min_rtt: u64,
+    global_max_rtt: u64,
+    scale: SparklineScale,
+    color_support: ColorSupport,
+    max_width: usize,
+) -> Vec<Span<'static>> {
+    if hop.sent == 0 || max_width == 0 {
+        return vec![];
+    }
+
+    let mut counts = vec![0usize; max_width];
+    for rtt in &hop.rtts {
+        let rtt_ms = utils::time::duration_to_ms_u64(*rtt);
+        let ratio = calculate_rtt_ratio(rtt_ms, global_min_rtt, global_max_rtt, scale);
+        counts[((ratio * max_width as f64) as usize).min(max_width - 1)] += 1;
+    }
+    let most = counts.iter().copied().max().unwrap_or(0).max(1);
+
+    counts
+        .iter()
+        .enumerate()
+        .map(|(bucket, &count)| {
+            if count == 0 {
+                return Span::raw(" ");
+            }
+            let char = utils::visualization::get_sparkline_char(count as f64 / most as f64);
+            let color = colors::get_smooth_gradient_color(bucket as f64 / max_width as f64, color_support);
+            Span::styled(char.to_string(), Style::default().fg(color))
+        })
+        .collect()
+}
+
+/// Generate loss spans: the share of probes lost in each slice of the history
+///
+/// The packet history is cut into as few equal slices as fit the width, so
+/// a bar is one probe until the history outgrows the column. Slices without
+/// loss stay a low bar, bursts of loss stand up in the loss color however
+/// healthy the replies around them look.
+pub fn create_loss_spans(hop: &HopStats, color_support: ColorSupport, max_width: usize) -> Vec<Span<'static>> {
+    if hop.sent == 0 || max_width == 0 {
+        return vec![];
+    }
+
+    let outcomes: Vec<_> = hop.packet_history.iter().collect();
+    let slice = outcomes.len().div_ceil(max_width).max(1);
+    let mut spans: Vec<Span<'static>> = outcomes
+        .chunks(slice)
+        .map(|outcomes| {
+            let lost = outcomes
+                .iter()
+                .filter(|outcome| matches!(outcome, crate::hop_stats::PacketOutcome::Lost))
+                .count();
+            let settled = outcomes
+                .iter()
+                .filter(|outcome| !matches!(outcome, crate::hop_stats::PacketOutcome::Pending))
+                .count();
+            if settled == 0 {
+                let color = colors::get_pending_color(color_support);
+                Span::styled("?".to_string(), Style::default().fg(color))
+            } else if lost == 0 {
+                let color = colors::get_smooth_gradient_color(0.0, color_support);
+                Span::styled("▁".to_string(), Style::default().fg(color))
+            } else {
+                let char = utils::visualization::get_sparkline_char(lost as f64 / settled as f64);
+                let color = colors::get_loss_color(color_support);
+                Span::styled(char.to_string(), Style::default().fg(color))
+            }
+        })
+        .collect();
+
+    if spans.len() < max_width {
+        spans.push(Span::raw(" ".repeat(max_width - spans.len())));
+    }
+
+    spans
+}
+
+/// Generate jitter spans: each reply's RTT difference from the reply before
+///
+/// Like the jitter columns, a lost probe doesn't reset the comparison, and
+/// the first reply has nothing to compare with. `global_min_jitter` and
+/// `global_max_jitter` span all hops' jitter, so steady hops stay low
+/// however far away they are.
+pub fn create_jitter_spans(
+    hop: &HopStats,
+    global_min_jitter: u64,
+    global_max_jitter: u64,
+    scale: SparklineScale,
+    color_support: ColorSupport,
+    max_width: usize,
+) -> Vec<Span<'static>> {
+    if hop.sent == 0 || max_width == 0 {
+        return vec![];
+    }
+
+    let mut previous = None;
+    let spans: Vec<Span<'static>> = hop
+        .packet_history
+        .iter()
+        .map(|outcome| match outcome {
+            crate::hop_stats::PacketOutcome::Received(rtt) => match previous.replace(*rtt) {
+                Some(previous) => {
+                    let jitter = utils::time::calculate_timing_jitter(*rtt, previous);
+                    let jitter_ms = utils::time::duration_to_ms_u64(jitter);
+                    let ratio = calculate_rtt_ratio(jitter_ms, global_min_jitter, global_max_jitter, scale);
+                    let char = utils::visualization::get_sparkline_char(ratio);
+                    let color = colors::get_smooth_gradient_color(ratio, color_support);
+                    Span::styled(char.to_string(), Style::default().fg(color))
+                }
+                None => Span::raw(" "),
+            },
+            crate::hop_stats::PacketOutcome::Lost => {
+                let color = colors::get_loss_color(color_support);
+                Span::styled("·".to_string(), Style::default().fg(color))
+            }
+            crate::hop_stats::PacketOutcome::Pending => {
+                let color = colors::get_pending_color(color_support);
+                Span::styled("?".to_string(), Style::default().fg(color))
+            }
+        })
+        .collect();
+
+    // Only the newest probes that fit are shown
+    let mut spans = spans[spans.len().saturating_sub(max_width)..].to_vec();
+    if spans.len() < max_width {
+        spans.push(Span::raw(" ".repeat(max_width - spans.len())));
+    }
+
+    spans
+}
+
 // ========================================
 // Terminal Capability Detection
 // ========================================
@@ -311,4 +481,83 @@ pub fn detect_color_support() -> ColorSupport {
 
     // Default to basic color support
     ColorSupport::Basic
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// The characters of a row of spans, colors aside
+    fn text(spans: &[Span]) -> String {
+        spans.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    /// A hop with one probe per entry: a reply after that many milliseconds, or None for a loss
+    fn hop_with(rtts: &[Option<u64>]) -> HopStats {
+        let mut hop = HopStats::new(1);
+        for rtt in rtts {
+            hop.increment_sent();
+            match rtt {
+                Some(ms) => hop.add_rtt(Duration::from_millis(*ms)),
+                None => hop.add_timeout(),
+            }
+        }
+        hop
+    }
+
+    #[test]
+    fn test_histogram_spans() {
+        let histogram = |hop: &HopStats, width| {
+            text(&create_histogram_spans(hop, 0, 100, SparklineScale::Linear, ColorSupport::None, width))
+        };
+        assert!(histogram(&HopStats::new(1), 4).is_empty()); // Never probed
+        assert!(histogram(&hop_with(&[Some(10)]), 0).is_empty());
+        assert_eq!(histogram(&hop_with(&[None]), 4), "    "); // No RTTs to count
+
+        assert_eq!(histogram(&hop_with(&[Some(60)]), 4), "  █ ");
+        // A bucket's lower edge belongs to it; the top of the scale and beyond to the last bucket
+        assert_eq!(histogram(&hop_with(&[Some(24), Some(25), Some(100), Some(250)]), 4), "▄▄ █");
+    }
+
+    #[test]
+    fn test_loss_spans() {
+        let loss = |hop: &HopStats, width| text(&create_loss_spans(hop, ColorSupport::None, width));
+        assert!(loss(&HopStats::new(1), 3).is_empty()); // Never probed
+        assert!(loss(&hop_with(&[None]), 0).is_empty());
+
+        assert_eq!(loss(&hop_with(&[None]), 3), "█  ");
+        assert_eq!(loss(&hop_with(&[Some(10)]), 3), "▁  ");
+        let mut pending = HopStats::new(1);
+        pending.increment_sent();
+        assert_eq!(loss(&pending, 3), "?  ");
+
+        // One probe per bar while they fit, then slices of equal size with the remainder last
+        let history = [Some(10), None, None, None, Some(10), Some(10)];
+        assert_eq!(loss(&hop_with(&history), 6), "▁███▁▁");
+        assert_eq!(loss(&hop_with(&history), 3), "▄█▁");
+        let mut hop = hop_with(&[None, Some(10), Some(10), Some(10), Some(10), Some(10), None]);
+        assert_eq!(loss(&hop, 3), "▃▁█");
+        hop.increment_sent(); // Not yet settled, so no part of the last slice's share
+        assert_eq!(loss(&hop, 3), "▃▁█");
+    }
+
+    #[test]
+    fn test_jitter_spans() {
+        let jitter = |hop: &HopStats, width| {
+            text(&create_jitter_spans(hop, 0, 50, SparklineScale::Linear, ColorSupport::None, width))
+        };
+        assert!(jitter(&HopStats::new(1), 3).is_empty()); // Never probed
+        assert!(jitter(&hop_with(&[Some(10)]), 0).is_empty());
+
+        assert_eq!(jitter(&hop_with(&[Some(10)]), 3), "   "); // Nothing to compare with yet
+        assert_eq!(jitter(&hop_with(&[None]), 3), "·  ");
+
+        // No change is the bottom of the scale, its top and beyond the full bar
+        assert_eq!(jitter(&hop_with(&[Some(10), Some(10), Some(60), Some(160)]), 4), " ▁██");
+        // A loss doesn't reset the comparison
+        assert_eq!(jitter(&hop_with(&[Some(10), None, Some(35)]), 3), " ·▄");
+        // Only the newest probes that fit
+        assert_eq!(jitter(&hop_with(&[Some(10), Some(20), Some(30), Some(40)]), 2), "▂▂");
+    }
+}
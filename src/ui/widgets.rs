@@ -5,15 +5,19 @@
 //! and layout calculations.
 
 use crate::args::Column;
+use crate::hop_stats::{PacketOutcome, Trend};
+use crate::ui::events::{key_label, COLUMN_SELECTOR_KEYS, KEYMAP};
+use crate::ui::state::{ColumnWidths, ViewMode};
 use crate::ui::visualization::{ColorSupport, VisualizationMode};
 use crate::utils;
-use crate::{HopStats, MtrSession, SparklineScale};
+use crate::{HopStats, MtrSession, RttUnit, SparklineScale};
 use ratatui::{
-    layout::{Alignment, Constraint},
-    style::{Color, Style},
+    layout::Alignment,
+    style::{Color, Modifier, Style},
     text::{Line, Span},
     widgets::{Block, Borders, Cell, Paragraph},
 };
+use std::{net::IpAddr, time::Duration};
 
 // ========================================
 // Column Selector State
@@ -86,83 +90,175 @@ impl ColumnSelectorState {
 // Table Cell and Layout Functions
 // ========================================
 
-/// Create table cells for a hop row
+/// What each column shows for a hop, as plain text (none for the graph)
+pub fn cell_texts(hop: &HopStats, hostname: &str, columns: &[Column], rtt_unit: RttUnit) -> Vec<String> {
+    columns
+        .iter()
+        .map(|column| match column {
+            Column::Hop => hop.hop.to_string(),
+            Column::Host => hostname.to_string(),
+            Column::Loss => {
+                if hop.sent > 0 {
+                    format!("{:.1}%", hop.loss_percent)
+                } else {
+                    "0.0%".to_string()
+                }
+            }
+            Column::Sent => hop.sent.to_string(),
+            Column::Avail => hop
+                .availability_percent()
+                .map(utils::format::format_percentage)
+                .unwrap_or_else(|| "???".to_string()),
+            Column::Last => utils::time::format_optional_rtt(hop.last_rtt, rtt_unit),
+            Column::Avg => utils::time::format_optional_rtt(hop.avg_rtt, rtt_unit),
+            Column::Ema => utils::time::format_optional_rtt(hop.ema_rtt, rtt_unit),
+            Column::Jitter => utils::time::format_optional_rtt(hop.last_jitter, rtt_unit),
+            Column::JitterAvg => utils::time::format_optional_rtt(hop.jitter_avg, rtt_unit),
+            Column::Best => utils::time::format_optional_rtt(hop.best_rtt, rtt_unit),
+            Column::Worst => utils::time::format_optional_rtt(hop.worst_rtt, rtt_unit),
+            Column::StDev => utils::time::format_optional_rtt(hop.rtt_stddev(), rtt_unit),
+            Column::P50 | Column::P90 | Column::P95 | Column::P99 => utils::time::format_optional_rtt(
+                column.percentile().and_then(|p| hop.rtt_percentile(p)),
+                rtt_unit,
+            ),
+            Column::Trend => hop.trend().map(|trend| trend.symbol().to_string()).unwrap_or_default(),
+            Column::ReplyTtl => hop.reply_ttl.map(|ttl| ttl.to_string()).unwrap_or_default(),
+            Column::Location => hop.geo.as_ref().map(|geo| geo.to_string()).unwrap_or_default(),
+            Column::Graph => String::new(),
+        })
+        .collect()
+}
+
+/// Create table cells for a hop row from its cell texts
 pub fn create_table_cells(
     hop: &HopStats,
-    hostname: &str,
+    texts: Vec<String>,
     sparkline_spans: &[Span<'static>],
     columns: &[Column],
 ) -> Vec<Cell<'static>> {
     columns
         .iter()
-        .map(|column| {
-            let cell_content = match column {
-                Column::Hop => hop.hop.to_string(),
-                Column::Host => hostname.to_string(),
-                Column::Loss => {
-                    if hop.sent > 0 {
-                        format!("{:.1}%", hop.loss_percent)
-                    } else {
-                        "0.0%".to_string()
-                    }
-                }
-                Column::Sent => hop.sent.to_string(),
-                Column::Last => {
-                    if let Some(rtt) = hop.last_rtt {
-                        // Use microsecond precision for very fast connections (< 1ms)
-                        if utils::time::duration_to_us_f64(rtt) < 1000.0 {
-                            utils::time::format_duration_us(rtt)
-                        } else {
-                            utils::time::format_duration_ms(rtt)
-                        }
-                    } else {
-                        "???".to_string()
-                    }
-                },
-                Column::Avg => utils::time::format_optional_duration_ms(hop.avg_rtt),
-                Column::Ema => utils::time::format_optional_duration_ms(hop.ema_rtt),
-                Column::Jitter => utils::time::format_optional_duration_ms(hop.last_jitter),
-                Column::JitterAvg => utils::time::format_optional_duration_ms(hop.jitter_avg),
-                Column::Best => utils::time::format_optional_duration_ms(hop.best_rtt),
-                Column::Worst => utils::time::format_optional_duration_ms(hop.worst_rtt),
-                Column::Graph => {
-                    return Cell::from(Line::from(sparkline_spans.to_vec()));
-                }
-            };
-
-            Cell::from(cell_content)
+        .zip(texts)
+        .map(|(&column, text)| match column {
+            Column::Graph => Cell::from(Line::from(sparkline_spans.to_vec())),
+            Column::Trend => {
+                let color = match hop.trend() {
+                    Some(Trend::Rising) => Color::Red,
+                    Some(Trend::Falling) => Color::Green,
+                    _ => Color::Gray,
+                };
+                Cell::from(Line::from(Span::styled(text, Style::default().fg(color))).alignment(Alignment::Right))
+            }
+            _ => aligned_cell(column, text),
         })
         .collect()
 }
 
-/// Create column layout constraints
-pub fn create_column_constraints(columns: &[Column]) -> Vec<Constraint> {
-    columns
+/// A cell holding `text`, numbers right-aligned under their header
+pub fn aligned_cell(column: Column, text: String) -> Cell<'static> {
+    if right_aligned(column) {
+        Cell::from(Line::from(text).alignment(Alignment::Right))
+    } else {
+        Cell::from(text)
+    }
+}
+
+/// Whether a column holds numbers, which line up on the right
+pub fn right_aligned(column: Column) -> bool {
+    !matches!(column, Column::Hop | Column::Host | Column::Location | Column::Graph)
+}
+
+/// Order hops by a table column: text columns A to Z, the rest worst (highest) first
+///
+/// Hops without a value for the column go last, and ties keep TTL order.
+pub fn sort_hops(hops: &mut [(usize, &HopStats)], column: Column) {
+    let text = |hop: &HopStats| match column {
+        Column::Host => Some(
+            hop.hostname
+                .clone()
+                .unwrap_or_else(|| utils::network::format_optional_ip(hop.addr)),
+        ),
+        _ => hop.geo.as_ref().map(|geo| geo.to_string()),
+    };
+    let ms = |rtt: Option<Duration>| rtt.map(utils::time::duration_to_ms_f64);
+    let value = |hop: &HopStats| match column {
+        Column::Loss => Some(hop.loss_percent),
+        Column::Sent => Some(hop.sent as f64),
+        Column::Avail => hop.availability_percent(),
+        Column::Last => ms(hop.last_rtt),
+        Column::Avg => ms(hop.avg_rtt),
+        Column::Ema => ms(hop.ema_rtt),
+        Column::Jitter => ms(hop.last_jitter),
+        Column::JitterAvg => ms(hop.jitter_avg),
+        Column::Best => ms(hop.best_rtt),
+        Column::Worst => ms(hop.worst_rtt),
+        Column::StDev => ms(hop.rtt_stddev()),
+        Column::P50 | Column::P90 | Column::P95 | Column::P99 => {
+            ms(column.percentile().and_then(|p| hop.rtt_percentile(p)))
+        }
+        Column::Trend => hop.trend().map(|trend| match trend {
+            Trend::Rising => 1.0,
+            Trend::Stable => 0.0,
+            Trend::Falling => -1.0,
+        }),
+        Column::ReplyTtl => hop.reply_ttl.map(f64::from),
+        Column::Hop | Column::Host | Column::Location | Column::Graph => None,
+    };
+
+    match column {
+        Column::Hop | Column::Graph => {}
+        Column::Host | Column::Location => hops.sort_by(|(_, a), (_, b)| match (text(a), text(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        }),
+        _ => hops.sort_by(|(_, a), (_, b)| match (value(a), value(b)) {
+            (Some(a), Some(b)) => b.total_cmp(&a),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        }),
+    }
+}
+
+/// Narrowest the Host and Graph columns go
+const MIN_HOST_WIDTH: u16 = 8;
+const MIN_GRAPH_WIDTH: u16 = 10;
+
+/// Widest the Host column grows to fit its names; `]` widens it further
+const MAX_FITTED_HOST_WIDTH: u16 = 40;
+
+/// Width of every table column, for a table `width` cells wide
+///
+/// `fitted` is what each column needs to show its header and content in
+/// full, which is what all but Host and Graph get. Host gets it up to a cap
+/// and Graph the space that's left, unless `widths` sets them; when the
+/// table doesn't fit, Graph and then Host give way down to their minimum.
+pub fn column_widths(columns: &[Column], fitted: &[u16], widths: ColumnWidths, width: u16) -> Vec<u16> {
+    let available = width.saturating_sub(columns.len().saturating_sub(1) as u16); // One cell between columns
+    let mut result: Vec<u16> = columns
         .iter()
-        .map(|column| {
-            match column {
-                Column::Hop => Constraint::Length(3),
-                Column::Host => Constraint::Percentage(20), // Increased to 20% for better readability
-                Column::Loss => Constraint::Length(5),
-                Column::Sent => Constraint::Length(3),
-                Column::Last | Column::Avg | Column::Ema | Column::Best | Column::Worst => {
-                    if columns.contains(&Column::Graph) {
-                        Constraint::Length(6)
-                    } else {
-                        Constraint::Length(9)
-                    }
-                }
-                Column::Jitter | Column::JitterAvg => {
-                    if columns.contains(&Column::Graph) {
-                        Constraint::Length(6)
-                    } else {
-                        Constraint::Length(9)
-                    }
-                }
-                Column::Graph => Constraint::Percentage(65), // Use 65% of available space (reduced to accommodate larger hostname column)
-            }
+        .zip(fitted)
+        .map(|(column, &fitted)| match column {
+            Column::Host => widths.host.unwrap_or(fitted.min(MAX_FITTED_HOST_WIDTH)).max(MIN_HOST_WIDTH),
+            Column::Graph => widths.graph.unwrap_or(0).max(MIN_GRAPH_WIDTH),
+            _ => fitted,
         })
-        .collect()
+        .collect();
+
+    let host = columns.iter().position(|&column| column == Column::Host);
+    let graph = columns.iter().position(|&column| column == Column::Graph);
+    if let (Some(graph), None) = (graph, widths.graph) {
+        let others = result.iter().sum::<u16>() - result[graph];
+        result[graph] = available.saturating_sub(others).max(MIN_GRAPH_WIDTH);
+    }
+
+    let mut excess = result.iter().sum::<u16>().saturating_sub(available);
+    for (index, min) in [(graph, MIN_GRAPH_WIDTH), (host, MIN_HOST_WIDTH)] {
+        if let Some(index) = index {
+            let give = excess.min(result[index].saturating_sub(min));
+            result[index] -= give;
+            excess -= give;
+        }
+    }
+    result
 }
 
 // ========================================
@@ -170,24 +266,36 @@ pub fn create_column_constraints(columns: &[Column]) -> Vec<Constraint> {
 // ========================================
 
 /// Create inline status text without borders
-pub fn create_status_text(session: &MtrSession, ui_state: &super::UiState) -> Line<'static> {
-    let total_sent: usize = session.hops.iter().map(|h| h.sent).sum();
-    let total_received: usize = session.hops.iter().map(|h| h.received).sum();
+pub fn create_status_text(session: &MtrSession, hops: &[HopStats], ui_state: &super::UiState) -> Line<'static> {
+    let total_sent: usize = hops.iter().map(|h| h.sent).sum();
+    let total_received: usize = hops.iter().map(|h| h.received).sum();
     let overall_loss = if total_sent > 0 {
         ((total_sent - total_received) as f64 / total_sent as f64) * 100.0
     } else {
         0.0
     };
 
-    let active_hops = session.hops.iter().filter(|h| h.sent > 0).count();
+    let active_hops = hops.iter().filter(|h| h.sent > 0).count();
+    // Sent and rounds both count from the start or the last reset, as does the elapsed time
+    let elapsed = session.stats.started().elapsed();
+    let probe_rate = total_sent as f64 / elapsed.as_secs_f64().max(1.0);
+    let in_flight = hops
+        .iter()
+        .flat_map(|h| h.packet_history.iter())
+        .filter(|outcome| matches!(outcome, PacketOutcome::Pending))
+        .count();
     let scale_name = match ui_state.current_sparkline_scale {
         SparklineScale::Linear => "Linear",
         SparklineScale::Logarithmic => "Log",
     };
 
-    let viz_mode = match ui_state.visualization_mode {
-        VisualizationMode::Sparkline => "Sparkline",
-        VisualizationMode::Heatmap => "Heatmap",
+    let viz_mode = match (ui_state.view_mode, ui_state.visualization_mode) {
+        (ViewMode::Tree, _) => "Tree",
+        (ViewMode::Table, VisualizationMode::Sparkline) => "Sparkline",
+        (ViewMode::Table, VisualizationMode::Heatmap) => "Heatmap",
+        (ViewMode::Table, VisualizationMode::Histogram) => "Histogram",
+        (ViewMode::Table, VisualizationMode::Loss) => "Loss",
+        (ViewMode::Table, VisualizationMode::Jitter) => "Jitter",
     };
 
     let hostname_mode = if ui_state.show_hostnames {
@@ -196,22 +304,84 @@ pub fn create_status_text(session: &MtrSession, ui_state: &super::UiState) -> Li
         "IPs"
     };
 
+    let source = session
+        .args
+        .address
+        .map(|source| format!(" from {}", source))
+        .unwrap_or_default();
+
     let main_text = format!(
-        "mtr-ng: {} → {} | Hops: {} | Sent: {} | Loss: {:.1}% | Scale: {} | Mode: {} | Display: {}",
+        "mtr-ng: {} → {}{} | Hops: {} | Sent: {} | Loss: {:.1}% | Up: {} | Rounds: {} | {:.1} probes/s, {} in flight \
+         | Interval: {} | Scale: {} | Mode: {} | Display: {} | RTT: {}",
         session.target,
         session.target_addr,
+        source,
         active_hops,
         total_sent,
         overall_loss,
+        utils::time::format_elapsed(elapsed),
+        session.stats.rounds(),
+        probe_rate,
+        in_flight,
+        utils::time::format_rtt_label(session.current_interval(), RttUnit::Auto),
         scale_name,
         viz_mode,
-        hostname_mode
+        hostname_mode,
+        ui_state.rtt_unit.name()
     );
 
-    Line::from(vec![
-        Span::raw(main_text),
+    let mut spans = Vec::new();
+    if let Some(ref notice) = ui_state.notice {
+        spans.push(Span::styled(
+            notice.clone(),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ));
+        spans.push(Span::raw(" | "));
+    }
+    if let Some(ref breach) = ui_state.breach {
+        spans.push(Span::styled(
+            format!("⚠ ALERT: {}", breach),
+            Style::default()
+                .fg(Color::White)
+                .bg(Color::Red)
+                .add_modifier(Modifier::BOLD | Modifier::SLOW_BLINK),
+        ));
+        spans.push(Span::raw(" | "));
+    }
+    if ui_state.frozen.is_some() {
+        spans.push(Span::styled(
+            "❄ Frozen (Space to resume)",
+            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+        ));
+        spans.push(Span::raw(" | "));
+    }
+    spans.push(Span::raw(main_text));
+    let route_changes = session.stats.route_history().total();
+    if route_changes > 0 {
+        spans.push(Span::raw(" | "));
+        spans.push(Span::styled(
+            format!(
+                "Route changed {} time{}",
+                route_changes,
+                if route_changes == 1 { "" } else { "s" }
+            ),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    spans.extend([
         Span::raw(" | "),
         Span::styled("? for help", Style::default().fg(Color::Gray)),
+    ]);
+
+    Line::from(spans)
+}
+
+/// Heading naming the address family of one table in a multi-session view
+pub fn create_session_label(session: &MtrSession) -> Line<'static> {
+    let family = if session.target_addr.is_ipv6() { "IPv6" } else { "IPv4" };
+    Line::from(vec![
+        Span::styled(family, Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+        Span::raw(format!(" {}", session.target_addr)),
     ])
 }
 
@@ -244,6 +414,7 @@ pub fn create_column_selector_popup(state: &ColumnSelectorState) -> Paragraph<'s
             Column::Host => "Hostname/IP",
             Column::Loss => "Packet Loss %",
             Column::Sent => "Packets Sent",
+            Column::Avail => "Availability %",
             Column::Last => "Last RTT",
             Column::Avg => "Average RTT",
             Column::Ema => "EMA RTT",
@@ -251,6 +422,14 @@ pub fn create_column_selector_popup(state: &ColumnSelectorState) -> Paragraph<'s
             Column::JitterAvg => "Average Jitter",
             Column::Best => "Best RTT",
             Column::Worst => "Worst RTT",
+            Column::StDev => "RTT Std. Deviation",
+            Column::P50 => "Median RTT",
+            Column::P90 => "90th Percentile RTT",
+            Column::P95 => "95th Percentile RTT",
+            Column::P99 => "99th Percentile RTT",
+            Column::Trend => "RTT Trend",
+            Column::ReplyTtl => "Reply TTL",
+            Column::Location => "GeoIP Location",
             Column::Graph => "RTT Graph",
         };
 
@@ -319,79 +498,237 @@ pub fn create_column_selector_popup(state: &ColumnSelectorState) -> Paragraph<'s
         .alignment(Alignment::Left)
 }
 
-/// Create help overlay with keyboard shortcuts
-pub fn create_help_overlay() -> Paragraph<'static> {
-    let help_text = vec![
-        Line::from(vec![Span::styled(
-            "Keyboard Shortcuts",
-            Style::default().fg(Color::Yellow),
-        )]),
-        Line::from(""),
-        Line::from(vec![
-            Span::styled("q", Style::default().fg(Color::Green)),
-            Span::raw(" / "),
-            Span::styled("ESC", Style::default().fg(Color::Green)),
-            Span::raw("  - Quit application"),
-        ]),
-        Line::from(vec![
-            Span::styled("r", Style::default().fg(Color::Green)),
-            Span::raw("        - Reset statistics"),
-        ]),
-        Line::from(vec![
-            Span::styled("s", Style::default().fg(Color::Green)),
-            Span::raw("        - Toggle sparkline scale (Linear/Log)"),
-        ]),
-        Line::from(vec![
-            Span::styled("c", Style::default().fg(Color::Green)),
-            Span::raw("        - Cycle color modes"),
-        ]),
-        Line::from(vec![
-            Span::styled("f", Style::default().fg(Color::Green)),
-            Span::raw("        - Toggle column fields"),
-        ]),
-        Line::from(vec![
-            Span::styled("o", Style::default().fg(Color::Green)),
-            Span::raw("        - Open column selector"),
-        ]),
-        Line::from(vec![
-            Span::styled("v", Style::default().fg(Color::Green)),
-            Span::raw("        - Toggle visualization mode"),
-        ]),
+/// The help overlay's text, generated from the keymap so it lists every key
+pub fn help_lines() -> Vec<Line<'static>> {
+    let heading = |text: &'static str| Line::from(Span::styled(text, Style::default().fg(Color::Yellow)));
+    let keys: Vec<(String, &'static str)> = KEYMAP
+        .iter()
+        .map(|binding| (key_label(binding.keys), binding.description))
+        .collect();
+    let selector_keys: Vec<(String, &'static str)> = COLUMN_SELECTOR_KEYS
+        .iter()
+        .map(|&(keys, description)| (keys.to_string(), description))
+        .collect();
+    let width = keys
+        .iter()
+        .chain(&selector_keys)
+        .map(|(keys, _)| keys.chars().count())
+        .max()
+        .unwrap_or(0);
+    let entry = |(keys, description): (String, &'static str)| {
         Line::from(vec![
-            Span::styled("h", Style::default().fg(Color::Green)),
-            Span::raw("        - Toggle hostname display"),
-        ]),
+            Span::styled(format!("{:<width$}", keys), Style::default().fg(Color::Green)),
+            Span::raw(format!(" - {}", description)),
+        ])
+    };
+
+    let mut lines = vec![heading("Keyboard Shortcuts"), Line::from("")];
+    lines.extend(keys.into_iter().map(entry));
+    lines.extend([Line::from(""), heading("Column Selector (when open)")]);
+    lines.extend(selector_keys.into_iter().map(entry));
+    lines.extend([
         Line::from(""),
-        Line::from(vec![Span::styled(
-            "Column Selector (when open):",
-            Style::default().fg(Color::Cyan),
-        )]),
-        Line::from(vec![
-            Span::styled("↑/↓", Style::default().fg(Color::Green)),
-            Span::raw("      - Navigate columns"),
-        ]),
-        Line::from(vec![
-            Span::styled("Space", Style::default().fg(Color::Green)),
-            Span::raw("     - Toggle column visibility"),
-        ]),
-        Line::from(vec![
-            Span::styled("←/→", Style::default().fg(Color::Green)),
-            Span::raw("      - Reorder columns"),
-        ]),
-        Line::from(vec![
-            Span::styled("Shift+↑/↓", Style::default().fg(Color::Green)),
-            Span::raw(" - Alternative column reordering"),
-        ]),
-    ];
+        heading("Mouse"),
+        Line::from("Click a row to select its hop, a header to sort by it;"),
+        Line::from("the wheel scrolls the table"),
+    ]);
+    lines
+}
 
-    Paragraph::new(help_text)
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("Help")
-                .title_alignment(Alignment::Center),
-        )
+/// Create help overlay from its [`help_lines`], `scroll` lines down
+///
+/// When they don't all fit, the bottom border says which are shown.
+pub fn create_help_overlay(lines: Vec<Line<'static>>, scroll: usize, rows: usize) -> Paragraph<'static> {
+    let total = lines.len();
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .title("Help")
+        .title_alignment(Alignment::Center);
+    if rows < total {
+        block = block.title_bottom(
+            Line::from(format!(
+                " {}-{} of {} · ↑/↓ PgUp/PgDn ",
+                scroll + 1,
+                (scroll + rows).min(total),
+                total
+            ))
+            .alignment(Alignment::Right),
+        );
+    }
+    Paragraph::new(lines)
+        .block(block)
         .alignment(Alignment::Left)
+        .scroll((scroll as u16, 0))
+}
+
+/// Create the detail popup of a hop from its [`hop_detail_lines`]
+pub fn create_hop_detail_popup(hop: &HopStats, lines: Vec<Line<'static>>) -> Paragraph<'static> {
+    Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Hop {}", hop.hop))
+            .title_alignment(Alignment::Center),
+    )
+}
+
+/// Rows of the RTT histogram in the hop detail popup
+const HISTOGRAM_BINS: usize = 8;
+/// Longest bar of the RTT histogram
+const HISTOGRAM_WIDTH: usize = 36;
+
+/// What the detail popup shows for a hop: who it is, its loss and timing, the
+/// other addresses answering for it and the shape of its RTT distribution
+pub fn hop_detail_lines(session: &MtrSession, hop: &HopStats, ui_state: &super::UiState) -> Vec<Line<'static>> {
+    let label = |name: &str| Span::styled(format!("{:<14}", name), Style::default().fg(Color::Yellow));
+    let field = |name: &str, value: String| Line::from(vec![label(name), Span::raw(value)]);
+    let rtt = |rtt| utils::time::format_optional_rtt(rtt, ui_state.rtt_unit);
+
+    let mut lines = vec![field("Address", utils::network::format_optional_ip(hop.addr))];
+    if let Some(ref hostname) = hop.hostname {
+        lines.push(field("Hostname", hostname.clone()));
+    }
+    if let Some(ref geo) = hop.geo {
+        lines.push(field("Location", geo.to_string()));
+    }
+    match hop.whois {
+        Some(ref whois) => {
+            if let Some(ref netname) = whois.netname {
+                lines.push(field("Network", netname.clone()));
+            }
+            if let Some(ref org) = whois.org {
+                lines.push(field("Organization", org.clone()));
+            }
+        }
+        None if session.whois.is_some() => lines.push(field("Network", "???".to_string())),
+        None => lines.push(field("Network", "(run with --whois to look up)".to_string())),
+    }
+    lines.push(Line::from(""));
+    lines.push(field(
+        "Loss",
+        format!("{:.1}% of {} sent", hop.loss_percent, hop.sent),
+    ));
+    let streaks = hop.loss_streaks();
+    if streaks.count > 0 {
+        let mut text = format!("{} (longest {} in a row)", streaks.count, streaks.longest);
+        if streaks.current > 0 {
+            text.push_str(&format!(", losing {} now", streaks.current));
+        }
+        lines.push(field("Loss streaks", text));
+    }
+    lines.push(field(
+        "RTT",
+        format!(
+            "last {}  avg {}  best {}  worst {}",
+            rtt(hop.last_rtt),
+            rtt(hop.avg_rtt),
+            rtt(hop.best_rtt),
+            rtt(hop.worst_rtt)
+        ),
+    ));
+    if hop.jitter_avg.is_some() {
+        let trend = match hop.jitter_trend() {
+            Some(Trend::Rising) => "  ↑ rising",
+            Some(Trend::Falling) => "  ↓ falling",
+            Some(Trend::Stable) => "  → steady",
+            None => "",
+        };
+        lines.push(field(
+            "Jitter",
+            format!("avg {}  last {}{}", rtt(hop.jitter_avg), rtt(hop.last_jitter), trend),
+        ));
+    }
+    if hop.route_changes > 0 {
+        lines.push(field("Route changes", hop.route_changes.to_string()));
+    }
+    if hop.has_multiple_paths() {
+        let responder = |addr: IpAddr, hostname: &Option<String>, percent: f64, avg: Option<Duration>| {
+            let name = match hostname {
+                Some(hostname) => format!("{} ({})", addr, hostname),
+                None => addr.to_string(),
+            };
+            Line::from(format!("  {:>5.1}%  {:>9}  {}", percent, rtt(avg), name))
+        };
+        lines.push(Line::from(""));
+        lines.push(Line::from(label("Responders")));
+        if let Some(addr) = hop.addr {
+            lines.push(responder(addr, &hop.hostname, hop.get_primary_path_percentage(), hop.avg_rtt));
+        }
+        for path in hop.get_alternate_paths() {
+            lines.push(responder(path.addr, &path.hostname, hop.get_path_percentage(path), path.avg_rtt));
+        }
+    }
+
+    if let Some((min, max, counts)) = hop.rtt_histogram(HISTOGRAM_BINS) {
+        lines.push(Line::from(""));
+        lines.push(Line::from(vec![
+            label("RTT histogram"),
+            Span::styled(format!("last {} replies", hop.rtts.len()), Style::default().fg(Color::Gray)),
+        ]));
+        let most = counts.iter().copied().max().unwrap_or(0).max(1);
+        let bin_width = (max - min) / HISTOGRAM_BINS as u32;
+        for (i, count) in counts.into_iter().enumerate() {
+            let from = min + bin_width * i as u32;
+            let color = super::visualization::colors::get_smooth_gradient_color(
+                i as f64 / (HISTOGRAM_BINS - 1) as f64,
+                ui_state.color_support,
+            );
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {:>9} ", rtt(Some(from)))),
+                Span::styled("█".repeat(count * HISTOGRAM_WIDTH / most), Style::default().fg(color)),
+                Span::raw(if count > 0 { format!(" {}", count) } else { String::new() }),
+            ]));
+        }
+    }
+
+    lines
+}
+
+/// Create the route change event list, newest first, in at most `rows` lines
+pub fn create_route_events_popup(session: &MtrSession, rows: usize) -> Paragraph<'static> {
+    let route_history = session.stats.route_history();
+    let lines: Vec<Line> = if route_history.total() == 0 {
+        vec![Line::from(Span::styled(
+            "No route changes seen",
+            Style::default().fg(Color::Gray),
+        ))]
+    } else {
+        route_history
+            .events()
+            .rev()
+            .take(rows)
+            .map(|change| Line::from(change.to_string()))
+            .collect()
+    };
+
+    Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Route Changes ({})", route_history.total()))
+            .title_alignment(Alignment::Center),
+    )
+}
+
+/// Create the prompt for the format of a snapshot (`w`)
+pub fn create_export_prompt() -> Paragraph<'static> {
+    let key = |key: &'static str| Span::styled(key, Style::default().fg(Color::Green));
+    Paragraph::new(vec![
+        Line::from(vec![
+            key("t"),
+            Span::raw(" text   "),
+            key("j"),
+            Span::raw(" JSON   "),
+            key("c"),
+            Span::raw(" CSV"),
+        ]),
+        Line::from(Span::styled("Any other key cancels", Style::default().fg(Color::Gray))),
+    ])
+    .alignment(Alignment::Center)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Write Snapshot")
+            .title_alignment(Alignment::Center),
+    )
 }
 
 /// Create RTT scale visualization widget
@@ -400,6 +737,7 @@ pub fn create_scale_widget(
     max_rtt: u64,
     scale: SparklineScale,
     color_support: ColorSupport,
+    rtt_unit: RttUnit,
     width: usize,
 ) -> Paragraph<'static> {
     if min_rtt == max_rtt {
@@ -436,11 +774,7 @@ pub fn create_scale_widget(
             }
         };
 
-        let label = if value < 1000 {
-            format!("{}ms", value)
-        } else {
-            format!("{:.1}s", value as f64 / 1000.0)
-        };
+        let label = utils::time::format_rtt_label(Duration::from_millis(value), rtt_unit);
 
         // Calculate the center position for this label on the gradient
         let center_pos = (ratio * (scale_width - 1) as f64) as usize;
@@ -494,8 +828,13 @@ pub fn create_scale_widget(
 // Utility Functions
 // ========================================
 
-/// Format hostname for display with length constraints
+/// Format hostname for display, cut to fit by whoever lays it out
 pub fn format_hostname(session: &MtrSession, hop: &HopStats, ui_state: &super::UiState) -> String {
+    // A hop that rejected the trace is labelled as such instead of by name
+    if session.blocked_hop().map(|index| session.stats.hop(index).hop) == Some(hop.hop) {
+        return hop.blocked_description();
+    }
+
     let base_hostname = if session.args.numeric || !ui_state.show_hostnames {
         // Show IP addresses when numeric mode or hostname toggle is off
         hop.addr
@@ -512,55 +851,61 @@ pub fn format_hostname(session: &MtrSession, hop: &HopStats, ui_state: &super::U
 
     // Add primary path percentage if multi-path
     let hostname = if hop.has_multiple_paths() {
-        if hop.addr.is_some() {
+        let hostname = if hop.addr.is_some() {
             let primary_percentage = hop.get_primary_path_percentage();
             format!("{} ({:.0}%)", base_hostname, primary_percentage)
         } else {
             base_hostname
+        };
+        // Alternates folded away in the table are counted instead
+        if ui_state.view_mode == ViewMode::Table && ui_state.paths_collapsed(hop.hop) {
+            let count = hop.get_alternate_paths().len();
+            format!("{} +{} path{}", hostname, count, if count == 1 { "" } else { "s" })
+        } else {
+            hostname
         }
     } else {
         base_hostname
     };
-
-    // With 20% width allocation, truncate longer hostnames appropriately
-    const MAX_HOSTNAME_LEN: usize = 40; // Increased to accommodate percentage
-    const TRUNCATED_LEN: usize = 37;
-
-    if hostname.len() > MAX_HOSTNAME_LEN {
-        format!("{}...", &hostname[..TRUNCATED_LEN])
-    } else {
-        hostname
+    let hostname = match hop.port_state {
+        Some(state) => format!("{} [{}]", hostname, state),
+        None => hostname,
+    };
+    let hostname = match hop.timestamps {
+        Some(timestamps) => format!("{} [{}]", hostname, timestamps),
+        None => hostname,
+    };
+    let hostname = match hop.dns_rcode {
+        Some(rcode) => format!("{} [DNS {}]", hostname, rcode),
+        None => hostname,
+    };
+    // Where the trace ends is ticked, and hops beyond it said to be past it
+    match session.destination_ttl() {
+        Some(ttl) if hop.hop == ttl => format!("{} ✓", hostname),
+        Some(ttl) if hop.hop > ttl => format!("{} [past target]", hostname),
+        _ => hostname,
     }
 }
 
-/// Calculate available width for graph column
-pub fn calculate_graph_width(table_area: &ratatui::layout::Rect, columns: &[Column]) -> usize {
-    if columns.contains(&Column::Graph) {
-        // Calculate actual width available for graph column (80% of remaining space)
-        let total_width = table_area.width.saturating_sub(4) as usize; // Account for borders
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Calculate space used by fixed-width columns
-        let fixed_width: usize = columns
-            .iter()
-            .map(|col| {
-                match col {
-                    Column::Hop => 3,
-                    Column::Loss => 5,
-                    Column::Sent => 3,
-                    Column::Last | Column::Avg | Column::Ema | Column::Best | Column::Worst => 6,
-                    Column::Jitter | Column::JitterAvg => 6,
-                    Column::Host | Column::Graph => 0, // These use percentage-based sizing
-                }
-            })
-            .sum();
+    #[test]
+    fn test_column_widths() {
+        let columns = [Column::Hop, Column::Host, Column::Loss, Column::Graph];
+        let fitted = [3, 25, 5, 5];
 
-        // Remaining space for Host (35%) and Graph (65%) columns
-        let remaining_width = total_width.saturating_sub(fixed_width);
-        let graph_width = (remaining_width * 65) / 100;
+        // Graph takes what the others leave, three cells going between columns
+        assert_eq!(column_widths(&columns, &fitted, ColumnWidths::default(), 80), vec![3, 25, 5, 44]);
 
-        // Ensure minimum usable width but no upper cap
-        utils::math::max_with_minimum(graph_width, 20)
-    } else {
-        20
+        // A long name is capped unless Host is set
+        let long = [3, 70, 5, 5];
+        assert_eq!(column_widths(&columns, &long, ColumnWidths::default(), 120)[1], MAX_FITTED_HOST_WIDTH);
+        let widths = ColumnWidths { host: Some(60), graph: Some(20) };
+        assert_eq!(column_widths(&columns, &long, widths, 120), vec![3, 60, 5, 20]);
+
+        // Too narrow a terminal squeezes Graph and then Host
+        assert_eq!(column_widths(&columns, &fitted, widths, 40), vec![3, 19, 5, 10]);
     }
-} 
\ No newline at end of file
+}
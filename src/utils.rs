@@ -2,6 +2,7 @@
 
 /// Time conversion utilities
 pub mod time {
+    use crate::args::RttUnit;
     use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
     /// Convert Duration to milliseconds as f64
@@ -58,6 +59,52 @@ pub mod time {
             .unwrap_or_else(|| "???".to_string())
     }
 
+    /// Format an RTT for a table cell in the selected unit
+    ///
+    /// Fixed units are printed without a suffix; `Auto` switches between μs
+    /// and ms and therefore always carries one.
+    pub fn format_rtt(duration: Duration, unit: RttUnit) -> String {
+        match unit {
+            RttUnit::Ms => format_duration_ms(duration),
+            RttUnit::Us => format!("{:.0}", duration_to_us_f64(duration)),
+            RttUnit::Auto => format_duration_us(duration),
+        }
+    }
+
+    /// Format optional RTT in the selected unit, or "???" if None
+    pub fn format_optional_rtt(duration: Option<Duration>, unit: RttUnit) -> String {
+        duration
+            .map(|d| format_rtt(d, unit))
+            .unwrap_or_else(|| "???".to_string())
+    }
+
+    /// Format an RTT as an axis label, always with a unit suffix
+    pub fn format_rtt_label(duration: Duration, unit: RttUnit) -> String {
+        match unit {
+            RttUnit::Ms => format!("{}ms", duration.as_millis()),
+            RttUnit::Us => format!("{}μs", duration.as_micros()),
+            RttUnit::Auto => {
+                if duration < Duration::from_millis(1) {
+                    format!("{}μs", duration.as_micros())
+                } else if duration < Duration::from_secs(1) {
+                    format!("{}ms", duration.as_millis())
+                } else {
+                    format!("{:.1}s", duration.as_secs_f64())
+                }
+            }
+        }
+    }
+
+    /// Format how long something has run, to the second: "42s", "5m07s", "2h05m"
+    pub fn format_elapsed(duration: Duration) -> String {
+        let secs = duration.as_secs();
+        match secs {
+            0..=59 => format!("{}s", secs),
+            60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+            _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+        }
+    }
+
     /// Get high-precision monotonic timestamp
     pub fn get_monotonic_timestamp() -> Instant {
         Instant::now()
@@ -276,13 +323,68 @@ pub mod network {
 
     /// Truncate hostname to specified length with ellipsis
     pub fn truncate_hostname(hostname: &str, max_len: usize) -> String {
-        if hostname.len() > max_len {
+        if hostname.chars().count() > max_len {
             let truncated_len = max_len.saturating_sub(3); // Reserve space for "..."
-            format!("{}...", &hostname[..truncated_len])
+            format!("{}...", hostname.chars().take(truncated_len).collect::<String>())
         } else {
             hostname.to_string()
         }
     }
+
+    /// Name of this machine, or "localhost" if it can't be read
+    pub fn local_hostname() -> String {
+        let mut buf = [0u8; 256];
+        let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if rc != 0 {
+            return "localhost".to_string();
+        }
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+}
+
+/// Where mtr-ng keeps its files
+pub mod paths {
+    use anyhow::{Context, Result};
+    use serde::de::DeserializeOwned;
+    use std::path::{Path, PathBuf};
+
+    /// `$XDG_DATA_HOME/mtr-ng`, falling back to `~/.local/share/mtr-ng`
+    pub fn data_dir() -> Option<PathBuf> {
+        let data_dir = std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+        Some(data_dir.join("mtr-ng"))
+    }
+
+    /// Read a JSON file, treating a missing one as `T::default()`
+    ///
+    /// `what` names the file in errors, as in "Invalid baseline file".
+    pub fn load_json_or_default<T: DeserializeOwned + Default>(path: &Path, what: &str) -> Result<T> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid {} file {}", what, path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Replace a file's contents, creating its directory if need be
+    ///
+    /// Writes a temporary file next to it and renames that into place, so a
+    /// crash never leaves a truncated file behind.
+    pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+    }
 }
 
 /// Layout and sizing utilities
@@ -399,6 +501,34 @@ pub mod icmp {
         Ok(packet)
     }
 
+    /// Describe an ICMP / ICMPv6 destination unreachable code
+    pub fn describe_unreachable(code: u8, ipv6: bool) -> &'static str {
+        if ipv6 {
+            match code {
+                0 => "no route",
+                1 => "admin prohibited",
+                2 => "beyond scope",
+                3 => "address unreachable",
+                4 => "port unreachable",
+                5 => "source policy failed",
+                6 => "reject route",
+                _ => "unreachable",
+            }
+        } else {
+            match code {
+                0 => "net unreachable",
+                1 => "host unreachable",
+                2 => "protocol unreachable",
+                3 => "port unreachable",
+                4 => "fragmentation needed",
+                5 => "source route failed",
+                6 | 7 => "unknown destination",
+                9 | 10 | 13 => "admin prohibited",
+                _ => "unreachable",
+            }
+        }
+    }
+
     /// Extract sequence number from ICMP packet
     pub fn extract_sequence_from_packet(packet: &[u8]) -> Option<u16> {
         if packet.len() >= 8 {
@@ -430,6 +560,9 @@ mod tests {
         assert_eq!(time::duration_to_ms_f64(duration), 1500.0);
         assert_eq!(time::duration_to_ms_u64(duration), 1500);
         assert_eq!(time::format_duration_ms(duration), "1500.0");
+        assert_eq!(time::format_elapsed(Duration::from_secs(42)), "42s");
+        assert_eq!(time::format_elapsed(Duration::from_secs(307)), "5m07s");
+        assert_eq!(time::format_elapsed(Duration::from_secs(7530)), "2h05m");
     }
 
     #[test]
@@ -474,6 +607,14 @@ mod tests {
         assert_eq!(icmp::extract_sequence_from_packet(&packet), Some(1234));
     }
 
+    #[test]
+    fn test_describe_unreachable() {
+        assert_eq!(icmp::describe_unreachable(13, false), "admin prohibited");
+        assert_eq!(icmp::describe_unreachable(3, false), "port unreachable");
+        assert_eq!(icmp::describe_unreachable(1, true), "admin prohibited");
+        assert_eq!(icmp::describe_unreachable(200, false), "unreachable");
+    }
+
     #[test]
     fn test_visualization_utils() {
         // Test basic sparkline character functionality
@@ -517,6 +658,25 @@ mod tests {
         assert_eq!(time::format_duration_us(large_duration), "1500.0ms");
     }
 
+    #[test]
+    fn test_rtt_unit_formatting() {
+        use crate::args::RttUnit;
+
+        let fast = Duration::from_micros(450);
+        let slow = Duration::from_micros(12_345);
+        assert_eq!(time::format_rtt(fast, RttUnit::Ms), "0.5");
+        assert_eq!(time::format_rtt(slow, RttUnit::Ms), "12.3");
+        assert_eq!(time::format_rtt(fast, RttUnit::Us), "450");
+        assert_eq!(time::format_rtt(slow, RttUnit::Us), "12345");
+        assert_eq!(time::format_rtt(fast, RttUnit::Auto), "450.0μs");
+        assert_eq!(time::format_rtt(slow, RttUnit::Auto), "12.3ms");
+        assert_eq!(time::format_optional_rtt(None, RttUnit::Us), "???");
+
+        assert_eq!(time::format_rtt_label(Duration::from_millis(1500), RttUnit::Ms), "1500ms");
+        assert_eq!(time::format_rtt_label(Duration::from_millis(2), RttUnit::Us), "2000μs");
+        assert_eq!(time::format_rtt_label(Duration::from_millis(1500), RttUnit::Auto), "1.5s");
+    }
+
     #[test]
     fn test_timing_jitter_calculation() {
         let current = Duration::from_millis(120);
@@ -604,4 +764,23 @@ mod tests {
         assert!(time::duration_to_us_f64(ultra_fast) < 1000.0); // Should use μs format
         assert!(time::duration_to_us_f64(ms_range) >= 1000.0);  // Should use ms format
     }
+
+    #[test]
+    fn test_json_file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("mtr-ng-paths-{}", std::process::id()));
+        let path = dir.join("values.json");
+        let missing: Vec<u32> = paths::load_json_or_default(&path, "test").unwrap();
+        assert!(missing.is_empty());
+
+        paths::write_atomic(&path, "[1, 2, 3]").unwrap();
+        let loaded: Vec<u32> = paths::load_json_or_default(&path, "test").unwrap();
+        assert_eq!(loaded, vec![1, 2, 3]);
+        assert!(!dir.join("values.json.tmp").exists());
+
+        std::fs::write(&path, "[1, 2").unwrap();
+        let err = paths::load_json_or_default::<Vec<u32>>(&path, "test").unwrap_err();
+        assert!(err.to_string().starts_with("Invalid test file"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,292 @@
+//! Whois annotation of hop addresses
+//!
+//! With `--whois`, every public hop address is looked up the way the `whois`
+//! command does it: IANA names the regional registry responsible for the
+//! address, and that registry's server returns the network name and the
+//! organization holding it. That is who to contact when a hop misbehaves.
+//!
+//! Lookups run on one background task, one query at a time and at most one
+//! query per second, so a long path never floods the registries. Every answer,
+//! including a failed lookup, is cached for the rest of the session.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    time::{self, Instant},
+};
+use tracing::debug;
+
+/// Server that refers an address to its regional registry
+const IANA_SERVER: &str = "whois.iana.org";
+
+/// Whois is served on TCP port 43 (RFC 3912)
+const WHOIS_PORT: u16 = 43;
+
+/// Minimum gap between two queries, to any server
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time allowed for connecting, sending and reading one answer
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Answers are cut off at this size
+const MAX_RESPONSE: u64 = 256 * 1024;
+
+/// Who holds a hop's address
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WhoisInfo {
+    pub addr: IpAddr,
+    pub netname: Option<String>,
+    pub org: Option<String>,
+}
+
+impl fmt::Display for WhoisInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.netname, &self.org) {
+            (Some(netname), Some(org)) => write!(f, "{} ({})", netname, org),
+            (Some(name), None) | (None, Some(name)) => f.write_str(name),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    Pending,
+    Done(Option<WhoisInfo>),
+}
+
+/// Rate-limited, caching whois lookups
+#[derive(Debug)]
+pub struct WhoisClient {
+    cache: Arc<Mutex<HashMap<IpAddr, Entry>>>,
+    queue: mpsc::UnboundedSender<IpAddr>,
+}
+
+impl WhoisClient {
+    /// Start the lookup task; must be called within a Tokio runtime
+    pub fn spawn() -> Self {
+        let cache = Arc::new(Mutex::new(HashMap::new()));
+        let (queue, requests) = mpsc::unbounded_channel();
+        tokio::spawn(run_lookups(requests, Arc::clone(&cache)));
+        Self { cache, queue }
+    }
+
+    /// Cached details for an address, queueing a lookup the first time it is seen
+    pub fn lookup(&self, addr: IpAddr) -> Option<WhoisInfo> {
+        if !is_public(addr) {
+            return None;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(&addr) {
+            Some(Entry::Done(info)) => info.clone(),
+            Some(Entry::Pending) => None,
+            None => {
+                cache.insert(addr, Entry::Pending);
+                let _ = self.queue.send(addr);
+                None
+            }
+        }
+    }
+}
+
+async fn run_lookups(mut requests: mpsc::UnboundedReceiver<IpAddr>, cache: Arc<Mutex<HashMap<IpAddr, Entry>>>) {
+    let mut next_query = Instant::now();
+    while let Some(addr) = requests.recv().await {
+        let info = match query_registry(addr, &mut next_query).await {
+            Ok(info) => info,
+            Err(e) => {
+                debug!("Whois lookup for {} failed: {:#}", addr, e);
+                None
+            }
+        };
+        cache.lock().unwrap().insert(addr, Entry::Done(info));
+    }
+}
+
+/// Ask IANA for the registry, then ask the registry about the address
+async fn query_registry(addr: IpAddr, next_query: &mut Instant) -> Result<Option<WhoisInfo>> {
+    let referral = query(IANA_SERVER, &addr.to_string(), next_query).await?;
+    let server = referral_server(&referral).ok_or_else(|| anyhow!("IANA has no registry for {}", addr))?;
+    // ARIN needs to be told to return just the network and its organization
+    let request = if server.eq_ignore_ascii_case("whois.arin.net") {
+        format!("n + {}", addr)
+    } else {
+        addr.to_string()
+    };
+    let response = query(&server, &request, next_query).await?;
+    Ok(parse_response(addr, &response))
+}
+
+async fn query(server: &str, request: &str, next_query: &mut Instant) -> Result<String> {
+    time::sleep_until(*next_query).await;
+    *next_query = Instant::now() + QUERY_INTERVAL;
+
+    let exchange = async {
+        let mut stream = TcpStream::connect((server, WHOIS_PORT)).await?;
+        stream.write_all(format!("{}\r\n", request).as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.take(MAX_RESPONSE).read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = time::timeout(QUERY_TIMEOUT, exchange)
+        .await
+        .map_err(|_| anyhow!("{} did not answer within {:?}", server, QUERY_TIMEOUT))?
+        .with_context(|| format!("Whois query to {} failed", server))?;
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+/// Key/value lines of a whois answer, skipping comments and empty values
+fn fields(text: &str) -> impl Iterator<Item = (String, &str)> {
+    text.lines()
+        .filter(|line| !line.starts_with(['%', '#']))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim()))
+        .filter(|(_, value)| !value.is_empty())
+}
+
+fn referral_server(text: &str) -> Option<String> {
+    fields(text)
+        .find(|(key, _)| key == "refer")
+        .map(|(_, value)| value.to_string())
+}
+
+/// Pick the network name and organization out of a registry's answer
+///
+/// RIPE, APNIC and AFRINIC use `netname`/`org-name` (or `descr`), ARIN
+/// `NetName`/`OrgName` and LACNIC `owner`. ARIN lists the covering networks
+/// before the most specific one, so later values win.
+fn parse_response(addr: IpAddr, text: &str) -> Option<WhoisInfo> {
+    let (mut netname, mut org, mut descr) = (None, None, None);
+    for (key, value) in fields(text) {
+        match key.as_str() {
+            "netname" => netname = Some(value.to_string()),
+            "org-name" | "orgname" | "owner" => org = Some(value.to_string()),
+            "descr" if descr.is_none() => descr = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    let org = org.or(descr);
+    if netname.is_none() && org.is_none() {
+        return None;
+    }
+    Some(WhoisInfo { addr, netname, org })
+}
+
+/// Whether an address is globally routed, and so registered to someone
+fn is_public(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            let shared = a == 100 && (b & 0xc0) == 64; // 100.64.0.0/10, carrier-grade NAT
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                || shared)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let [first, second, ..] = v6.segments();
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00 // Unique local
+                || (first & 0xffc0) == 0xfe80 // Link local
+                || (first == 0x2001 && second == 0x0db8)) // Documentation
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_registry_answers() {
+        let addr: IpAddr = "193.0.6.139".parse().unwrap();
+        let ripe = "\
+% This is the RIPE Database query service.
+
+inetnum:        193.0.0.0 - 193.0.23.255
+netname:        RIPE-NCC
+descr:          RIPE Network Coordination Centre
+org:            ORG-RIEN1-RIPE
+
+organisation:   ORG-RIEN1-RIPE
+org-name:       Reseaux IP Europeens Network Coordination Centre (RIPE NCC)
+";
+        let info = parse_response(addr, ripe).unwrap();
+        assert_eq!(info.netname.as_deref(), Some("RIPE-NCC"));
+        assert_eq!(
+            info.org.as_deref(),
+            Some("Reseaux IP Europeens Network Coordination Centre (RIPE NCC)")
+        );
+
+        let arin = "\
+# ARIN WHOIS data and services are subject to the Terms of Use
+
+NetRange:       8.0.0.0 - 8.127.255.255
+NetName:        LVLT-ORG-8-8
+OrgName:        Level 3 Parent, LLC
+
+NetRange:       8.8.8.0 - 8.8.8.255
+NetName:        GOGL
+OrgName:        Google LLC
+";
+        let info = parse_response("8.8.8.8".parse().unwrap(), arin).unwrap();
+        assert_eq!(info.to_string(), "GOGL (Google LLC)");
+
+        // An answer without any of the fields is no answer
+        assert_eq!(parse_response(addr, "% No entries found\n"), None);
+    }
+
+    #[test]
+    fn test_referral_server() {
+        let iana = "\
+% IANA WHOIS server
+
+refer:        whois.arin.net
+
+inetnum:      8.0.0.0 - 8.255.255.255
+whois:        whois.arin.net
+";
+        assert_eq!(referral_server(iana).as_deref(), Some("whois.arin.net"));
+        assert_eq!(referral_server("% nothing here\n"), None);
+    }
+
+    #[test]
+    fn test_only_public_addresses_are_looked_up() {
+        for public in ["8.8.8.8", "193.0.6.139", "2001:4860::8888", "::ffff:8.8.8.8"] {
+            assert!(is_public(public.parse().unwrap()), "{}", public);
+        }
+        for private in [
+            "10.1.2.3",
+            "192.168.0.1",
+            "100.64.0.1",
+            "127.0.0.1",
+            "192.0.2.1",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "2001:db8::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(private.parse().unwrap()), "{}", private);
+        }
+    }
+}